@@ -0,0 +1,101 @@
+extern crate futures;
+extern crate tokio;
+extern crate websocket;
+
+use std::fmt::Debug;
+
+use futures::{future, Future, Stream};
+use tokio::codec::{Framed, FramedParts};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::runtime::TaskExecutor;
+use websocket::async::{MsgCodecCtx, Server};
+use websocket::dataframe::DataFrame;
+use websocket::header::WebSocketProtocol;
+use websocket::server::InvalidConnection;
+use websocket::{ClientBuilder, DataFrameCodec};
+
+// the server which all the frames are relayed to, e.g. the async-server example
+const UPSTREAM: &'static str = "ws://127.0.0.1:2794";
+
+fn main() {
+	let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+	let executor = runtime.executor();
+	let server = Server::bind("127.0.0.1:2795", &tokio::reactor::Handle::default()).unwrap();
+
+	let f = server
+		.incoming()
+		.then(future::ok)
+		.filter(|event| match event {
+			Ok(_) => true,
+			Err(InvalidConnection { ref error, .. }) => {
+				println!("Bad client: {}", error);
+				false
+			}
+		})
+		.and_then(|event| event)
+		.map_err(|_| ())
+		.for_each(move |(upgrade, addr)| {
+			println!("Relaying a connection from: {}", addr);
+			let mut upstream = ClientBuilder::new(UPSTREAM).unwrap();
+			for protocol in upgrade.protocols() {
+				upstream = upstream.add_protocol(protocol.clone());
+			}
+
+			// connect to the upstream server first, so the client gets the
+			// protocol it picked
+			let f = upstream
+				.async_connect_insecure()
+				.and_then(move |(upstream, headers)| {
+					let upgrade = match headers.get::<WebSocketProtocol>() {
+						Some(protocols) => upgrade.use_protocol(protocols[0].clone()),
+						None => upgrade,
+					};
+					upgrade.accept().map(|(client, _)| (client, upstream))
+				})
+				.and_then(|(client, upstream)| {
+					// we're the server for the client and the client of the upstream server
+					let (client_sink, client_stream) =
+						into_frames(client, MsgCodecCtx::Server).split();
+					let (upstream_sink, upstream_stream) =
+						into_frames(upstream, MsgCodecCtx::Client).split();
+
+					// frames are relayed one by one, fragmented messages stay
+					// fragmented and control frames keep their position
+					let to_upstream = client_stream.forward(upstream_sink);
+					let to_client = upstream_stream.forward(client_sink);
+					to_upstream.join(to_client)
+				});
+
+			spawn_future(f, "Relay Status", &executor);
+			Ok(())
+		});
+
+	runtime.block_on(f).unwrap();
+}
+
+// Switch a connection from messages to dataframes, keeping the data which was
+// already read or not written yet.
+fn into_frames<S, C>(
+	framed: Framed<S, C>,
+	context: MsgCodecCtx,
+) -> Framed<S, DataFrameCodec<DataFrame>>
+where
+	S: AsyncRead + AsyncWrite,
+{
+	let parts = framed.into_parts();
+	let mut frame_parts = FramedParts::new(parts.io, DataFrameCodec::default(context));
+	frame_parts.read_buf = parts.read_buf;
+	frame_parts.write_buf = parts.write_buf;
+	Framed::from_parts(frame_parts)
+}
+
+fn spawn_future<F, I, E>(f: F, desc: &'static str, executor: &TaskExecutor)
+where
+	F: Future<Item = I, Error = E> + 'static + Send,
+	E: Debug,
+{
+	executor.spawn(
+		f.map_err(move |e| println!("{}: '{:?}'", desc, e))
+			.map(move |_| println!("{}: Finished.", desc)),
+	);
+}
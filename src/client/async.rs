@@ -70,6 +70,6 @@ pub type Client<S> = Framed<S, MessageCodec<OwnedMessage>>;
 /// the headers that came back from the server handshake.
 /// If the user used a protocol or attached some other headers check these response
 /// headers to see if the server accepted the protocol or other custom header.
-/// This crate will not automatically close the connection if the server refused
-/// to use the user protocols given to it, you must check that the server accepted.
+/// The handshake fails if the server picked a protocol which wasn't offered,
+/// but it is up to the user whether talking without any of them is fine.
 pub type ClientNew<S> = Box<Future<Item = (Client<S>, Headers), Error = WebSocketError> + Send>;
@@ -14,7 +14,9 @@
 //! `websocket::{sync, async}::client` module which will have all sync or all async things.
 
 pub mod builder;
-pub use self::builder::{ClientBuilder, ParseError, Url};
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+pub use self::builder::TlsOptions;
+pub use self::builder::{ClientBuilder, ConnectOptions, HttpProxy, ParseError, Url};
 
 #[cfg(feature = "async")]
 pub mod async;
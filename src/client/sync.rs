@@ -5,6 +5,7 @@ use std::io::Result as IoResult;
 use std::io::{Read, Write};
 use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::time::Duration;
 
 use dataframe::DataFrame;
 use header::extensions::Extension;
@@ -108,6 +109,17 @@ where
 	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().set_nonblocking(nonblocking)
 	}
+
+	/// Sets how long receiving waits for data, see `stream::sync::set_timeouts`.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_read_timeout(timeout)
+	}
+
+	/// Sets how long sending waits for the data to be written, see
+	/// `stream::sync::set_timeouts`.
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().as_tcp().set_write_timeout(timeout)
+	}
 }
 
 impl<S> Client<S>
@@ -177,15 +189,27 @@ where
 	}
 
 	/// Access the headers that were sent in the server's handshake response.
-	/// This is a catch all for headers other than protocols and extensions.
+	/// This is a catch all for headers other than protocols and extensions,
+	/// e.g. a session id or rate limits the server tells about. The status of
+	/// the response was always 101 Switching Protocols, anything else fails
+	/// the handshake with a `WebSocketError::StatusCodeError`. The cookies
+	/// the server sets are in the `SetCookie` header.
 	pub fn headers(&self) -> &Headers {
 		&self.headers
 	}
 
-	/// **If you supplied a protocol, you must check that it was accepted by
-	/// the server** using this function.
-	/// This is not done automatically because the terms of accepting a protocol
-	/// can get complicated, especially if some protocols depend on others, etc.
+	/// The protocols in the server's response, the handshake makes sure that
+	/// this is at most one of the offered protocols, see `protocol`.
+	pub fn protocols(&self) -> &[String] {
+		self.headers
+			.get::<WebSocketProtocol>()
+			.map(|p| p.0.as_slice())
+			.unwrap_or(&[])
+	}
+
+	/// The protocol the server picked from the ones offered with
+	/// `ClientBuilder::add_protocol`, `None` if it didn't pick any. A server
+	/// which picks one that wasn't offered fails the handshake.
 	///
 	/// ```rust,no_run
 	/// # use websocket::ClientBuilder;
@@ -194,14 +218,13 @@ where
 	///     .connect_insecure()
 	///     .unwrap();
 	///
-	/// // be sure to check the protocol is there!
-	/// assert!(client.protocols().iter().any(|p| p as &str == "xmpp"));
+	/// match client.protocol() {
+	///     Some("xmpp") => println!("talking XMPP"),
+	///     _ => println!("the server didn't pick a protocol"),
+	/// }
 	/// ```
-	pub fn protocols(&self) -> &[String] {
-		self.headers
-			.get::<WebSocketProtocol>()
-			.map(|p| p.0.as_slice())
-			.unwrap_or(&[])
+	pub fn protocol(&self) -> Option<&str> {
+		self.protocols().first().map(|protocol| &protocol[..])
 	}
 
 	/// If you supplied a protocol, be sure to check if it was accepted by the
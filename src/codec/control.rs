@@ -0,0 +1,823 @@
+//! Adapters that take care of websocket control messages.
+//!
+//! The codecs in the `ws` module only ever see one direction of a connection
+//! at a time, so they can't answer control messages themselves. The types in
+//! this module wrap a whole framed connection (something that is both a
+//! `Stream` and a `Sink` of messages, e.g. an async `Client`) instead.
+use std::io;
+
+use futures::sync::mpsc;
+use futures::try_ready;
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+
+use message::{CloseData, OwnedMessage};
+use result::WebSocketError;
+
+/// Wraps a framed websocket connection and answers every incoming ping
+/// with a pong carrying the same payload.
+///
+/// The pong is passed to the underlying sink the next time this stream is
+/// polled or something is sent through it, before any other outgoing message.
+/// If several pings arrive before the pong could be sent, only the most recent
+/// one is answered (which RFC 6455 permits).
+///
+/// By default pings are answered silently, use `set_forward_pings` to still
+/// receive them.
+///
+///# Example
+///
+///```rust,no_run
+///# extern crate websocket;
+///use websocket::async::futures::{Future, Stream};
+///use websocket::async::AutoPong;
+///use websocket::ClientBuilder;
+///# fn main() {
+///
+///let f = ClientBuilder::new("ws://127.0.0.1:9002")
+///    .unwrap()
+///    .async_connect_insecure()
+///    .map_err(|e| e.into())
+///    .and_then(|(client, _)| {
+///        AutoPong::new(client).for_each(|message| {
+///            println!("Received {:?}", message);
+///            Ok(())
+///        })
+///    });
+///# }
+///```
+pub struct AutoPong<S> {
+	inner: S,
+	pong: Option<OwnedMessage>,
+	forward_pings: bool,
+}
+
+impl<S> AutoPong<S>
+where
+	S: Stream<Item = OwnedMessage>
+		+ Sink<SinkItem = OwnedMessage, SinkError = <S as Stream>::Error>,
+{
+	/// Answer the pings received on `inner`.
+	pub fn new(inner: S) -> Self {
+		AutoPong {
+			inner,
+			pong: None,
+			forward_pings: false,
+		}
+	}
+
+	/// Set whether pings are still yielded by the stream after they have
+	/// been answered, the default is not to.
+	pub fn set_forward_pings(&mut self, forward: bool) {
+		self.forward_pings = forward;
+	}
+
+	/// A reference to the wrapped connection.
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	/// A mutable reference to the wrapped connection.
+	///
+	/// Sending messages directly through it can overtake a pending pong.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.inner
+	}
+
+	/// Unwrap the connection, a pong which has not been sent yet is lost.
+	pub fn into_inner(self) -> S {
+		self.inner
+	}
+
+	// Hands a pending pong to the sink, returns whether nothing is pending
+	// anymore.
+	fn start_send_pong(&mut self) -> Result<bool, <S as Stream>::Error> {
+		if let Some(pong) = self.pong.take() {
+			if let AsyncSink::NotReady(pong) = self.inner.start_send(pong)? {
+				self.pong = Some(pong);
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+}
+
+impl<S> Stream for AutoPong<S>
+where
+	S: Stream<Item = OwnedMessage>
+		+ Sink<SinkItem = OwnedMessage, SinkError = <S as Stream>::Error>,
+{
+	type Item = OwnedMessage;
+	type Error = <S as Stream>::Error;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		loop {
+			if self.pong.is_some() && self.start_send_pong()? {
+				// drive the pong out, without waiting for it
+				self.inner.poll_complete()?;
+			}
+
+			let message = match try_ready!(self.inner.poll()) {
+				Some(message) => message,
+				None => return Ok(Async::Ready(None)),
+			};
+
+			if let OwnedMessage::Ping(ref data) = message {
+				// the codec never decodes longer control frames anyway
+				if data.len() <= 125 {
+					self.pong = Some(OwnedMessage::Pong(data.clone()));
+				}
+			}
+
+			if !message.is_ping() || self.forward_pings {
+				return Ok(Async::Ready(Some(message)));
+			}
+		}
+	}
+}
+
+impl<S> Sink for AutoPong<S>
+where
+	S: Stream<Item = OwnedMessage>
+		+ Sink<SinkItem = OwnedMessage, SinkError = <S as Stream>::Error>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = <S as Stream>::Error;
+
+	fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+		if !self.start_send_pong()? {
+			return Ok(AsyncSink::NotReady(item));
+		}
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+		if !self.start_send_pong()? {
+			return Ok(Async::NotReady);
+		}
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), Self::SinkError> {
+		if !self.start_send_pong()? {
+			return Ok(Async::NotReady);
+		}
+		self.inner.close()
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CloseState {
+	Open,
+	// we sent a close and wait for the peer to echo it
+	CloseSent,
+	// the peer sent a close, our echo still has to be sent
+	CloseReceived,
+	Closed,
+}
+
+/// Wraps a framed websocket connection and takes care of the closing
+/// handshake.
+///
+/// When the peer sends a close message it is yielded as usual and a close
+/// message with the same status code is echoed before the stream ends.
+/// When a close message is sent through this sink incoming messages are still
+/// yielded until the peer echoes the close, then the stream ends.
+///
+/// Once a close message has been sent (by the application or as an echo)
+/// sending anything else fails with a `WebSocketError::DataFrameError`, which
+/// isn't fatal as the connection still receives. When the closing handshake
+/// is complete the wrapped sink is closed, after the stream ended the
+/// connection can be dropped.
+pub struct CloseHandshake<S> {
+	inner: S,
+	state: CloseState,
+	echo: Option<OwnedMessage>,
+	inner_closed: bool,
+}
+
+impl<S> CloseHandshake<S>
+where
+	S: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	/// Take care of the closing handshake on `inner`.
+	pub fn new(inner: S) -> Self {
+		CloseHandshake {
+			inner,
+			state: CloseState::Open,
+			echo: None,
+			inner_closed: false,
+		}
+	}
+
+	/// Whether a close message has been sent, i.e. nothing else can be sent
+	/// anymore.
+	pub fn is_closing(&self) -> bool {
+		self.state != CloseState::Open
+	}
+
+	/// Whether the closing handshake is complete and the wrapped sink has
+	/// been closed.
+	pub fn is_closed(&self) -> bool {
+		self.state == CloseState::Closed && self.inner_closed
+	}
+
+	/// A reference to the wrapped connection.
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	/// A mutable reference to the wrapped connection.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.inner
+	}
+
+	/// Unwrap the connection, an echo which has not been sent yet is lost.
+	pub fn into_inner(self) -> S {
+		self.inner
+	}
+
+	// Sends a pending echo and closes the wrapped sink once the closing
+	// handshake is complete, returns whether nothing is pending anymore.
+	fn poll_echo(&mut self) -> Poll<(), WebSocketError> {
+		if let Some(echo) = self.echo.take() {
+			if let AsyncSink::NotReady(echo) = self.inner.start_send(echo)? {
+				self.echo = Some(echo);
+				return Ok(Async::NotReady);
+			}
+			self.state = CloseState::Closed;
+		}
+		if self.state == CloseState::Closed && !self.inner_closed {
+			// this flushes the echo first
+			try_ready!(self.inner.close());
+			self.inner_closed = true;
+		}
+		Ok(Async::Ready(()))
+	}
+}
+
+impl<S> Stream for CloseHandshake<S>
+where
+	S: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		match self.state {
+			CloseState::CloseReceived | CloseState::Closed => {
+				try_ready!(self.poll_echo());
+				return Ok(Async::Ready(None));
+			}
+			CloseState::Open | CloseState::CloseSent => (),
+		}
+
+		let message = match try_ready!(self.inner.poll()) {
+			Some(message) => message,
+			None => return Ok(Async::Ready(None)),
+		};
+
+		if let OwnedMessage::Close(ref data) = message {
+			if self.state == CloseState::Open {
+				self.state = CloseState::CloseReceived;
+				self.echo = Some(OwnedMessage::Close(data.as_ref().map(|data| CloseData {
+					status_code: data.status_code,
+					reason: String::new(),
+				})));
+			} else {
+				self.state = CloseState::Closed;
+			}
+		}
+		Ok(Async::Ready(Some(message)))
+	}
+}
+
+impl<S> Sink for CloseHandshake<S>
+where
+	S: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+		if self.state != CloseState::Open {
+			return Err(WebSocketError::DataFrameError(
+				"Cannot send a message after a close message",
+			));
+		}
+		let is_close = item.is_close();
+		let result = self.inner.start_send(item)?;
+		if is_close && result.is_ready() {
+			self.state = CloseState::CloseSent;
+		}
+		Ok(result)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+		try_ready!(self.poll_echo());
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), Self::SinkError> {
+		try_ready!(self.poll_echo());
+		if self.inner_closed {
+			return Ok(Async::Ready(()));
+		}
+		self.inner.close()
+	}
+}
+
+/// Separate the control messages of a framed websocket connection from its
+/// data messages, so pings are answered no matter how long the application
+/// takes to process the data.
+///
+/// The connection is run by the returned `ControlDispatch`, which has to be
+/// spawned on its own. It answers every ping with a pong right away, which
+/// is sent before any other outgoing message. Text and binary messages are
+/// yielded in order by the `DataMessages` stream, which is also the sink for
+/// outgoing messages. Pings, pongs and close messages are yielded by the
+/// `ControlMessages` stream, drop it if you are not interested in them.
+///
+/// Up to `capacity` data messages are buffered for the application. Once
+/// they are all waiting the connection isn't read anymore (and pings aren't
+/// answered anymore) until the application catches up. Wrap the connection in
+/// a `CloseHandshake` first to also have close messages echoed.
+///
+///# Example
+///
+///```rust,no_run
+///# extern crate tokio;
+///# extern crate websocket;
+///use websocket::async::futures::{Future, Stream};
+///use websocket::async::separate_control;
+///use websocket::ClientBuilder;
+///# fn main() {
+///
+///let f = ClientBuilder::new("ws://127.0.0.1:9002")
+///    .unwrap()
+///    .async_connect_insecure()
+///    .and_then(|(client, _)| {
+///        let (dispatch, data, _) = separate_control(client, 16);
+///        tokio::spawn(dispatch.map_err(|e| println!("Connection failed: {:?}", e)));
+///        data.for_each(|message| {
+///            println!("Received {:?}", message);
+///            Ok(())
+///        })
+///    });
+///tokio::run(f.map_err(|_| ()));
+///# }
+///```
+pub fn separate_control<S>(
+	inner: S,
+	capacity: usize,
+) -> (ControlDispatch<S>, DataMessages, ControlMessages)
+where
+	S: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	let (data_sender, data_receiver) = mpsc::channel(capacity);
+	let (outgoing_sender, outgoing_receiver) = mpsc::channel(capacity);
+	let (control_sender, control_receiver) = mpsc::unbounded();
+	let dispatch = ControlDispatch {
+		inner,
+		pong: None,
+		incoming_done: false,
+		data: data_sender,
+		data_pending: None,
+		control: control_sender,
+		outgoing: outgoing_receiver,
+		outgoing_pending: None,
+	};
+	let data = DataMessages {
+		incoming: data_receiver,
+		outgoing: outgoing_sender,
+	};
+	let control = ControlMessages {
+		incoming: control_receiver,
+	};
+	(dispatch, data, control)
+}
+
+/// Runs a connection passed to `separate_control`, this future resolves
+/// once the connection ended or fails with the error of the connection.
+pub struct ControlDispatch<S> {
+	inner: S,
+	pong: Option<OwnedMessage>,
+	incoming_done: bool,
+	data: mpsc::Sender<OwnedMessage>,
+	// a data message which didn't fit into the channel yet
+	data_pending: Option<OwnedMessage>,
+	control: mpsc::UnboundedSender<OwnedMessage>,
+	outgoing: mpsc::Receiver<OwnedMessage>,
+	// an outgoing message which the connection didn't accept yet
+	outgoing_pending: Option<OwnedMessage>,
+}
+
+impl<S> ControlDispatch<S>
+where
+	S: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	/// A reference to the wrapped connection.
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	/// Unwrap the connection, messages which have not been passed on yet
+	/// are lost.
+	pub fn into_inner(self) -> S {
+		self.inner
+	}
+
+	// Reads incoming messages until the connection or the data channel isn't
+	// ready, answering pings on the way.
+	fn poll_incoming(&mut self) -> Result<(), WebSocketError> {
+		loop {
+			if let Some(message) = self.data_pending.take() {
+				match self.data.start_send(message) {
+					Ok(AsyncSink::NotReady(message)) => {
+						self.data_pending = Some(message);
+						return Ok(());
+					}
+					// nobody receives the data messages anymore, drop them
+					Ok(AsyncSink::Ready) | Err(_) => (),
+				}
+			}
+			if self.incoming_done {
+				return Ok(());
+			}
+
+			let message = match self.inner.poll()? {
+				Async::Ready(Some(message)) => message,
+				Async::Ready(None) => {
+					self.incoming_done = true;
+					continue;
+				}
+				Async::NotReady => return Ok(()),
+			};
+			if message.is_data() {
+				self.data_pending = Some(message);
+				continue;
+			}
+
+			if let OwnedMessage::Ping(ref data) = message {
+				// the codec never decodes longer control frames anyway
+				if data.len() <= 125 {
+					self.pong = Some(OwnedMessage::Pong(data.clone()));
+					self.start_send_pong()?;
+				}
+			}
+			let _ = self.control.unbounded_send(message);
+		}
+	}
+
+	// Hands a pending pong to the sink, returns whether nothing is pending
+	// anymore.
+	fn start_send_pong(&mut self) -> Result<bool, WebSocketError> {
+		if let Some(pong) = self.pong.take() {
+			if let AsyncSink::NotReady(pong) = self.inner.start_send(pong)? {
+				self.pong = Some(pong);
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	// Passes the outgoing messages to the connection, after a pending pong.
+	fn poll_outgoing(&mut self) -> Poll<(), WebSocketError> {
+		while self.start_send_pong()? {
+			let message = match self.outgoing_pending.take() {
+				Some(message) => message,
+				None => match self.outgoing.poll() {
+					Ok(Async::Ready(Some(message))) => message,
+					Ok(Async::Ready(None)) | Ok(Async::NotReady) | Err(()) => break,
+				},
+			};
+			if let AsyncSink::NotReady(message) = self.inner.start_send(message)? {
+				self.outgoing_pending = Some(message);
+				break;
+			}
+		}
+		self.inner.poll_complete()
+	}
+}
+
+impl<S> Future for ControlDispatch<S>
+where
+	S: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = ();
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<(), WebSocketError> {
+		self.poll_incoming()?;
+		let flushed = self.poll_outgoing()?;
+		if self.incoming_done && self.data_pending.is_none() && flushed.is_ready() {
+			return Ok(Async::Ready(()));
+		}
+		Ok(Async::NotReady)
+	}
+}
+
+/// The data messages of a connection passed to `separate_control`, and the
+/// sink for all outgoing messages.
+///
+/// The stream ends when the `ControlDispatch` is done, sending fails with a
+/// broken pipe error then.
+pub struct DataMessages {
+	incoming: mpsc::Receiver<OwnedMessage>,
+	outgoing: mpsc::Sender<OwnedMessage>,
+}
+
+impl Stream for DataMessages {
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		// receiving from a channel never fails
+		Ok(self.incoming.poll().unwrap_or(Async::Ready(None)))
+	}
+}
+
+impl Sink for DataMessages {
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+		self.outgoing.start_send(item).map_err(|_| dispatch_gone())
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+		self.outgoing.poll_complete().map_err(|_| dispatch_gone())
+	}
+
+	fn close(&mut self) -> Poll<(), Self::SinkError> {
+		self.outgoing.close().map_err(|_| dispatch_gone())
+	}
+}
+
+fn dispatch_gone() -> WebSocketError {
+	io::Error::new(io::ErrorKind::BrokenPipe, "The connection has ended").into()
+}
+
+/// The pings, pongs and close messages of a connection passed to
+/// `separate_control`, the stream ends when the `ControlDispatch` is done.
+pub struct ControlMessages {
+	incoming: mpsc::UnboundedReceiver<OwnedMessage>,
+}
+
+impl Stream for ControlMessages {
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+		Ok(self.incoming.poll().unwrap_or(Async::Ready(None)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::ws::{Context, MessageCodec};
+	use futures::Future;
+	use message::Message;
+	use std::io::Cursor;
+	use stream::ReadWritePair;
+	use tokio_codec::{Decoder, Framed};
+	use ws::Message as MessageTrait;
+
+	type Connection =
+		Framed<ReadWritePair<Cursor<Vec<u8>>, Cursor<Vec<u8>>>, MessageCodec<OwnedMessage>>;
+
+	fn connect(incoming: &[Message]) -> Connection {
+		let mut input = Vec::new();
+		for message in incoming {
+			message.serialize(&mut input, false).unwrap();
+		}
+		MessageCodec::default(Context::Client)
+			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])))
+	}
+
+	// collects the messages of a stream which may end without a close message
+	fn received<S>(stream: S) -> Vec<OwnedMessage>
+	where
+		S: Stream<Item = OwnedMessage, Error = WebSocketError>,
+	{
+		let mut messages = Vec::new();
+		for result in stream.wait() {
+			match result {
+				Ok(message) => messages.push(message),
+				Err(WebSocketError::ConnectionDropped) => break,
+				Err(e) => panic!("unexpected error {:?}", e),
+			}
+		}
+		messages
+	}
+
+	fn sent(connection: Connection) -> Vec<OwnedMessage> {
+		let written = connection.into_parts().io.1.into_inner();
+		received(
+			MessageCodec::default(Context::Server)
+				.framed(ReadWritePair(Cursor::new(written), Cursor::new(vec![]))),
+		)
+	}
+
+	#[test]
+	fn answers_pings_with_same_payload() {
+		let payload: Vec<u8> = (0..125).collect();
+		let mut client = AutoPong::new(connect(&[
+			Message::ping(payload.clone()),
+			Message::text("hi"),
+			Message::ping(vec![1, 2, 3]),
+		]));
+
+		assert_eq!(
+			received(client.by_ref()),
+			vec![OwnedMessage::Text("hi".to_string())]
+		);
+		assert_eq!(
+			sent(client.into_inner()),
+			vec![
+				OwnedMessage::Pong(payload),
+				OwnedMessage::Pong(vec![1, 2, 3])
+			]
+		);
+	}
+
+	#[test]
+	fn forwards_pings() {
+		let mut client = AutoPong::new(connect(&[Message::ping(vec![1]), Message::ping(vec![2])]));
+		client.set_forward_pings(true);
+
+		assert_eq!(
+			received(client.by_ref()),
+			vec![OwnedMessage::Ping(vec![1]), OwnedMessage::Ping(vec![2])]
+		);
+		assert_eq!(
+			sent(client.into_inner()),
+			vec![OwnedMessage::Pong(vec![1]), OwnedMessage::Pong(vec![2])]
+		);
+	}
+
+	#[test]
+	fn pong_goes_out_before_sent_messages() {
+		let mut client = AutoPong::new(connect(&[Message::ping(vec![7])]));
+		client.set_forward_pings(true);
+		let (message, client) = client.into_future().wait().map_err(|e| e.0).unwrap();
+		assert_eq!(message, Some(OwnedMessage::Ping(vec![7])));
+
+		let client = client
+			.send(OwnedMessage::Text("after".to_string()))
+			.wait()
+			.unwrap();
+		assert_eq!(
+			sent(client.into_inner()),
+			vec![
+				OwnedMessage::Pong(vec![7]),
+				OwnedMessage::Text("after".to_string())
+			]
+		);
+	}
+
+	#[test]
+	fn separates_control_messages() {
+		let (mut dispatch, data, control) = separate_control(
+			connect(&[
+				Message::ping(vec![1]),
+				Message::text("a"),
+				Message::pong(vec![2]),
+				Message::binary(vec![3]),
+				Message::ping(vec![4]),
+				Message::close(),
+			]),
+			8,
+		);
+		let data = data
+			.send(OwnedMessage::Text("out".to_string()))
+			.wait()
+			.unwrap();
+
+		(&mut dispatch).wait().unwrap();
+		assert_eq!(
+			sent(dispatch.into_inner()),
+			vec![
+				OwnedMessage::Pong(vec![1]),
+				OwnedMessage::Pong(vec![4]),
+				OwnedMessage::Text("out".to_string())
+			]
+		);
+		assert_eq!(
+			data.collect().wait().unwrap(),
+			vec![
+				OwnedMessage::Text("a".to_string()),
+				OwnedMessage::Binary(vec![3])
+			]
+		);
+		assert_eq!(
+			control.collect().wait().unwrap(),
+			vec![
+				OwnedMessage::Ping(vec![1]),
+				OwnedMessage::Pong(vec![2]),
+				OwnedMessage::Ping(vec![4]),
+				OwnedMessage::Close(None)
+			]
+		);
+	}
+
+	#[test]
+	fn echoes_peer_close() {
+		let close = OwnedMessage::Close(Some(CloseData {
+			status_code: 1001,
+			reason: "going away".to_string(),
+		}));
+		let mut client = CloseHandshake::new(connect(&[
+			Message::text("hi"),
+			close.clone().into(),
+			Message::text("ignored"),
+		]));
+
+		let received = client.by_ref().collect().wait().unwrap();
+		assert_eq!(received, vec![OwnedMessage::Text("hi".to_string()), close]);
+		assert!(client.is_closed());
+		match client.start_send(OwnedMessage::Text("late".to_string())) {
+			Err(ref e @ WebSocketError::DataFrameError(_)) if !e.is_fatal() => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		assert_eq!(
+			sent(client.into_inner()),
+			vec![OwnedMessage::Close(Some(CloseData {
+				status_code: 1001,
+				reason: String::new(),
+			}))]
+		);
+	}
+
+	#[test]
+	fn waits_for_echo_of_own_close() {
+		let client = CloseHandshake::new(connect(&[
+			Message::text("in flight"),
+			Message::close(),
+			Message::text("ignored"),
+		]));
+		let mut client = client.send(OwnedMessage::Close(None)).wait().unwrap();
+		assert!(client.is_closing());
+		assert!(!client.is_closed());
+
+		let received = client.by_ref().collect().wait().unwrap();
+		assert_eq!(
+			received,
+			vec![
+				OwnedMessage::Text("in flight".to_string()),
+				OwnedMessage::Close(None)
+			]
+		);
+		assert!(client.is_closed());
+		assert_eq!(sent(client.into_inner()), vec![OwnedMessage::Close(None)]);
+	}
+
+	// a connection which records whether its sink was closed
+	struct Closable(Connection, bool);
+
+	impl Stream for Closable {
+		type Item = OwnedMessage;
+		type Error = WebSocketError;
+
+		fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+			self.0.poll()
+		}
+	}
+
+	impl Sink for Closable {
+		type SinkItem = OwnedMessage;
+		type SinkError = WebSocketError;
+
+		fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+			assert!(!self.1, "sent after closing");
+			self.0.start_send(item)
+		}
+
+		fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+			self.0.poll_complete()
+		}
+
+		fn close(&mut self) -> Poll<(), WebSocketError> {
+			try_ready!(self.0.close());
+			self.1 = true;
+			Ok(Async::Ready(()))
+		}
+	}
+
+	#[test]
+	fn closes_sink_after_echo() {
+		let mut client = CloseHandshake::new(Closable(connect(&[Message::close()]), false));
+		let received = client.by_ref().collect().wait().unwrap();
+		assert_eq!(received, vec![OwnedMessage::Close(None)]);
+		assert!(client.get_ref().1);
+		assert!(client.is_closed());
+		assert!(client.close().unwrap().is_ready());
+		assert_eq!(sent(client.into_inner().0), vec![OwnedMessage::Close(None)]);
+	}
+}
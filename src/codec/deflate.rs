@@ -0,0 +1,428 @@
+//! Support for the permessage-deflate extension (RFC 7692).
+//!
+//! Once the extension was negotiated during the HTTP upgrade (see the
+//! `Sec-WebSocket-Extensions` header), `MessageCodec::set_deflate` with the
+//! agreed `DeflateConfig` makes the codec compress outgoing data messages and
+//! decompress incoming ones. The codec takes care of the websocket side of
+//! the extension: the RSV1 bit, the `0x00 0x00 0xff 0xff` trailer which is
+//! removed from every compressed message and control messages, which are
+//! never compressed.
+//!
+//! The DEFLATE algorithm itself is provided by implementing `Compressor` and
+//! `Decompressor`, e.g. on top of the raw (headerless) streams of the
+//! `flate2` crate:
+//!
+//!```rust,ignore
+//!use std::io;
+//!use flate2::{Compress, FlushCompress};
+//!use websocket::codec::deflate::Compressor;
+//!
+//!struct Deflater(Compress);
+//!
+//!impl Compressor for Deflater {
+//!    fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+//!        let start = self.0.total_in();
+//!        loop {
+//!            output.reserve(input.len() / 2 + 64);
+//!            let read = (self.0.total_in() - start) as usize;
+//!            self.0.compress_vec(&input[read..], output, FlushCompress::Sync)?;
+//!            // the flush is complete once there was output space left over
+//!            let read = (self.0.total_in() - start) as usize;
+//!            if read == input.len() && output.len() < output.capacity() {
+//!                return Ok(());
+//!            }
+//!        }
+//!    }
+//!
+//!    fn reset(&mut self) {
+//!        self.0.reset();
+//!    }
+//!}
+//!```
+use std::fmt;
+use std::io;
+
+use codec::ws::Context;
+use header::deflate::DeflateParams;
+use header::extensions::{Extension, Parameter};
+use result::{WebSocketError, WebSocketResult};
+
+pub use header::deflate::{MAX_WINDOW_BITS, PERMESSAGE_DEFLATE};
+
+const INVALID_PARAMETER: &str = "Invalid permessage-deflate parameter";
+
+/// The default for `DeflateConfig::compress_threshold`, messages shorter
+/// than this are rarely worth compressing.
+pub const DEFAULT_COMPRESS_THRESHOLD: usize = 64;
+
+/// The parameters of the permessage-deflate extension which were agreed on
+/// during the handshake, named like in RFC 7692.
+///
+/// The codec uses them to decide when to reset its `Compressor` and
+/// `Decompressor`. The window sizes have to be applied when creating them,
+/// see `compressor_window_bits` and `decompressor_window_bits`, which
+/// `MessageCodec::with_options` does by passing them to a `DeflateBackend`.
+///
+/// Without context takeover every message is compressed on its own, which
+/// costs some compression ratio but means that neither end has to keep the
+/// sliding window of a connection in between messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateConfig {
+	/// The server resets its compressor after each message, so the
+	/// client can reset its decompressor as well.
+	pub server_no_context_takeover: bool,
+	/// The client resets its compressor after each message, so the
+	/// server can reset its decompressor as well.
+	pub client_no_context_takeover: bool,
+	/// The base-2 logarithm of the window size the server compresses
+	/// with, between 8 and 15.
+	pub server_max_window_bits: u8,
+	/// The base-2 logarithm of the window size the client compresses
+	/// with, between 8 and 15.
+	pub client_max_window_bits: u8,
+	/// Outgoing data messages with a payload shorter than this (in bytes)
+	/// are sent uncompressed. This is a local choice and doesn't have to be
+	/// negotiated, the peer has to accept uncompressed messages anyway.
+	pub compress_threshold: usize,
+}
+
+impl Default for DeflateConfig {
+	/// Context takeover in both directions with the largest window, which
+	/// is what the extension uses without any parameters, and
+	/// `DEFAULT_COMPRESS_THRESHOLD`.
+	fn default() -> Self {
+		DeflateConfig {
+			server_no_context_takeover: false,
+			client_no_context_takeover: false,
+			server_max_window_bits: MAX_WINDOW_BITS,
+			client_max_window_bits: MAX_WINDOW_BITS,
+			compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+		}
+	}
+}
+
+impl From<DeflateParams> for DeflateConfig {
+	/// The negotiated parameters (see `header::deflate::DeflateOffer`) with
+	/// `DEFAULT_COMPRESS_THRESHOLD`.
+	fn from(params: DeflateParams) -> Self {
+		DeflateConfig {
+			server_no_context_takeover: params.server_no_context_takeover,
+			client_no_context_takeover: params.client_no_context_takeover,
+			server_max_window_bits: params.server_max_window_bits,
+			client_max_window_bits: params.client_max_window_bits,
+			compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+		}
+	}
+}
+
+impl DeflateConfig {
+	/// Whether the compressor of a codec in the role of `context` is reset
+	/// after each message.
+	pub fn compressor_no_context_takeover(&self, context: Context) -> bool {
+		match context {
+			Context::Server => self.server_no_context_takeover,
+			Context::Client => self.client_no_context_takeover,
+		}
+	}
+
+	/// Whether the decompressor of a codec in the role of `context` is reset
+	/// after each message, because the peer doesn't take over its context.
+	pub fn decompressor_no_context_takeover(&self, context: Context) -> bool {
+		match context {
+			Context::Server => self.client_no_context_takeover,
+			Context::Client => self.server_no_context_takeover,
+		}
+	}
+
+	/// The window bits the `Compressor` of a codec in the role of `context`
+	/// may use at most.
+	pub fn compressor_window_bits(&self, context: Context) -> u8 {
+		match context {
+			Context::Server => self.server_max_window_bits,
+			Context::Client => self.client_max_window_bits,
+		}
+	}
+
+	/// The window bits the `Decompressor` of a codec in the role of `context`
+	/// needs.
+	pub fn decompressor_window_bits(&self, context: Context) -> u8 {
+		match context {
+			Context::Server => self.client_max_window_bits,
+			Context::Client => self.server_max_window_bits,
+		}
+	}
+
+	/// Read the parameters of a negotiated permessage-deflate extension,
+	/// e.g. from the `Sec-WebSocket-Extensions` header of a server's response.
+	///
+	/// Parameters which are unknown, repeated or have an invalid value are
+	/// a `WebSocketError::ProtocolError`, the connection has to be failed then.
+	pub fn from_extension(extension: &Extension) -> WebSocketResult<Self> {
+		if extension.name != PERMESSAGE_DEFLATE {
+			return Err(WebSocketError::ProtocolError(
+				"Not a permessage-deflate extension",
+			));
+		}
+
+		let mut config = DeflateConfig::default();
+		let mut seen = Vec::new();
+		for param in &extension.params {
+			if seen.contains(&&param.name[..]) {
+				return Err(WebSocketError::ProtocolError(INVALID_PARAMETER));
+			}
+			seen.push(&param.name[..]);
+
+			let value = param.value.as_ref().map(|value| value.trim_matches('"'));
+			match (&param.name[..], value) {
+				("server_no_context_takeover", None) => config.server_no_context_takeover = true,
+				("client_no_context_takeover", None) => config.client_no_context_takeover = true,
+				("server_max_window_bits", Some(bits)) => {
+					config.server_max_window_bits = parse_window_bits(bits)?
+				}
+				// a client may offer this without a value
+				("client_max_window_bits", None) => (),
+				("client_max_window_bits", Some(bits)) => {
+					config.client_max_window_bits = parse_window_bits(bits)?
+				}
+				_ => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+			}
+		}
+		Ok(config)
+	}
+
+	/// The extension with these parameters, leaving out the default ones.
+	pub fn to_extension(&self) -> Extension {
+		let mut extension = Extension::new(PERMESSAGE_DEFLATE.to_string());
+		let flags = [
+			(
+				"server_no_context_takeover",
+				self.server_no_context_takeover,
+			),
+			(
+				"client_no_context_takeover",
+				self.client_no_context_takeover,
+			),
+		];
+		for &(name, set) in &flags {
+			if set {
+				extension
+					.params
+					.push(Parameter::new(name.to_string(), None));
+			}
+		}
+		let window_bits = [
+			("server_max_window_bits", self.server_max_window_bits),
+			("client_max_window_bits", self.client_max_window_bits),
+		];
+		for &(name, bits) in &window_bits {
+			if bits != MAX_WINDOW_BITS {
+				extension
+					.params
+					.push(Parameter::new(name.to_string(), Some(bits.to_string())));
+			}
+		}
+		extension
+	}
+}
+
+fn parse_window_bits(value: &str) -> WebSocketResult<u8> {
+	match value.parse() {
+		Ok(bits @ 8..=15) if !value.starts_with('0') => Ok(bits),
+		_ => Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+	}
+}
+
+/// Creates the compressors and decompressors of connections which use the
+/// permessage-deflate extension, e.g. for `MessageCodec::with_options`.
+pub trait DeflateBackend: fmt::Debug + Send + Sync {
+	/// A compressor which uses a window of at most `1 << window_bits` bytes.
+	fn compressor(&self, window_bits: u8) -> Box<dyn Compressor>;
+
+	/// A decompressor for data which was compressed with a window of at
+	/// most `1 << window_bits` bytes.
+	fn decompressor(&self, window_bits: u8) -> Box<dyn Decompressor>;
+}
+
+/// Compresses the payloads of outgoing data messages.
+pub trait Compressor: Send {
+	/// Compress `input` and append the result to `output`.
+	///
+	/// The compressed data has to be completed with a sync flush, i.e. it
+	/// must end with an empty uncompressed block (`0x00 0x00 0xff 0xff`).
+	/// Unless `reset` is called, the next message may refer to the data of
+	/// the previous ones.
+	fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+
+	/// Forget all previous data, so the next message is compressed on its own.
+	fn reset(&mut self);
+}
+
+/// Decompresses the payloads of incoming data messages.
+pub trait Decompressor: Send {
+	/// Decompress `input`, which is one part of a compressed message, and
+	/// append the result to `output`.
+	///
+	/// A message is usually decompressed in several calls, one for each
+	/// dataframe and the last one with the `0x00 0x00 0xff 0xff` trailer.
+	/// Invalid data should be reported as an `io::ErrorKind::InvalidData`
+	/// error.
+	///
+	/// Decompression should stop as soon as more than `limit` bytes were
+	/// appended to `output` (a few bytes more don't hurt), the message is
+	/// too large then and the codec fails with
+	/// `WebSocketError::MessageTooLarge` without needing the rest of it.
+	/// This keeps a small, highly compressed message from inflating to an
+	/// arbitrary size in memory.
+	fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>, limit: usize) -> io::Result<()>;
+
+	/// Forget all previous data, so the next message is decompressed on its own.
+	fn reset(&mut self);
+}
+
+/// A DEFLATE implementation for tests which only uses uncompressed blocks,
+/// that's enough to check the framing of permessage-deflate.
+#[cfg(test)]
+pub mod stored {
+	use super::*;
+
+	/// Compresses into uncompressed blocks only.
+	pub struct Stored;
+
+	impl Compressor for Stored {
+		fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+			for block in input.chunks(0xffff) {
+				let len = block.len() as u16;
+				output.push(0x00);
+				output.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+				output.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+				output.extend_from_slice(block);
+			}
+			// sync flush
+			output.extend_from_slice(&[0x00, 0x00, 0x00, 0xff, 0xff]);
+			Ok(())
+		}
+
+		fn reset(&mut self) {}
+	}
+
+	/// Decompresses what `Stored` compressed.
+	#[derive(Default)]
+	pub struct StoredDecompressor {
+		pending: Vec<u8>,
+	}
+
+	impl Decompressor for StoredDecompressor {
+		fn decompress(
+			&mut self,
+			input: &[u8],
+			output: &mut Vec<u8>,
+			limit: usize,
+		) -> io::Result<()> {
+			let start = output.len();
+			self.pending.extend_from_slice(input);
+			while self.pending.len() >= 5 && output.len() - start <= limit {
+				let len = self.pending[1] as usize | (self.pending[2] as usize) << 8;
+				if self.pending[0] != 0x00 || self.pending[3] != !self.pending[1] {
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "not stored"));
+				}
+				if self.pending.len() < 5 + len {
+					break;
+				}
+				output.extend_from_slice(&self.pending[5..5 + len]);
+				self.pending.drain(..5 + len);
+			}
+			Ok(())
+		}
+
+		fn reset(&mut self) {
+			self.pending.clear();
+		}
+	}
+
+	/// Creates `Stored` compressors and decompressors for any window size.
+	#[derive(Debug)]
+	pub struct StoredBackend;
+
+	impl DeflateBackend for StoredBackend {
+		fn compressor(&self, _: u8) -> Box<dyn Compressor> {
+			Box::new(Stored)
+		}
+
+		fn decompressor(&self, _: u8) -> Box<dyn Decompressor> {
+			Box::new(StoredDecompressor::default())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn extension(params: &[(&str, Option<&str>)]) -> Extension {
+		Extension {
+			name: PERMESSAGE_DEFLATE.to_string(),
+			params: params
+				.iter()
+				.map(|&(name, value)| Parameter::new(name.to_string(), value.map(str::to_string)))
+				.collect(),
+		}
+	}
+
+	#[test]
+	fn config_from_extension() {
+		assert_eq!(
+			DeflateConfig::from_extension(&extension(&[])).unwrap(),
+			DeflateConfig::default()
+		);
+
+		let config = DeflateConfig::from_extension(&extension(&[
+			("server_no_context_takeover", None),
+			("client_max_window_bits", Some("10")),
+			("server_max_window_bits", Some("\"8\"")),
+		]))
+		.unwrap();
+		assert_eq!(
+			config,
+			DeflateConfig {
+				server_no_context_takeover: true,
+				server_max_window_bits: 8,
+				client_max_window_bits: 10,
+				..DeflateConfig::default()
+			}
+		);
+		assert_eq!(config.compressor_window_bits(Context::Client), 10);
+		assert_eq!(config.decompressor_window_bits(Context::Client), 8);
+		assert!(config.decompressor_no_context_takeover(Context::Client));
+		assert!(!config.compressor_no_context_takeover(Context::Client));
+
+		// and back
+		assert_eq!(
+			DeflateConfig::from_extension(&config.to_extension()).unwrap(),
+			config
+		);
+	}
+
+	#[test]
+	fn config_from_invalid_extension() {
+		let invalid = vec![
+			extension(&[("unknown", None)]),
+			extension(&[("server_no_context_takeover", Some("1"))]),
+			extension(&[("server_max_window_bits", None)]),
+			extension(&[("server_max_window_bits", Some("7"))]),
+			extension(&[("client_max_window_bits", Some("16"))]),
+			extension(&[("client_max_window_bits", Some("010"))]),
+			extension(&[
+				("client_no_context_takeover", None),
+				("client_no_context_takeover", None),
+			]),
+			Extension::new("deflate-frame".to_string()),
+		];
+		for extension in invalid {
+			assert!(
+				DeflateConfig::from_extension(&extension).is_err(),
+				"{} should be invalid",
+				extension
+			);
+		}
+	}
+}
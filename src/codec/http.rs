@@ -220,19 +220,15 @@ pub enum HttpCodecError {
 
 impl Display for HttpCodecError {
 	fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
-		fmt.write_str(self.description())
-	}
-}
-
-impl Error for HttpCodecError {
-	fn description(&self) -> &str {
 		match *self {
-			HttpCodecError::Io(ref e) => e.description(),
-			HttpCodecError::Http(ref e) => e.description(),
+			HttpCodecError::Io(ref e) => e.fmt(fmt),
+			HttpCodecError::Http(ref e) => e.fmt(fmt),
 		}
 	}
+}
 
-	fn cause(&self) -> Option<&Error> {
+impl Error for HttpCodecError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match *self {
 			HttpCodecError::Io(ref error) => Some(error),
 			HttpCodecError::Http(ref error) => Some(error),
@@ -9,6 +9,14 @@
 //! encoding and decoding websocket messages (and dataframes if you want to go
 //! more low level) in the `ws` module.
 //! See it's module level documentation for more info.
+//!
+//! The `deflate` module has the interfaces needed to plug a compression
+//! library into the `ws` codecs for the permessage-deflate extension.
+//!
+//! Finally the `control` module has adapters for framed websocket connections
+//! which take care of control messages, e.g. answering pings.
 
+pub mod control;
+pub mod deflate;
 pub mod http;
 pub mod ws;
@@ -7,22 +7,36 @@
 //! For websocket messages, see the documentation for `MessageCodec`, for
 //! dataframes see the documentation for `DataFrameCodec`
 
-use std::borrow::Borrow;
-use std::io::Cursor;
+use std::borrow::{Borrow, Cow};
+use std::cmp;
+use std::convert::TryFrom;
+use std::io::Write;
 use std::marker::PhantomData;
-use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use bytes::BufMut;
+use bytes::Bytes;
 use bytes::BytesMut;
+use rand::RngCore;
 use tokio_codec::Decoder;
 use tokio_codec::Encoder;
+use tokio_codec::Framed;
 
+use codec::deflate::{Compressor, Decompressor, DeflateBackend, DeflateConfig, PERMESSAGE_DEFLATE};
 use dataframe::DataFrame;
-use message::OwnedMessage;
-use result::WebSocketError;
+use dataframe::Opcode;
+use header::extensions::Extension;
+use message::Type;
+use message::{CloseData, GenericMessage, OwnedMessage};
+use result::{ProtocolError, WebSocketError, WebSocketResult};
 use ws::dataframe::DataFrame as DataFrameTrait;
 use ws::message::Message as MessageTrait;
-use ws::util::header::read_header;
+use ws::util::header::{parse_header, ParseResult};
+use ws::util::header::{DataFrameFlags, DataFrameHeader};
+use ws::util::mask::gen_mask;
+use ws::util::mask::{apply_mask, mask_data_in_place};
+use ws::util::utf8::Utf8Validator;
 
 /// Even though a websocket connection may look perfectly symmetrical
 /// in reality there are small differences between clients and servers.
@@ -45,19 +59,61 @@ pub enum Context {
  * Dataframes *
  **************/
 
+/// Payloads shorter than this (in bytes) are always copied by
+/// `DataFrameCodec::encode_vectored`, for them a second write costs more than
+/// the copy.
+pub const VECTORED_PAYLOAD_THRESHOLD: usize = 4096;
+
 /// A codec for decoding and encoding websocket dataframes.
 ///
 /// This codec decodes dataframes into the crates default implementation
-/// of `Dataframe` but can encode and send any struct that implements the
-/// `ws::Dataframe` trait. The type of struct to encode is given by the `D`
-/// type parameter in the struct.
+/// of `DataFrame` and, when created with `DataFrameCodec::default`, encodes
+/// the same type, so frames can be passed from one connection to another
+/// unchanged: fragmented messages keep their fragmentation and control
+/// frames stay where they were. The payloads are always unmasked when they
+/// are decoded and masked again when they are encoded, depending on the
+/// `Context` of each codec. Decoding copies a payload out of the read buffer
+/// into the `data` of the dataframe once, unmasking it at the same time,
+/// see `DataFrame::parse`. `MessageCodec` with a `Bytes` payload doesn't copy
+/// it at all.
+///
+/// It can also encode any other implementation of the `ws::DataFrame`
+/// trait through `DataFrameCodec<Box<ws::DataFrame>>`. The type of struct to
+/// encode is given by the `D` type parameter in the struct.
 ///
 /// Using dataframes directly is meant for users who want low-level access to the
-/// connection. If you don't want to do anything low-level please use the
+/// connection, e.g. proxies (see the `async-frame-relay` example). If you
+/// don't want to do anything low-level please use the
 /// `MessageCodec` codec instead, or better yet use the `ClientBuilder` to make
 /// clients and the `Server` to make servers.
+///
+///```rust
+///# extern crate bytes;
+///# extern crate tokio_codec;
+///# extern crate websocket;
+///use bytes::BytesMut;
+///use tokio_codec::{Decoder, Encoder};
+///use websocket::async::MsgCodecCtx;
+///use websocket::dataframe::{DataFrame, Opcode};
+///use websocket::DataFrameCodec;
+///# fn main() {
+///
+///let mut client = DataFrameCodec::default(MsgCodecCtx::Client);
+///let mut server = DataFrameCodec::default(MsgCodecCtx::Server);
+///
+///let frame = DataFrame::new(false, Opcode::Text, b"Hello".to_vec());
+///let mut wire = BytesMut::new();
+///client.encode(frame.clone(), &mut wire).unwrap();
+///assert_eq!(server.decode(&mut wire).unwrap(), Some(frame));
+///# }
+///```
 pub struct DataFrameCodec<D> {
 	is_server: bool,
+	mask_outgoing: bool,
+	rng: Option<Box<dyn RngCore + Send>>,
+	max_frame_size: usize,
+	reserved_bits: DataFrameFlags,
+	strict_masking: bool,
 	frame_type: PhantomData<D>,
 }
 
@@ -82,46 +138,268 @@ impl<D> DataFrameCodec<D> {
 	pub fn new(context: Context) -> DataFrameCodec<D> {
 		DataFrameCodec {
 			is_server: context == Context::Server,
+			mask_outgoing: true,
+			rng: None,
+			max_frame_size: usize::MAX,
+			reserved_bits: DataFrameFlags::empty(),
+			strict_masking: true,
 			frame_type: PhantomData,
 		}
 	}
-}
 
-impl<D> Decoder for DataFrameCodec<D> {
-	type Item = DataFrame;
-	type Error = WebSocketError;
+	/// Whether outgoing dataframes are masked, this is always false
+	/// for servers.
+	pub fn mask_outgoing(&self) -> bool {
+		!self.is_server && self.mask_outgoing
+	}
 
-	// TODO: do not retry to read the header on each new data (keep a buffer)
-	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		let (header, bytes_read) = {
-			// we'll make a fake reader and keep track of the bytes read
-			let mut reader = Cursor::new(src.as_ref());
-
-			// read header to get the size, bail if not enough
-			let header = match read_header(&mut reader) {
-				Ok(head) => head,
-				Err(WebSocketError::NoDataAvailable) => return Ok(None),
-				Err(e) => return Err(e),
-			};
+	/// Set whether a client should mask its outgoing dataframes, the default
+	/// is `true` and this has no effect for servers (which never mask).
+	///
+	/// Sending unmasked frames as a client violates RFC 6455 and most servers
+	/// will drop the connection, only set this to `false` if both ends of
+	/// the connection are under your control (e.g. on a loopback link) and
+	/// the server accepts unmasked frames.
+	pub fn set_mask_outgoing(&mut self, mask: bool) {
+		self.mask_outgoing = mask;
+	}
+
+	/// Use `rng` to generate the mask keys of outgoing dataframes instead
+	/// of the default thread local random number generator.
+	///
+	/// This makes it possible to produce byte-exact output in tests or to use
+	/// a cheaper source of entropy. Keep in mind that RFC 6455 expects the
+	/// mask keys to be unpredictable to intermediaries.
+	pub fn set_rng<R>(&mut self, rng: R)
+	where
+		R: RngCore + Send + 'static,
+	{
+		self.rng = Some(Box::new(rng));
+	}
+
+	// Generates the mask key for the next outgoing dataframe, if it is masked.
+	fn next_mask(&mut self) -> Option<[u8; 4]> {
+		if !self.mask_outgoing() {
+			return None;
+		}
+		Some(match self.rng {
+			Some(ref mut rng) => {
+				let mut key = [0; 4];
+				rng.fill_bytes(&mut key);
+				key
+			}
+			None => gen_mask(),
+		})
+	}
+
+	/// Encode `item` for vectored output, e.g. using `Write::write_vectored`.
+	///
+	/// If the payload of `item` is long, doesn't need masking and is
+	/// available as a slice (see `ws::DataFrame::payload_bytes`) only the
+	/// header is written to `dst` and the payload is returned: it has to be
+	/// written right after the contents of `dst`, without being copied.
+	/// Otherwise the whole dataframe is written to `dst` and `None` is
+	/// returned, just like `encode` would.
+	///
+	///```rust
+	///# extern crate bytes;
+	///# extern crate websocket;
+	///# use std::io::{IoSlice, Write};
+	///use bytes::BytesMut;
+	///use websocket::async::MsgCodecCtx;
+	///use websocket::codec::ws::DataFrameCodec;
+	///use websocket::dataframe::{DataFrame, Opcode};
+	///# fn main() {
+	///
+	///let frame = DataFrame::new(true, Opcode::Binary, vec![0; 1 << 20]);
+	///let mut codec = DataFrameCodec::default(MsgCodecCtx::Server);
+	///let mut header = BytesMut::new();
+	///let payload = codec.encode_vectored(&frame, &mut header).unwrap();
+	///
+	///let mut output = Vec::new();
+	///let bufs = [IoSlice::new(&header), IoSlice::new(payload.unwrap_or(&[]))];
+	///output.write_vectored(&bufs).unwrap();
+	///# assert_eq!(output.len(), 10 + (1 << 20));
+	///# }
+	///```
+	pub fn encode_vectored<'f, F>(
+		&mut self,
+		item: &'f F,
+		dst: &mut BytesMut,
+	) -> Result<Option<&'f [u8]>, WebSocketError>
+	where
+		F: DataFrameTrait + ?Sized,
+	{
+		let mask = self.next_mask();
+		if mask.is_none() && item.size() >= VECTORED_PAYLOAD_THRESHOLD {
+			if let Some(payload) = item.payload_bytes() {
+				dst.reserve(item.frame_size(false) - payload.len());
+				item.write_header(&mut (&mut *dst).writer(), None)?;
+				return Ok(Some(payload));
+			}
+		}
+
+		let frame_size = item.frame_size(mask.is_some());
+		if frame_size > dst.remaining_mut() {
+			dst.reserve(frame_size);
+		}
+		item.write_to_buffer(&mut dst.writer(), mask)?;
+		Ok(None)
+	}
+
+	/// The maximum payload size (in bytes) of a single incoming dataframe.
+	pub fn max_frame_size(&self) -> usize {
+		self.max_frame_size
+	}
+
+	/// Set the maximum payload size (in bytes) of a single incoming dataframe.
+	/// By default there is no limit.
+	///
+	/// Decoding a header which declares a longer payload fails immediately
+	/// with `WebSocketError::MessageTooLarge`, the connection should then be
+	/// closed with status code 1009.
+	pub fn set_max_frame_size(&mut self, size: usize) {
+		self.max_frame_size = size;
+	}
+
+	/// Which of the three reserved bits (RSV1, RSV2 and RSV3) incoming
+	/// dataframes may have set.
+	pub fn allowed_reserved_bits(&self) -> [bool; 3] {
+		[
+			self.reserved_bits.contains(DataFrameFlags::RSV1),
+			self.reserved_bits.contains(DataFrameFlags::RSV2),
+			self.reserved_bits.contains(DataFrameFlags::RSV3),
+		]
+	}
+
+	/// Set which of the three reserved bits (RSV1, RSV2 and RSV3) incoming
+	/// dataframes may have set, by default none.
+	///
+	/// The reserved bits may only be used by a negotiated extension, e.g.
+	/// permessage-deflate uses RSV1. Decoding a dataframe with any other
+	/// reserved bit set fails with a `ProtocolError::ReservedBits`.
+	pub fn set_allowed_reserved_bits(&mut self, reserved: [bool; 3]) {
+		let mut bits = DataFrameFlags::empty();
+		bits.set(DataFrameFlags::RSV1, reserved[0]);
+		bits.set(DataFrameFlags::RSV2, reserved[1]);
+		bits.set(DataFrameFlags::RSV3, reserved[2]);
+		self.reserved_bits = bits;
+	}
+
+	/// Whether incoming dataframes which are masked the wrong way are
+	/// rejected, see `set_strict_masking`.
+	pub fn strict_masking(&self) -> bool {
+		self.strict_masking
+	}
+
+	/// Set whether a server rejects unmasked dataframes from its client and
+	/// a client rejects masked dataframes from its server, the default is
+	/// `true`.
+	///
+	/// RFC 6455 requires a server to fail the connection when it receives an
+	/// unmasked frame, and a client should do the same for a masked frame.
+	/// Decoding one is a `ProtocolError::UnexpectedMask` then (close the
+	/// connection with status code 1002). Only set this to `false` for
+	/// trusted clients which don't mask (see `set_mask_outgoing`) or for
+	/// servers which mask their frames anyway, those are unmasked then.
+	pub fn set_strict_masking(&mut self, strict: bool) {
+		self.strict_masking = strict;
+	}
+
+	// Checks the mask bit of an incoming dataframe against the role of
+	// this codec.
+	fn check_mask(&self, header: &DataFrameHeader) -> Result<(), WebSocketError> {
+		let masked = header.mask.is_some();
+		if self.strict_masking && masked != self.is_server {
+			return Err(ProtocolError::UnexpectedMask {
+				opcode: header.opcode,
+				masked,
+			}
+			.into());
+		}
+		Ok(())
+	}
+
+	// Checks the header of an incoming dataframe against the limits of this
+	// codec, failing as soon as it declares a data frame payload longer than
+	// `max_data_len` (control frames are already limited to 125 bytes by the
+	// header parser).
+	fn check_header(
+		&self,
+		header: &DataFrameHeader,
+		max_data_len: u64,
+	) -> Result<(), WebSocketError> {
+		let reserved = header.flags - DataFrameFlags::FIN;
+		if !self.reserved_bits.contains(reserved) {
+			return Err(ProtocolError::ReservedBits(reserved.bits()).into());
+		}
+
+		self.check_mask(header)?;
+
+		// bail before waiting for (and buffering) a payload we will never accept
+		if header.len > self.max_frame_size as u64
+			|| (header.opcode < 8 && header.len > max_data_len)
+		{
+			return Err(WebSocketError::MessageTooLarge);
+		}
+		Ok(())
+	}
 
-			(header, reader.position())
+	// Decodes the header and the unmasked payload of a dataframe, see
+	// `check_header`.
+	// TODO: do not retry to read the header on each new data (keep a buffer)
+	fn decode_payload(
+		&mut self,
+		src: &mut BytesMut,
+		max_data_len: u64,
+	) -> Result<Option<(DataFrameHeader, BytesMut)>, WebSocketError> {
+		// parse the header to get the size, bail if not enough
+		let (header, bytes_read) = match parse_header(src)? {
+			ParseResult::Complete { header, header_len } => (header, header_len),
+			ParseResult::Incomplete { .. } => return Ok(None),
 		};
+		self.check_header(&header, max_data_len)?;
+
+		if header.len > usize::MAX as u64 {
+			return Err(WebSocketError::MessageTooLarge);
+		}
+		let len = header.len as usize;
 
 		// check if we have enough bytes to continue
-		if header.len + bytes_read > src.len() as u64 {
+		if src.len() - bytes_read < len {
 			return Ok(None);
 		}
 
-		// TODO: using usize is not the right thing here (can be larger)
-		let _ = src.split_to(bytes_read as usize);
-		let body = src.split_to(header.len as usize).to_vec();
+		let _ = src.split_to(bytes_read);
+		let mut body = src.split_to(len);
+
+		// unmask the payload where it is in the read buffer, so it only has to
+		// be copied once (if at all) into the message
+		let mut header = header;
+		if let Some(mask) = header.mask.take() {
+			apply_mask(&mut body, mask);
+		}
+		Ok(Some((header, body)))
+	}
+}
+
+impl<D> Decoder for DataFrameCodec<D> {
+	type Item = DataFrame;
+	type Error = WebSocketError;
 
-		// construct a dataframe
-		Ok(Some(DataFrame::read_dataframe_body(
-			header,
-			body,
-			self.is_server,
-		)?))
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		// the limits only need the header, don't wait for the payload
+		match parse_header(src)? {
+			ParseResult::Complete { header, .. } => self.check_header(&header, u64::MAX)?,
+			ParseResult::Incomplete { .. } => return Ok(None),
+		}
+		match DataFrame::parse(src)? {
+			Some((frame, len)) => {
+				src.advance(len);
+				Ok(Some(frame))
+			}
+			None => Ok(None),
+		}
 	}
 }
 
@@ -133,12 +411,12 @@ where
 	type Error = WebSocketError;
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		let masked = !self.is_server;
-		let frame_size = item.borrow().frame_size(masked);
+		let mask = self.next_mask();
+		let frame_size = item.borrow().frame_size(mask.is_some());
 		if frame_size > dst.remaining_mut() {
 			dst.reserve(frame_size);
 		}
-		item.borrow().write_to(&mut dst.writer(), masked)
+		item.borrow().write_to_buffer(&mut dst.writer(), mask)
 	}
 }
 
@@ -146,6 +424,361 @@ where
  * Messages *
  ************/
 
+/// The default maximum size of an incoming message (64 MiB), see
+/// `MessageCodec::set_max_message_size`.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// The default maximum number of dataframes an incoming message may be
+/// fragmented into, see `MessageCodec::set_max_fragments`.
+pub const DEFAULT_MAX_FRAGMENTS: usize = 4096;
+
+/// What `MessageCodec` does with incoming text messages which are not valid
+/// UTF-8.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidUtf8 {
+	/// Fail decoding with a `ProtocolError::InvalidUtf8`, as RFC 6455 demands.
+	/// The connection should then be closed with status code 1007.
+	///
+	/// This is the default.
+	Error,
+	/// Decode the message as `OwnedMessage::Binary` instead.
+	Binary,
+	/// Decode the message as `OwnedMessage::Text`, replacing invalid
+	/// sequences with `U+FFFD REPLACEMENT CHARACTER`.
+	Lossy,
+}
+
+/// The fragment size (64 KiB) that is suggested for
+/// `MessageCodec::set_fragment_size`.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 64 << 10;
+
+/// A container for the payloads of decoded messages, see
+/// `MessageCodecBuilder::build_with_payload`.
+///
+/// The payload of an unfragmented message is handed over as the part of the
+/// read buffer it was received in, so a container which can take over a
+/// `BytesMut` without copying it (like `Bytes`) doesn't copy the payload at
+/// all. `Vec<u8>` has to copy it once.
+pub trait Payload: Sized {
+	/// The type of the decoded messages, `OwnedMessage` for `Vec<u8>` and
+	/// usually `GenericMessage<Self>`.
+	type Message: From<GenericMessage<Self>>;
+
+	/// Take over the payload of a dataframe.
+	fn from_bytes_mut(bytes: BytesMut) -> Self;
+
+	/// Take over a payload which is in a `Vec` already, e.g. because it was
+	/// decompressed.
+	fn from_vec(vec: Vec<u8>) -> Self {
+		Self::from_bytes_mut(BytesMut::from(vec))
+	}
+
+	/// The payload as a slice.
+	fn as_slice(&self) -> &[u8];
+
+	/// Append the payload of the next fragment of the message.
+	fn extend(&mut self, data: &[u8]);
+
+	/// Convert the payload into a `Vec`, e.g. for the text of a message.
+	fn into_vec(self) -> Vec<u8> {
+		self.as_slice().to_vec()
+	}
+}
+
+impl Payload for Vec<u8> {
+	type Message = OwnedMessage;
+
+	fn from_bytes_mut(bytes: BytesMut) -> Self {
+		bytes.to_vec()
+	}
+
+	fn from_vec(vec: Vec<u8>) -> Self {
+		vec
+	}
+
+	fn as_slice(&self) -> &[u8] {
+		self
+	}
+
+	fn extend(&mut self, data: &[u8]) {
+		self.extend_from_slice(data);
+	}
+
+	fn into_vec(self) -> Vec<u8> {
+		self
+	}
+}
+
+impl Payload for Bytes {
+	type Message = GenericMessage<Bytes>;
+
+	fn from_bytes_mut(bytes: BytesMut) -> Self {
+		bytes.freeze()
+	}
+
+	fn from_vec(vec: Vec<u8>) -> Self {
+		Bytes::from(vec)
+	}
+
+	fn as_slice(&self) -> &[u8] {
+		self
+	}
+
+	fn extend(&mut self, data: &[u8]) {
+		self.extend_from_slice(data);
+	}
+}
+
+impl<P> From<GenericMessage<P>> for OwnedMessage
+where
+	P: Payload,
+{
+	fn from(message: GenericMessage<P>) -> Self {
+		match message {
+			GenericMessage::Text(text) => OwnedMessage::Text(text),
+			GenericMessage::Binary(data) => OwnedMessage::Binary(data.into_vec()),
+			GenericMessage::Close(data) => OwnedMessage::Close(data),
+			GenericMessage::Ping(data) => OwnedMessage::Ping(data.into_vec()),
+			GenericMessage::Pong(data) => OwnedMessage::Pong(data.into_vec()),
+		}
+	}
+}
+
+impl<P> From<OwnedMessage> for GenericMessage<P>
+where
+	P: Payload,
+{
+	fn from(message: OwnedMessage) -> Self {
+		match message {
+			OwnedMessage::Text(text) => GenericMessage::Text(text),
+			OwnedMessage::Binary(data) => GenericMessage::Binary(P::from_vec(data)),
+			OwnedMessage::Close(data) => GenericMessage::Close(data),
+			OwnedMessage::Ping(data) => GenericMessage::Ping(P::from_vec(data)),
+			OwnedMessage::Pong(data) => GenericMessage::Pong(P::from_vec(data)),
+		}
+	}
+}
+
+// The empty uncompressed block which ends every message compressed by the
+// permessage-deflate extension, it is not sent over the wire.
+const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// The state of the permessage-deflate extension of a `MessageCodec`.
+struct Deflate {
+	config: DeflateConfig,
+	compressor: Box<dyn Compressor>,
+	decompressor: Box<dyn Decompressor>,
+	reset_compressor: bool,
+	reset_decompressor: bool,
+	// the message which is being received is compressed
+	inflating: bool,
+}
+
+impl Deflate {
+	fn new(
+		config: DeflateConfig,
+		compressor: Box<dyn Compressor>,
+		decompressor: Box<dyn Decompressor>,
+	) -> Self {
+		Deflate {
+			config,
+			compressor,
+			decompressor,
+			reset_compressor: false,
+			reset_decompressor: false,
+			inflating: false,
+		}
+	}
+
+	// Compresses `message` into a single dataframe with RSV1 set, or returns
+	// `None` if it is sent uncompressed, e.g. because it is a control
+	// message.
+	fn compress<M>(&mut self, message: &M) -> Result<Option<DataFrame>, WebSocketError>
+	where
+		M: MessageTrait,
+	{
+		let (opcode, payload) = match message.opcode_and_payload() {
+			Some((opcode, payload)) if opcode < 8 => (opcode, payload),
+			_ => return Ok(None),
+		};
+		if payload.len() < self.config.compress_threshold {
+			return Ok(None);
+		}
+
+		let mut data = Vec::with_capacity(payload.len() / 2 + 16);
+		self.compressor.compress(&payload, &mut data)?;
+		if self.reset_compressor {
+			self.compressor.reset();
+		}
+		if !data.ends_with(&DEFLATE_TRAILER) {
+			return Err(WebSocketError::DataFrameError(
+				"Compressed data doesn't end with a sync flush",
+			));
+		}
+		let len = data.len() - DEFLATE_TRAILER.len();
+		data.truncate(len);
+		// an empty message is sent as a single (incomplete) empty block
+		if data.is_empty() {
+			data.push(0x00);
+		}
+		let mut frame = DataFrame::new(true, Opcode::try_from(opcode)?, data);
+		frame.reserved[0] = true;
+		Ok(Some(frame))
+	}
+
+	// Decompresses the next fragment of the message which is being received,
+	// failing as soon as it gets longer than `limit`.
+	fn decompress(
+		&mut self,
+		data: &[u8],
+		finished: bool,
+		limit: usize,
+	) -> Result<Vec<u8>, WebSocketError> {
+		let mut output = Vec::with_capacity(cmp::min(data.len() * 2, limit));
+		self.decompressor.decompress(data, &mut output, limit)?;
+		if finished && output.len() <= limit {
+			let limit = limit - output.len();
+			self.decompressor
+				.decompress(&DEFLATE_TRAILER, &mut output, limit)?;
+			if self.reset_decompressor {
+				self.decompressor.reset();
+			}
+		}
+		if output.len() > limit {
+			return Err(WebSocketError::MessageTooLarge);
+		}
+		Ok(output)
+	}
+}
+
+/// Counters of the traffic which went through a `MessageCodec`.
+///
+/// The counters are updated while messages are encoded and decoded, so
+/// they can be read at any time through the handle returned by
+/// `MessageCodec::stats_handle`, even once the codec was moved into a
+/// `Framed` transport which was split into a sink and a stream.
+///
+/// The byte counts are the payload lengths of the dataframes as they are
+/// sent over the wire, i.e. without the headers and after compression.
+/// Outgoing messages are only counted if they know their opcode and size,
+/// see `ws::Message::opcode_and_size`.
+#[derive(Debug, Default)]
+pub struct CodecStats {
+	frames_sent: AtomicU64,
+	frames_received: AtomicU64,
+	bytes_sent: AtomicU64,
+	bytes_received: AtomicU64,
+	pings_sent: AtomicU64,
+	pings_received: AtomicU64,
+	pongs_sent: AtomicU64,
+	pongs_received: AtomicU64,
+	closes_sent: AtomicU64,
+	closes_received: AtomicU64,
+}
+
+impl CodecStats {
+	/// The number of dataframes which were encoded.
+	pub fn frames_sent(&self) -> u64 {
+		self.frames_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of dataframes which were decoded.
+	pub fn frames_received(&self) -> u64 {
+		self.frames_received.load(Ordering::Relaxed)
+	}
+
+	/// The payload bytes of all encoded dataframes.
+	pub fn bytes_sent(&self) -> u64 {
+		self.bytes_sent.load(Ordering::Relaxed)
+	}
+
+	/// The payload bytes of all decoded dataframes.
+	pub fn bytes_received(&self) -> u64 {
+		self.bytes_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of pings which were encoded.
+	pub fn pings_sent(&self) -> u64 {
+		self.pings_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of pings which were decoded.
+	pub fn pings_received(&self) -> u64 {
+		self.pings_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of pongs which were encoded.
+	pub fn pongs_sent(&self) -> u64 {
+		self.pongs_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of pongs which were decoded.
+	pub fn pongs_received(&self) -> u64 {
+		self.pongs_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of close messages which were encoded.
+	pub fn closes_sent(&self) -> u64 {
+		self.closes_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of close messages which were decoded.
+	pub fn closes_received(&self) -> u64 {
+		self.closes_received.load(Ordering::Relaxed)
+	}
+
+	fn count(
+		frames: &AtomicU64,
+		bytes: &AtomicU64,
+		control: Option<&AtomicU64>,
+		count: u64,
+		len: u64,
+	) {
+		frames.fetch_add(count, Ordering::Relaxed);
+		bytes.fetch_add(len, Ordering::Relaxed);
+		if let Some(counter) = control {
+			counter.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	fn count_received(&self, opcode: u8, len: u64) {
+		let control = match opcode {
+			8 => Some(&self.closes_received),
+			9 => Some(&self.pings_received),
+			10 => Some(&self.pongs_received),
+			_ => None,
+		};
+		CodecStats::count(&self.frames_received, &self.bytes_received, control, 1, len);
+	}
+
+	// Counts a message which was just encoded as `frames` dataframes with
+	// `len` payload bytes together.
+	fn count_sent(&self, opcode: u8, frames: u64, len: u64) {
+		let control = match opcode {
+			8 => Some(&self.closes_sent),
+			9 => Some(&self.pings_sent),
+			10 => Some(&self.pongs_sent),
+			_ => None,
+		};
+		CodecStats::count(&self.frames_sent, &self.bytes_sent, control, frames, len);
+	}
+}
+
+/// The extensions which were negotiated during the handshake of a
+/// connection, as far as the `MessageCodec` is concerned.
+///
+/// Currently only permessage-deflate is supported, which also needs a
+/// `DeflateBackend` for the actual (de)compression.
+#[derive(Clone, Debug, Default)]
+pub struct CodecOptions {
+	/// The extensions the server accepted, i.e. the contents of the
+	/// `Sec-WebSocket-Extensions` header of its response.
+	pub extensions: Vec<Extension>,
+	/// Creates the compressor and decompressor if permessage-deflate was
+	/// negotiated.
+	pub deflate_backend: Option<Arc<dyn DeflateBackend>>,
+}
+
 /// A codec for asynchronously decoding and encoding websocket messages.
 ///
 /// This codec decodes messages into the `OwnedMessage` struct, so using this
@@ -153,6 +786,14 @@ where
 /// any type of message that implements the `ws::Message` trait (that type is
 /// decided by the `M` type parameter) like `OwnedMessage` and `Message`.
 ///
+/// To send the same message over many connections without cloning its
+/// payload each time use a `MessageCodec<Arc<OwnedMessage>>`, or encode
+/// `Message`s borrowing their payload.
+///
+/// The `P` type parameter is the container the payloads of decoded messages
+/// are stored in, see `Payload`. With the default of `Vec<u8>` the codec
+/// decodes `OwnedMessage`s.
+///
 /// Warning: if you don't know what your doing or want a simple websocket connection
 /// please use the `ClientBuilder` or the `Server` structs. You should only use this
 /// after a websocket handshake has already been completed on the stream you are
@@ -193,11 +834,30 @@ where
 ///
 ///runtime.block_on(f).unwrap();
 ///# }
-pub struct MessageCodec<M>
+pub struct MessageCodec<M, P = Vec<u8>>
 where
 	M: MessageTrait,
 {
-	buffer: Vec<DataFrame>,
+	// the payload of the message whose fragments are being received
+	payload: Option<P>,
+	// the opcode of its first dataframe, `None` between messages
+	message_opcode: Option<Opcode>,
+	fragments: usize,
+	buffered_size: usize,
+	max_message_size: usize,
+	max_fragments: usize,
+	fragment_size: Option<usize>,
+	send_buffer_limit: Option<usize>,
+	queued_bytes: usize,
+	// validates the current message while its fragments arrive, if it is text
+	text_validator: Option<Utf8Validator>,
+	invalid_utf8: InvalidUtf8,
+	// the current text message turned out to be invalid UTF-8
+	invalid_text: bool,
+	deflate: Option<Deflate>,
+	stats: Arc<CodecStats>,
+	// the data of the close message which was decoded, if any
+	received_close: Option<Option<CloseData>>,
 	dataframe_codec: DataFrameCodec<DataFrame>,
 	message_type: PhantomData<fn(M)>,
 }
@@ -213,7 +873,7 @@ impl MessageCodec<OwnedMessage> {
 	/// If you have your own implementation of websocket messages, you can
 	/// use the `new` method to create a codec for that implementation.
 	pub fn default(context: Context) -> Self {
-		Self::new(context)
+		MessageCodecBuilder::new(context).build()
 	}
 }
 
@@ -227,205 +887,3228 @@ where
 	/// If you just want to use a normal codec without a specific implementation
 	/// of a websocket message, take a look at `MessageCodec::default`.
 	pub fn new(context: Context) -> MessageCodec<M> {
-		MessageCodec {
-			buffer: Vec::new(),
-			dataframe_codec: DataFrameCodec::new(context),
-			message_type: PhantomData,
+		MessageCodecBuilder::new(context).build()
+	}
+
+	/// Creates a codec for a connection which negotiated the extensions in
+	/// `options`.
+	///
+	/// Dataframes with the RSV1 bit set are only accepted if permessage-deflate
+	/// was negotiated. Unsupported extensions, or permessage-deflate without
+	/// a `DeflateBackend`, fail with a `WebSocketError::ProtocolError`.
+	pub fn with_options(
+		context: Context,
+		options: &CodecOptions,
+	) -> WebSocketResult<MessageCodec<M>> {
+		let mut codec = MessageCodec::new(context);
+		for extension in &options.extensions {
+			if extension.name != PERMESSAGE_DEFLATE || codec.deflate.is_some() {
+				return Err(WebSocketError::ProtocolError(
+					"Unsupported extension negotiated",
+				));
+			}
+			let backend = options
+				.deflate_backend
+				.as_ref()
+				.ok_or(WebSocketError::ProtocolError(
+					"No backend for the negotiated permessage-deflate extension",
+				))?;
+			let config = DeflateConfig::from_extension(extension)?;
+			let compressor = backend.compressor(config.compressor_window_bits(context));
+			let decompressor = backend.decompressor(config.decompressor_window_bits(context));
+			codec.enable_deflate(Deflate::new(config, compressor, decompressor));
 		}
+		Ok(codec)
 	}
 }
 
-impl<M> Decoder for MessageCodec<M>
+impl<M, P> MessageCodec<M, P>
 where
 	M: MessageTrait,
 {
-	type Item = OwnedMessage;
-	type Error = WebSocketError;
+	/// The maximum size (in bytes) of the payload of an incoming message,
+	/// summed over all of its fragments.
+	pub fn max_message_size(&self) -> usize {
+		self.max_message_size
+	}
 
-	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		while let Some(frame) = self.dataframe_codec.decode(src)? {
-			let is_first = self.buffer.is_empty();
-			let finished = frame.finished;
+	/// Set the maximum size (in bytes) of the payload of an incoming message,
+	/// summed over all of its fragments. Defaults to `DEFAULT_MAX_MESSAGE_SIZE`.
+	///
+	/// The limit is checked against the length declared in each dataframe
+	/// header, so decoding fails with `WebSocketError::MessageTooLarge` before
+	/// the offending payload is buffered.
+	pub fn set_max_message_size(&mut self, size: usize) {
+		self.max_message_size = size;
+	}
 
-			match frame.opcode as u8 {
-				// continuation code
-				0 if is_first => {
-					return Err(WebSocketError::ProtocolError(
-						"Unexpected continuation data frame opcode",
-					));
-				}
-				// control frame
-				8...15 => {
-					return Ok(Some(OwnedMessage::from_dataframes(vec![frame])?));
-				}
-				// data frame
-				1...7 if !is_first => {
-					return Err(WebSocketError::ProtocolError(
-						"Unexpected data frame opcode",
-					));
-				}
-				// its good
-				_ => {
-					self.buffer.push(frame);
-				}
-			};
+	/// The maximum number of dataframes an incoming message may consist of.
+	pub fn max_fragments(&self) -> usize {
+		self.max_fragments
+	}
 
-			if finished {
-				let buffer = mem::replace(&mut self.buffer, Vec::new());
-				return Ok(Some(OwnedMessage::from_dataframes(buffer)?));
-			}
-		}
+	/// Set the maximum number of dataframes an incoming message may consist of.
+	/// Defaults to `DEFAULT_MAX_FRAGMENTS`.
+	///
+	/// This protects against peers sending an endless stream of tiny
+	/// continuation frames, once the limit is exceeded decoding fails with
+	/// a `ProtocolError::TooManyFragments`.
+	pub fn set_max_fragments(&mut self, fragments: usize) {
+		self.max_fragments = fragments;
+	}
 
-		Ok(None)
+	/// What happens with incoming text messages which are not valid UTF-8.
+	pub fn invalid_utf8(&self) -> InvalidUtf8 {
+		self.invalid_utf8
 	}
-}
 
-impl<M> Encoder for MessageCodec<M>
-where
-	M: MessageTrait,
-{
-	type Item = M;
-	type Error = WebSocketError;
+	/// Set what happens with incoming text messages which are not valid
+	/// UTF-8, by default decoding fails.
+	///
+	/// Only change this if you have to talk to a peer which is known to
+	/// send broken text messages.
+	pub fn set_invalid_utf8(&mut self, invalid_utf8: InvalidUtf8) {
+		self.invalid_utf8 = invalid_utf8;
+	}
 
-	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		let masked = !self.dataframe_codec.is_server;
-		let frame_size = item.message_size(masked);
-		if frame_size > dst.remaining_mut() {
-			dst.reserve(frame_size);
-		}
-		item.serialize(&mut dst.writer(), masked)
+	/// Whether outgoing messages are masked, see
+	/// `DataFrameCodec::mask_outgoing`.
+	pub fn mask_outgoing(&self) -> bool {
+		self.dataframe_codec.mask_outgoing()
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use futures::{Future, Sink, Stream};
-	use message::CloseData;
-	use message::Message;
-	use std::io::Cursor;
-	use stream::ReadWritePair;
+	/// Set whether a client should mask its outgoing messages,
+	/// see `DataFrameCodec::set_mask_outgoing` for why you most likely
+	/// don't want to change this.
+	pub fn set_mask_outgoing(&mut self, mask: bool) {
+		self.dataframe_codec.set_mask_outgoing(mask);
+	}
 
-	#[test]
-	fn owned_message_predicts_size() {
-		let messages = vec![
-			OwnedMessage::Text("nilbog".to_string()),
-			OwnedMessage::Binary(vec![1, 2, 3, 4]),
-			OwnedMessage::Binary(vec![42; 256]),
-			OwnedMessage::Binary(vec![42; 65535]),
-			OwnedMessage::Binary(vec![42; 65555]),
-			OwnedMessage::Ping("beep".to_string().into_bytes()),
-			OwnedMessage::Pong("boop".to_string().into_bytes()),
-			OwnedMessage::Close(None),
-			OwnedMessage::Close(Some(CloseData {
-				status_code: 64,
+	/// Use `rng` to generate the mask keys of outgoing messages,
+	/// see `DataFrameCodec::set_rng`.
+	pub fn set_rng<R>(&mut self, rng: R)
+	where
+		R: RngCore + Send + 'static,
+	{
+		self.dataframe_codec.set_rng(rng);
+	}
+
+	/// Whether incoming dataframes which are masked the wrong way are
+	/// rejected, see `DataFrameCodec::strict_masking`.
+	pub fn strict_masking(&self) -> bool {
+		self.dataframe_codec.strict_masking()
+	}
+
+	/// Set whether incoming dataframes which are masked the wrong way are
+	/// rejected, see `DataFrameCodec::set_strict_masking`.
+	pub fn set_strict_masking(&mut self, strict: bool) {
+		self.dataframe_codec.set_strict_masking(strict);
+	}
+
+	/// The maximum payload size (in bytes) of outgoing dataframes, if
+	/// outgoing messages are fragmented.
+	pub fn fragment_size(&self) -> Option<usize> {
+		self.fragment_size
+	}
+
+	/// Split outgoing data messages into dataframes with payloads of at most
+	/// `size` bytes, or don't fragment them at all if `None` (the default).
+	///
+	/// Control messages are never fragmented. `DEFAULT_FRAGMENT_SIZE` is a
+	/// reasonable size if you don't have any specific requirements.
+	pub fn set_fragment_size(&mut self, size: Option<usize>) {
+		self.fragment_size = size;
+	}
+
+	/// The maximum number of bytes which may be waiting to be written when
+	/// a data message is encoded, if there is a limit.
+	pub fn send_buffer_limit(&self) -> Option<usize> {
+		self.send_buffer_limit
+	}
+
+	/// Refuse to encode data messages while more than `limit` previously
+	/// encoded bytes are still waiting to be written, or don't limit them at
+	/// all if `None` (the default).
+	///
+	/// Encoding a data message fails with `WebSocketError::SendBufferFull`
+	/// then. The encoder owns the message at that point, so it is dropped
+	/// without being sent. To send it later instead, check
+	/// `send_buffer_full` before encoding it and wait until some of the
+	/// buffered data was written (e.g. after flushing the sink). Control
+	/// messages are always encoded, so pongs and close messages can still be
+	/// sent to a slow peer.
+	pub fn set_send_buffer_limit(&mut self, limit: Option<usize>) {
+		self.send_buffer_limit = limit;
+	}
+
+	/// Whether encoding a data message into `dst` would fail with
+	/// `WebSocketError::SendBufferFull`, because more than the send buffer
+	/// limit is waiting to be written.
+	pub fn send_buffer_full(&self, dst: &BytesMut) -> bool {
+		match self.send_buffer_limit {
+			Some(limit) => dst.len() > limit,
+			None => false,
+		}
+	}
+
+	/// The number of bytes which were waiting to be written after the last
+	/// message was encoded (or refused).
+	///
+	/// The codec only sees the send buffer while encoding, so this doesn't go
+	/// down as the buffered data is written but only the next time a message
+	/// is encoded.
+	pub fn queued_bytes(&self) -> usize {
+		self.queued_bytes
+	}
+
+	// Fails if a data message would exceed the send buffer limit.
+	fn check_send_buffer(&mut self, is_control: bool, dst: &BytesMut) -> WebSocketResult<()> {
+		self.queued_bytes = dst.len();
+		if !is_control && self.send_buffer_full(dst) {
+			return Err(WebSocketError::SendBufferFull);
+		}
+		Ok(())
+	}
+
+	/// Encode a message for vectored output without copying its payload,
+	/// see `DataFrameCodec::encode_vectored`. This never fragments the
+	/// message.
+	///
+	/// The crate's `OwnedMessage` and `Message` both implement the
+	/// `ws::DataFrame` trait.
+	pub fn encode_vectored<'f, F>(
+		&mut self,
+		item: &'f F,
+		dst: &mut BytesMut,
+	) -> Result<Option<&'f [u8]>, WebSocketError>
+	where
+		F: DataFrameTrait + ?Sized,
+	{
+		self.check_send_buffer(item.opcode() >= 8, dst)?;
+		let payload = self.dataframe_codec.encode_vectored(item, dst)?;
+		self.stats.count_sent(item.opcode(), 1, item.size() as u64);
+		self.queued_bytes = dst.len() + payload.map_or(0, |payload| payload.len());
+		Ok(payload)
+	}
+
+	/// Whether some fragments of an incoming message have been decoded, but
+	/// not its last one yet.
+	pub fn in_message(&self) -> bool {
+		self.message_opcode.is_some()
+	}
+
+	/// Whether a close message has been decoded.
+	///
+	/// The other end doesn't send anything after a close message, whatever
+	/// arrives after it is left in the buffer without being decoded (see
+	/// `into_raw_parts`), and the stream of messages ends without an error
+	/// when the connection is closed.
+	pub fn close_received(&self) -> bool {
+		self.received_close.is_some()
+	}
+
+	/// The status code and reason of the close message which has been
+	/// decoded, if it had any. This is still available after decoding failed,
+	/// e.g. for an application which stopped handling messages already.
+	pub fn received_close_data(&self) -> Option<&CloseData> {
+		self.received_close.as_ref().and_then(Option::as_ref)
+	}
+
+	/// The traffic which went through this codec so far.
+	pub fn stats(&self) -> &CodecStats {
+		&self.stats
+	}
+
+	/// A handle to the statistics of this codec, which stays up to date
+	/// after the codec was moved into a `Framed` transport.
+	pub fn stats_handle(&self) -> Arc<CodecStats> {
+		self.stats.clone()
+	}
+
+	/// The maximum payload size (in bytes) of a single incoming dataframe.
+	pub fn max_frame_size(&self) -> usize {
+		self.dataframe_codec.max_frame_size()
+	}
+
+	/// Set the maximum payload size (in bytes) of a single incoming dataframe,
+	/// see `DataFrameCodec::set_max_frame_size`.
+	pub fn set_max_frame_size(&mut self, size: usize) {
+		self.dataframe_codec.set_max_frame_size(size);
+	}
+
+	/// The parameters of the permessage-deflate extension, if it is used.
+	pub fn deflate(&self) -> Option<&DeflateConfig> {
+		self.deflate.as_ref().map(|deflate| &deflate.config)
+	}
+
+	/// Use the permessage-deflate extension (RFC 7692) with the parameters
+	/// which were negotiated during the handshake. See the `codec::deflate`
+	/// module.
+	///
+	/// Outgoing data messages are compressed by `compressor` and sent with
+	/// the RSV1 bit set, incoming dataframes may then have the RSV1 bit set
+	/// and are decompressed by `decompressor` before they are reassembled.
+	/// Both are reset after each message if `config` rules out context
+	/// takeover in their direction.
+	///
+	/// The limits on the size of incoming messages apply to the decompressed
+	/// data. Messages encoded with `encode_vectored` and messages which don't
+	/// implement `ws::Message::opcode_and_payload` are never compressed.
+	pub fn set_deflate<C, D>(&mut self, config: DeflateConfig, compressor: C, decompressor: D)
+	where
+		C: Compressor + 'static,
+		D: Decompressor + 'static,
+	{
+		self.enable_deflate(Deflate::new(
+			config,
+			Box::new(compressor),
+			Box::new(decompressor),
+		));
+	}
+
+	fn enable_deflate(&mut self, mut deflate: Deflate) {
+		let context = if self.dataframe_codec.is_server {
+			Context::Server
+		} else {
+			Context::Client
+		};
+		deflate.reset_compressor = deflate.config.compressor_no_context_takeover(context);
+		deflate.reset_decompressor = deflate.config.decompressor_no_context_takeover(context);
+		let mut reserved = self.dataframe_codec.allowed_reserved_bits();
+		reserved[0] = true;
+		self.dataframe_codec.set_allowed_reserved_bits(reserved);
+		self.deflate = Some(deflate);
+	}
+
+	// Encodes a dataframe as part of the message stream, fragmenting it
+	// like messages are.
+	fn encode_frame(&mut self, frame: DataFrame, dst: &mut BytesMut) -> Result<(), WebSocketError> {
+		let opcode = frame.opcode as u8;
+		let len = frame.data.len() as u64;
+		let mut frames = 1;
+		match self.fragment_size {
+			Some(fragment_size) => {
+				// every additional fragment adds a header of at most 14 bytes
+				let frame_size = frame.frame_size(self.dataframe_codec.mask_outgoing());
+				let frame_size = frame_size + frame_size / fragment_size.max(1) * 14;
+				if frame_size > dst.remaining_mut() {
+					dst.reserve(frame_size);
+				}
+				frames = 0;
+				let dataframe_codec = &mut self.dataframe_codec;
+				// every dataframe takes the next mask
+				frame.write_fragmented_to_buffer(&mut dst.writer(), fragment_size, &mut || {
+					frames += 1;
+					dataframe_codec.next_mask()
+				})?;
+			}
+			None => self.dataframe_codec.encode(frame, dst)?,
+		}
+		self.stats.count_sent(opcode, frames, len);
+		Ok(())
+	}
+}
+
+/// Build a `MessageCodec` with a custom configuration.
+///
+/// All options start out with the same defaults `MessageCodec::new` uses, so
+/// only the ones that should differ need to be set.
+///
+///```rust
+///# extern crate websocket;
+///use websocket::async::{MessageCodecBuilder, MsgCodecCtx};
+///use websocket::async::MessageCodec;
+///use websocket::OwnedMessage;
+///# fn main() {
+///
+///let codec: MessageCodec<OwnedMessage> = MessageCodecBuilder::new(MsgCodecCtx::Server)
+///    .max_message_size(1 << 20)
+///    .max_frame_size(64 << 10)
+///    .max_fragments(64)
+///    .build();
+///
+///assert_eq!(codec.max_message_size(), 1 << 20);
+///# }
+///```
+pub struct MessageCodecBuilder {
+	context: Context,
+	mask_outgoing: bool,
+	strict_masking: bool,
+	rng: Option<Box<dyn RngCore + Send>>,
+	max_message_size: usize,
+	max_frame_size: usize,
+	max_fragments: usize,
+	fragment_size: Option<usize>,
+	send_buffer_limit: Option<usize>,
+	invalid_utf8: InvalidUtf8,
+	deflate: Option<Deflate>,
+}
+
+impl MessageCodecBuilder {
+	/// Create a new builder for a codec with a role of `context` (either
+	/// `Client` or `Server`).
+	pub fn new(context: Context) -> Self {
+		MessageCodecBuilder {
+			context,
+			mask_outgoing: true,
+			strict_masking: true,
+			rng: None,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+			max_frame_size: usize::MAX,
+			max_fragments: DEFAULT_MAX_FRAGMENTS,
+			fragment_size: None,
+			send_buffer_limit: None,
+			invalid_utf8: InvalidUtf8::Error,
+			deflate: None,
+		}
+	}
+
+	/// Change the role of the codec.
+	pub fn context(mut self, context: Context) -> Self {
+		self.context = context;
+		self
+	}
+
+	/// Set whether a client should mask its outgoing messages,
+	/// see `DataFrameCodec::set_mask_outgoing`.
+	pub fn mask_outgoing(mut self, mask: bool) -> Self {
+		self.mask_outgoing = mask;
+		self
+	}
+
+	/// Set whether incoming dataframes which are masked the wrong way are
+	/// rejected, see `DataFrameCodec::set_strict_masking`.
+	pub fn strict_masking(mut self, strict: bool) -> Self {
+		self.strict_masking = strict;
+		self
+	}
+
+	/// Use `rng` to generate the mask keys of outgoing messages,
+	/// see `DataFrameCodec::set_rng`.
+	pub fn rng<R>(mut self, rng: R) -> Self
+	where
+		R: RngCore + Send + 'static,
+	{
+		self.rng = Some(Box::new(rng));
+		self
+	}
+
+	/// Set the maximum size of an incoming message,
+	/// see `MessageCodec::set_max_message_size`.
+	pub fn max_message_size(mut self, size: usize) -> Self {
+		self.max_message_size = size;
+		self
+	}
+
+	/// Set the maximum payload size of a single incoming dataframe,
+	/// see `DataFrameCodec::set_max_frame_size`.
+	pub fn max_frame_size(mut self, size: usize) -> Self {
+		self.max_frame_size = size;
+		self
+	}
+
+	/// Set the maximum number of dataframes an incoming message may consist of,
+	/// see `MessageCodec::set_max_fragments`.
+	pub fn max_fragments(mut self, fragments: usize) -> Self {
+		self.max_fragments = fragments;
+		self
+	}
+
+	/// Split outgoing data messages into dataframes with payloads of at most
+	/// `size` bytes, see `MessageCodec::set_fragment_size`.
+	pub fn fragment_size(mut self, size: usize) -> Self {
+		self.fragment_size = Some(size);
+		self
+	}
+
+	/// Refuse to encode data messages while more than `limit` bytes are
+	/// waiting to be written, see `MessageCodec::set_send_buffer_limit`.
+	pub fn send_buffer_limit(mut self, limit: usize) -> Self {
+		self.send_buffer_limit = Some(limit);
+		self
+	}
+
+	/// Set what happens with incoming text messages which are not valid
+	/// UTF-8, see `MessageCodec::set_invalid_utf8`.
+	pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Self {
+		self.invalid_utf8 = invalid_utf8;
+		self
+	}
+
+	/// Use the permessage-deflate extension,
+	/// see `MessageCodec::set_deflate`.
+	pub fn deflate<C, D>(mut self, config: DeflateConfig, compressor: C, decompressor: D) -> Self
+	where
+		C: Compressor + 'static,
+		D: Decompressor + 'static,
+	{
+		self.deflate = Some(Deflate::new(
+			config,
+			Box::new(compressor),
+			Box::new(decompressor),
+		));
+		self
+	}
+
+	/// Create the codec, the message type `M` it encodes is usually inferred
+	/// and defaults to `OwnedMessage` in most places of this crate.
+	pub fn build<M>(self) -> MessageCodec<M>
+	where
+		M: MessageTrait,
+	{
+		self.build_with_payload()
+	}
+
+	/// Create a codec which stores the payloads of decoded messages in a
+	/// `P`, e.g. `Bytes`. See `Payload` for the type of message it decodes.
+	///
+	///```rust
+	///# extern crate bytes;
+	///# extern crate tokio_codec;
+	///# extern crate websocket;
+	///use bytes::{Bytes, BytesMut};
+	///use tokio_codec::Decoder;
+	///use websocket::async::{MessageCodec, MessageCodecBuilder, MsgCodecCtx};
+	///use websocket::ws::Message;
+	///use websocket::{GenericMessage, OwnedMessage};
+	///# fn main() {
+	///
+	///let mut codec: MessageCodec<OwnedMessage, Bytes> =
+	///    MessageCodecBuilder::new(MsgCodecCtx::Client).build_with_payload();
+	///let mut input = Vec::new();
+	///OwnedMessage::Binary(vec![1, 2, 3])
+	///    .serialize(&mut input, false)
+	///    .unwrap();
+	///
+	///let message = codec.decode(&mut BytesMut::from(input)).unwrap();
+	///assert_eq!(message, Some(GenericMessage::Binary(Bytes::from(vec![1, 2, 3]))));
+	///# }
+	///```
+	pub fn build_with_payload<M, P>(self) -> MessageCodec<M, P>
+	where
+		M: MessageTrait,
+		P: Payload,
+	{
+		let mut dataframe_codec = DataFrameCodec::new(self.context);
+		dataframe_codec.set_mask_outgoing(self.mask_outgoing);
+		dataframe_codec.set_strict_masking(self.strict_masking);
+		dataframe_codec.rng = self.rng;
+		dataframe_codec.set_max_frame_size(self.max_frame_size);
+		let mut codec = MessageCodec {
+			payload: None,
+			message_opcode: None,
+			fragments: 0,
+			buffered_size: 0,
+			max_message_size: self.max_message_size,
+			max_fragments: self.max_fragments,
+			fragment_size: self.fragment_size,
+			send_buffer_limit: self.send_buffer_limit,
+			queued_bytes: 0,
+			text_validator: None,
+			invalid_utf8: self.invalid_utf8,
+			invalid_text: false,
+			deflate: None,
+			stats: Arc::new(CodecStats::default()),
+			received_close: None,
+			dataframe_codec,
+			message_type: PhantomData,
+		};
+		if let Some(deflate) = self.deflate {
+			codec.enable_deflate(deflate);
+		}
+		codec
+	}
+}
+
+impl<M, P> Decoder for MessageCodec<M, P>
+where
+	M: MessageTrait,
+	P: Payload,
+{
+	type Item = P::Message;
+	type Error = WebSocketError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		// nothing may follow a close message (RFC 6455 5.5.1), whatever
+		// comes after it stays in `src` for `into_raw_parts`
+		if self.received_close.is_some() {
+			return Ok(None);
+		}
+		loop {
+			let remaining = self.max_message_size.saturating_sub(self.buffered_size);
+			let (header, mut data) =
+				match self.dataframe_codec.decode_payload(src, remaining as u64)? {
+					Some(frame) => frame,
+					None => return Ok(None),
+				};
+			let is_first = self.message_opcode.is_none();
+			let finished = header.flags.contains(DataFrameFlags::FIN);
+			let compressed = header.flags.contains(DataFrameFlags::RSV1);
+			self.stats.count_received(header.opcode, data.len() as u64);
+
+			// only the first dataframe of a data message can be marked as
+			// compressed, RSV1 is rejected earlier if deflate isn't used
+			if compressed && !(is_first && header.opcode < 8) {
+				return Err(ProtocolError::UnexpectedCompression(header.opcode).into());
+			}
+
+			match header.opcode {
+				// continuation code
+				0 if is_first => {
+					return Err(ProtocolError::UnexpectedContinuation.into());
+				}
+				// control frame, these may be sent in between the fragments of
+				// a data message: return it right away, the buffered fragments
+				// are kept until the rest of the message arrives
+				8...15 => {
+					let frame = DataFrame::read_dataframe_body(header, data.to_vec(), false)?;
+					let message = OwnedMessage::from_dataframes(vec![frame])?;
+					if let OwnedMessage::Close(ref data) = message {
+						self.received_close = Some(data.clone());
+					}
+					return Ok(Some(GenericMessage::from(message).into()));
+				}
+				// data frame
+				1...7 if !is_first => {
+					return Err(ProtocolError::ExpectedContinuation(header.opcode).into());
+				}
+				// too many fragments
+				_ if self.fragments >= self.max_fragments => {
+					return Err(ProtocolError::TooManyFragments(self.max_fragments).into());
+				}
+				// its good
+				_ => {
+					if is_first {
+						self.message_opcode = Opcode::new(header.opcode);
+						self.text_validator = match self.message_opcode {
+							Some(Opcode::Text) => Some(Utf8Validator::new()),
+							_ => None,
+						};
+						if let Some(ref mut deflate) = self.deflate {
+							deflate.inflating = compressed;
+						}
+					}
+					if let Some(ref mut deflate) = self.deflate {
+						if deflate.inflating {
+							data = BytesMut::from(deflate.decompress(&data, finished, remaining)?);
+						}
+					}
+					// fail as soon as the text can't be valid anymore
+					let valid = match self.text_validator {
+						Some(ref mut validator) => validator.feed(&data).and_then(|_| {
+							if finished {
+								validator.finish()
+							} else {
+								Ok(())
+							}
+						}),
+						None => Ok(()),
+					};
+					if let Err(e) = valid {
+						if self.invalid_utf8 == InvalidUtf8::Error {
+							return Err(ProtocolError::InvalidUtf8(e).into());
+						}
+						self.text_validator = None;
+						self.invalid_text = true;
+					}
+					self.buffered_size += data.len();
+					self.fragments += 1;
+					match self.payload {
+						Some(ref mut payload) => payload.extend(&data),
+						// an unfragmented payload is taken over as it is
+						None => self.payload = Some(P::from_bytes_mut(data)),
+					}
+				}
+			};
+
+			if finished {
+				let payload = self
+					.payload
+					.take()
+					.unwrap_or_else(|| P::from_bytes_mut(BytesMut::new()));
+				let opcode = self.message_opcode.take();
+				self.fragments = 0;
+				self.buffered_size = 0;
+				if self.invalid_text {
+					self.invalid_text = false;
+					return Ok(Some(
+						match self.invalid_utf8 {
+							InvalidUtf8::Binary => GenericMessage::Binary(payload),
+							_ => GenericMessage::Text(
+								String::from_utf8_lossy(payload.as_slice()).into_owned(),
+							),
+						}
+						.into(),
+					));
+				}
+				let message = match opcode {
+					Some(Opcode::Text) => GenericMessage::Text(
+						String::from_utf8(payload.into_vec())
+							.map_err(|e| ProtocolError::InvalidUtf8(e.utf8_error()))?,
+					),
+					_ => GenericMessage::Binary(payload),
+				};
+				return Ok(Some(message.into()));
+			}
+		}
+	}
+
+	/// Decode the messages which are left when the connection was closed.
+	///
+	/// The stream of messages only ends cleanly if a Close message was
+	/// received before, no matter what came after it. Otherwise the
+	/// connection was dropped and this fails with
+	/// `WebSocketError::IncompleteFrame` if it ended in the middle of a
+	/// dataframe or with `WebSocketError::ConnectionDropped`.
+	fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		if let Some(message) = self.decode(src)? {
+			return Ok(Some(message));
+		}
+		if self.received_close.is_some() {
+			return Ok(None);
+		}
+		if !src.is_empty() {
+			return Err(WebSocketError::IncompleteFrame);
+		}
+		Err(WebSocketError::ConnectionDropped)
+	}
+}
+
+/// Take a framed websocket connection apart, e.g. to switch it over to
+/// another protocol.
+///
+/// Returns the stream, the bytes which were read but not decoded yet and
+/// the bytes which were encoded but not written yet, in this order. The
+/// bytes read start with the first dataframe the codec didn't decode
+/// completely; only the already decoded fragments of an unfinished message
+/// are dropped (see `MessageCodec::in_message`), so switch protocols after a
+/// complete message, e.g. a close message. Nothing is decoded after a close
+/// message, the bytes following it are all returned.
+pub fn into_raw_parts<S, M, P>(framed: Framed<S, MessageCodec<M, P>>) -> (S, BytesMut, BytesMut)
+where
+	M: MessageTrait,
+{
+	let parts = framed.into_parts();
+	(parts.io, parts.read_buf, parts.write_buf)
+}
+
+impl<M, P> Encoder for MessageCodec<M, P>
+where
+	M: MessageTrait,
+{
+	type Item = M;
+	type Error = WebSocketError;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.check_send_buffer(item.is_control(), dst)?;
+		self.encode_message(item, dst)?;
+		self.queued_bytes = dst.len();
+		Ok(())
+	}
+}
+
+impl<M, P> MessageCodec<M, P>
+where
+	M: MessageTrait,
+{
+	fn encode_message(&mut self, item: M, dst: &mut BytesMut) -> Result<(), WebSocketError> {
+		if let Some(ref mut deflate) = self.deflate {
+			if !item.is_raw_dataframe() {
+				if let Some(frame) = deflate.compress(&item)? {
+					return self.encode_frame(frame, dst);
+				}
+			}
+		}
+
+		let mut frame_size = item.message_size(self.dataframe_codec.mask_outgoing());
+		if let Some(fragment_size) = self.fragment_size {
+			// every additional fragment adds a header of at most 14 bytes
+			frame_size += frame_size / fragment_size.max(1) * 14;
+		}
+		if frame_size > dst.remaining_mut() {
+			dst.reserve(frame_size);
+		}
+
+		let mut frames = 1;
+		match self.fragment_size {
+			Some(fragment_size) => {
+				frames = 0;
+				let dataframe_codec = &mut self.dataframe_codec;
+				// every dataframe takes the next mask
+				item.serialize_fragmented(&mut dst.writer(), fragment_size, &mut || {
+					frames += 1;
+					dataframe_codec.next_mask()
+				})?;
+			}
+			None => {
+				let mask = self.dataframe_codec.next_mask();
+				item.serialize_to_buffer(&mut dst.writer(), mask)?;
+			}
+		}
+		if let Some((opcode, len)) = item.opcode_and_size() {
+			self.stats.count_sent(opcode, frames, len as u64);
+		}
+		Ok(())
+	}
+}
+
+/// An outgoing message or a hand-built dataframe, for a `MessageCodec` which
+/// has to send both over the same connection.
+///
+/// Use a `MessageCodec<Outgoing>` for that, it is encoded like a
+/// `MessageCodec<OwnedMessage>` but dataframes are sent exactly as they are:
+/// they are never fragmented or compressed and keep their reserved bits.
+/// The messages before and after a dataframe aren't affected by it, e.g.
+/// the compression context is left alone. Keep in mind that a data
+/// dataframe which isn't the last fragment of its message has to be followed
+/// by its continuation frames before the next message.
+///
+///```rust
+///# extern crate bytes;
+///# extern crate tokio_codec;
+///# extern crate websocket;
+///use bytes::BytesMut;
+///use tokio_codec::Encoder;
+///use websocket::async::{MessageCodec, MsgCodecCtx, Outgoing};
+///use websocket::dataframe::{DataFrame, Opcode};
+///use websocket::OwnedMessage;
+///# fn main() {
+///
+///let mut codec = MessageCodec::new(MsgCodecCtx::Server);
+///let mut dst = BytesMut::new();
+///let mut frame = DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]);
+///frame.reserved = [false, true, false];
+///codec.encode(Outgoing::Frame(frame), &mut dst).unwrap();
+///codec
+///    .encode(OwnedMessage::Text("hello".to_string()).into(), &mut dst)
+///    .unwrap();
+///
+///assert_eq!(&dst[..5], &[0xA2, 0x03, 1, 2, 3]);
+///# }
+///```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outgoing {
+	/// A message, which is sent like `MessageCodec<OwnedMessage>` would
+	Message(OwnedMessage),
+	/// A dataframe, which is sent exactly as it is
+	Frame(DataFrame),
+}
+
+impl From<OwnedMessage> for Outgoing {
+	fn from(message: OwnedMessage) -> Self {
+		Outgoing::Message(message)
+	}
+}
+
+impl From<DataFrame> for Outgoing {
+	fn from(frame: DataFrame) -> Self {
+		Outgoing::Frame(frame)
+	}
+}
+
+impl MessageTrait for Outgoing {
+	fn serialize(&self, writer: &mut dyn Write, masked: bool) -> WebSocketResult<()> {
+		match *self {
+			Outgoing::Message(ref message) => message.serialize(writer, masked),
+			Outgoing::Frame(ref frame) => frame.write_to(writer, masked),
+		}
+	}
+
+	fn serialize_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		match *self {
+			Outgoing::Message(ref message) => message.serialize_with_mask(writer, masking_key),
+			Outgoing::Frame(ref frame) => frame.write_to_with_mask(writer, masking_key),
+		}
+	}
+
+	fn serialize_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		match *self {
+			Outgoing::Message(ref message) => message.serialize_to_buffer(buffer, masking_key),
+			Outgoing::Frame(ref frame) => frame.write_to_buffer(buffer, masking_key),
+		}
+	}
+
+	fn serialize_fragmented(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		match *self {
+			Outgoing::Message(ref message) => {
+				message.serialize_fragmented(buffer, fragment_size, next_mask)
+			}
+			Outgoing::Frame(ref frame) => frame.write_to_buffer(buffer, next_mask()),
+		}
+	}
+
+	fn message_size(&self, masked: bool) -> usize {
+		match *self {
+			Outgoing::Message(ref message) => message.message_size(masked),
+			Outgoing::Frame(ref frame) => frame.frame_size(masked),
+		}
+	}
+
+	fn is_raw_dataframe(&self) -> bool {
+		match *self {
+			Outgoing::Message(_) => false,
+			Outgoing::Frame(_) => true,
+		}
+	}
+
+	fn opcode_and_payload(&self) -> Option<(u8, Cow<'_, [u8]>)> {
+		match *self {
+			Outgoing::Message(ref message) => message.opcode_and_payload(),
+			Outgoing::Frame(_) => None,
+		}
+	}
+
+	fn opcode_and_size(&self) -> Option<(u8, usize)> {
+		match *self {
+			Outgoing::Message(ref message) => message.opcode_and_size(),
+			Outgoing::Frame(ref frame) => Some((frame.opcode as u8, frame.data.len())),
+		}
+	}
+
+	fn is_control(&self) -> bool {
+		match *self {
+			Outgoing::Message(ref message) => message.is_control(),
+			Outgoing::Frame(ref frame) => frame.opcode.is_control(),
+		}
+	}
+
+	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
+	where
+		D: DataFrameTrait,
+	{
+		OwnedMessage::from_dataframes(frames).map(Outgoing::Message)
+	}
+}
+
+/******************
+ * Message chunks *
+ ******************/
+
+/// A part of an incoming websocket message, as produced by
+/// `MessageChunkCodec`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageChunk {
+	/// A new data message of the given type (`Text` or `Binary`) starts, its
+	/// payload follows in `Data` chunks.
+	Start(Type),
+	/// The next part of the payload of the current data message.
+	///
+	/// The payload of text messages is not validated, and a chunk may end in
+	/// the middle of a UTF-8 character.
+	Data(Bytes),
+	/// The current data message is complete.
+	End,
+	/// A complete control message (ping, pong or close), which can also
+	/// arrive in between the chunks of a data message.
+	Control(OwnedMessage),
+}
+
+// The data frame whose payload is currently being streamed.
+struct ChunkedFrame {
+	remaining: u64,
+	mask: Option<[u8; 4]>,
+	offset: usize,
+	finished: bool,
+}
+
+/// A codec which decodes websocket messages in chunks as their payload
+/// arrives, instead of reassembling whole messages like `MessageCodec`.
+///
+/// Use this if messages can be too large to keep them in memory: payload
+/// bytes are passed on as soon as they are read, even before the rest of
+/// their dataframe arrived, so the memory used doesn't depend on the size of
+/// messages or dataframes. Control messages are small and always decoded as a
+/// whole. If the codec uses permessage-deflate (see
+/// `MessageCodec::set_deflate`) compressed payloads are decompressed chunk by
+/// chunk.
+///
+/// Encoding works exactly like it does in `MessageCodec`.
+pub struct MessageChunkCodec<M>
+where
+	M: MessageTrait,
+{
+	codec: MessageCodec<M>,
+	in_message: bool,
+	inflating: bool,
+	frame: Option<ChunkedFrame>,
+}
+
+impl MessageChunkCodec<OwnedMessage> {
+	/// Create a new `MessageChunkCodec` with a role of `context` which sends
+	/// `OwnedMessage` structs.
+	pub fn default(context: Context) -> Self {
+		Self::new(context)
+	}
+}
+
+impl<M> MessageChunkCodec<M>
+where
+	M: MessageTrait,
+{
+	/// Create a new `MessageChunkCodec` with a role of `context`, which can
+	/// send any kind of message.
+	pub fn new(context: Context) -> Self {
+		Self::from_codec(MessageCodec::new(context))
+	}
+
+	/// Create a new `MessageChunkCodec` which encodes like `codec`.
+	///
+	/// Limits on the size of incoming messages do not apply when decoding
+	/// chunks, except for the maximum frame size and that a single chunk may
+	/// not decompress to more than the maximum message size.
+	pub fn from_codec(codec: MessageCodec<M>) -> Self {
+		MessageChunkCodec {
+			codec,
+			in_message: false,
+			inflating: false,
+			frame: None,
+		}
+	}
+
+	/// The `MessageCodec` used for encoding.
+	pub fn codec(&self) -> &MessageCodec<M> {
+		&self.codec
+	}
+
+	/// The `MessageCodec` used for encoding, mutably.
+	pub fn codec_mut(&mut self) -> &mut MessageCodec<M> {
+		&mut self.codec
+	}
+}
+
+impl<M> Decoder for MessageChunkCodec<M>
+where
+	M: MessageTrait,
+{
+	type Item = MessageChunk;
+	type Error = WebSocketError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		loop {
+			if let Some(ref mut frame) = self.frame {
+				if frame.remaining > 0 {
+					if src.is_empty() {
+						return Ok(None);
+					}
+					let len = cmp::min(frame.remaining, src.len() as u64) as usize;
+					let mut data = src.split_to(len);
+					if let Some(mask) = frame.mask {
+						let offset = frame.offset;
+						let mask = [
+							mask[offset % 4],
+							mask[(offset + 1) % 4],
+							mask[(offset + 2) % 4],
+							mask[(offset + 3) % 4],
+						];
+						mask_data_in_place(mask, &mut data);
+					}
+					frame.remaining -= len as u64;
+					frame.offset = (frame.offset + len) % 4;
+					if !self.inflating {
+						return Ok(Some(MessageChunk::Data(data.freeze())));
+					}
+					let limit = self.codec.max_message_size;
+					if let Some(ref mut deflate) = self.codec.deflate {
+						let data = deflate.decompress(&data, false, limit)?;
+						if !data.is_empty() {
+							return Ok(Some(MessageChunk::Data(Bytes::from(data))));
+						}
+					}
+					continue;
+				}
+			}
+
+			if let Some(frame) = self.frame.take() {
+				if frame.finished {
+					// the end of a compressed message may still be buffered
+					// in the decompressor
+					if self.inflating {
+						self.inflating = false;
+						let limit = self.codec.max_message_size;
+						if let Some(ref mut deflate) = self.codec.deflate {
+							let data = deflate.decompress(&[], true, limit)?;
+							if !data.is_empty() {
+								self.frame = Some(frame);
+								return Ok(Some(MessageChunk::Data(Bytes::from(data))));
+							}
+						}
+					}
+					self.in_message = false;
+					return Ok(Some(MessageChunk::End));
+				}
+			}
+
+			let (header, bytes_read) = match parse_header(src)? {
+				ParseResult::Complete { header, header_len } => (header, header_len),
+				ParseResult::Incomplete { .. } => return Ok(None),
+			};
+
+			let dataframe_codec = &self.codec.dataframe_codec;
+			if header.len > dataframe_codec.max_frame_size as u64 {
+				return Err(WebSocketError::MessageTooLarge);
+			}
+			dataframe_codec.check_mask(&header)?;
+
+			let reserved = header.flags - DataFrameFlags::FIN;
+			if !dataframe_codec.reserved_bits.contains(reserved) {
+				return Err(ProtocolError::ReservedBits(reserved.bits()).into());
+			}
+
+			// only the first dataframe of a data message can be marked as
+			// compressed, RSV1 is only allowed if deflate is used
+			let compressed = header.flags.contains(DataFrameFlags::RSV1);
+			if compressed && (self.in_message || header.opcode >= 8) {
+				return Err(ProtocolError::UnexpectedCompression(header.opcode).into());
+			}
+
+			// control frames are short (the header parser ensures that), so
+			// they are only decoded once they arrived in full
+			if header.opcode >= 8 {
+				if bytes_read as u64 + header.len > src.len() as u64 {
+					return Ok(None);
+				}
+				let _ = src.split_to(bytes_read);
+				let mut body = src.split_to(header.len as usize).to_vec();
+				// the mask was checked against the role of the codec already
+				let mut header = header;
+				if let Some(mask) = header.mask.take() {
+					mask_data_in_place(mask, &mut body);
+				}
+				let frame = DataFrame::read_dataframe_body(header, body, false)?;
+				return Ok(Some(MessageChunk::Control(OwnedMessage::from_dataframes(
+					vec![frame],
+				)?)));
+			}
+
+			let start = match (Opcode::new(header.opcode), self.in_message) {
+				(Some(Opcode::Continuation), true) => None,
+				(Some(Opcode::Continuation), false) => {
+					return Err(ProtocolError::UnexpectedContinuation.into());
+				}
+				(Some(Opcode::Text), false) => Some(Type::Text),
+				(Some(Opcode::Binary), false) => Some(Type::Binary),
+				(Some(Opcode::Text), true) | (Some(Opcode::Binary), true) => {
+					return Err(ProtocolError::ExpectedContinuation(header.opcode).into());
+				}
+				_ => return Err(ProtocolError::ReservedOpcode(header.opcode).into()),
+			};
+
+			let _ = src.split_to(bytes_read);
+			if !self.in_message {
+				self.inflating = compressed;
+			}
+			self.in_message = true;
+			self.frame = Some(ChunkedFrame {
+				remaining: header.len,
+				mask: header.mask,
+				offset: 0,
+				finished: header.flags.contains(DataFrameFlags::FIN),
+			});
+			if let Some(opcode) = start {
+				return Ok(Some(MessageChunk::Start(opcode)));
+			}
+		}
+	}
+}
+
+impl<M> Encoder for MessageChunkCodec<M>
+where
+	M: MessageTrait,
+{
+	type Item = M;
+	type Error = WebSocketError;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.codec.encode(item, dst)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::deflate::stored::{Stored, StoredDecompressor};
+	use dataframe::Opcode;
+	use futures::{Future, Sink, Stream};
+	use message::Message;
+	use message::{CloseCode, CloseData};
+	use result::ProtocolError;
+	use std::io;
+	use std::io::Cursor;
+	use stream::ReadWritePair;
+
+	#[test]
+	fn owned_message_predicts_size() {
+		let messages = vec![
+			OwnedMessage::Text("nilbog".to_string()),
+			OwnedMessage::Binary(vec![1, 2, 3, 4]),
+			OwnedMessage::Binary(vec![42; 256]),
+			OwnedMessage::Binary(vec![42; 65535]),
+			OwnedMessage::Binary(vec![42; 65555]),
+			OwnedMessage::Ping("beep".to_string().into_bytes()),
+			OwnedMessage::Pong("boop".to_string().into_bytes()),
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 64,
 				reason: "because".to_string(),
 			})),
 		];
 
-		for message in messages.into_iter() {
-			let masked_predicted = message.message_size(true);
-			let mut masked_buf = Vec::new();
-			message.serialize(&mut masked_buf, true).unwrap();
-			assert_eq!(masked_buf.len(), masked_predicted);
+		for message in messages.into_iter() {
+			let masked_predicted = message.message_size(true);
+			let mut masked_buf = Vec::new();
+			message.serialize(&mut masked_buf, true).unwrap();
+			assert_eq!(masked_buf.len(), masked_predicted);
+
+			let unmasked_predicted = message.message_size(false);
+			let mut unmasked_buf = Vec::new();
+			message.serialize(&mut unmasked_buf, false).unwrap();
+			assert_eq!(unmasked_buf.len(), unmasked_predicted);
+		}
+	}
+
+	#[test]
+	fn cow_message_predicts_size() {
+		let messages = vec![
+			Message::binary(vec![1, 2, 3, 4]),
+			Message::binary(vec![42; 256]),
+			Message::binary(vec![42; 65535]),
+			Message::binary(vec![42; 65555]),
+			Message::text("nilbog".to_string()),
+			Message::ping("beep".to_string().into_bytes()),
+			Message::pong("boop".to_string().into_bytes()),
+			Message::close(),
+			Message::close_because(64, "because"),
+		];
+
+		for message in messages.iter() {
+			let masked_predicted = message.message_size(true);
+			let mut masked_buf = Vec::new();
+			message.serialize(&mut masked_buf, true).unwrap();
+			assert_eq!(masked_buf.len(), masked_predicted);
+
+			let unmasked_predicted = message.message_size(false);
+			let mut unmasked_buf = Vec::new();
+			message.serialize(&mut unmasked_buf, false).unwrap();
+			assert_eq!(unmasked_buf.len(), unmasked_predicted);
+		}
+	}
+
+	#[test]
+	fn message_codec_client_send_receive() {
+		let mut input = Vec::new();
+		Message::text("50 schmeckels")
+			.serialize(&mut input, false)
+			.unwrap();
+
+		let f = MessageCodec::new(Context::Client)
+			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])))
+			.into_future()
+			.map_err(|e| e.0)
+			.map(|(m, s)| {
+				assert_eq!(m, Some(OwnedMessage::Text("50 schmeckels".to_string())));
+				s
+			})
+			.and_then(|s| s.send(Message::text("ethan bradberry")))
+			.and_then(|s| {
+				let mut stream = s.into_parts().io;
+				stream.1.set_position(0);
+				println!("buffer: {:?}", stream.1);
+				MessageCodec::default(Context::Server)
+					.framed(ReadWritePair(stream.1, stream.0))
+					.into_future()
+					.map_err(|e| e.0)
+					.map(|(message, _)| {
+						assert_eq!(message, Some(Message::text("ethan bradberry").into()))
+					})
+			});
+
+		use tokio;
+		tokio::runtime::Builder::new()
+			.build()
+			.unwrap()
+			.block_on(f)
+			.unwrap();
+	}
+
+	#[test]
+	fn message_codec_server_send_receive() {
+		use tokio;
+		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+		let mut input = Vec::new();
+		Message::text("50 schmeckels")
+			.serialize(&mut input, true)
+			.unwrap();
+
+		let f = MessageCodec::new(Context::Server)
+			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])))
+			.into_future()
+			.map_err(|e| e.0)
+			.map(|(m, s)| {
+				assert_eq!(m, Some(OwnedMessage::Text("50 schmeckels".to_string())));
+				s
+			})
+			.and_then(|s| s.send(Message::text("ethan bradberry")))
+			.map(|s| {
+				let mut written = vec![];
+				Message::text("ethan bradberry")
+					.serialize(&mut written, false)
+					.unwrap();
+				assert_eq!(written, s.into_parts().io.1.into_inner());
+			});
+
+		runtime.block_on(f).unwrap();
+	}
+
+	#[test]
+	fn message_codec_handles_interleaved_control_frames() {
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, b"Hel".to_vec())
+			.write_to(&mut input, true)
+			.unwrap();
+		let ping_start = input.len();
+		DataFrame::new(true, Opcode::Ping, b"ping".to_vec())
+			.write_to(&mut input, true)
+			.unwrap();
+		DataFrame::new(false, Opcode::Continuation, b"l".to_vec())
+			.write_to(&mut input, true)
+			.unwrap();
+		DataFrame::new(true, Opcode::Pong, Vec::new())
+			.write_to(&mut input, true)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, b"o".to_vec())
+			.write_to(&mut input, true)
+			.unwrap();
+
+		let mut codec = MessageCodec::default(Context::Server);
+		// the first fragment and half of the ping
+		let mut src = BytesMut::from(&input[..ping_start + 3]);
+		assert_eq!(codec.decode(&mut src).unwrap(), None);
+		src.extend_from_slice(&input[ping_start + 3..]);
+
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Ping(b"ping".to_vec()))
+		);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Pong(Vec::new()))
+		);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Text("Hello".to_string()))
+		);
+		assert_eq!(codec.decode(&mut src).unwrap(), None);
+		assert!(src.is_empty());
+
+		// a data frame still can't interrupt a fragmented message
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, b"Hel".to_vec())
+			.write_to(&mut input, true)
+			.unwrap();
+		DataFrame::new(true, Opcode::Ping, Vec::new())
+			.write_to(&mut input, true)
+			.unwrap();
+		DataFrame::new(true, Opcode::Binary, b"lo".to_vec())
+			.write_to(&mut input, true)
+			.unwrap();
+		let mut src = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Ping(Vec::new()))
+		);
+		assert!(codec.decode(&mut src).is_err());
+	}
+
+	#[test]
+	fn message_codec_rejects_large_message() {
+		let mut input = Vec::new();
+		Message::binary(vec![0; 100])
+			.serialize(&mut input, false)
+			.unwrap();
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_message_size(99);
+		let mut src = BytesMut::from(input.clone());
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_message_size(100);
+		let mut src = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Binary(vec![0; 100]))
+		);
+	}
+
+	#[test]
+	fn message_codec_rejects_large_fragmented_message() {
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![b'a'; 60])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![b'a'; 60])
+			.write_to(&mut input, false)
+			.unwrap();
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_message_size(100);
+		let mut src = BytesMut::from(input);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_rejects_large_header_early() {
+		// a binary frame claiming a 2^40 byte payload, without any payload
+		let input = vec![0x82, 0x7F, 0, 0, 0x01, 0, 0, 0, 0, 0];
+
+		let mut codec = MessageCodec::default(Context::Client);
+		let mut src = BytesMut::from(input);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn dataframe_codec_relays_frames() {
+		let frames = vec![
+			DataFrame::new(false, Opcode::Text, b"Hel".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"ping".to_vec()),
+			DataFrame::new(false, Opcode::Continuation, Vec::new()),
+			DataFrame::new(true, Opcode::Continuation, b"lo".to_vec()),
+		];
+
+		for &(from, to) in &[
+			(Context::Client, Context::Server),
+			(Context::Server, Context::Client),
+		] {
+			let mut sender = DataFrameCodec::default(from);
+			let mut receiver = DataFrameCodec::default(to);
+			let mut wire = BytesMut::new();
+			for frame in &frames {
+				sender.encode(frame.clone(), &mut wire).unwrap();
+			}
+			// client frames carry a 4 byte mask key
+			let mask_len = if from == Context::Client { 16 } else { 0 };
+			assert_eq!(wire.len(), 4 * 2 + mask_len + 9);
+
+			let mut relayed = Vec::new();
+			while let Some(frame) = receiver.decode(&mut wire).unwrap() {
+				relayed.push(frame);
+			}
+			assert_eq!(relayed, frames);
+		}
+	}
+
+	#[test]
+	fn dataframe_codec_rejects_large_frames() {
+		// 16 bit extended length
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Binary, vec![0; 200])
+			.write_to(&mut input, false)
+			.unwrap();
+
+		let mut codec = DataFrameCodec::default(Context::Client);
+		codec.set_max_frame_size(199);
+		// only the header has arrived so far
+		let mut src = BytesMut::from(&input[..4]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut codec = DataFrameCodec::default(Context::Client);
+		codec.set_max_frame_size(200);
+		let mut src = BytesMut::from(input);
+		assert_eq!(codec.decode(&mut src).unwrap().unwrap().data, vec![0; 200]);
+
+		// 64 bit extended length
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Binary, vec![0; 70000])
+			.write_to(&mut input, false)
+			.unwrap();
+
+		let mut codec = DataFrameCodec::default(Context::Client);
+		codec.set_max_frame_size(65536);
+		let mut src = BytesMut::from(&input[..10]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut codec = DataFrameCodec::default(Context::Client);
+		codec.set_max_frame_size(70000);
+		let mut src = BytesMut::from(input);
+		assert_eq!(codec.decode(&mut src).unwrap().unwrap().data.len(), 70000);
+	}
+
+	#[test]
+	fn message_codec_rejects_large_frames() {
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Binary, vec![0; 10])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![0; 300])
+			.write_to(&mut input, false)
+			.unwrap();
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_frame_size(256);
+		let mut src = BytesMut::from(&input[..16]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_limits_fragments() {
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Binary, vec![])
+			.write_to(&mut input, false)
+			.unwrap();
+		for _ in 0..3 {
+			DataFrame::new(false, Opcode::Continuation, vec![])
+				.write_to(&mut input, false)
+				.unwrap();
+		}
+		DataFrame::new(true, Opcode::Continuation, vec![1])
+			.write_to(&mut input, false)
+			.unwrap();
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_fragments(4);
+		let mut src = BytesMut::from(input.clone());
+		match codec.decode(&mut src) {
+			Err(WebSocketError::Protocol(ProtocolError::TooManyFragments(4))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_fragments(5);
+		let mut src = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Binary(vec![1]))
+		);
+	}
+
+	#[test]
+	fn message_codec_builder_applies_options() {
+		let codec: MessageCodec<OwnedMessage> = MessageCodecBuilder::new(Context::Client)
+			.context(Context::Server)
+			.max_message_size(10)
+			.max_frame_size(5)
+			.max_fragments(2)
+			.build();
+		assert!(codec.dataframe_codec.is_server);
+		assert!(!codec.mask_outgoing());
+		assert_eq!(codec.max_message_size(), 10);
+		assert_eq!(codec.max_frame_size(), 5);
+		assert_eq!(codec.max_fragments(), 2);
+
+		let codec = MessageCodec::default(Context::Client);
+		assert!(!codec.dataframe_codec.is_server);
+		assert!(codec.mask_outgoing());
+		assert_eq!(codec.max_message_size(), DEFAULT_MAX_MESSAGE_SIZE);
+		assert_eq!(codec.max_frame_size(), usize::MAX);
+		assert_eq!(codec.max_fragments(), DEFAULT_MAX_FRAGMENTS);
+	}
+
+	#[test]
+	fn message_codec_unmasked_client() {
+		let message = Message::binary(vec![1, 2, 3]);
+		let mut expected = Vec::new();
+		message.serialize(&mut expected, false).unwrap();
+
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.mask_outgoing(false)
+			.build();
+		let mut dst = BytesMut::new();
+		codec.encode(message.clone(), &mut dst).unwrap();
+		assert_eq!(&dst[..], &expected[..]);
+
+		let mut codec = MessageCodec::new(Context::Client);
+		let mut dst = BytesMut::new();
+		codec.encode(message, &mut dst).unwrap();
+		assert_eq!(dst.len(), expected.len() + 4);
+		assert_eq!(dst[1] & 0x80, 0x80);
+	}
+
+	#[cfg(feature = "nightly")]
+	fn bench_client_encode(b: &mut ::test::Bencher, mask: bool) {
+		let message = OwnedMessage::Binary(vec![42; 64 << 10]);
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.mask_outgoing(mask)
+			.build();
+		let mut dst = BytesMut::with_capacity(message.message_size(true));
+		b.bytes = (64 << 10) as u64;
+		b.iter(|| {
+			dst.clear();
+			codec.encode(message.clone(), &mut dst).unwrap();
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_client_encode_masked(b: &mut ::test::Bencher) {
+		bench_client_encode(b, true);
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_client_encode_unmasked(b: &mut ::test::Bencher) {
+		bench_client_encode(b, false);
+	}
+
+	#[test]
+	fn message_codec_uses_custom_rng() {
+		use rand::rngs::mock::StepRng;
+
+		// the mock rng emits 0x04030201 as its first u32
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.rng(StepRng::new(0x0403_0201, 0))
+			.build();
+		let mut dst = BytesMut::new();
+		codec
+			.encode(Message::binary(vec![0, 0, 0, 0, 0]), &mut dst)
+			.unwrap();
+		assert_eq!(&dst[..], &[0x82, 0x85, 1, 2, 3, 4, 1, 2, 3, 4, 1][..]);
+
+		let mut codec = DataFrameCodec::<Box<dyn DataFrameTrait>>::new(Context::Client);
+		codec.set_rng(StepRng::new(0x0403_0201, 0));
+		let mut dst = BytesMut::new();
+		let frame = DataFrame::new(true, Opcode::Text, b"ab".to_vec());
+		codec.encode(Box::new(frame), &mut dst).unwrap();
+		assert_eq!(&dst[..], &[0x81, 0x82, 1, 2, 3, 4, b'a' ^ 1, b'b' ^ 2][..]);
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_server_decode(b: &mut ::test::Bencher) {
+		let mut input = Vec::new();
+		Message::binary(vec![42; 1 << 20])
+			.serialize(&mut input, true)
+			.unwrap();
+		let mut codec = MessageCodec::<OwnedMessage>::new(Context::Server);
+		b.bytes = 1 << 20;
+		b.iter(|| {
+			let mut src = BytesMut::from(&input[..]);
+			::test::black_box(codec.decode(&mut src).unwrap().unwrap());
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_server_decode_frame(b: &mut ::test::Bencher) {
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Binary, vec![42; 1 << 20])
+			.write_to(&mut input, true)
+			.unwrap();
+		let mut codec = DataFrameCodec::<DataFrame>::new(Context::Server);
+		let mut src = BytesMut::with_capacity(input.len());
+		b.bytes = 1 << 20;
+		b.iter(|| {
+			src.extend_from_slice(&input);
+			::test::black_box(codec.decode(&mut src).unwrap().unwrap());
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_server_decode_bytes(b: &mut ::test::Bencher) {
+		let mut input = Vec::new();
+		Message::binary(vec![42; 1 << 20])
+			.serialize(&mut input, true)
+			.unwrap();
+		let mut codec: MessageCodec<OwnedMessage, Bytes> =
+			MessageCodecBuilder::new(Context::Server).build_with_payload();
+		b.bytes = 1 << 20;
+		b.iter(|| {
+			let mut src = BytesMut::from(&input[..]);
+			::test::black_box(codec.decode(&mut src).unwrap().unwrap());
+		});
+	}
+
+	#[test]
+	fn message_codec_decodes_into_bytes() {
+		let mut codec: MessageCodec<OwnedMessage, Bytes> =
+			MessageCodecBuilder::new(Context::Client).build_with_payload();
+		let mut input = Vec::new();
+		OwnedMessage::Binary(vec![1; 300])
+			.serialize(&mut input, false)
+			.unwrap();
+		let mut src = BytesMut::from(&input[..]);
+		// the payload follows the 4 byte header
+		let start = src.as_ptr() as usize + 4;
+		match codec.decode(&mut src).unwrap() {
+			Some(GenericMessage::Binary(data)) => {
+				assert_eq!(data, Bytes::from(vec![1; 300]));
+				// taken over from the read buffer without a copy
+				assert_eq!(data.as_ptr() as usize, start);
+			}
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut src = BytesMut::new();
+		for frame in &[
+			DataFrame::new(false, Opcode::Text, b"Hel".to_vec()),
+			DataFrame::new(false, Opcode::Continuation, b"lo".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"ping".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"!".to_vec()),
+		] {
+			let mut buf = Vec::new();
+			frame.write_to(&mut buf, false).unwrap();
+			src.extend_from_slice(&buf);
+		}
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(GenericMessage::Ping(Bytes::from(&b"ping"[..])))
+		);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(GenericMessage::Text("Hello!".to_string()))
+		);
+
+		let message: OwnedMessage = GenericMessage::Binary(Bytes::from(vec![2])).into();
+		assert_eq!(message, OwnedMessage::Binary(vec![2]));
+	}
+
+	#[test]
+	fn message_codec_encodes_shared_messages() {
+		use std::sync::Arc;
+
+		let message = Arc::new(OwnedMessage::Binary(vec![7; 300]));
+		let mut expected = Vec::new();
+		message.serialize(&mut expected, false).unwrap();
+
+		let mut codec = MessageCodec::<Arc<OwnedMessage>>::new(Context::Server);
+		let mut dst = BytesMut::new();
+		codec.encode(message.clone(), &mut dst).unwrap();
+		codec.encode(message.clone(), &mut dst).unwrap();
+		assert_eq!(&dst[..expected.len()], &expected[..]);
+		assert_eq!(&dst[expected.len()..], &expected[..]);
+		assert_eq!(Arc::strong_count(&message), 1);
+
+		let payload = [1, 2, 3];
+		let mut codec = MessageCodec::<Message>::new(Context::Server);
+		let mut dst = BytesMut::new();
+		codec
+			.encode(Message::binary(&payload[..]), &mut dst)
+			.unwrap();
+		assert_eq!(&dst[..], &[0x82, 3, 1, 2, 3][..]);
+	}
+
+	#[test]
+	fn message_codec_encodes_bytes_messages() {
+		let messages = vec![
+			GenericMessage::Text("Hello".to_string()),
+			GenericMessage::Binary(Bytes::from(vec![7; 300])),
+			GenericMessage::Ping(Bytes::from(&b"ping"[..])),
+			GenericMessage::Pong(Bytes::new()),
+			GenericMessage::Close(None),
+			GenericMessage::Close(Some(CloseData::new(1000, "bye".to_string()))),
+		];
+		for message in messages {
+			let owned = OwnedMessage::from(message.clone());
+			for &fragment_size in &[None, Some(100)] {
+				let mut expected_codec = MessageCodec::<OwnedMessage>::new(Context::Server);
+				let mut codec = MessageCodec::<GenericMessage<Bytes>>::new(Context::Server);
+				expected_codec.set_fragment_size(fragment_size);
+				codec.set_fragment_size(fragment_size);
+				let mut expected = BytesMut::new();
+				expected_codec.encode(owned.clone(), &mut expected).unwrap();
+				let mut dst = BytesMut::new();
+				codec.encode(message.clone(), &mut dst).unwrap();
+				assert_eq!(dst, expected);
+			}
+		}
+
+		// clones share the payload, which is sent from where it is
+		let message = GenericMessage::Binary(Bytes::from(vec![7; VECTORED_PAYLOAD_THRESHOLD]));
+		let copy = message.clone();
+		let mut codec = MessageCodec::<GenericMessage<Bytes>>::new(Context::Server);
+		let mut dst = BytesMut::new();
+		let payload = codec.encode_vectored(&copy, &mut dst).unwrap().unwrap();
+		match message {
+			GenericMessage::Binary(ref data) => assert_eq!(payload.as_ptr(), data.as_ptr()),
+			_ => unreachable!(),
+		}
+	}
+
+	#[cfg(feature = "nightly")]
+	fn bench_broadcast<M, F>(b: &mut ::test::Bencher, message: M, mut send: F)
+	where
+		M: Clone,
+		F: FnMut(M, &mut BytesMut),
+	{
+		const SUBSCRIBERS: usize = 100;
+		let mut dsts = vec![BytesMut::with_capacity(70 << 10); SUBSCRIBERS];
+		b.bytes = (SUBSCRIBERS * (64 << 10)) as u64;
+		b.iter(|| {
+			for dst in dsts.iter_mut() {
+				dst.clear();
+				send(message.clone(), dst);
+			}
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_broadcast_vec(b: &mut ::test::Bencher) {
+		let mut codec = MessageCodec::<OwnedMessage>::new(Context::Server);
+		let message = OwnedMessage::Binary(vec![42; 64 << 10]);
+		bench_broadcast(b, message, |message, dst| {
+			codec.encode(message, dst).unwrap();
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_broadcast_bytes(b: &mut ::test::Bencher) {
+		let mut codec = MessageCodec::<GenericMessage<Bytes>>::new(Context::Server);
+		let message = GenericMessage::Binary(Bytes::from(vec![42; 64 << 10]));
+		bench_broadcast(b, message, |message, dst| {
+			codec.encode(message, dst).unwrap();
+		});
+	}
+
+	#[test]
+	fn message_codec_encode_vectored() {
+		let messages = [
+			OwnedMessage::Binary(vec![42; VECTORED_PAYLOAD_THRESHOLD]),
+			OwnedMessage::Text("a".repeat(70000)),
+			OwnedMessage::Binary(vec![1, 2, 3]),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1000,
+				reason: "bye".to_string(),
+			})),
+		];
+		let copied = [false, false, true, true];
+
+		for (message, &copied) in messages.iter().zip(copied.iter()) {
+			let mut expected = Vec::new();
+			message.serialize(&mut expected, false).unwrap();
+
+			let mut codec = MessageCodec::<OwnedMessage>::new(Context::Server);
+			let mut dst = BytesMut::new();
+			let payload = codec.encode_vectored(message, &mut dst).unwrap();
+			assert_eq!(payload.is_none(), copied);
+
+			let mut output = dst.to_vec();
+			output.extend_from_slice(payload.unwrap_or(&[]));
+			assert_eq!(output, expected);
+		}
+
+		// clients have to mask, which always needs a copy
+		let mut codec = MessageCodec::<OwnedMessage>::new(Context::Client);
+		let mut dst = BytesMut::new();
+		let payload = codec.encode_vectored(&messages[0], &mut dst).unwrap();
+		assert!(payload.is_none());
+		assert_eq!(dst.len(), messages[0].message_size(true));
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_server_encode_broadcast(b: &mut ::test::Bencher) {
+		let message = ::std::sync::Arc::new(OwnedMessage::Binary(vec![42; 1 << 20]));
+		let mut codec = MessageCodec::new(Context::Server);
+		let mut dst = BytesMut::with_capacity(message.message_size(false));
+		b.bytes = 1 << 20;
+		b.iter(|| {
+			dst.clear();
+			codec.encode(message.clone(), &mut dst).unwrap();
+			::test::black_box(&dst);
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_server_encode_vectored_broadcast(b: &mut ::test::Bencher) {
+		let message = OwnedMessage::Binary(vec![42; 1 << 20]);
+		let mut codec = MessageCodec::<OwnedMessage>::new(Context::Server);
+		let mut dst = BytesMut::with_capacity(16);
+		b.bytes = 1 << 20;
+		b.iter(|| {
+			dst.clear();
+			let payload = codec.encode_vectored(&message, &mut dst).unwrap();
+			::test::black_box((&dst, payload));
+		});
+	}
+
+	#[cfg(feature = "nightly")]
+	#[bench]
+	fn bench_client_encode_small(b: &mut ::test::Bencher) {
+		let message = Message::binary(&b"hello websocket"[..]);
+		let mut codec = MessageCodec::new(Context::Client);
+		let mut dst = BytesMut::with_capacity(1024);
+		b.iter(|| {
+			dst.clear();
+			codec.encode(message.clone(), &mut dst).unwrap();
+			::test::black_box(&dst);
+		});
+	}
+
+	#[test]
+	fn message_codec_fragments_outgoing_messages() {
+		let messages = [
+			OwnedMessage::Binary((0..250).collect()),
+			// the split happens in the middle of a character
+			OwnedMessage::Text("€".repeat(50)),
+			OwnedMessage::Binary(vec![1; 100]),
+			OwnedMessage::Binary(vec![]),
+			OwnedMessage::Ping(vec![2; 125]),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1000,
+				reason: "r".repeat(123),
+			})),
+		];
+		// number of frames each message should be split into
+		let frames = [3, 2, 1, 1, 1, 1];
+
+		for &(encoder, decoder) in [
+			(Context::Client, Context::Server),
+			(Context::Server, Context::Client),
+		]
+		.iter()
+		{
+			let mut encoder = MessageCodecBuilder::new(encoder).fragment_size(100).build();
+			let mut dst = BytesMut::new();
+			for message in messages.iter() {
+				encoder.encode(message.clone(), &mut dst).unwrap();
+			}
+
+			let mut dataframes = DataFrameCodec::default(decoder);
+			let mut src = dst.clone();
+			for &count in frames.iter() {
+				for i in 0..count {
+					let frame = dataframes.decode(&mut src).unwrap().unwrap();
+					assert_eq!(frame.finished, i == count - 1);
+					assert!(frame.data.len() <= 100 || frame.opcode.is_control());
+					assert_eq!(i > 0, frame.opcode == Opcode::Continuation);
+				}
+			}
+			assert!(src.is_empty());
+
+			let mut decoder = MessageCodec::<OwnedMessage>::new(decoder);
+			for message in messages.iter() {
+				assert_eq!(decoder.decode(&mut dst).unwrap().as_ref(), Some(message));
+			}
+		}
+	}
+
+	#[test]
+	fn message_chunk_codec_streams_payloads() {
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Binary, vec![1, 2, 3, 4, 5])
+			.write_to(&mut input, true)
+			.unwrap();
+		Message::ping(vec![9, 9])
+			.serialize(&mut input, true)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![6, 7])
+			.write_to(&mut input, true)
+			.unwrap();
+		Message::text("").serialize(&mut input, true).unwrap();
+
+		let mut codec = MessageChunkCodec::default(Context::Server);
+		let mut src = BytesMut::new();
+		let mut chunks = Vec::new();
+		// feed the data byte per byte, the payload comes out as it arrives
+		for byte in input {
+			src.extend_from_slice(&[byte]);
+			while let Some(chunk) = codec.decode(&mut src).unwrap() {
+				chunks.push(chunk);
+			}
+		}
+
+		let mut expected = vec![MessageChunk::Start(Type::Binary)];
+		for i in 1..8 {
+			if i == 6 {
+				expected.push(MessageChunk::Control(OwnedMessage::Ping(vec![9, 9])));
+			}
+			expected.push(MessageChunk::Data(Bytes::from(vec![i])));
+		}
+		expected.push(MessageChunk::End);
+		expected.push(MessageChunk::Start(Type::Text));
+		expected.push(MessageChunk::End);
+		assert_eq!(chunks, expected);
+	}
+
+	#[test]
+	fn message_chunk_codec_rejects_bad_sequences() {
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Continuation, vec![1])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageChunkCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::UnexpectedContinuation)) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![1])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Binary, vec![1])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageChunkCodec::default(Context::Client);
+		let mut src = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(MessageChunk::Start(Type::Text))
+		);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(MessageChunk::Data(Bytes::from(vec![1])))
+		);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::Protocol(ProtocolError::ExpectedContinuation(0x2))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut codec = MessageChunkCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(&[0x83, 0x00][..])) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(0x3))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// the reserved bits of control frames are checked as well
+		let mut codec = MessageChunkCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(&[0x99, 0x00][..])) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedBits(0x10))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_rejects_non_minimal_lengths() {
+		let mut input = vec![0x82, 0x7E, 0x00, 0x05];
+		input.extend_from_slice(&[0; 5]);
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::NonMinimalLength(5))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut input = vec![0x82, 0x7F, 0, 0, 0, 0, 0, 0, 0x01, 0x00];
+		input.extend_from_slice(&[0; 256]);
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::NonMinimalLength(256))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let input = vec![0x82, 0x7F, 0xFF, 0, 0, 0, 0, 0, 0, 0];
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidLength(_))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_send_buffer_limit() {
+		let mut codec = MessageCodecBuilder::new(Context::Server)
+			.send_buffer_limit(10)
+			.build();
+		let mut dst = BytesMut::new();
+		codec
+			.encode(OwnedMessage::Binary(vec![0; 20]), &mut dst)
+			.unwrap();
+		assert_eq!(codec.queued_bytes(), 22);
+
+		assert!(codec.send_buffer_full(&dst));
+		match codec.encode(OwnedMessage::Text("full".to_string()), &mut dst) {
+			Err(WebSocketError::SendBufferFull) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		assert_eq!(dst.len(), 22);
+		// control messages still go out
+		codec.encode(OwnedMessage::Pong(vec![1]), &mut dst).unwrap();
+		codec.encode(OwnedMessage::Close(None), &mut dst).unwrap();
+		assert_eq!(codec.queued_bytes(), 22 + 3 + 2);
+
+		// the transport wrote the buffer
+		dst.clear();
+		assert!(!codec.send_buffer_full(&dst));
+		codec
+			.encode(OwnedMessage::Text("ok".to_string()), &mut dst)
+			.unwrap();
+		assert_eq!(codec.queued_bytes(), 4);
+
+		let frame = DataFrame::new(true, Opcode::Binary, vec![0; 20]);
+		codec.encode_vectored(&frame, &mut dst).unwrap();
+		match codec.encode_vectored(&frame, &mut dst) {
+			Err(WebSocketError::SendBufferFull) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_into_raw_parts() {
+		let mut input = Vec::new();
+		OwnedMessage::Text("switch".to_string())
+			.serialize(&mut input, false)
+			.unwrap();
+		let mut second = Vec::new();
+		OwnedMessage::Binary(vec![7; 20])
+			.serialize(&mut second, false)
+			.unwrap();
+		input.extend_from_slice(&second[..10]);
+
+		let framed = MessageCodec::default(Context::Client)
+			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])));
+		let (message, mut framed) = framed.into_future().wait().map_err(|e| e.0).unwrap();
+		assert_eq!(message, Some(OwnedMessage::Text("switch".to_string())));
+		assert!(!framed.codec().in_message());
+		assert!(framed
+			.start_send(OwnedMessage::Close(None))
+			.unwrap()
+			.is_ready());
+
+		let (io, read_buf, write_buf) = into_raw_parts(framed);
+		assert_eq!(&read_buf[..], &second[..10]);
+		// a masked close message
+		assert_eq!(write_buf.len(), 6);
+		assert_eq!(&write_buf[..2], &[0x88, 0x80][..]);
+		// nothing was written to the stream yet
+		assert!(io.1.get_ref().is_empty());
+	}
+
+	#[test]
+	fn message_codec_into_raw_parts_after_close() {
+		let mut input = Vec::new();
+		OwnedMessage::Close(None)
+			.serialize(&mut input, false)
+			.unwrap();
+		let close_len = input.len();
+		input.extend_from_slice(b"raw protocol");
+
+		let mut codec = MessageCodec::default(Context::Client);
+		let mut src = BytesMut::from(&input[..]);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Close(None))
+		);
+		assert_eq!(codec.decode(&mut src).unwrap(), None);
+		assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+		assert_eq!(&src[..], &input[close_len..]);
+
+		let framed = MessageCodec::default(Context::Client)
+			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])));
+		let (message, framed) = framed.into_future().wait().map_err(|e| e.0).unwrap();
+		assert_eq!(message, Some(OwnedMessage::Close(None)));
+		let (_, read_buf, _) = into_raw_parts(framed);
+		assert_eq!(&read_buf[..], &b"raw protocol"[..]);
+	}
+
+	#[test]
+	fn message_codec_decode_eof() {
+		let mut input = Vec::new();
+		OwnedMessage::Binary(vec![1; 10])
+			.serialize(&mut input, false)
+			.unwrap();
+
+		// in the middle of the header and of the payload
+		for &len in &[1, 5] {
+			let mut codec = MessageCodec::default(Context::Client);
+			let mut src = BytesMut::from(&input[..len]);
+			assert_eq!(codec.decode(&mut src).unwrap(), None);
+			match codec.decode_eof(&mut src) {
+				Err(WebSocketError::IncompleteFrame) => (),
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+
+		// the last message before the end is still decoded
+		let mut codec = MessageCodec::default(Context::Client);
+		let mut src = BytesMut::from(&input[..]);
+		assert_eq!(
+			codec.decode_eof(&mut src).unwrap(),
+			Some(OwnedMessage::Binary(vec![1; 10]))
+		);
+		match codec.decode_eof(&mut src) {
+			Err(WebSocketError::ConnectionDropped) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// after the closing handshake
+		OwnedMessage::Close(None)
+			.serialize(&mut input, false)
+			.unwrap();
+		let mut codec = MessageCodec::default(Context::Client);
+		let mut src = BytesMut::from(&input[..]);
+		assert!(codec.decode(&mut src).unwrap().is_some());
+		assert_eq!(
+			codec.decode_eof(&mut src).unwrap(),
+			Some(OwnedMessage::Close(None))
+		);
+		assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+	}
+
+	#[test]
+	fn message_codec_ends_after_close() {
+		let close = OwnedMessage::Close(Some(CloseData::new(1008, "policy".to_string())));
+		let mut input = Vec::new();
+		close.serialize(&mut input, false).unwrap();
+
+		// nothing after the close, the middle of the next header, an invalid
+		// dataframe or a valid message which shouldn't have been sent
+		let mut message = Vec::new();
+		OwnedMessage::Text("late".to_string())
+			.serialize(&mut message, false)
+			.unwrap();
+		let garbage: [&[u8]; 4] = [&[], &[0x82, 0x7E, 0x01], &[0x83, 0x00], &message];
+		for garbage in garbage.iter() {
+			let mut input = input.clone();
+			input.extend_from_slice(garbage);
+			let framed = MessageCodec::default(Context::Client)
+				.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])));
+			let mut stream = Stream::wait(framed);
+			assert_eq!(stream.next().unwrap().unwrap(), close);
+			match stream.next() {
+				None => (),
+				other => panic!("unexpected result {:?}", other),
+			}
+			let framed = stream.into_inner();
+			assert!(framed.codec().close_received());
+			assert_eq!(
+				framed.codec().received_close_data(),
+				Some(&CloseData::new(1008, "policy".to_string()))
+			);
+		}
+
+		let mut codec = MessageCodec::default(Context::Client);
+		assert!(!codec.close_received());
+		let mut input = Vec::new();
+		OwnedMessage::Close(None)
+			.serialize(&mut input, false)
+			.unwrap();
+		let mut src = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Close(None))
+		);
+		assert!(codec.close_received());
+		assert_eq!(codec.received_close_data(), None);
+	}
+
+	#[test]
+	fn message_codec_checks_masking() {
+		let frame = DataFrame::new(true, Opcode::Binary, b"data".to_vec());
+		let mut masked = Vec::new();
+		frame.write_to(&mut masked, true).unwrap();
+		let mut unmasked = Vec::new();
+		frame.write_to(&mut unmasked, false).unwrap();
+		let ping = OwnedMessage::Ping(b"ping".to_vec());
+		let mut unmasked_ping = Vec::new();
+		ping.serialize(&mut unmasked_ping, false).unwrap();
+
+		let message = OwnedMessage::Binary(b"data".to_vec());
+		let mut server = MessageCodec::default(Context::Server);
+		let mut src = BytesMut::from(&masked[..]);
+		assert_eq!(server.decode(&mut src).unwrap(), Some(message.clone()));
+		for &(input, opcode) in &[(&unmasked, 0x2), (&unmasked_ping, 0x9)] {
+			let mut server = MessageCodec::default(Context::Server);
+			match server.decode(&mut BytesMut::from(&input[..])) {
+				Err(WebSocketError::Protocol(ProtocolError::UnexpectedMask {
+					opcode: o,
+					masked: false,
+				})) => assert_eq!(o, opcode),
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+
+		// opting out for clients which don't mask
+		let mut server = MessageCodecBuilder::new(Context::Server)
+			.strict_masking(false)
+			.build::<OwnedMessage>();
+		let mut src = BytesMut::from(&unmasked[..]);
+		src.extend_from_slice(&unmasked_ping);
+		src.extend_from_slice(&masked);
+		assert_eq!(server.decode(&mut src).unwrap(), Some(message.clone()));
+		assert_eq!(server.decode(&mut src).unwrap(), Some(ping));
+		assert_eq!(server.decode(&mut src).unwrap(), Some(message.clone()));
+
+		let mut client = MessageCodec::default(Context::Client);
+		let mut src = BytesMut::from(&unmasked[..]);
+		assert_eq!(client.decode(&mut src).unwrap(), Some(message.clone()));
+		let mut client = MessageCodec::default(Context::Client);
+		match client.decode(&mut BytesMut::from(&masked[..])) {
+			Err(WebSocketError::Protocol(ProtocolError::UnexpectedMask {
+				opcode: 0x2,
+				masked: true,
+			})) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// talking to a server which masks its frames
+		let mut client = MessageCodecBuilder::new(Context::Client)
+			.strict_masking(false)
+			.build::<OwnedMessage>();
+		let mut src = BytesMut::from(&masked[..]);
+		src.extend_from_slice(&unmasked);
+		assert_eq!(client.decode(&mut src).unwrap(), Some(message.clone()));
+		assert_eq!(client.decode(&mut src).unwrap(), Some(message));
+
+		let mut chunks = MessageChunkCodec::default(Context::Server);
+		match chunks.decode(&mut BytesMut::from(&unmasked[..])) {
+			Err(WebSocketError::Protocol(ProtocolError::UnexpectedMask { .. })) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		let error = WebSocketError::from(ProtocolError::UnexpectedMask {
+			opcode: 0x9,
+			masked: true,
+		});
+		assert_eq!(
+			error.to_string(),
+			"WebSocketError: WebSocket protocol violation: masked dataframe received (opcode \
+			 0x9)"
+		);
+		assert_eq!(error.close_code(), Some(CloseCode::ProtocolError));
+	}
+
+	#[test]
+	fn dataframe_codec_handles_huge_lengths() {
+		// a binary frame claiming a u64::MAX byte payload
+		let input = vec![
+			0x82, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2,
+		];
+		let mut codec = DataFrameCodec::default(Context::Client);
+		let mut src = BytesMut::from(input);
+		// either an error or waiting for more data, but never a panic
+		match codec.decode(&mut src) {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidLength(_))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn decode_errors_are_fatal() {
+		// what a server could send which a client must not accept
+		let inputs: &[&[u8]] = &[
+			// a reserved opcode and reserved bits
+			&[0x83, 0x00],
+			&[0xC2, 0x00],
+			// a fragmented ping and a ping with more than 125 bytes
+			&[0x09, 0x00],
+			&[0x89, 0x7E, 0x00, 0x7E],
+			// lengths which aren't minimally encoded or way too large
+			&[0x82, 0x7E, 0x00, 0x05, 1, 2, 3, 4, 5],
+			&[0x82, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+			&[0x82, 0x7F, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+			// a masked frame
+			&[0x82, 0x81, 1, 2, 3, 4, 5],
+			// continuations which don't belong to a message (or are missing)
+			&[0x80, 0x00],
+			&[0x01, 0x00, 0x82, 0x00],
+			// invalid UTF-8 and invalid close payloads
+			&[0x81, 0x01, 0xFF],
+			&[0x88, 0x02, 0x03, 0xED],
+			&[0x88, 0x01, 0x00],
+		];
+		for &input in inputs {
+			let mut frames = DataFrameCodec::default(Context::Client);
+			let mut messages = MessageCodec::<OwnedMessage>::default(Context::Client);
+			let mut chunks = MessageChunkCodec::default(Context::Client);
+			let results = [
+				decode_all(&mut frames, input),
+				decode_all(&mut messages, input),
+				decode_all(&mut chunks, input),
+				DataFrame::parse(input).map(|_| ()),
+				DataFrame::read_dataframe(&mut &input[..], false).map(|_| ()),
+			];
+			for result in results.iter() {
+				if let Err(ref e) = *result {
+					assert!(e.is_fatal(), "{:?} for {:?}", e, input);
+				}
+			}
+			let failed = results.iter().any(|result| result.is_err());
+			assert!(failed, "{:?} was accepted", input);
+		}
+	}
+
+	// decodes everything in `input`, discarding the results
+	fn decode_all<C>(codec: &mut C, input: &[u8]) -> WebSocketResult<()>
+	where
+		C: Decoder<Error = WebSocketError>,
+	{
+		let mut src = BytesMut::from(input);
+		while codec.decode(&mut src)?.is_some() {}
+		Ok(())
+	}
+
+	#[test]
+	fn dataframe_codec_rejects_reserved_bits() {
+		let mut frame = DataFrame::new(true, Opcode::Binary, vec![1, 2]);
+		frame.reserved = [true, false, false];
+		let mut input = Vec::new();
+		frame.write_to(&mut input, false).unwrap();
+
+		let mut codec = DataFrameCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input.clone())) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedBits(0x40))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		let mut codec = DataFrameCodec::default(Context::Client);
+		codec.set_allowed_reserved_bits([true, false, false]);
+		assert_eq!(codec.allowed_reserved_bits(), [true, false, false]);
+		let decoded = codec.decode(&mut BytesMut::from(input)).unwrap();
+		assert_eq!(decoded, Some(frame));
+
+		let mut frame = DataFrame::new(true, Opcode::Ping, vec![]);
+		frame.reserved = [false, true, false];
+		let mut input = Vec::new();
+		frame.write_to(&mut input, false).unwrap();
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedBits(0x20))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn message_codec_rejects_invalid_control_frames() {
+		// ping with 126 bytes of payload, the header alone is enough to fail
+		let mut input = vec![0x89, 0x7E, 0x00, 0x7E];
+		input.extend_from_slice(&[0; 126]);
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(&input[..4])) {
+			Err(WebSocketError::Protocol(ProtocolError::ControlFrameTooLarge(126))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// fragmented close
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Close, vec![0x03, 0xE8])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(&input[..])) {
+			Err(e @ WebSocketError::Protocol(ProtocolError::FragmentedControlFrame(0x8))) => {
+				assert!(e.to_string().ends_with("(opcode 0x8)"))
+			}
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// the same close with FIN is fine
+		input[0] |= 0x80;
+		let mut codec = MessageCodec::default(Context::Client);
+		assert_eq!(
+			codec.decode(&mut BytesMut::from(input)).unwrap(),
+			Some(OwnedMessage::Close(Some(CloseData::new(
+				1000,
+				String::new()
+			))))
+		);
+	}
+
+	#[test]
+	fn message_codec_refuses_to_encode_invalid_control_messages() {
+		let messages = vec![
+			OwnedMessage::Ping(vec![0; 126]),
+			OwnedMessage::Pong(vec![0; 1000]),
+			OwnedMessage::Close(Some(CloseData {
+				status_code: 1000,
+				reason: "r".repeat(124),
+			})),
+		];
+
+		for message in messages {
+			assert!(message.serialize(&mut Vec::new(), false).is_err());
 
-			let unmasked_predicted = message.message_size(false);
-			let mut unmasked_buf = Vec::new();
-			message.serialize(&mut unmasked_buf, false).unwrap();
-			assert_eq!(unmasked_buf.len(), unmasked_predicted);
+			for &fragment_size in [None, Some(10)].iter() {
+				let mut codec = MessageCodec::new(Context::Client);
+				codec.set_fragment_size(fragment_size);
+				let mut dst = BytesMut::new();
+				match codec.encode(message.clone(), &mut dst) {
+					Err(WebSocketError::DataFrameError(_)) => (),
+					other => panic!("unexpected result {:?}", other),
+				}
+				// nothing invalid made it into the output
+				assert!(dst.is_empty());
+			}
 		}
+
+		// the limits themselves are fine
+		let mut codec = MessageCodec::new(Context::Client);
+		let mut dst = BytesMut::new();
+		codec
+			.encode(OwnedMessage::Ping(vec![0; 125]), &mut dst)
+			.unwrap();
+		let close = OwnedMessage::Close(Some(CloseData {
+			status_code: 1000,
+			reason: "r".repeat(123),
+		}));
+		codec.encode(close, &mut dst).unwrap();
 	}
 
 	#[test]
-	fn cow_message_predicts_size() {
-		let messages = vec![
-			Message::binary(vec![1, 2, 3, 4]),
-			Message::binary(vec![42; 256]),
-			Message::binary(vec![42; 65535]),
-			Message::binary(vec![42; 65555]),
-			Message::text("nilbog".to_string()),
-			Message::ping("beep".to_string().into_bytes()),
-			Message::pong("boop".to_string().into_bytes()),
-			Message::close(),
-			Message::close_because(64, "because"),
+	fn message_codec_validates_text_incrementally() {
+		// a 4 byte code point split across two frames
+		let text = "😀".as_bytes();
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, text[..1].to_vec())
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, text[1..].to_vec())
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageCodec::default(Context::Client);
+		assert_eq!(
+			codec.decode(&mut BytesMut::from(input)).unwrap(),
+			Some(OwnedMessage::Text("😀".to_string()))
+		);
+
+		// a lone continuation byte fails before the message is complete
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![b'a', 0x80])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidUtf8(_))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// a code point which is never completed
+		let mut input = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![b'a'])
+			.write_to(&mut input, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![0xE2, 0x82])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidUtf8(_))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// binary messages are not validated
+		let mut input = Vec::new();
+		DataFrame::new(true, Opcode::Binary, vec![0x80])
+			.write_to(&mut input, false)
+			.unwrap();
+		let mut codec = MessageCodec::default(Context::Client);
+		assert_eq!(
+			codec.decode(&mut BytesMut::from(input)).unwrap(),
+			Some(OwnedMessage::Binary(vec![0x80]))
+		);
+	}
+
+	#[test]
+	fn message_codec_falls_back_on_invalid_utf8() {
+		let mut single = Vec::new();
+		DataFrame::new(true, Opcode::Text, vec![b'a', 0xFF, b'b'])
+			.write_to(&mut single, false)
+			.unwrap();
+		let mut fragmented = Vec::new();
+		DataFrame::new(false, Opcode::Text, vec![b'a', 0xE2])
+			.write_to(&mut fragmented, false)
+			.unwrap();
+		DataFrame::new(true, Opcode::Continuation, vec![b'b'])
+			.write_to(&mut fragmented, false)
+			.unwrap();
+
+		let cases = [
+			(single, vec![b'a', 0xFF, b'b']),
+			(fragmented, vec![b'a', 0xE2, b'b']),
 		];
+		for &(ref input, ref expected) in cases.iter() {
+			let mut codec = MessageCodec::default(Context::Client);
+			match codec.decode(&mut BytesMut::from(&input[..])) {
+				Err(WebSocketError::Protocol(ProtocolError::InvalidUtf8(_))) => (),
+				other => panic!("unexpected result {:?}", other),
+			}
 
-		for message in messages.iter() {
-			let masked_predicted = message.message_size(true);
-			let mut masked_buf = Vec::new();
-			message.serialize(&mut masked_buf, true).unwrap();
-			assert_eq!(masked_buf.len(), masked_predicted);
+			let mut codec: MessageCodec<OwnedMessage> = MessageCodecBuilder::new(Context::Client)
+				.invalid_utf8(InvalidUtf8::Binary)
+				.build();
+			// a valid message afterwards is not affected
+			let mut src = BytesMut::from(&input[..]);
+			Message::text("ok")
+				.serialize(&mut (&mut src).writer(), false)
+				.unwrap();
+			assert_eq!(
+				codec.decode(&mut src).unwrap(),
+				Some(OwnedMessage::Binary(expected.clone()))
+			);
+			assert_eq!(
+				codec.decode(&mut src).unwrap(),
+				Some(OwnedMessage::Text("ok".to_string()))
+			);
 
-			let unmasked_predicted = message.message_size(false);
-			let mut unmasked_buf = Vec::new();
-			message.serialize(&mut unmasked_buf, false).unwrap();
-			assert_eq!(unmasked_buf.len(), unmasked_predicted);
+			let mut codec = MessageCodec::default(Context::Client);
+			codec.set_invalid_utf8(InvalidUtf8::Lossy);
+			assert_eq!(
+				codec.decode(&mut BytesMut::from(&input[..])).unwrap(),
+				Some(OwnedMessage::Text("a\u{FFFD}b".to_string()))
+			);
 		}
 	}
 
 	#[test]
-	fn message_codec_client_send_receive() {
+	fn message_codec_rejects_reserved_opcodes() {
+		for opcode in (3..8).chain(11..16) {
+			let mut codec = MessageCodec::default(Context::Client);
+			let mut src = BytesMut::from(vec![0x80 | opcode, 0x01, 0x00]);
+			match codec.decode(&mut src) {
+				Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(o))) => {
+					assert_eq!(o, opcode)
+				}
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+
+		// also in the middle of a fragmented message
 		let mut input = Vec::new();
-		Message::text("50 schmeckels")
-			.serialize(&mut input, false)
+		DataFrame::new(false, Opcode::Text, vec![b'a'])
+			.write_to(&mut input, false)
 			.unwrap();
+		input.extend_from_slice(&[0x8B, 0x00]);
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(input)) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(0xB))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
 
-		let f = MessageCodec::new(Context::Client)
-			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])))
-			.into_future()
-			.map_err(|e| e.0)
-			.map(|(m, s)| {
-				assert_eq!(m, Some(OwnedMessage::Text("50 schmeckels".to_string())));
-				s
-			})
-			.and_then(|s| s.send(Message::text("ethan bradberry")))
-			.and_then(|s| {
-				let mut stream = s.into_parts().io;
-				stream.1.set_position(0);
-				println!("buffer: {:?}", stream.1);
-				MessageCodec::default(Context::Server)
-					.framed(ReadWritePair(stream.1, stream.0))
-					.into_future()
-					.map_err(|e| e.0)
-					.map(|(message, _)| {
-						assert_eq!(message, Some(Message::text("ethan bradberry").into()))
-					})
-			});
+	fn compress_all() -> DeflateConfig {
+		DeflateConfig {
+			compress_threshold: 0,
+			..DeflateConfig::default()
+		}
+	}
 
-		use tokio;
-		tokio::runtime::Builder::new()
+	fn deflate_codec<M: MessageTrait>(context: Context) -> MessageCodec<M> {
+		MessageCodecBuilder::new(context)
+			.mask_outgoing(false)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
 			.build()
-			.unwrap()
-			.block_on(f)
+	}
+
+	#[test]
+	fn message_codec_deflate_encode() {
+		// the example of RFC 7692 section 7.2.3.3
+		let mut codec = deflate_codec(Context::Server);
+		let mut dst = BytesMut::new();
+		codec.encode(Message::text("Hello"), &mut dst).unwrap();
+		assert_eq!(
+			&dst[..],
+			&[0xc1, 0x0b, 0x00, 0x05, 0x00, 0xfa, 0xff, b'H', b'e', b'l', b'l', b'o', 0x00][..]
+		);
+
+		// control messages are never compressed
+		dst.clear();
+		codec.encode(Message::ping(&b"ping"[..]), &mut dst).unwrap();
+		assert_eq!(&dst[..], &[0x89, 0x04, b'p', b'i', b'n', b'g'][..]);
+
+		// only the first fragment has RSV1 set
+		let mut codec = MessageCodecBuilder::new(Context::Server)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
+			.fragment_size(6)
+			.build();
+		dst.clear();
+		codec
+			.encode(Message::binary(&b"Hello"[..]), &mut dst)
 			.unwrap();
+		assert_eq!(
+			&dst[..],
+			&[
+				0x42, 0x06, 0x00, 0x05, 0x00, 0xfa, 0xff, b'H', 0x80, 0x05, b'e', b'l', b'l', b'o',
+				0x00,
+			][..]
+		);
+
+		// an empty message
+		dst.clear();
+		codec.encode(Message::binary(Vec::new()), &mut dst).unwrap();
+		assert_eq!(&dst[..], &[0xc2, 0x01, 0x00][..]);
 	}
 
 	#[test]
-	fn message_codec_server_send_receive() {
-		use tokio;
-		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
-		let mut input = Vec::new();
-		Message::text("50 schmeckels")
-			.serialize(&mut input, true)
+	fn message_codec_deflate_round_trip() {
+		let messages = vec![
+			OwnedMessage::Text("Hello".to_string()),
+			OwnedMessage::Binary(vec![7; 100_000]),
+			OwnedMessage::Ping(b"ping".to_vec()),
+			OwnedMessage::Text(String::new()),
+			OwnedMessage::Close(None),
+		];
+
+		let mut client = MessageCodecBuilder::new(Context::Client)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
+			.fragment_size(1000)
+			.build::<OwnedMessage>();
+		let mut server = deflate_codec::<OwnedMessage>(Context::Server);
+		let mut wire = BytesMut::new();
+		for message in &messages {
+			client.encode(message.clone(), &mut wire).unwrap();
+		}
+		for message in messages {
+			assert_eq!(server.decode(&mut wire).unwrap(), Some(message));
+		}
+		assert!(wire.is_empty());
+
+		// uncompressed messages are still accepted
+		let mut server = deflate_codec::<OwnedMessage>(Context::Server);
+		let mut wire = BytesMut::new();
+		MessageCodec::new(Context::Client)
+			.encode(Message::text("plain"), &mut wire)
 			.unwrap();
+		assert_eq!(
+			server.decode(&mut wire).unwrap(),
+			Some(OwnedMessage::Text("plain".to_string()))
+		);
+	}
 
-		let f = MessageCodec::new(Context::Server)
-			.framed(ReadWritePair(Cursor::new(input), Cursor::new(vec![])))
-			.into_future()
-			.map_err(|e| e.0)
-			.map(|(m, s)| {
-				assert_eq!(m, Some(OwnedMessage::Text("50 schmeckels".to_string())));
-				s
-			})
-			.and_then(|s| s.send(Message::text("ethan bradberry")))
-			.map(|s| {
-				let mut written = vec![];
-				Message::text("ethan bradberry")
-					.serialize(&mut written, false)
-					.unwrap();
-				assert_eq!(written, s.into_parts().io.1.into_inner());
-			});
+	#[test]
+	fn message_codec_deflate_fragmented_input() {
+		// a compressed message split into two dataframes with a ping in between
+		let input = vec![
+			0x41, 0x03, 0x00, 0x05, 0x00, 0x89, 0x00, 0x80, 0x08, 0xfa, 0xff, b'H', b'e', b'l',
+			b'l', b'o', 0x00,
+		];
+		let mut codec = deflate_codec::<OwnedMessage>(Context::Client);
+		let mut src = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Ping(Vec::new()))
+		);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Text("Hello".to_string()))
+		);
 
-		runtime.block_on(f).unwrap();
+		// the limits apply to the decompressed size
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
+			.max_message_size(4)
+			.build::<OwnedMessage>();
+		let mut src = BytesMut::from(
+			&[
+				0xc1, 0x0b, 0x00, 0x05, 0x00, 0xfa, 0xff, b'H', b'e', b'l', b'l', b'o', 0x00,
+			][..],
+		);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	// Inflates every byte of its input (but the trailer) to that many KiB of
+	// zeros, like a highly compressed message would, and counts how much it
+	// inflated.
+	#[derive(Default)]
+	struct Zeros {
+		inflated: Arc<AtomicU64>,
+	}
+
+	impl Decompressor for Zeros {
+		fn decompress(
+			&mut self,
+			input: &[u8],
+			output: &mut Vec<u8>,
+			limit: usize,
+		) -> io::Result<()> {
+			if input == DEFLATE_TRAILER {
+				return Ok(());
+			}
+			let start = output.len();
+			for &kib in input {
+				for _ in 0..kib {
+					if output.len() - start > limit {
+						return Ok(());
+					}
+					output.extend_from_slice(&[0; 1024]);
+					self.inflated.fetch_add(1024, Ordering::Relaxed);
+				}
+			}
+			Ok(())
+		}
+
+		fn reset(&mut self) {}
+	}
+
+	#[test]
+	fn message_codec_deflate_limits_inflation() {
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.deflate(compress_all(), Stored, Zeros::default())
+			.max_message_size(4096)
+			.build::<OwnedMessage>();
+		let mut src = BytesMut::from(&[0xc2, 0x01, 0x04][..]);
+		assert_eq!(
+			codec.decode(&mut src).unwrap(),
+			Some(OwnedMessage::Binary(vec![0; 4096]))
+		);
+
+		// one byte over the limit
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.deflate(compress_all(), Stored, Zeros::default())
+			.max_message_size(4095)
+			.build::<OwnedMessage>();
+		let mut src = BytesMut::from(&[0xc2, 0x01, 0x04][..]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// 4 bytes which would inflate to almost 1 MiB are given up on right
+		// after the limit, also in a fragment after the first one
+		let zeros = Zeros::default();
+		let inflated = zeros.inflated.clone();
+		let mut codec = MessageCodecBuilder::new(Context::Client)
+			.deflate(compress_all(), Stored, zeros)
+			.max_message_size(4096)
+			.build::<OwnedMessage>();
+		let mut src = BytesMut::from(&[0x42, 0x01, 0x02, 0x80, 0x04, 0xff, 0xff, 0xff, 0xff][..]);
+		match codec.decode(&mut src) {
+			Err(WebSocketError::MessageTooLarge) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		assert!(inflated.load(Ordering::Relaxed) <= 4096 + 1024);
+	}
+
+	#[test]
+	fn message_chunk_codec_without_strict_masking() {
+		// an unmasked ping and a masked one, in the wrong direction each
+		let mut unmasked = Vec::new();
+		Message::ping(vec![1, 2])
+			.serialize(&mut unmasked, false)
+			.unwrap();
+		let mut masked = Vec::new();
+		Message::ping(vec![1, 2])
+			.serialize(&mut masked, true)
+			.unwrap();
+
+		for &(context, ref input) in &[(Context::Server, unmasked), (Context::Client, masked)] {
+			let mut codec = MessageChunkCodec::default(context);
+			match codec.decode(&mut BytesMut::from(&input[..])) {
+				Err(WebSocketError::Protocol(ProtocolError::UnexpectedMask { .. })) => (),
+				other => panic!("unexpected result {:?}", other),
+			}
+
+			let mut codec = MessageChunkCodec::default(context);
+			codec.codec_mut().set_strict_masking(false);
+			assert_eq!(
+				codec.decode(&mut BytesMut::from(&input[..])).unwrap(),
+				Some(MessageChunk::Control(OwnedMessage::Ping(vec![1, 2])))
+			);
+		}
+	}
+
+	#[test]
+	fn message_chunk_codec_deflate() {
+		let mut server = MessageCodecBuilder::new(Context::Server)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
+			.fragment_size(8)
+			.build::<OwnedMessage>();
+		let mut input = BytesMut::new();
+		server
+			.encode(OwnedMessage::Text("Hello world".to_string()), &mut input)
+			.unwrap();
+		server
+			.encode(OwnedMessage::Ping(vec![1]), &mut input)
+			.unwrap();
+		assert_eq!(input[0], 0x41);
+
+		let mut codec =
+			MessageChunkCodec::from_codec(deflate_codec::<OwnedMessage>(Context::Client));
+		let mut src = BytesMut::new();
+		let mut chunks = Vec::new();
+		for &byte in input.iter() {
+			src.extend_from_slice(&[byte]);
+			while let Some(chunk) = codec.decode(&mut src).unwrap() {
+				chunks.push(chunk);
+			}
+		}
+		assert_eq!(
+			chunks,
+			vec![
+				MessageChunk::Start(Type::Text),
+				MessageChunk::Data(Bytes::from(&b"Hello world"[..])),
+				MessageChunk::End,
+				MessageChunk::Control(OwnedMessage::Ping(vec![1])),
+			]
+		);
+
+		let inputs: Vec<&[u8]> = vec![
+			// compressed ping
+			&[0xc9, 0x00],
+			// compressed continuation
+			&[0x01, 0x01, 0x00, 0xc0, 0x00],
+			// RSV2 is still not allowed
+			&[0xa1, 0x00],
+		];
+		for input in inputs {
+			let codec = deflate_codec::<OwnedMessage>(Context::Client);
+			let mut codec = MessageChunkCodec::from_codec(codec);
+			let mut src = BytesMut::from(input);
+			let result = loop {
+				match codec.decode(&mut src) {
+					Ok(Some(_)) => (),
+					other => break other,
+				}
+			};
+			match result {
+				Err(WebSocketError::Protocol(ProtocolError::UnexpectedCompression(_)))
+				| Err(WebSocketError::Protocol(ProtocolError::ReservedBits(0x20))) => (),
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+	}
+
+	#[test]
+	fn message_codec_deflate_rejects_misplaced_rsv1() {
+		let inputs: Vec<&[u8]> = vec![
+			// compressed ping
+			&[0xc9, 0x00],
+			// compressed continuation
+			&[0x01, 0x01, 0x00, 0xc0, 0x00],
+		];
+		for input in inputs {
+			let mut codec = deflate_codec::<OwnedMessage>(Context::Client);
+			match codec.decode(&mut BytesMut::from(input)) {
+				Err(WebSocketError::Protocol(ProtocolError::UnexpectedCompression(_))) => (),
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+
+		// without deflate RSV1 is never allowed
+		let mut codec = MessageCodec::default(Context::Client);
+		match codec.decode(&mut BytesMut::from(&[0xc1, 0x01, 0x00][..])) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedBits(0x40))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	// Replaces a message which is the same as the previous one with a
+	// reference to it, so it can only be decompressed with the context of
+	// the previous message.
+	#[derive(Default)]
+	struct Repeat {
+		last: Option<Vec<u8>>,
+		pending: Vec<u8>,
+	}
+
+	impl Compressor for Repeat {
+		fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+			if self.last.as_ref().map(|last| &last[..]) == Some(input) {
+				output.extend_from_slice(&[0xEE, 0x00, 0x00, 0x00, 0xff, 0xff]);
+			} else {
+				Stored.compress(input, output)?;
+			}
+			self.last = Some(input.to_vec());
+			Ok(())
+		}
+
+		fn reset(&mut self) {
+			self.last = None;
+		}
+	}
+
+	impl Decompressor for Repeat {
+		fn decompress(&mut self, input: &[u8], output: &mut Vec<u8>, _: usize) -> io::Result<()> {
+			self.pending.extend_from_slice(input);
+			if !self.pending.ends_with(&[0x00, 0x00, 0x00, 0xff, 0xff]) {
+				return Ok(());
+			}
+			let message = if self.pending[0] == 0xEE {
+				self.last
+					.clone()
+					.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing context"))?
+			} else {
+				let mut message = Vec::new();
+				StoredDecompressor::default().decompress(
+					&self.pending,
+					&mut message,
+					usize::MAX,
+				)?;
+				message
+			};
+			self.pending.clear();
+			output.extend_from_slice(&message);
+			self.last = Some(message);
+			Ok(())
+		}
+
+		fn reset(&mut self) {
+			self.last = None;
+		}
+	}
+
+	#[test]
+	fn message_codec_deflate_context_takeover() {
+		// the server doesn't take over its context, the client does
+		let config = DeflateConfig {
+			server_no_context_takeover: true,
+			..compress_all()
+		};
+		let mut client = MessageCodecBuilder::new(Context::Client)
+			.deflate(config, Repeat::default(), Repeat::default())
+			.build::<OwnedMessage>();
+		let mut server = MessageCodecBuilder::new(Context::Server)
+			.deflate(config, Repeat::default(), Repeat::default())
+			.build::<OwnedMessage>();
+		let message = OwnedMessage::Text("Hello".to_string());
+
+		// the second message refers to the first one, so the server has to
+		// keep its context
+		let mut wire = BytesMut::new();
+		client.encode(message.clone(), &mut wire).unwrap();
+		client.encode(message.clone(), &mut wire).unwrap();
+		// the second payload is only the 2 byte reference
+		assert_eq!(wire.len(), (6 + 11) + (6 + 2));
+		assert_eq!(server.decode(&mut wire).unwrap(), Some(message.clone()));
+		assert_eq!(server.decode(&mut wire).unwrap(), Some(message.clone()));
+
+		// the server compresses every message on its own, which the client
+		// decompresses after resetting its context
+		server.encode(message.clone(), &mut wire).unwrap();
+		let len = wire.len();
+		server.encode(message.clone(), &mut wire).unwrap();
+		assert_eq!(wire.len(), 2 * len);
+		assert_eq!(client.decode(&mut wire).unwrap(), Some(message.clone()));
+		assert_eq!(client.decode(&mut wire).unwrap(), Some(message.clone()));
+
+		// a server which wrongly expects the client not to take over its
+		// context can't decompress the second message
+		let mut server = MessageCodecBuilder::new(Context::Server)
+			.deflate(
+				DeflateConfig {
+					client_no_context_takeover: true,
+					..config
+				},
+				Repeat::default(),
+				Repeat::default(),
+			)
+			.build::<OwnedMessage>();
+		let message = OwnedMessage::Text("Bye".to_string());
+		client.encode(message.clone(), &mut wire).unwrap();
+		client.encode(message.clone(), &mut wire).unwrap();
+		assert_eq!(server.decode(&mut wire).unwrap(), Some(message));
+		assert!(server.decode(&mut wire).is_err());
+	}
+
+	#[test]
+	fn message_codec_deflate_threshold() {
+		let mut codec = MessageCodecBuilder::new(Context::Server)
+			.deflate(
+				DeflateConfig::default(),
+				Stored,
+				StoredDecompressor::default(),
+			)
+			.build::<OwnedMessage>();
+		let mut dst = BytesMut::new();
+		codec
+			.encode(OwnedMessage::Binary(vec![1; 63]), &mut dst)
+			.unwrap();
+		// sent uncompressed with RSV1 clear
+		assert_eq!(&dst[..2], &[0x82, 63][..]);
+		assert_eq!(dst.len(), 2 + 63);
+
+		dst.clear();
+		codec
+			.encode(OwnedMessage::Binary(vec![1; 64]), &mut dst)
+			.unwrap();
+		assert_eq!(&dst[..2], &[0xc2, 5 + 64 + 1][..]);
+
+		// the receiver handles both
+		let mut client = MessageCodecBuilder::new(Context::Client)
+			.deflate(
+				DeflateConfig::default(),
+				Stored,
+				StoredDecompressor::default(),
+			)
+			.build::<OwnedMessage>();
+		codec
+			.encode(OwnedMessage::Text("tiny".to_string()), &mut dst)
+			.unwrap();
+		assert_eq!(
+			client.decode(&mut dst).unwrap(),
+			Some(OwnedMessage::Binary(vec![1; 64]))
+		);
+		assert_eq!(
+			client.decode(&mut dst).unwrap(),
+			Some(OwnedMessage::Text("tiny".to_string()))
+		);
+	}
+
+	#[derive(Debug)]
+	struct StoredBackend;
+
+	impl DeflateBackend for StoredBackend {
+		fn compressor(&self, window_bits: u8) -> Box<dyn Compressor> {
+			assert_eq!(window_bits, 10);
+			Box::new(Stored)
+		}
+
+		fn decompressor(&self, window_bits: u8) -> Box<dyn Decompressor> {
+			assert_eq!(window_bits, 15);
+			Box::new(StoredDecompressor::default())
+		}
+	}
+
+	#[test]
+	fn message_codec_with_options() {
+		let compressed = [
+			0xc1, 0x0b, 0x00, 0x05, 0x00, 0xfa, 0xff, b'H', b'e', b'l', b'l', b'o', 0x00,
+		];
+		let mut deflate = Extension::new(PERMESSAGE_DEFLATE.to_string());
+		deflate.params.push(::header::extensions::Parameter::new(
+			"client_max_window_bits".to_string(),
+			Some("10".to_string()),
+		));
+		let mut options = CodecOptions {
+			extensions: vec![deflate.clone()],
+			deflate_backend: Some(Arc::new(StoredBackend)),
+		};
+
+		let mut codec =
+			MessageCodec::<OwnedMessage>::with_options(Context::Client, &options).unwrap();
+		assert_eq!(codec.deflate().unwrap().client_max_window_bits, 10);
+		assert_eq!(
+			codec.decode(&mut BytesMut::from(&compressed[..])).unwrap(),
+			Some(OwnedMessage::Text("Hello".to_string()))
+		);
+
+		// without any extensions RSV1 is a protocol error
+		let mut codec =
+			MessageCodec::<OwnedMessage>::with_options(Context::Client, &CodecOptions::default())
+				.unwrap();
+		assert!(codec.deflate().is_none());
+		assert!(codec.decode(&mut BytesMut::from(&compressed[..])).is_err());
+
+		// deflate can only be used with a backend, and only once
+		options.extensions.push(deflate);
+		assert!(MessageCodec::<OwnedMessage>::with_options(Context::Client, &options).is_err());
+		options.extensions.pop();
+		options.deflate_backend = None;
+		assert!(MessageCodec::<OwnedMessage>::with_options(Context::Client, &options).is_err());
+
+		let options = CodecOptions {
+			extensions: vec![Extension::new("x-unknown".to_string())],
+			deflate_backend: None,
+		};
+		assert!(MessageCodec::<OwnedMessage>::with_options(Context::Client, &options).is_err());
+	}
+
+	#[test]
+	fn message_codec_counts_traffic() {
+		let messages = [
+			OwnedMessage::Text("hello".to_string()),
+			OwnedMessage::Ping(b"p".to_vec()),
+			// three dataframes
+			OwnedMessage::Binary(vec![0; 300]),
+			OwnedMessage::Pong(Vec::new()),
+			OwnedMessage::Close(None),
+		];
+		let mut client = MessageCodecBuilder::new(Context::Client)
+			.fragment_size(100)
+			.build::<OwnedMessage>();
+		let mut server = MessageCodec::default(Context::Server);
+		let client_stats = client.stats_handle();
+
+		let mut wire = BytesMut::new();
+		for message in messages.iter().cloned() {
+			client.encode(message, &mut wire).unwrap();
+		}
+		while server.decode(&mut wire).unwrap().is_some() {}
+
+		for &(sent, received) in &[
+			(client_stats.frames_sent(), server.stats().frames_received()),
+			(client_stats.bytes_sent(), server.stats().bytes_received()),
+			(client_stats.pings_sent(), server.stats().pings_received()),
+			(client_stats.pongs_sent(), server.stats().pongs_received()),
+			(client_stats.closes_sent(), server.stats().closes_received()),
+		] {
+			assert_eq!(sent, received);
+		}
+		assert_eq!(client_stats.frames_sent(), 7);
+		assert_eq!(client_stats.bytes_sent(), 5 + 1 + 300);
+		assert_eq!(client_stats.pings_sent(), 1);
+		assert_eq!(client_stats.pongs_sent(), 1);
+		assert_eq!(client_stats.closes_sent(), 1);
+		assert_eq!(client_stats.frames_received(), 0);
+
+		// the payload of vectored output is counted as well
+		let frame = DataFrame::new(true, Opcode::Binary, vec![0; 5000]);
+		server.encode_vectored(&frame, &mut wire).unwrap().unwrap();
+		assert_eq!(server.stats().frames_sent(), 1);
+		assert_eq!(server.stats().bytes_sent(), 5000);
+
+		// and so are dataframes sent as they are
+		let mut codec = MessageCodecBuilder::new(Context::Server)
+			.fragment_size(100)
+			.build::<Outgoing>();
+		let frame = DataFrame::new(false, Opcode::Text, vec![b'a'; 300]);
+		codec.encode(Outgoing::Frame(frame), &mut wire).unwrap();
+		assert_eq!(codec.stats().frames_sent(), 1);
+		assert_eq!(codec.stats().bytes_sent(), 300);
+	}
+
+	#[test]
+	fn message_codec_encodes_raw_frames() {
+		let mut server = MessageCodecBuilder::new(Context::Server)
+			.fragment_size(4)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
+			.build::<Outgoing>();
+		let mut dst = BytesMut::new();
+		server
+			.encode(OwnedMessage::Text("Hello".to_string()).into(), &mut dst)
+			.unwrap();
+		let start = dst.len();
+		// neither fragmented nor compressed
+		let frame = DataFrame::new(true, Opcode::Binary, vec![7; 10]);
+		server.encode(frame.clone().into(), &mut dst).unwrap();
+		let mut raw = Vec::new();
+		frame.write_to(&mut raw, false).unwrap();
+		assert_eq!(&dst[start..], &raw[..]);
+		server
+			.encode(OwnedMessage::Text("Hello".to_string()).into(), &mut dst)
+			.unwrap();
+
+		let mut client = MessageCodecBuilder::new(Context::Client)
+			.deflate(compress_all(), Stored, StoredDecompressor::default())
+			.build::<OwnedMessage>();
+		for message in [
+			OwnedMessage::Text("Hello".to_string()),
+			OwnedMessage::Binary(vec![7; 10]),
+			OwnedMessage::Text("Hello".to_string()),
+		]
+		.iter()
+		{
+			assert_eq!(client.decode(&mut dst).unwrap().as_ref(), Some(message));
+		}
+		assert!(dst.is_empty());
+
+		// reserved bits are kept as they are
+		let mut frame = DataFrame::new(false, Opcode::Continuation, b"ab".to_vec());
+		frame.reserved = [false, false, true];
+		server.encode(frame.into(), &mut dst).unwrap();
+		assert_eq!(&dst[..], &[0x10, 0x02, b'a', b'b'][..]);
 	}
 }
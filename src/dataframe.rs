@@ -1,11 +1,20 @@
 //! Module containing the default implementation of data frames.
-use result::{WebSocketError, WebSocketResult};
+use message::{IntoCowBytes, Message, Type};
+use result::{ProtocolError, WebSocketError, WebSocketResult};
+use std::borrow::{Borrow, Cow};
+use std::cmp;
+use std::convert::TryFrom;
 use std::io::{self, Read, Write};
 use ws::dataframe::DataFrame as DataFrameable;
 use ws::util::header as dfh;
 use ws::util::header::DataFrameHeader;
 use ws::util::mask;
 
+pub use ws::util::header::{parse_header, ParseResult};
+
+// the longest payload of a close, ping or pong dataframe
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
 /// Represents a WebSocket data frame.
 ///
 /// The data held in a DataFrame is never masked.
@@ -37,13 +46,79 @@ impl DataFrame {
 		}
 	}
 
+	/// Creates a ping dataframe, failing if `data` is longer than 125 bytes.
+	pub fn ping(data: Vec<u8>) -> WebSocketResult<DataFrame> {
+		DataFrame::control(Opcode::Ping, data)
+	}
+
+	/// Creates a pong dataframe, failing if `data` is longer than 125 bytes.
+	pub fn pong(data: Vec<u8>) -> WebSocketResult<DataFrame> {
+		DataFrame::control(Opcode::Pong, data)
+	}
+
+	/// Creates a close dataframe with a status code and the reason for
+	/// closing, failing if `reason` is longer than 123 bytes.
+	pub fn close(status_code: u16, reason: &str) -> WebSocketResult<DataFrame> {
+		let mut data = Vec::with_capacity(2 + reason.len());
+		data.push((status_code >> 8) as u8);
+		data.push(status_code as u8);
+		data.extend_from_slice(reason.as_bytes());
+		DataFrame::control(Opcode::Close, data)
+	}
+
+	/// Whether the first reserved bit (RSV1) is set, which is used by
+	/// extensions like permessage-deflate.
+	///
+	/// The reserved bits are written as they are and kept when reading a
+	/// dataframe, but a `DataFrameCodec` rejects incoming ones unless they
+	/// were allowed with `set_allowed_reserved_bits`.
+	pub fn rsv1(&self) -> bool {
+		self.reserved[0]
+	}
+
+	/// Set the first reserved bit (RSV1).
+	pub fn set_rsv1(&mut self, set: bool) {
+		self.reserved[0] = set;
+	}
+
+	/// Whether the second reserved bit (RSV2) is set.
+	pub fn rsv2(&self) -> bool {
+		self.reserved[1]
+	}
+
+	/// Set the second reserved bit (RSV2).
+	pub fn set_rsv2(&mut self, set: bool) {
+		self.reserved[1] = set;
+	}
+
+	/// Whether the third reserved bit (RSV3) is set.
+	pub fn rsv3(&self) -> bool {
+		self.reserved[2]
+	}
+
+	/// Set the third reserved bit (RSV3).
+	pub fn set_rsv3(&mut self, set: bool) {
+		self.reserved[2] = set;
+	}
+
+	// Control frames are never fragmented and have short payloads (RFC 6455
+	// section 5.5).
+	fn control(opcode: Opcode, data: Vec<u8>) -> WebSocketResult<DataFrame> {
+		if data.len() > MAX_CONTROL_PAYLOAD {
+			return Err(WebSocketError::DataFrameError(
+				"Control frame length too long",
+			));
+		}
+		Ok(DataFrame::new(true, opcode, data))
+	}
+
 	/// Take the body and header of a dataframe and combine it into a single
 	/// Dataframe struct. A websocket message can be made up of many individual
 	/// dataframes, use the methods from the Message or OwnedMessage structs to
 	/// take many of these and create a websocket message.
 	pub fn read_dataframe_body(
 		header: DataFrameHeader,
-		body: Vec<u8>,
+		mut body: Vec<u8>,
 		should_be_masked: bool,
 	) -> WebSocketResult<Self> {
 		let finished = header.flags.contains(dfh::DataFrameFlags::FIN);
@@ -54,20 +129,27 @@ impl DataFrame {
 			header.flags.contains(dfh::DataFrameFlags::RSV3),
 		];
 
-		let opcode = Opcode::new(header.opcode).expect("Invalid header opcode!");
+		let opcode = Opcode::try_from(header.opcode)?;
 
 		let data = match header.mask {
 			Some(mask) => {
 				if !should_be_masked {
-					return Err(WebSocketError::DataFrameError(
-						"Expected unmasked data frame",
-					));
+					return Err(ProtocolError::UnexpectedMask {
+						opcode: header.opcode,
+						masked: true,
+					}
+					.into());
 				}
-				mask::mask_data(mask, &body)
+				mask::mask_data_in_place(mask, &mut body);
+				body
 			}
 			None => {
 				if should_be_masked {
-					return Err(WebSocketError::DataFrameError("Expected masked data frame"));
+					return Err(ProtocolError::UnexpectedMask {
+						opcode: header.opcode,
+						masked: false,
+					}
+					.into());
 				}
 				body
 			}
@@ -81,20 +163,90 @@ impl DataFrame {
 		})
 	}
 
+	/// Parses the dataframe at the start of `buf`, without any `Read`er.
+	///
+	/// Returns the dataframe (unmasked, if it was masked) and the number of
+	/// bytes it took up, or `None` if `buf` doesn't contain all of it yet.
+	/// Anything after the dataframe is left alone.
+	///
+	/// The payload is copied out of `buf` and unmasked in the same pass, that
+	/// copy into the `data` of the dataframe is the only one.
+	///
+	///```rust
+	///use websocket::dataframe::{DataFrame, Opcode};
+	///
+	///let buf = [0x82, 0x02, 1, 2, 0x89];
+	///assert!(DataFrame::parse(&buf[..3]).unwrap().is_none());
+	///let (frame, len) = DataFrame::parse(&buf).unwrap().unwrap();
+	///assert_eq!(frame, DataFrame::new(true, Opcode::Binary, vec![1, 2]));
+	///assert_eq!(len, 4);
+	///```
+	pub fn parse(buf: &[u8]) -> WebSocketResult<Option<(DataFrame, usize)>> {
+		Ok(DataFrame::parse_frame(buf, None)?.ok())
+	}
+
+	// The dataframe parser behind `parse` and `read_dataframe`. If `buf` is
+	// incomplete this returns how many more bytes are needed at least, and
+	// the mask is checked against `should_be_masked` if it is given.
+	fn parse_frame(
+		buf: &[u8],
+		should_be_masked: Option<bool>,
+	) -> WebSocketResult<Result<(DataFrame, usize), usize>> {
+		let (mut header, header_len) = match parse_header(buf)? {
+			ParseResult::Complete { header, header_len } => (header, header_len),
+			ParseResult::Incomplete { needed } => return Ok(Err(needed)),
+		};
+		if header.len > (usize::MAX - header_len) as u64 {
+			return Err(WebSocketError::MessageTooLarge);
+		}
+		let frame_len = header_len + header.len as usize;
+		if buf.len() < frame_len {
+			return Ok(Err(frame_len - buf.len()));
+		}
+
+		let masked = header.mask.is_some();
+		match should_be_masked {
+			Some(expected) if expected != masked => {
+				return Err(ProtocolError::UnexpectedMask {
+					opcode: header.opcode,
+					masked,
+				}
+				.into());
+			}
+			_ => (),
+		}
+		let payload = &buf[header_len..frame_len];
+		let data = match header.mask.take() {
+			Some(mask) => mask::mask_data(mask, payload),
+			None => payload.to_vec(),
+		};
+		let frame = DataFrame::read_dataframe_body(header, data, false)?;
+		Ok(Ok((frame, frame_len)))
+	}
+
 	/// Reads a DataFrame from a Reader.
 	pub fn read_dataframe<R>(reader: &mut R, should_be_masked: bool) -> WebSocketResult<Self>
 	where
 		R: Read,
 	{
-		let header = dfh::read_header(reader)?;
-
-		let mut data: Vec<u8> = Vec::with_capacity(header.len as usize);
-		let read = reader.take(header.len).read_to_end(&mut data)?;
-		if (read as u64) < header.len {
-			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+		// only read as much as the parser asks for, so the buffer grows as
+		// the payload actually arrives and nothing after the dataframe is read
+		let mut buf = Vec::new();
+		loop {
+			match DataFrame::parse_frame(&buf, Some(should_be_masked))? {
+				Ok((frame, _)) => return Ok(frame),
+				Err(needed) => {
+					let read = reader.take(needed as u64).read_to_end(&mut buf)?;
+					if read < needed {
+						return Err(io::Error::new(
+							io::ErrorKind::UnexpectedEof,
+							"incomplete dataframe",
+						)
+						.into());
+					}
+				}
+			}
 		}
-
-		DataFrame::read_dataframe_body(header, data, should_be_masked)
 	}
 }
 
@@ -129,6 +281,270 @@ impl DataFrameable for DataFrame {
 	fn take_payload(self) -> Vec<u8> {
 		self.data
 	}
+
+	#[inline(always)]
+	fn payload_bytes(&self) -> Option<&[u8]> {
+		Some(&self.data)
+	}
+}
+
+// Lets `DataFrameCodec<DataFrame>` encode the crate's dataframes directly,
+// like it does for `Box<ws::DataFrame>`.
+impl Borrow<dyn DataFrameable> for DataFrame {
+	fn borrow(&self) -> &(dyn DataFrameable + 'static) {
+		self
+	}
+}
+
+/// A WebSocket data frame which may borrow its payload.
+///
+/// This is the counterpart of `DataFrame` for sending: framing data which is
+/// borrowed, e.g. the payload of a `Message::binary(&bytes[..])`, doesn't copy
+/// it into a new `Vec` first. Like with `DataFrame` the payload is never
+/// masked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorrowedDataFrame<'a> {
+	/// Whether or no this constitutes the end of a message
+	pub finished: bool,
+	/// The reserved portion of the data frame (RFC6455 5.2)
+	pub reserved: [bool; 3],
+	/// The opcode associated with this data frame
+	pub opcode: Opcode,
+	/// The payload associated with this data frame
+	pub data: Cow<'a, [u8]>,
+}
+
+impl<'a> BorrowedDataFrame<'a> {
+	/// Creates a new BorrowedDataFrame.
+	pub fn new<B>(finished: bool, opcode: Opcode, data: B) -> Self
+	where
+		B: IntoCowBytes<'a>,
+	{
+		BorrowedDataFrame {
+			finished,
+			reserved: [false; 3],
+			opcode,
+			data: data.into(),
+		}
+	}
+
+	/// Converts this into a `DataFrame`, copying the payload if it is
+	/// borrowed.
+	pub fn into_owned(self) -> DataFrame {
+		DataFrame {
+			finished: self.finished,
+			reserved: self.reserved,
+			opcode: self.opcode,
+			data: self.data.into_owned(),
+		}
+	}
+}
+
+impl<'a> From<&'a DataFrame> for BorrowedDataFrame<'a> {
+	fn from(frame: &'a DataFrame) -> Self {
+		BorrowedDataFrame {
+			finished: frame.finished,
+			reserved: frame.reserved,
+			opcode: frame.opcode,
+			data: Cow::Borrowed(&frame.data),
+		}
+	}
+}
+
+impl<'a> From<Message<'a>> for BorrowedDataFrame<'a> {
+	/// Frames a message as one unfragmented dataframe. The payload stays
+	/// borrowed, unless it has to be prefixed with the status code of a
+	/// close message.
+	fn from(message: Message<'a>) -> Self {
+		let opcode = match message.opcode {
+			Type::Text => Opcode::Text,
+			Type::Binary => Opcode::Binary,
+			Type::Close => Opcode::Close,
+			Type::Ping => Opcode::Ping,
+			Type::Pong => Opcode::Pong,
+		};
+		let data = match message.cd_status_code {
+			Some(_) => Cow::Owned(message.take_payload()),
+			None => message.payload,
+		};
+		BorrowedDataFrame {
+			finished: true,
+			reserved: [false; 3],
+			opcode,
+			data,
+		}
+	}
+}
+
+impl<'a> DataFrameable for BorrowedDataFrame<'a> {
+	#[inline(always)]
+	fn is_last(&self) -> bool {
+		self.finished
+	}
+
+	#[inline(always)]
+	fn opcode(&self) -> u8 {
+		self.opcode as u8
+	}
+
+	#[inline(always)]
+	fn reserved(&self) -> &[bool; 3] {
+		&self.reserved
+	}
+
+	#[inline(always)]
+	fn size(&self) -> usize {
+		self.data.len()
+	}
+
+	#[inline(always)]
+	fn write_payload(&self, socket: &mut dyn Write) -> WebSocketResult<()> {
+		socket.write_all(&self.data)?;
+		Ok(())
+	}
+
+	#[inline(always)]
+	fn take_payload(self) -> Vec<u8> {
+		self.data.into_owned()
+	}
+
+	#[inline(always)]
+	fn payload_bytes(&self) -> Option<&[u8]> {
+		Some(&self.data)
+	}
+}
+
+impl Borrow<dyn DataFrameable> for BorrowedDataFrame<'static> {
+	fn borrow(&self) -> &(dyn DataFrameable + 'static) {
+		self
+	}
+}
+
+/// Splits `payload` into a message of dataframes with at most `chunk_size`
+/// bytes of payload each.
+///
+/// The first dataframe has the given opcode, the following ones are
+/// continuation frames and only the last one is finished. An empty payload
+/// is a single empty dataframe. Control frames can't be fragmented, so for a
+/// control `opcode` (or a `chunk_size` of 0) the whole payload is one
+/// dataframe.
+///
+///```rust
+///use websocket::dataframe::{fragment, Opcode};
+///
+///let frames: Vec<_> = fragment(Opcode::Text, b"Hello", 2).collect();
+///assert_eq!(frames.len(), 3);
+///assert_eq!(frames[1].opcode, Opcode::Continuation);
+///assert!(frames[2].finished);
+///```
+pub fn fragment(opcode: Opcode, payload: &[u8], chunk_size: usize) -> Fragments<'_> {
+	Fragments(fragment_borrowed(opcode, payload, chunk_size))
+}
+
+/// Like `fragment`, but the dataframes borrow their payloads from `payload`
+/// instead of copying them.
+pub fn fragment_borrowed(
+	opcode: Opcode,
+	payload: &[u8],
+	chunk_size: usize,
+) -> BorrowedFragments<'_> {
+	let chunk_size = if opcode.is_control() || chunk_size == 0 {
+		payload.len()
+	} else {
+		chunk_size
+	};
+	BorrowedFragments {
+		opcode: Some(opcode),
+		payload,
+		chunk_size,
+	}
+}
+
+/// An iterator over the dataframes of a fragmented payload, see `fragment`.
+#[derive(Debug, Clone)]
+pub struct Fragments<'a>(BorrowedFragments<'a>);
+
+impl<'a> Iterator for Fragments<'a> {
+	type Item = DataFrame;
+
+	fn next(&mut self) -> Option<DataFrame> {
+		self.0.next().map(BorrowedDataFrame::into_owned)
+	}
+}
+
+/// An iterator over the borrowed dataframes of a fragmented payload, see
+/// `fragment_borrowed`.
+#[derive(Debug, Clone)]
+pub struct BorrowedFragments<'a> {
+	// the opcode of the first dataframe, `None` after it
+	opcode: Option<Opcode>,
+	payload: &'a [u8],
+	chunk_size: usize,
+}
+
+impl<'a> BorrowedFragments<'a> {
+	// An iterator without any dataframes.
+	fn empty() -> Self {
+		BorrowedFragments {
+			opcode: None,
+			payload: &[],
+			chunk_size: 0,
+		}
+	}
+}
+
+impl<'a> Iterator for BorrowedFragments<'a> {
+	type Item = BorrowedDataFrame<'a>;
+
+	fn next(&mut self) -> Option<BorrowedDataFrame<'a>> {
+		if self.opcode.is_none() && self.payload.is_empty() {
+			return None;
+		}
+		let opcode = self.opcode.take().unwrap_or(Opcode::Continuation);
+		let len = cmp::min(self.chunk_size, self.payload.len());
+		let (chunk, rest) = self.payload.split_at(len);
+		self.payload = rest;
+		Some(BorrowedDataFrame::new(rest.is_empty(), opcode, chunk))
+	}
+}
+
+/// An iterator over the dataframes a message is sent in, see
+/// `Message::dataframes` and `OwnedMessage::dataframes`.
+#[derive(Debug, Clone)]
+pub struct MessageDataFrames<'a> {
+	// the frame of a close message with a status code, which isn't stored
+	// as one slice
+	single: Option<BorrowedDataFrame<'a>>,
+	fragments: BorrowedFragments<'a>,
+}
+
+impl<'a> MessageDataFrames<'a> {
+	/// The dataframes of `message`, with payloads of at most `chunk_size`
+	/// bytes (see `fragment_borrowed`) or unfragmented for `None`.
+	pub fn new<D>(message: &'a D, chunk_size: Option<usize>) -> Self
+	where
+		D: DataFrameable + ?Sized,
+	{
+		let opcode = Opcode::new(message.opcode()).expect("messages have valid opcodes");
+		match message.payload() {
+			Cow::Borrowed(payload) => MessageDataFrames {
+				single: None,
+				fragments: fragment_borrowed(opcode, payload, chunk_size.unwrap_or(0)),
+			},
+			Cow::Owned(payload) => MessageDataFrames {
+				single: Some(BorrowedDataFrame::new(true, opcode, payload)),
+				fragments: BorrowedFragments::empty(),
+			},
+		}
+	}
+}
+
+impl<'a> Iterator for MessageDataFrames<'a> {
+	type Item = BorrowedDataFrame<'a>;
+
+	fn next(&mut self) -> Option<BorrowedDataFrame<'a>> {
+		self.single.take().or_else(|| self.fragments.next())
+	}
 }
 
 /// Represents a WebSocket data frame opcode
@@ -194,6 +610,50 @@ impl Opcode {
 			_ => return None,
 		})
 	}
+
+	/// Whether this is the opcode of a control frame (close, ping, pong or
+	/// one reserved for further control frames).
+	pub fn is_control(self) -> bool {
+		self as u8 >= 8
+	}
+
+	/// Whether this is the opcode of a data frame (continuation, text,
+	/// binary or one reserved for further non-control frames).
+	pub fn is_data(self) -> bool {
+		!self.is_control()
+	}
+}
+
+impl TryFrom<u8> for Opcode {
+	type Error = WebSocketError;
+
+	/// Converts the opcode nibble of a dataframe header.
+	///
+	/// Unlike `Opcode::new` this fails with `ProtocolError::ReservedOpcode`
+	/// for the opcodes which aren't defined (yet), 0x3 to 0x7 and 0xB to 0xF,
+	/// and with a `WebSocketError::DataFrameError` for values above 0xF.
+	fn try_from(op: u8) -> WebSocketResult<Opcode> {
+		match Opcode::new(op) {
+			Some(Opcode::NonControl1)
+			| Some(Opcode::NonControl2)
+			| Some(Opcode::NonControl3)
+			| Some(Opcode::NonControl4)
+			| Some(Opcode::NonControl5)
+			| Some(Opcode::Control1)
+			| Some(Opcode::Control2)
+			| Some(Opcode::Control3)
+			| Some(Opcode::Control4)
+			| Some(Opcode::Control5) => Err(ProtocolError::ReservedOpcode(op).into()),
+			Some(opcode) => Ok(opcode),
+			None => Err(WebSocketError::DataFrameError("Invalid data frame opcode")),
+		}
+	}
+}
+
+impl From<Opcode> for u8 {
+	fn from(opcode: Opcode) -> u8 {
+		opcode as u8
+	}
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -219,6 +679,15 @@ mod tests {
 		assert_eq!(obtained, expected);
 	}
 
+	#[test]
+	fn read_huge_length_without_panic() {
+		// a binary frame claiming a u64::MAX byte payload
+		let dataframe = [
+			0x82, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2,
+		];
+		assert!(DataFrame::read_dataframe(&mut &dataframe[..], false).is_err());
+	}
+
 	#[test]
 	fn read_incomplete_payloads() {
 		let mut data = vec![0x8au8, 0x08, 0x19, 0xac, 0xab, 0x8a, 0x52, 0x4e, 0x05, 0x00];
@@ -237,6 +706,255 @@ mod tests {
 		assert_eq!(more_payload.unwrap().data, payload);
 	}
 
+	#[test]
+	fn control_dataframes() {
+		use message::{CloseData, OwnedMessage};
+		use ws::Message;
+
+		let frames = vec![
+			(
+				DataFrame::ping(vec![1; 125]).unwrap(),
+				OwnedMessage::Ping(vec![1; 125]),
+			),
+			(
+				DataFrame::pong(Vec::new()).unwrap(),
+				OwnedMessage::Pong(Vec::new()),
+			),
+			(
+				DataFrame::close(1001, "gone").unwrap(),
+				OwnedMessage::Close(Some(CloseData::new(1001, "gone".to_string()))),
+			),
+		];
+		for (frame, message) in frames {
+			assert!(frame.finished);
+			let mut bytes = Vec::new();
+			frame.write_to(&mut bytes, false).unwrap();
+			let read = DataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+			assert_eq!(read, frame);
+			assert_eq!(OwnedMessage::from_dataframes(vec![read]).unwrap(), message);
+		}
+		assert_eq!(DataFrame::close(1000, "").unwrap().data, vec![0x03, 0xE8]);
+
+		for result in vec![
+			DataFrame::ping(vec![0; 126]),
+			DataFrame::pong(vec![0; 200]),
+			DataFrame::close(1000, &"r".repeat(124)),
+		] {
+			match result {
+				Err(WebSocketError::DataFrameError(_)) => (),
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+	}
+
+	#[test]
+	fn borrowed_dataframes() {
+		use message::OwnedMessage;
+		use ws::Message as MessageTrait;
+
+		let payload = vec![7u8; 300];
+		let frame = BorrowedDataFrame::from(Message::binary(&payload[..]));
+		match frame.data {
+			Cow::Borrowed(data) => assert_eq!(data.as_ptr(), payload.as_ptr()),
+			Cow::Owned(_) => panic!("the payload was copied"),
+		}
+		assert_eq!(frame.payload(), Cow::Borrowed(&payload[..]));
+
+		// serialized just like the owned dataframe and the message itself
+		let owned = DataFrame::new(true, Opcode::Binary, payload.clone());
+		let mut expected = Vec::new();
+		owned.write_to(&mut expected, false).unwrap();
+		let mut bytes = Vec::new();
+		frame.write_to(&mut bytes, false).unwrap();
+		assert_eq!(bytes, expected);
+		bytes.clear();
+		Message::binary(&payload[..])
+			.serialize(&mut bytes, false)
+			.unwrap();
+		assert_eq!(bytes, expected);
+
+		assert_eq!(BorrowedDataFrame::from(&owned), frame);
+		assert_eq!(frame.clone().into_owned(), owned);
+		assert_eq!(
+			OwnedMessage::from_dataframes(vec![frame]).unwrap(),
+			OwnedMessage::Binary(payload.clone())
+		);
+
+		let close = BorrowedDataFrame::from(Message::close_because(1000, "bye"));
+		assert_eq!(close.opcode, Opcode::Close);
+		assert_eq!(&close.data[..], b"\x03\xE8bye");
+		assert_eq!(
+			BorrowedDataFrame::from(Message::close()).payload(),
+			Cow::Borrowed(&[][..])
+		);
+	}
+
+	#[test]
+	fn frame_size_at_length_boundaries() {
+		use message::OwnedMessage;
+		use ws::Message as MessageTrait;
+
+		let sizes = [(0, 2), (125, 2), (126, 4), (65535, 4), (65536, 10)];
+		for &(len, header_len) in sizes.iter() {
+			for &masked in [false, true].iter() {
+				let expected = header_len + if masked { 4 } else { 0 } + len;
+				let frame = DataFrame::new(true, Opcode::Binary, vec![0; len]);
+				assert_eq!(frame.frame_size(masked), expected);
+
+				let mut written = Vec::new();
+				let key = if masked { Some([1, 2, 3, 4]) } else { None };
+				frame.write_to_with_mask(&mut written, key).unwrap();
+				assert_eq!(written.len(), expected);
+
+				assert_eq!(
+					Message::binary(&frame.data[..]).message_size(masked),
+					expected
+				);
+				assert_eq!(
+					OwnedMessage::Binary(frame.data).message_size(masked),
+					expected
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn reserved_bits_round_trip() {
+		let mut frame = DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]);
+		assert!(!frame.rsv1() && !frame.rsv2() && !frame.rsv3());
+		frame.set_rsv2(true);
+		assert_eq!(frame.reserved, [false, true, false]);
+
+		let mut bytes = Vec::new();
+		frame.write_to(&mut bytes, false).unwrap();
+		assert_eq!(bytes[0], 0x80 | 0x20 | 0x02);
+		let read = DataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert!(read.rsv2());
+		assert_eq!(read, frame);
+
+		frame.set_rsv1(true);
+		frame.set_rsv3(true);
+		frame.set_rsv2(false);
+		bytes.clear();
+		frame.write_to(&mut bytes, true).unwrap();
+		assert_eq!(bytes[0], 0x80 | 0x40 | 0x10 | 0x02);
+		let read = DataFrame::read_dataframe(&mut &bytes[..], true).unwrap();
+		assert_eq!(read.reserved, [true, false, true]);
+	}
+
+	#[test]
+	fn fragment_payloads() {
+		let payload: Vec<u8> = (0..20).collect();
+		for len in 0..=payload.len() {
+			for chunk_size in 1..=len + 2 {
+				let frames: Vec<_> =
+					fragment(Opcode::Binary, &payload[..len], chunk_size).collect();
+				let expected_frames = cmp::max(1, (len + chunk_size - 1) / chunk_size);
+				assert_eq!(frames.len(), expected_frames);
+
+				let mut data = Vec::new();
+				for (i, frame) in frames.iter().enumerate() {
+					let last = i + 1 == frames.len();
+					assert_eq!(frame.finished, last);
+					let opcode = if i == 0 {
+						Opcode::Binary
+					} else {
+						Opcode::Continuation
+					};
+					assert_eq!(frame.opcode, opcode);
+					assert!(frame.data.len() <= chunk_size);
+					assert!(last || frame.data.len() == chunk_size);
+					data.extend_from_slice(&frame.data);
+				}
+				assert_eq!(data, &payload[..len]);
+			}
+		}
+
+		// control frames and a chunk size of 0 aren't fragmented
+		let frames: Vec<_> = fragment(Opcode::Ping, &payload, 3).collect();
+		assert_eq!(
+			frames,
+			vec![DataFrame::new(true, Opcode::Ping, payload.clone())]
+		);
+		let frames: Vec<_> = fragment(Opcode::Text, &payload, 0).collect();
+		assert_eq!(
+			frames,
+			vec![DataFrame::new(true, Opcode::Text, payload.clone())]
+		);
+	}
+
+	#[test]
+	fn parse_dataframes() {
+		let text = DataFrame::new(false, Opcode::Text, b"Hello".to_vec());
+		let ping = DataFrame::new(true, Opcode::Ping, vec![1, 2, 3]);
+		let binary = DataFrame::new(true, Opcode::Binary, vec![7; 300]);
+		let mut buf = Vec::new();
+		text.write_to(&mut buf, true).unwrap();
+		ping.write_to(&mut buf, false).unwrap();
+		binary.write_to(&mut buf, true).unwrap();
+		let sizes = [
+			text.frame_size(true),
+			ping.frame_size(false),
+			binary.frame_size(true),
+		];
+
+		let mut start = 0;
+		for (expected, &size) in [text, ping, binary].iter().zip(sizes.iter()) {
+			for end in start..start + size {
+				assert_eq!(DataFrame::parse(&buf[start..end]).unwrap(), None);
+			}
+			let (frame, len) = DataFrame::parse(&buf[start..]).unwrap().unwrap();
+			assert_eq!(frame, *expected);
+			assert_eq!(len, size);
+			start += len;
+		}
+		assert_eq!(start, buf.len());
+
+		match DataFrame::parse(&[0x83, 0x00]) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(3))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn opcode_from_u8() {
+		let defined = [
+			(0x0, Opcode::Continuation),
+			(0x1, Opcode::Text),
+			(0x2, Opcode::Binary),
+			(0x8, Opcode::Close),
+			(0x9, Opcode::Ping),
+			(0xA, Opcode::Pong),
+		];
+		for op in 0..=0xFu8 {
+			match defined.iter().find(|&&(value, _)| value == op) {
+				Some(&(_, expected)) => {
+					let opcode = Opcode::try_from(op).unwrap();
+					assert_eq!(opcode, expected);
+					assert_eq!(u8::from(opcode), op);
+					assert_eq!(opcode.is_control(), op >= 8);
+					assert_eq!(opcode.is_data(), op < 8);
+				}
+				None => match Opcode::try_from(op) {
+					Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(reserved))) => {
+						assert_eq!(reserved, op)
+					}
+					other => panic!("{:#X} should be reserved, got {:?}", op, other),
+				},
+			}
+			// the reserved opcodes are still classified
+			let opcode = Opcode::new(op).unwrap();
+			assert_eq!(u8::from(opcode), op);
+			assert_eq!(opcode.is_control(), op >= 8);
+		}
+		for op in 0x10..=0xFFu8 {
+			match Opcode::try_from(op) {
+				Err(WebSocketError::DataFrameError(_)) => (),
+				other => panic!("{:#X} should be invalid, got {:?}", op, other),
+			}
+		}
+	}
+
 	#[bench]
 	fn bench_read_dataframe(b: &mut Bencher) {
 		let data = b"The quick brown fox jumps over the lazy dog";
@@ -268,6 +986,32 @@ mod tests {
 		assert_eq!(&obtained[..], &expected[..]);
 	}
 
+	#[test]
+	fn test_write_dataframe_with_mask() {
+		// the examples of RFC 6455 section 5.7
+		let hello = DataFrame::new(true, Opcode::Text, b"Hello".to_vec());
+		let mut obtained = Vec::new();
+		hello.write_to_with_mask(&mut obtained, None).unwrap();
+		assert_eq!(obtained, [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+		let expected = [
+			0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+		];
+		let key = Some([0x37, 0xfa, 0x21, 0x3d]);
+		obtained.clear();
+		hello.write_to_with_mask(&mut obtained, key).unwrap();
+		assert_eq!(obtained, expected);
+		obtained.clear();
+		hello.write_to_buffer(&mut obtained, key).unwrap();
+		assert_eq!(obtained, expected);
+
+		let binary = DataFrame::new(true, Opcode::Binary, vec![0; 256]);
+		obtained.clear();
+		binary.write_to_with_mask(&mut obtained, None).unwrap();
+		assert_eq!(&obtained[..4], &[0x82, 0x7E, 0x01, 0x00]);
+		assert_eq!(obtained.len(), 4 + 256);
+	}
+
 	#[bench]
 	fn bench_write_dataframe(b: &mut Bencher) {
 		let data = b"The quick brown fox jumps over the lazy dog";
@@ -59,6 +59,25 @@ impl WebSocketAccept {
 		let WebSocketAccept(accept) = *self;
 		base64::encode(&accept)
 	}
+
+	/// Check the value of a Sec-WebSocket-Accept header against this
+	/// (expected) one.
+	///
+	/// Surrounding whitespace is ignored, otherwise the value has to be
+	/// exactly the serialized accept value. The comparison takes the same
+	/// time no matter where the values differ.
+	pub fn verify(&self, header_value: &str) -> bool {
+		let expected = self.serialize();
+		let value = header_value.trim();
+		if value.len() != expected.len() {
+			return false;
+		}
+		value
+			.bytes()
+			.zip(expected.bytes())
+			.fold(0, |diff, (a, b)| diff | (a ^ b))
+			== 0
+	}
 }
 
 impl Header for WebSocketAccept {
@@ -110,6 +129,19 @@ mod tests {
 		assert!(accept.is_err()); // > 20 bytes
 	}
 
+	#[test]
+	fn test_header_accept_verify() {
+		let key = FromStr::from_str("dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+		let accept = WebSocketAccept::new(&key);
+		assert!(accept.verify("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+		assert!(accept.verify(" s3pPLMBiTxaQ9kYGzzhZRbK+xOo=  \t"));
+
+		assert!(!accept.verify("s3pPLMBiTxaQ9kYGzzhZRbK+xOp="));
+		assert!(!accept.verify("s3pPLMBiTxaQ9kYGzzhZRbK+xOo"));
+		assert!(!accept.verify("s3pP LMBiTxaQ9kYGzzhZRbK+xOo="));
+		assert!(!accept.verify(""));
+	}
+
 	#[bench]
 	fn bench_header_accept_new(b: &mut test::Bencher) {
 		let key = WebSocketKey::new();
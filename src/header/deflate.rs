@@ -0,0 +1,354 @@
+//! Offers and responses of the permessage-deflate extension (RFC 7692).
+//!
+//! This only concerns the `Sec-WebSocket-Extensions` header, so it can be
+//! used by any client or server. The compression itself is done by the
+//! codec, see `codec::deflate`.
+
+use super::extensions::{Extension, Parameter};
+use result::{WebSocketError, WebSocketResult};
+
+/// The name of the extension in the `Sec-WebSocket-Extensions` header.
+pub const PERMESSAGE_DEFLATE: &str = "permessage-deflate";
+
+/// The largest (and default) LZ77 window of DEFLATE, 32 KiB.
+pub const MAX_WINDOW_BITS: u8 = 15;
+
+/// The smallest LZ77 window which can be negotiated, 256 bytes.
+pub const MIN_WINDOW_BITS: u8 = 8;
+
+const INVALID_RESPONSE: &str = "Invalid permessage-deflate response";
+const UNOFFERED_PARAMETER: &str =
+	"permessage-deflate response has a parameter which wasn't offered";
+
+/// A permessage-deflate offer of a client, built from the options the
+/// client supports.
+///
+/// ```rust
+/// # use websocket::header::deflate::DeflateOffer;
+/// let offer = DeflateOffer::new()
+///     .client_max_window_bits(None)
+///     .server_max_window_bits(12);
+/// assert_eq!(
+///     offer.serialize(),
+///     "permessage-deflate; client_max_window_bits; server_max_window_bits=12"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeflateOffer {
+	server_no_context_takeover: bool,
+	client_no_context_takeover: bool,
+	server_max_window_bits: Option<u8>,
+	client_max_window_bits: Option<Option<u8>>,
+}
+
+impl DeflateOffer {
+	/// An offer without any parameters, i.e. context takeover and the largest
+	/// window in both directions.
+	pub fn new() -> Self {
+		DeflateOffer::default()
+	}
+
+	/// Ask the server to reset its compressor after each message.
+	pub fn server_no_context_takeover(mut self, set: bool) -> Self {
+		self.server_no_context_takeover = set;
+		self
+	}
+
+	/// Announce that the client resets its compressor after each message.
+	pub fn client_no_context_takeover(mut self, set: bool) -> Self {
+		self.client_no_context_takeover = set;
+		self
+	}
+
+	/// Ask the server to compress with a window of at most `1 << bits` bytes.
+	///
+	/// # Panics
+	///
+	/// If `bits` isn't between 8 and 15.
+	pub fn server_max_window_bits(mut self, bits: u8) -> Self {
+		assert_window_bits(bits);
+		self.server_max_window_bits = Some(bits);
+		self
+	}
+
+	/// Announce that the client can compress with a smaller window if the
+	/// server asks it to. With `Some(bits)` the client compresses with a
+	/// window of at most `1 << bits` bytes in any case.
+	///
+	/// # Panics
+	///
+	/// If `bits` isn't between 8 and 15.
+	pub fn client_max_window_bits(mut self, bits: Option<u8>) -> Self {
+		if let Some(bits) = bits {
+			assert_window_bits(bits);
+		}
+		self.client_max_window_bits = Some(bits);
+		self
+	}
+
+	/// The offer as an extension of the `Sec-WebSocket-Extensions` header.
+	pub fn to_extension(&self) -> Extension {
+		let mut extension = Extension::new(PERMESSAGE_DEFLATE.to_string());
+		let mut push = |name: &str, value: Option<u8>| {
+			extension.params.push(Parameter::new(
+				name.to_string(),
+				value.map(|value| value.to_string()),
+			))
+		};
+		if self.server_no_context_takeover {
+			push("server_no_context_takeover", None);
+		}
+		if self.client_no_context_takeover {
+			push("client_no_context_takeover", None);
+		}
+		if let Some(bits) = self.client_max_window_bits {
+			push("client_max_window_bits", bits);
+		}
+		if let Some(bits) = self.server_max_window_bits {
+			push("server_max_window_bits", Some(bits));
+		}
+		extension
+	}
+
+	/// The offer as a `Sec-WebSocket-Extensions` value, e.g.
+	/// `permessage-deflate; client_max_window_bits; server_max_window_bits=12`.
+	pub fn serialize(&self) -> String {
+		self.to_extension().to_string()
+	}
+
+	/// Check the server's response to this offer and get the parameters both
+	/// ends have to use.
+	///
+	/// Following RFC 7692 the server may add the no context takeover flags
+	/// and `server_max_window_bits` on its own, but it may not ask for a
+	/// larger window than offered or send `client_max_window_bits` if the
+	/// offer didn't have it. Those, unknown, repeated and invalid parameters
+	/// are a `WebSocketError::ProtocolError`, the connection has to be failed
+	/// then.
+	pub fn parse_response(&self, extension: &Extension) -> WebSocketResult<DeflateParams> {
+		if extension.name != PERMESSAGE_DEFLATE {
+			return Err(WebSocketError::ProtocolError(
+				"Not a permessage-deflate extension",
+			));
+		}
+
+		let mut params = DeflateParams::default();
+		let mut seen = Vec::new();
+		for param in &extension.params {
+			if seen.contains(&&param.name[..]) {
+				return Err(WebSocketError::ProtocolError(INVALID_RESPONSE));
+			}
+			seen.push(&param.name[..]);
+
+			let value = param.value.as_ref().map(|value| &value[..]);
+			match (&param.name[..], value) {
+				("server_no_context_takeover", None) => params.server_no_context_takeover = true,
+				("client_no_context_takeover", None) => params.client_no_context_takeover = true,
+				("server_max_window_bits", Some(bits)) => {
+					let bits = parse_window_bits(bits)?;
+					if bits > self.server_max_window_bits.unwrap_or(MAX_WINDOW_BITS) {
+						return Err(WebSocketError::ProtocolError(INVALID_RESPONSE));
+					}
+					params.server_max_window_bits = bits;
+				}
+				("client_max_window_bits", Some(bits)) => {
+					let bits = parse_window_bits(bits)?;
+					match self.client_max_window_bits {
+						None => return Err(WebSocketError::ProtocolError(UNOFFERED_PARAMETER)),
+						Some(Some(offered)) if bits > offered => {
+							return Err(WebSocketError::ProtocolError(INVALID_RESPONSE));
+						}
+						Some(_) => params.client_max_window_bits = bits,
+					}
+				}
+				_ => return Err(WebSocketError::ProtocolError(INVALID_RESPONSE)),
+			}
+		}
+
+		// a limit the client announced holds even if the server didn't repeat it
+		if let Some(Some(offered)) = self.client_max_window_bits {
+			params.client_max_window_bits = params.client_max_window_bits.min(offered);
+		}
+		// the server has to agree to a smaller window for itself
+		if self.server_max_window_bits.is_some() && !seen.contains(&"server_max_window_bits") {
+			return Err(WebSocketError::ProtocolError(INVALID_RESPONSE));
+		}
+		params.client_no_context_takeover |= self.client_no_context_takeover;
+		Ok(params)
+	}
+}
+
+/// The parameters of the permessage-deflate extension as agreed on during the
+/// handshake, named like in RFC 7692.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateParams {
+	/// The server resets its compressor after each message.
+	pub server_no_context_takeover: bool,
+	/// The client resets its compressor after each message.
+	pub client_no_context_takeover: bool,
+	/// The base-2 logarithm of the window size the server compresses with.
+	pub server_max_window_bits: u8,
+	/// The base-2 logarithm of the window size the client compresses with.
+	pub client_max_window_bits: u8,
+}
+
+impl Default for DeflateParams {
+	/// Context takeover in both directions with the largest window, which is
+	/// what the extension uses without any parameters.
+	fn default() -> Self {
+		DeflateParams {
+			server_no_context_takeover: false,
+			client_no_context_takeover: false,
+			server_max_window_bits: MAX_WINDOW_BITS,
+			client_max_window_bits: MAX_WINDOW_BITS,
+		}
+	}
+}
+
+fn assert_window_bits(bits: u8) {
+	assert!(
+		(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&bits),
+		"window bits have to be between 8 and 15"
+	);
+}
+
+fn parse_window_bits(value: &str) -> WebSocketResult<u8> {
+	match value.parse() {
+		Ok(bits @ MIN_WINDOW_BITS..=MAX_WINDOW_BITS) if !value.starts_with('0') => Ok(bits),
+		_ => Err(WebSocketError::ProtocolError(INVALID_RESPONSE)),
+	}
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+
+	fn response(params: &str) -> Extension {
+		format!("{}{}", PERMESSAGE_DEFLATE, params).parse().unwrap()
+	}
+
+	#[test]
+	fn test_serialize_offer() {
+		assert_eq!(DeflateOffer::new().serialize(), "permessage-deflate");
+		let offer = DeflateOffer::new()
+			.server_no_context_takeover(true)
+			.client_no_context_takeover(true)
+			.client_max_window_bits(Some(10))
+			.server_max_window_bits(12);
+		assert_eq!(
+			offer.serialize(),
+			"permessage-deflate; server_no_context_takeover; client_no_context_takeover; \
+			 client_max_window_bits=10; server_max_window_bits=12"
+		);
+		assert_eq!(
+			DeflateOffer::new().client_max_window_bits(None).serialize(),
+			"permessage-deflate; client_max_window_bits"
+		);
+	}
+
+	#[test]
+	#[should_panic]
+	fn test_offer_invalid_window_bits() {
+		DeflateOffer::new().server_max_window_bits(16);
+	}
+
+	#[test]
+	fn test_parse_response() {
+		let offer = DeflateOffer::new()
+			.client_max_window_bits(None)
+			.server_max_window_bits(12);
+		assert_eq!(
+			offer
+				.parse_response(&response("; server_max_window_bits=12"))
+				.unwrap(),
+			DeflateParams {
+				server_max_window_bits: 12,
+				..DeflateParams::default()
+			}
+		);
+		assert_eq!(
+			offer
+				.parse_response(&response(
+					"; server_max_window_bits=\"10\"; client_max_window_bits=9; \
+					 server_no_context_takeover"
+				))
+				.unwrap(),
+			DeflateParams {
+				server_no_context_takeover: true,
+				client_no_context_takeover: false,
+				server_max_window_bits: 10,
+				client_max_window_bits: 9,
+			}
+		);
+
+		// the server may always add these
+		let plain = DeflateOffer::new();
+		assert_eq!(
+			plain
+				.parse_response(&response(
+					"; client_no_context_takeover; server_max_window_bits=10"
+				))
+				.unwrap(),
+			DeflateParams {
+				client_no_context_takeover: true,
+				server_max_window_bits: 10,
+				..DeflateParams::default()
+			}
+		);
+
+		// limits the client announced itself
+		let limited = DeflateOffer::new()
+			.client_max_window_bits(Some(11))
+			.client_no_context_takeover(true);
+		assert_eq!(
+			limited.parse_response(&response("")).unwrap(),
+			DeflateParams {
+				client_no_context_takeover: true,
+				client_max_window_bits: 11,
+				..DeflateParams::default()
+			}
+		);
+	}
+
+	#[test]
+	fn test_parse_invalid_response() {
+		let offer = DeflateOffer::new()
+			.client_max_window_bits(Some(12))
+			.server_max_window_bits(12);
+		let invalid = [
+			// not offered
+			(DeflateOffer::new(), "; client_max_window_bits=10"),
+			// larger than offered
+			(offer, "; server_max_window_bits=13"),
+			(
+				offer,
+				"; server_max_window_bits=12; client_max_window_bits=13",
+			),
+			// the server didn't agree to the smaller window
+			(offer, ""),
+			(
+				offer,
+				"; server_max_window_bits=12; server_max_window_bits=12",
+			),
+			(offer, "; server_max_window_bits=12; client_max_window_bits"),
+			(offer, "; server_max_window_bits"),
+			(offer, "; server_max_window_bits=7"),
+			(offer, "; server_max_window_bits=012"),
+			(
+				offer,
+				"; server_max_window_bits=12; client_no_context_takeover=1",
+			),
+			(offer, "; server_max_window_bits=12; x-unknown"),
+		];
+		for &(ref offer, params) in invalid.iter() {
+			assert!(
+				offer.parse_response(&response(params)).is_err(),
+				"{:?} should be invalid",
+				params
+			);
+		}
+		assert!(DeflateOffer::new()
+			.parse_response(&Extension::new("deflate-frame".to_string()))
+			.is_err());
+	}
+}
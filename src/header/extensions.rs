@@ -1,18 +1,22 @@
 //! Provides the Sec-WebSocket-Extensions header.
 
+use super::is_token;
 use hyper;
-use hyper::header::parsing::{fmt_comma_delimited, from_comma_delimited};
+use hyper::header::parsing::fmt_comma_delimited;
 use hyper::header::{Header, HeaderFormat};
 use result::{WebSocketError, WebSocketResult};
 use std::fmt;
 use std::ops::Deref;
-use std::str::FromStr;
+use std::str::{self, FromStr};
 
 const INVALID_EXTENSION: &str = "Invalid Sec-WebSocket-Extensions extension name";
-
-// TODO: check if extension name is valid according to spec
+const INVALID_PARAMETER: &str = "Invalid Sec-WebSocket-Extensions extension parameter";
+const UNTERMINATED_QUOTE: &str = "Unterminated quoted string in Sec-WebSocket-Extensions";
 
 /// Represents a Sec-WebSocket-Extensions header
+///
+/// The extensions of several header lines are merged into one list, in the
+/// order of the lines.
 #[derive(PartialEq, Clone, Debug)]
 pub struct WebSocketExtensions(pub Vec<Extension>);
 
@@ -24,6 +28,37 @@ impl Deref for WebSocketExtensions {
 	}
 }
 
+impl WebSocketExtensions {
+	/// The canonical header value, e.g.
+	/// `permessage-deflate; client_max_window_bits=10, x-foo`.
+	///
+	/// Parameter values which aren't tokens are quoted.
+	pub fn serialize(&self) -> String {
+		self.to_string()
+	}
+}
+
+impl FromStr for WebSocketExtensions {
+	type Err = WebSocketError;
+
+	/// Parses a comma separated list of extensions like
+	/// `permessage-deflate; client_max_window_bits; server_max_window_bits="10"`.
+	///
+	/// Empty list elements are skipped, anything which isn't a token (or a
+	/// quoted token for parameter values, see RFC 6455 section 9.1) is a
+	/// `WebSocketError::ProtocolError`.
+	fn from_str(s: &str) -> WebSocketResult<WebSocketExtensions> {
+		let mut extensions = Vec::new();
+		for extension in split_unquoted(s, b',')? {
+			let extension = extension.trim();
+			if !extension.is_empty() {
+				extensions.push(extension.parse()?);
+			}
+		}
+		Ok(WebSocketExtensions(extensions))
+	}
+}
+
 #[derive(PartialEq, Clone, Debug)]
 /// A WebSocket extension
 pub struct Extension {
@@ -47,22 +82,31 @@ impl FromStr for Extension {
 	type Err = WebSocketError;
 
 	fn from_str(s: &str) -> WebSocketResult<Extension> {
-		let mut ext = s.split(';').map(str::trim);
+		let mut ext = split_unquoted(s, b';')?.into_iter().map(str::trim);
+		let name = ext.next().unwrap_or("");
+		if !is_token(name) {
+			return Err(WebSocketError::ProtocolError(INVALID_EXTENSION));
+		}
+
+		let params = ext
+			.map(|param| {
+				let (name, value) = match param.find('=') {
+					Some(i) => (
+						param[..i].trim_end(),
+						Some(parse_value(param[i + 1..].trim_start())?),
+					),
+					None => (param, None),
+				};
+				if !is_token(name) {
+					return Err(WebSocketError::ProtocolError(INVALID_PARAMETER));
+				}
+				Ok(Parameter::new(name.to_string(), value))
+			})
+			.collect::<WebSocketResult<_>>()?;
+
 		Ok(Extension {
-			name: match ext.next() {
-				Some(x) => x.to_string(),
-				None => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
-			},
-			params: ext
-				.map(|x| {
-					let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
-
-					Parameter {
-						name: pair.next().unwrap(),
-						value: pair.next(),
-					}
-				})
-				.collect(),
+			name: name.to_string(),
+			params,
 		})
 	}
 }
@@ -82,7 +126,7 @@ impl fmt::Display for Extension {
 pub struct Parameter {
 	/// The name of this parameter
 	pub name: String,
-	/// The value of this parameter, if any
+	/// The value of this parameter, if any (without quotes)
 	pub value: Option<String>,
 }
 
@@ -96,20 +140,91 @@ impl Parameter {
 impl fmt::Display for Parameter {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}", self.name)?;
-		if let Some(ref x) = self.value {
-			write!(f, "={}", x)?;
+		match self.value {
+			Some(ref x) if is_token(x) => write!(f, "={}", x)?,
+			Some(ref x) => {
+				f.write_str("=\"")?;
+				for c in x.chars() {
+					if c == '"' || c == '\\' {
+						f.write_str("\\")?;
+					}
+					write!(f, "{}", c)?;
+				}
+				f.write_str("\"")?;
+			}
+			None => (),
 		}
 		Ok(())
 	}
 }
 
+// Splits `s` at every `separator` which isn't part of a quoted string.
+fn split_unquoted(s: &str, separator: u8) -> WebSocketResult<Vec<&str>> {
+	let mut parts = Vec::new();
+	let mut start = 0;
+	let mut quoted = false;
+	let mut escaped = false;
+	for (i, b) in s.bytes().enumerate() {
+		if escaped {
+			escaped = false;
+		} else if quoted {
+			match b {
+				b'\\' => escaped = true,
+				b'"' => quoted = false,
+				_ => (),
+			}
+		} else if b == b'"' {
+			quoted = true;
+		} else if b == separator {
+			parts.push(&s[start..i]);
+			start = i + 1;
+		}
+	}
+	if quoted {
+		return Err(WebSocketError::ProtocolError(UNTERMINATED_QUOTE));
+	}
+	parts.push(&s[start..]);
+	Ok(parts)
+}
+
+// Parses a parameter value, which is a token, possibly as a quoted string.
+fn parse_value(value: &str) -> WebSocketResult<String> {
+	if is_token(value) {
+		return Ok(value.to_string());
+	}
+	if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+		let mut unquoted = String::with_capacity(value.len() - 2);
+		let mut chars = value[1..value.len() - 1].chars();
+		while let Some(c) = chars.next() {
+			match c {
+				'\\' => match chars.next() {
+					Some(c) => unquoted.push(c),
+					None => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+				},
+				'"' => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+				c => unquoted.push(c),
+			}
+		}
+		if is_token(&unquoted) {
+			return Ok(unquoted);
+		}
+	}
+	Err(WebSocketError::ProtocolError(INVALID_PARAMETER))
+}
+
 impl Header for WebSocketExtensions {
 	fn header_name() -> &'static str {
 		"Sec-WebSocket-Extensions"
 	}
 
 	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketExtensions> {
-		from_comma_delimited(raw).map(WebSocketExtensions)
+		let mut extensions = Vec::new();
+		for line in raw {
+			let line = str::from_utf8(line).map_err(|_| hyper::Error::Header)?;
+			let WebSocketExtensions(mut parsed) = line.parse().map_err(|_| hyper::Error::Header)?;
+			extensions.append(&mut parsed);
+		}
+		Ok(WebSocketExtensions(extensions))
 	}
 }
 
@@ -147,6 +262,88 @@ mod tests {
 		);
 	}
 
+	fn parse(lines: &[&str]) -> hyper::Result<WebSocketExtensions> {
+		let raw: Vec<Vec<u8>> = lines.iter().map(|line| line.as_bytes().to_vec()).collect();
+		Header::parse_header(&raw[..])
+	}
+
+	#[test]
+	fn test_header_extensions_params() {
+		let extensions = parse(&[
+			" permessage-deflate ;client_max_window_bits = 10;  server_no_context_takeover , ,",
+			"x-foo; bar=\"b\\az\"; qux",
+		])
+		.unwrap();
+		let mut deflate = Extension::new("permessage-deflate".to_string());
+		deflate.params = vec![
+			Parameter::new("client_max_window_bits".to_string(), Some("10".to_string())),
+			Parameter::new("server_no_context_takeover".to_string(), None),
+		];
+		let mut foo = Extension::new("x-foo".to_string());
+		foo.params = vec![
+			Parameter::new("bar".to_string(), Some("baz".to_string())),
+			Parameter::new("qux".to_string(), None),
+		];
+		assert_eq!(extensions, WebSocketExtensions(vec![deflate, foo]));
+
+		let canonical =
+			"permessage-deflate; client_max_window_bits=10; server_no_context_takeover, x-foo; bar=baz; qux";
+		assert_eq!(extensions.serialize(), canonical);
+		assert_eq!(parse(&[canonical]).unwrap(), extensions);
+		assert_eq!(
+			canonical.parse::<WebSocketExtensions>().unwrap(),
+			extensions
+		);
+
+		// quoted commas and semicolons don't split anything, but the value
+		// is no token then
+		assert!(parse(&["foo; bar=\"a,b\", baz"]).is_err());
+		assert!(parse(&["foo; bar=\"a;b\""]).is_err());
+
+		// values which aren't tokens are quoted
+		let mut quoted = Extension::new("foo".to_string());
+		quoted.params.push(Parameter::new(
+			"bar".to_string(),
+			Some("a \"b\"".to_string()),
+		));
+		assert_eq!(
+			WebSocketExtensions(vec![quoted]).serialize(),
+			"foo; bar=\"a \\\"b\\\"\""
+		);
+
+		assert_eq!(parse(&[""]).unwrap(), WebSocketExtensions(vec![]));
+	}
+
+	#[test]
+	fn test_header_extensions_malformed() {
+		let malformed = [
+			";",
+			"foo;",
+			"foo;;bar",
+			"fo o",
+			"foo; =1",
+			"foo; bar=",
+			"foo; bar=1=2",
+			"foo; bar=\"1",
+			"foo; bar=\"\"",
+			"foo; bar=\"\\\"",
+			"foo; bar=\"a\"b\"",
+			"foo; bar=1 2",
+			"foo; b\"ar\"",
+			"\"foo\"",
+			"f\u{f6}o",
+			"foo, bar; baz=\u{e4}",
+		];
+		for value in malformed.iter() {
+			assert!(parse(&[value]).is_err(), "{:?} should be malformed", value);
+		}
+		assert!(Header::parse_header(&[vec![b'f', 0xFF]][..])
+			.map(|e: WebSocketExtensions| e)
+			.is_err());
+		// a single malformed line spoils all of them
+		assert!(parse(&["foo", "bar;"]).is_err());
+	}
+
 	#[bench]
 	fn bench_header_extensions_parse(b: &mut test::Bencher) {
 		let value = vec![b"foo, bar; baz; qux=quux".to_vec()];
@@ -3,7 +3,9 @@ use hyper;
 use hyper::header::parsing::from_one_raw_str;
 use hyper::header::{Header, HeaderFormat};
 use rand;
-use result::{WebSocketError, WebSocketResult};
+use rand::RngCore;
+use result::{ProtocolError, WebSocketError, WebSocketResult};
+use std::error::Error;
 use std::fmt::{self, Debug};
 use std::str::FromStr;
 
@@ -21,30 +23,93 @@ impl FromStr for WebSocketKey {
 	type Err = WebSocketError;
 
 	fn from_str(key: &str) -> WebSocketResult<WebSocketKey> {
-		match base64::decode(key) {
-			Ok(vec) => {
-				if vec.len() != 16 {
-					return Err(WebSocketError::ProtocolError(
-						"Sec-WebSocket-Key must be 16 bytes",
-					));
-				}
-				let mut array = [0u8; 16];
-				array[..16].clone_from_slice(&vec[..16]);
-				Ok(WebSocketKey(array))
-			}
-			Err(_) => Err(WebSocketError::ProtocolError(
-				"Invalid Sec-WebSocket-Accept",
-			)),
+		WebSocketKey::validate(key).map_err(|e| ProtocolError::InvalidKey(e).into())
+	}
+}
+
+/// Why a Sec-WebSocket-Key is invalid, see `WebSocketKey::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidKeyError {
+	/// The key is empty
+	Empty,
+	/// The key isn't the canonical (padded) Base64 encoding of some bytes
+	InvalidBase64,
+	/// The key decodes to this many bytes instead of 16
+	WrongLength(usize),
+}
+
+impl fmt::Display for InvalidKeyError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.write_str(self.message())?;
+		if let InvalidKeyError::WrongLength(len) = *self {
+			write!(fmt, " (got {} bytes)", len)?;
 		}
+		Ok(())
 	}
 }
 
+impl InvalidKeyError {
+	fn message(&self) -> &'static str {
+		match *self {
+			InvalidKeyError::Empty => "Sec-WebSocket-Key is empty",
+			InvalidKeyError::InvalidBase64 => "Sec-WebSocket-Key is not valid Base64",
+			InvalidKeyError::WrongLength(_) => "Sec-WebSocket-Key must be 16 bytes",
+		}
+	}
+}
+
+impl Error for InvalidKeyError {}
+
 impl WebSocketKey {
 	/// Generate a new, random WebSocketKey
 	pub fn new() -> WebSocketKey {
 		let key = rand::random();
 		WebSocketKey(key)
 	}
+
+	/// Generate a new WebSocketKey with the given random number generator,
+	/// e.g. a seeded one for reproducible handshakes.
+	pub fn new_with_rng<R: RngCore>(rng: &mut R) -> WebSocketKey {
+		let mut key = [0; 16];
+		rng.fill_bytes(&mut key);
+		WebSocketKey(key)
+	}
+
+	/// Create a WebSocketKey from its 16 bytes.
+	pub fn from_bytes(key: [u8; 16]) -> WebSocketKey {
+		WebSocketKey(key)
+	}
+
+	/// Create a WebSocketKey from its Base64 encoding, e.g. the value of a
+	/// Sec-WebSocket-Key header, which `serialize` returns again.
+	///
+	/// The value has to be the canonical (padded) encoding of 16 bytes,
+	/// otherwise this fails with a `WebSocketError::ProtocolError`.
+	pub fn from_serialized(key: &str) -> WebSocketResult<WebSocketKey> {
+		key.parse()
+	}
+
+	/// Check the Sec-WebSocket-Key a client sent, which has to be the
+	/// canonical Base64 encoding of 16 bytes, and tell what's wrong with it
+	/// otherwise. Servers should answer invalid keys with 400 Bad Request.
+	pub fn validate(key: &str) -> Result<WebSocketKey, InvalidKeyError> {
+		if key.is_empty() {
+			return Err(InvalidKeyError::Empty);
+		}
+		let bytes = base64::decode(key).map_err(|_| InvalidKeyError::InvalidBase64)?;
+		if bytes.len() != 16 {
+			return Err(InvalidKeyError::WrongLength(bytes.len()));
+		}
+		let mut array = [0u8; 16];
+		array.copy_from_slice(&bytes);
+		let parsed = WebSocketKey(array);
+		// the accept value is computed from the serialized key, so it has to
+		// be exactly what the client sent
+		if parsed.serialize() != key {
+			return Err(InvalidKeyError::InvalidBase64);
+		}
+		Ok(parsed)
+	}
 	/// Return the Base64 encoding of this WebSocketKey
 	pub fn serialize(&self) -> String {
 		let WebSocketKey(key) = *self;
@@ -93,13 +158,79 @@ mod tests {
 		let key = WebSocketKey::from_str("YSByZWFsbCBnb29kIGtleQ==");
 		assert!(key.is_ok()); // 16 bytes
 
+		// < 16 bytes
 		let key = WebSocketKey::from_str("YSBzaG9ydCBrZXk=");
-		assert!(key.is_err()); // < 16 bytes
+		match key {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidKey(
+				InvalidKeyError::WrongLength(11),
+			))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
 
 		let key = WebSocketKey::from_str("YSB2ZXJ5IHZlcnkgbG9uZyBrZXk=");
 		assert!(key.is_err()); // > 16 bytes
 	}
 
+	#[test]
+	fn test_validate_key() {
+		use header::WebSocketAccept;
+
+		let key = WebSocketKey::validate("dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+		assert_eq!(
+			WebSocketAccept::new(&key).serialize(),
+			"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+		);
+
+		assert_eq!(WebSocketKey::validate(""), Err(InvalidKeyError::Empty));
+		// 15 bytes
+		assert_eq!(
+			WebSocketKey::validate("dGhlIHNhbXBsZSBub25j"),
+			Err(InvalidKeyError::WrongLength(15))
+		);
+		assert_eq!(
+			WebSocketKey::validate("dGhlIHNhbXBsZSBub25jZQ==").map(|_| ()),
+			Ok(())
+		);
+		for key in [
+			"dGhlIHNhbXBsZSBub25jZQ=!",
+			"dGhlIHNhbXBsZSBub25jZQ",
+			"dGhlIHNhbXBsZSBub25jZR==",
+			"dGhlIHNhbXBs ZSBub25jZQ==",
+		]
+		.iter()
+		{
+			assert_eq!(
+				WebSocketKey::validate(key),
+				Err(InvalidKeyError::InvalidBase64),
+				"{}",
+				key
+			);
+		}
+		assert_eq!(
+			InvalidKeyError::WrongLength(15).to_string(),
+			"Sec-WebSocket-Key must be 16 bytes (got 15 bytes)"
+		);
+	}
+
+	#[test]
+	fn test_header_key_construction() {
+		let serialized = "dGhlIHNhbXBsZSBub25jZQ==";
+		let key = WebSocketKey::from_serialized(serialized).unwrap();
+		assert_eq!(key, WebSocketKey::from_bytes(*b"the sample nonce"));
+		assert_eq!(key.serialize(), serialized);
+
+		// only canonical encodings, which serialize to the same string
+		assert!(WebSocketKey::from_serialized("dGhlIHNhbXBsZSBub25jZR==").is_err());
+		assert!(WebSocketKey::from_serialized("dGhlIHNhbXBsZSBub25jZQ").is_err());
+		assert!(WebSocketKey::from_serialized(" dGhlIHNhbXBsZSBub25jZQ==").is_err());
+
+		use rand::rngs::mock::StepRng;
+		let first = WebSocketKey::new_with_rng(&mut StepRng::new(1, 1));
+		let second = WebSocketKey::new_with_rng(&mut StepRng::new(1, 1));
+		assert_eq!(first, second);
+		assert_ne!(first, WebSocketKey::new_with_rng(&mut StepRng::new(2, 1)));
+	}
+
 	#[bench]
 	fn bench_header_key_new(b: &mut test::Bencher) {
 		b.iter(|| {
@@ -5,15 +5,92 @@
 
 pub use self::accept::WebSocketAccept;
 pub use self::extensions::WebSocketExtensions;
-pub use self::key::WebSocketKey;
-pub use self::origin::Origin;
+pub use self::key::{InvalidKeyError, WebSocketKey};
+pub use self::origin::{Origin, OriginMatcher, ParsedOrigin};
 pub use self::protocol::WebSocketProtocol;
 pub use self::version::WebSocketVersion;
 pub use hyper::header::*;
 
 mod accept;
+pub mod deflate;
 pub mod extensions;
 mod key;
 mod origin;
 mod protocol;
 mod version;
+
+/// The elements of the comma separated list header `name`, in the order of
+/// its lines, which are treated as one list as RFC 7230 requires.
+///
+/// Whitespace around the elements is removed and empty ones are skipped,
+/// invalid UTF-8 isn't part of any element. `None` if the header is
+/// missing.
+pub fn header_tokens<'a>(headers: &'a Headers, name: &str) -> Option<Vec<&'a str>> {
+	headers.get_raw(name).map(|lines| {
+		lines
+			.iter()
+			.filter_map(|line| ::std::str::from_utf8(line).ok())
+			.flat_map(|line| line.split(','))
+			.map(str::trim)
+			.filter(|token| !token.is_empty())
+			.collect()
+	})
+}
+
+/// Whether the comma separated list header `name` contains `token`,
+/// ignoring ASCII case, e.g. `Upgrade` in `Connection: keep-alive, upgrade`.
+pub fn has_token(headers: &Headers, name: &str, token: &str) -> bool {
+	header_tokens(headers, name)
+		.map(|tokens| tokens.iter().any(|t| t.eq_ignore_ascii_case(token)))
+		.unwrap_or(false)
+}
+
+// Whether `s` is a token as defined by RFC 7230 section 3.2.6.
+fn is_token(s: &str) -> bool {
+	!s.is_empty()
+		&& s.bytes().all(|b| match b {
+			b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+			| b'`' | b'|' | b'~' => true,
+			_ => b.is_ascii_alphanumeric(),
+		})
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+
+	fn headers(name: &str, lines: &[&str]) -> Headers {
+		let mut headers = Headers::new();
+		let lines = lines.iter().map(|line| line.as_bytes().to_vec()).collect();
+		headers.set_raw(name.to_string(), lines);
+		headers
+	}
+
+	#[test]
+	fn test_header_tokens() {
+		let connection = headers("Connection", &["keep-alive, Upgrade"]);
+		assert_eq!(
+			header_tokens(&connection, "connection"),
+			Some(vec!["keep-alive", "Upgrade"])
+		);
+		assert!(has_token(&connection, "Connection", "upgrade"));
+		assert!(!has_token(&connection, "Connection", "close"));
+
+		let upgrade = headers("Upgrade", &["WebSocket"]);
+		assert!(has_token(&upgrade, "Upgrade", "websocket"));
+
+		let lines = headers("Connection", &[" keep-alive ,, ", "", "\tUPGRADE\t,x"]);
+		assert_eq!(
+			header_tokens(&lines, "Connection"),
+			Some(vec!["keep-alive", "UPGRADE", "x"])
+		);
+		assert!(has_token(&lines, "Connection", "Upgrade"));
+
+		// no partial matches
+		let other = headers("Connection", &["upgraded, keep-alive upgrade"]);
+		assert!(!has_token(&other, "Connection", "upgrade"));
+
+		assert_eq!(header_tokens(&Headers::new(), "Connection"), None);
+		assert!(!has_token(&Headers::new(), "Connection", "upgrade"));
+	}
+}
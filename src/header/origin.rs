@@ -1,13 +1,178 @@
 use hyper;
 use hyper::header::parsing::from_one_raw_str;
 use hyper::header::{Header, HeaderFormat};
+use result::{WebSocketError, WebSocketResult};
 use std::fmt;
 use std::ops::Deref;
+use std::str::FromStr;
+use url::Url;
+
+const INVALID_ORIGIN: &str = "Invalid Origin";
 
 /// Represents an Origin header
+///
+/// Browsers send it with every handshake, servers should check it (see
+/// `matches`) so other websites can't connect to them in the name of their
+/// users.
 #[derive(PartialEq, Clone, Debug)]
 pub struct Origin(pub String);
 
+impl Origin {
+	/// Parse the value of this header, see `ParsedOrigin`.
+	pub fn parse(&self) -> WebSocketResult<ParsedOrigin> {
+		self.0.parse()
+	}
+
+	/// Whether this origin is valid and allowed by `allowed`.
+	pub fn matches(&self, allowed: &OriginMatcher) -> bool {
+		self.parse()
+			.map(|origin| allowed.matches(&origin))
+			.unwrap_or(false)
+	}
+}
+
+/// The value of an Origin header, as defined by RFC 6454.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ParsedOrigin {
+	/// The `null` origin, which browsers send e.g. for local files and
+	/// sandboxed documents.
+	Null,
+	/// An origin like `https://example.com:8443`.
+	Tuple {
+		/// The scheme, in lower case
+		scheme: String,
+		/// The host, in lower case, with IPv6 addresses in brackets
+		host: String,
+		/// The port, the default one of the scheme if the origin has none
+		port: u16,
+	},
+}
+
+impl FromStr for ParsedOrigin {
+	type Err = WebSocketError;
+
+	/// Parses `null` or `scheme://host[:port]`, anything else (e.g. a path
+	/// or credentials) is a `WebSocketError::ProtocolError`.
+	fn from_str(s: &str) -> WebSocketResult<ParsedOrigin> {
+		if s == "null" {
+			return Ok(ParsedOrigin::Null);
+		}
+		let invalid = || WebSocketError::ProtocolError(INVALID_ORIGIN);
+		let url = Url::parse(s).map_err(|_| invalid())?;
+		let has_path = url.path() != "/" || s.ends_with('/');
+		if has_path
+			|| !url.username().is_empty()
+			|| url.password().is_some()
+			|| url.query().is_some()
+			|| url.fragment().is_some()
+		{
+			return Err(invalid());
+		}
+		let host = url.host_str().ok_or_else(invalid)?;
+		let port = url.port_or_known_default().ok_or_else(invalid)?;
+		Ok(ParsedOrigin::Tuple {
+			scheme: url.scheme().to_string(),
+			host: host.to_lowercase(),
+			port,
+		})
+	}
+}
+
+impl fmt::Display for ParsedOrigin {
+	/// The serialized origin, without the port if it's the default one.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParsedOrigin::Null => f.write_str("null"),
+			ParsedOrigin::Tuple {
+				ref scheme,
+				ref host,
+				port,
+			} => {
+				write!(f, "{}://{}", scheme, host)?;
+				if Some(port) != default_port(scheme) {
+					write!(f, ":{}", port)?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+	Url::parse(&format!("{}://example.com", scheme))
+		.ok()
+		.and_then(|url| url.port_or_known_default())
+}
+
+/// A policy for which origins may connect, see `Origin::matches`.
+///
+/// Matchers can be parsed from strings like `https://example.com`,
+/// `https://*.example.com` or `null`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum OriginMatcher {
+	/// Exactly this origin.
+	Exact(ParsedOrigin),
+	/// The subdomains of `host` (but not `host` itself) with the given scheme
+	/// and port, i.e. `https://*.example.com`.
+	Subdomains {
+		/// The scheme, in lower case
+		scheme: String,
+		/// The domain the subdomains belong to, in lower case
+		host: String,
+		/// The port
+		port: u16,
+	},
+	/// Any origin which one of these allows.
+	AnyOf(Vec<OriginMatcher>),
+}
+
+impl OriginMatcher {
+	/// Whether `origin` is allowed.
+	pub fn matches(&self, origin: &ParsedOrigin) -> bool {
+		match *self {
+			OriginMatcher::Exact(ref allowed) => allowed == origin,
+			OriginMatcher::Subdomains {
+				ref scheme,
+				ref host,
+				port,
+			} => match *origin {
+				ParsedOrigin::Tuple {
+					scheme: ref origin_scheme,
+					host: ref origin_host,
+					port: origin_port,
+				} => {
+					origin_scheme == scheme
+						&& origin_port == port
+						&& origin_host.len() > host.len() + 1
+						&& origin_host.ends_with(&host[..])
+						&& origin_host[..origin_host.len() - host.len()].ends_with('.')
+				}
+				ParsedOrigin::Null => false,
+			},
+			OriginMatcher::AnyOf(ref matchers) => {
+				matchers.iter().any(|matcher| matcher.matches(origin))
+			}
+		}
+	}
+}
+
+impl FromStr for OriginMatcher {
+	type Err = WebSocketError;
+
+	fn from_str(s: &str) -> WebSocketResult<OriginMatcher> {
+		let wildcard = s.find("://*.").map(|i| (&s[..i], &s[i + 5..]));
+		match wildcard {
+			Some((scheme, domain)) => match format!("{}://{}", scheme, domain).parse()? {
+				ParsedOrigin::Tuple { scheme, host, port } => {
+					Ok(OriginMatcher::Subdomains { scheme, host, port })
+				}
+				ParsedOrigin::Null => Err(WebSocketError::ProtocolError(INVALID_ORIGIN)),
+			},
+			None => s.parse().map(OriginMatcher::Exact),
+		}
+	}
+}
+
 impl Deref for Origin {
 	type Target = String;
 	fn deref(&self) -> &String {
@@ -55,6 +220,113 @@ mod tests {
 		assert_eq!(&headers.to_string()[..], "Origin: foo bar\r\n");
 	}
 
+	fn parsed(scheme: &str, host: &str, port: u16) -> ParsedOrigin {
+		ParsedOrigin::Tuple {
+			scheme: scheme.to_string(),
+			host: host.to_string(),
+			port,
+		}
+	}
+
+	#[test]
+	fn test_parse_origin() {
+		let origins = [
+			("null", ParsedOrigin::Null, "null"),
+			(
+				"https://example.com",
+				parsed("https", "example.com", 443),
+				"https://example.com",
+			),
+			(
+				"HTTPS://Example.COM:443",
+				parsed("https", "example.com", 443),
+				"https://example.com",
+			),
+			(
+				"http://example.com:8080",
+				parsed("http", "example.com", 8080),
+				"http://example.com:8080",
+			),
+			(
+				"http://127.0.0.1",
+				parsed("http", "127.0.0.1", 80),
+				"http://127.0.0.1",
+			),
+			(
+				"http://[::1]:8080",
+				parsed("http", "[::1]", 8080),
+				"http://[::1]:8080",
+			),
+			(
+				"https://[0:0::1]:443",
+				parsed("https", "[::1]", 443),
+				"https://[::1]",
+			),
+		];
+		for &(value, ref expected, serialized) in origins.iter() {
+			let origin = Origin(value.to_string()).parse().unwrap();
+			assert_eq!(origin, *expected);
+			assert_eq!(origin.to_string(), serialized);
+		}
+
+		let invalid = [
+			"",
+			"Null",
+			"example.com",
+			"https://",
+			"https://example.com/",
+			"https://example.com/path",
+			"https://example.com?query",
+			"https://user@example.com",
+			"https://example.com:99999",
+			"http://[::1",
+			"foo://example.com",
+		];
+		for value in invalid.iter() {
+			assert!(
+				Origin(value.to_string()).parse().is_err(),
+				"{:?} should be invalid",
+				value
+			);
+		}
+	}
+
+	#[test]
+	fn test_match_origin() {
+		let allowed = OriginMatcher::AnyOf(vec![
+			"https://example.com".parse().unwrap(),
+			"https://*.example.org".parse().unwrap(),
+			"http://[::1]:8080".parse().unwrap(),
+		]);
+		let origins = [
+			("https://example.com", true),
+			("https://example.com:443", true),
+			("http://example.com", false),
+			("https://www.example.com", false),
+			("https://www.example.org", true),
+			("https://a.b.example.org", true),
+			("https://example.org", false),
+			("https://evilexample.org", false),
+			("https://www.example.org:8443", false),
+			("http://[::1]:8080", true),
+			("http://[::1]", false),
+			("null", false),
+			("https://example.com/", false),
+		];
+		for &(value, expected) in origins.iter() {
+			assert_eq!(
+				Origin(value.to_string()).matches(&allowed),
+				expected,
+				"{}",
+				value
+			);
+		}
+
+		let null = "null".parse().unwrap();
+		assert!(Origin("null".to_string()).matches(&null));
+		assert!("null://*.example.com".parse::<OriginMatcher>().is_err());
+	}
+
 	#[bench]
 	fn bench_header_origin_parse(b: &mut test::Bencher) {
 		let value = vec![b"foobar".to_vec()];
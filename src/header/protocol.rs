@@ -1,12 +1,18 @@
+use super::is_token;
 use hyper;
-use hyper::header::parsing::{fmt_comma_delimited, from_comma_delimited};
+use hyper::header::parsing::fmt_comma_delimited;
 use hyper::header::{Header, HeaderFormat};
+use result::{WebSocketError, WebSocketResult};
 use std::fmt;
 use std::ops::Deref;
+use std::str::{self, FromStr};
 
-// TODO: only allow valid protocol names to be added
+const INVALID_PROTOCOL: &str = "Invalid Sec-WebSocket-Protocol protocol name";
 
 /// Represents a Sec-WebSocket-Protocol header
+///
+/// The protocols of several header lines are merged into one list, in the
+/// order of the lines.
 #[derive(PartialEq, Clone, Debug)]
 pub struct WebSocketProtocol(pub Vec<String>);
 
@@ -17,13 +23,64 @@ impl Deref for WebSocketProtocol {
 	}
 }
 
+impl WebSocketProtocol {
+	/// The header value, the protocols separated by commas.
+	pub fn serialize(&self) -> String {
+		self.to_string()
+	}
+
+	/// The protocol a server should pick from the protocols its client
+	/// offered, i.e. the first offered one which the server supports (see
+	/// RFC 6455 section 1.9), or `None` if it supports none of them.
+	///
+	///```rust
+	///use websocket::header::WebSocketProtocol;
+	///
+	///let offered: WebSocketProtocol = "chat.v2, chat".parse().unwrap();
+	///assert_eq!(offered.select(&["chat", "chat.v2"]), Some("chat.v2"));
+	///assert_eq!(offered.select(&["mqtt"]), None);
+	///```
+	pub fn select(&self, supported: &[&str]) -> Option<&str> {
+		self.iter()
+			.map(String::as_str)
+			.find(|protocol| supported.contains(protocol))
+	}
+}
+
+impl FromStr for WebSocketProtocol {
+	type Err = WebSocketError;
+
+	/// Parses a comma separated list of protocols, empty list elements are
+	/// skipped and protocols which aren't tokens are a
+	/// `WebSocketError::ProtocolError`.
+	fn from_str(s: &str) -> WebSocketResult<WebSocketProtocol> {
+		let mut protocols = Vec::new();
+		for protocol in s.split(',').map(str::trim) {
+			if protocol.is_empty() {
+				continue;
+			}
+			if !is_token(protocol) {
+				return Err(WebSocketError::ProtocolError(INVALID_PROTOCOL));
+			}
+			protocols.push(protocol.to_string());
+		}
+		Ok(WebSocketProtocol(protocols))
+	}
+}
+
 impl Header for WebSocketProtocol {
 	fn header_name() -> &'static str {
 		"Sec-WebSocket-Protocol"
 	}
 
 	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketProtocol> {
-		from_comma_delimited(raw).map(WebSocketProtocol)
+		let mut protocols = Vec::new();
+		for line in raw {
+			let line = str::from_utf8(line).map_err(|_| hyper::Error::Header)?;
+			let WebSocketProtocol(mut parsed) = line.parse().map_err(|_| hyper::Error::Header)?;
+			protocols.append(&mut parsed);
+		}
+		Ok(WebSocketProtocol(protocols))
 	}
 }
 
@@ -60,6 +117,39 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn test_header_protocol_parse() {
+		let raw = vec![b" chat.v2 ,, chat".to_vec(), b"mqtt".to_vec()];
+		let protocols: WebSocketProtocol = Header::parse_header(&raw[..]).unwrap();
+		assert_eq!(
+			protocols,
+			WebSocketProtocol(vec![
+				"chat.v2".to_string(),
+				"chat".to_string(),
+				"mqtt".to_string()
+			])
+		);
+		assert_eq!(protocols.serialize(), "chat.v2, chat, mqtt");
+		assert_eq!(
+			protocols.serialize().parse::<WebSocketProtocol>().unwrap(),
+			protocols
+		);
+
+		assert_eq!(protocols.select(&["mqtt", "chat"]), Some("chat"));
+		assert_eq!(protocols.select(&["Chat"]), None);
+		assert_eq!(protocols.select(&[]), None);
+
+		for value in ["chat v2", "chat;v2", "\"chat\"", "ch\u{e4}t", "chat/2"].iter() {
+			assert!(
+				value.parse::<WebSocketProtocol>().is_err(),
+				"{:?} should be invalid",
+				value
+			);
+			let raw = vec![b"chat".to_vec(), value.as_bytes().to_vec()];
+			assert!(<WebSocketProtocol as Header>::parse_header(&raw[..]).is_err());
+		}
+	}
+
 	#[bench]
 	fn bench_header_protocol_parse(b: &mut test::Bencher) {
 		let value = vec![b"foo, bar".to_vec()];
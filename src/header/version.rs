@@ -1,9 +1,15 @@
 use hyper;
-use hyper::header::parsing::from_one_raw_str;
 use hyper::header::{Header, HeaderFormat};
+use result::{ProtocolError, WebSocketResult};
 use std::fmt::{self, Debug};
+use std::str;
 
 /// Represents a Sec-WebSocket-Version header
+///
+/// A client asks for one version, a server which doesn't support it may list
+/// the versions it supports instead, e.g. `13, 8, 7`. Such a list (or the
+/// values of several header lines, which are joined with commas) is kept as
+/// `Unknown`, see `versions`.
 #[derive(PartialEq, Clone)]
 pub enum WebSocketVersion {
 	/// The version of WebSocket defined in RFC6455
@@ -21,15 +27,55 @@ impl fmt::Debug for WebSocketVersion {
 	}
 }
 
+impl WebSocketVersion {
+	/// The header value, `13` for the version defined in RFC6455.
+	pub fn serialize(&self) -> String {
+		self.to_string()
+	}
+
+	/// The versions in this header, failing with a
+	/// `ProtocolError::InvalidVersion` if one of them isn't a number from 0
+	/// to 255.
+	pub fn versions(&self) -> WebSocketResult<Vec<u8>> {
+		match *self {
+			WebSocketVersion::WebSocket13 => Ok(vec![13]),
+			WebSocketVersion::Unknown(ref value) => value
+				.split(',')
+				.map(str::trim)
+				.map(|version| match version.parse::<u8>() {
+					Ok(number) if version.bytes().all(|b| b.is_ascii_digit()) => Ok(number),
+					_ => Err(ProtocolError::InvalidVersion.into()),
+				})
+				.collect(),
+		}
+	}
+
+	/// Whether `version` is one of the versions in this header.
+	pub fn contains(&self, version: u8) -> bool {
+		self.versions()
+			.map(|versions| versions.contains(&version))
+			.unwrap_or(false)
+	}
+}
+
 impl Header for WebSocketVersion {
 	fn header_name() -> &'static str {
 		"Sec-WebSocket-Version"
 	}
 
 	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketVersion> {
-		from_one_raw_str(raw).map(|s: String| match &s[..] {
+		if raw.is_empty() {
+			return Err(hyper::Error::Header);
+		}
+		let lines = raw
+			.iter()
+			.map(|line| str::from_utf8(line).map(str::trim))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|_| hyper::Error::Header)?;
+		let value = lines.join(", ");
+		Ok(match &value[..] {
 			"13" => WebSocketVersion::WebSocket13,
-			_ => WebSocketVersion::Unknown(s),
+			_ => WebSocketVersion::Unknown(value),
 		})
 	}
 }
@@ -50,6 +96,7 @@ impl fmt::Display for WebSocketVersion {
 mod tests {
 	use super::*;
 	use hyper::header::Header;
+	use result::WebSocketError;
 	use test;
 
 	#[test]
@@ -63,6 +110,31 @@ mod tests {
 		assert_eq!(&headers.to_string()[..], "Sec-WebSocket-Version: 13\r\n");
 	}
 
+	#[test]
+	fn test_websocket_version_list() {
+		let raw = vec![b"13".to_vec()];
+		let version: WebSocketVersion = Header::parse_header(&raw[..]).unwrap();
+		assert_eq!(version, WebSocketVersion::WebSocket13);
+		assert_eq!(version.serialize(), "13");
+		assert_eq!(version.versions().unwrap(), vec![13]);
+
+		let raw = vec![b"13, 8".to_vec(), b" 7 ".to_vec()];
+		let versions: WebSocketVersion = Header::parse_header(&raw[..]).unwrap();
+		assert_eq!(versions.serialize(), "13, 8, 7");
+		assert_eq!(versions.versions().unwrap(), vec![13, 8, 7]);
+		assert!(versions.contains(13) && versions.contains(7));
+		assert!(!versions.contains(9));
+
+		for value in ["", "13,", "256", "+8", "8 7", "x"].iter() {
+			let version = WebSocketVersion::Unknown(value.to_string());
+			match version.versions() {
+				Err(WebSocketError::Protocol(ProtocolError::InvalidVersion)) => (),
+				other => panic!("{:?} should be invalid, got {:?}", value, other),
+			}
+			assert!(!version.contains(8));
+		}
+	}
+
 	#[bench]
 	fn bench_header_version_parse(b: &mut test::Bencher) {
 		let value = vec![b"13".to_vec()];
@@ -45,7 +45,18 @@ pub extern crate futures;
 extern crate hyper;
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 extern crate native_tls;
+#[cfg(feature = "sync")]
+extern crate net2;
 extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(feature = "serde", test))]
+extern crate bincode;
+#[cfg(all(feature = "async", unix))]
+extern crate mio;
+#[cfg(all(feature = "serde", test))]
+extern crate serde_json;
 extern crate sha1;
 #[cfg(test)]
 extern crate tokio;
@@ -57,6 +68,8 @@ extern crate tokio_io;
 extern crate tokio_reactor;
 #[cfg(feature = "async")]
 extern crate tokio_tcp;
+#[cfg(feature = "async")]
+extern crate tokio_timer;
 #[cfg(feature = "async-ssl")]
 extern crate tokio_tls;
 extern crate unicase;
@@ -83,6 +96,7 @@ macro_rules! upsert_header {
 pub mod dataframe;
 pub mod header;
 pub mod message;
+pub mod ping;
 pub mod result;
 pub mod ws;
 
@@ -121,7 +135,9 @@ pub mod sync {
 
 	/// A collection of handy synchronous-only parts of the `client` module.
 	pub mod client {
-		pub use client::builder::ClientBuilder;
+		#[cfg(feature = "sync-ssl")]
+		pub use client::builder::TlsOptions;
+		pub use client::builder::{ClientBuilder, ConnectOptions, HttpProxy};
 		pub use client::sync::*;
 	}
 	pub use client::sync::Client;
@@ -131,10 +147,21 @@ pub mod sync {
 #[cfg(feature = "async")]
 pub mod async {
 	pub use codec;
+	pub use codec::control::{separate_control, AutoPong, CloseHandshake};
+	pub use codec::control::{ControlDispatch, ControlMessages, DataMessages};
+	pub use codec::deflate::{Compressor, Decompressor, DeflateBackend, DeflateConfig};
 	pub use codec::http::HttpClientCodec;
 	pub use codec::http::HttpServerCodec;
+	pub use codec::ws::into_raw_parts;
 	pub use codec::ws::Context as MsgCodecCtx;
+	pub use codec::ws::DataFrameCodec;
+	pub use codec::ws::InvalidUtf8;
 	pub use codec::ws::MessageCodec;
+	pub use codec::ws::MessageCodecBuilder;
+	pub use codec::ws::Outgoing;
+	pub use codec::ws::Payload;
+	pub use codec::ws::{CodecOptions, CodecStats};
+	pub use codec::ws::{MessageChunk, MessageChunkCodec};
 
 	pub use stream::async as stream;
 	pub use stream::async::Stream;
@@ -151,7 +178,9 @@ pub mod async {
 	/// A collection of handy asynchronous-only parts of the `client` module.
 	pub mod client {
 		pub use client::async::*;
-		pub use client::builder::ClientBuilder;
+		#[cfg(feature = "async-ssl")]
+		pub use client::builder::TlsOptions;
+		pub use client::builder::{ClientBuilder, ConnectOptions, HttpProxy};
 	}
 	pub use client::async::Client;
 
@@ -164,7 +193,11 @@ pub mod async {
 }
 
 pub use self::client::builder::ClientBuilder;
+#[cfg(feature = "async")]
+pub use self::codec::ws::DataFrameCodec;
+pub use self::message::CloseCode;
 pub use self::message::CloseData;
+pub use self::message::GenericMessage;
 pub use self::message::Message;
 pub use self::message::OwnedMessage;
 
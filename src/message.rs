@@ -1,17 +1,23 @@
 //! Module containing the default implementation for messages.
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use dataframe::Opcode;
-use result::{WebSocketError, WebSocketResult};
+use dataframe::{MessageDataFrames, Opcode};
+use result::{ProtocolError, WebSocketError, WebSocketResult};
 use std::borrow::Cow;
+use std::cmp;
 use std::io;
 use std::io::Write;
 use std::str::from_utf8;
+use std::string::FromUtf8Error;
 use ws;
 use ws::dataframe::DataFrame as DataFrameTrait;
 use ws::util::bytes_to_string;
 
 const FALSE_RESERVED_BITS: &[bool; 3] = &[false; 3];
 
+/// The longest reason a close message can have, so it fits into a control
+/// frame together with the status code.
+const MAX_CLOSE_REASON: usize = 123;
+
 /// Valid types of messages (in the default implementation)
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum Type {
@@ -27,6 +33,18 @@ pub enum Type {
 	Close = 8,
 }
 
+impl From<Type> for Opcode {
+	fn from(message_type: Type) -> Opcode {
+		match message_type {
+			Type::Text => Opcode::Text,
+			Type::Binary => Opcode::Binary,
+			Type::Ping => Opcode::Ping,
+			Type::Pong => Opcode::Pong,
+			Type::Close => Opcode::Close,
+		}
+	}
+}
+
 /// Represents a WebSocket message.
 ///
 /// This message also has the ability to not own its payload, and stores its entire payload in
@@ -70,6 +88,25 @@ impl<'a> Message<'a> {
 		)
 	}
 
+	/// Create a new WebSocket message with text data from bytes which should
+	/// be UTF-8, invalid sequences are replaced with `U+FFFD`. Valid data
+	/// isn't copied.
+	///
+	///```rust
+	///# use websocket::Message;
+	///let message = Message::text_lossy(&b"caf\xe9"[..]);
+	///assert_eq!(message, Message::text("caf\u{FFFD}"));
+	///```
+	pub fn text_lossy<B>(data: B) -> Self
+	where
+		B: IntoCowBytes<'a>,
+	{
+		Message::text(match data.into() {
+			Cow::Borrowed(data) => String::from_utf8_lossy(data),
+			Cow::Owned(data) => Cow::Owned(string_from_utf8_lossy(data)),
+		})
+	}
+
 	/// Create a new WebSocket message with binary data
 	pub fn binary<B>(data: B) -> Self
 	where
@@ -87,6 +124,11 @@ impl<'a> Message<'a> {
 	/// Create a new WebSocket message that signals the end of a WebSocket
 	/// connection and provide a text reason and a status code for why.
 	/// Messages can still be sent after sending this message.
+	///
+	/// The reason can be at most 123 bytes long, sending a longer one fails.
+	/// `try_close_because` checks this right away and
+	/// `close_because_truncating` shortens the reason instead. See
+	/// `close_with` for a `CloseCode` instead of a raw status code.
 	pub fn close_because<S>(code: u16, reason: S) -> Self
 	where
 		S: Into<Cow<'a, str>>,
@@ -101,8 +143,62 @@ impl<'a> Message<'a> {
 		)
 	}
 
+	/// Like `close_because`, but a reason longer than 123 bytes is a
+	/// `WebSocketError::DataFrameError` instead of a message which can't be
+	/// sent.
+	pub fn try_close_because<S>(code: u16, reason: S) -> WebSocketResult<Self>
+	where
+		S: Into<Cow<'a, str>>,
+	{
+		let reason = reason.into();
+		if reason.len() > MAX_CLOSE_REASON {
+			return Err(WebSocketError::DataFrameError("Close reason too long"));
+		}
+		Ok(Message::close_because(code, reason))
+	}
+
+	/// Like `close_because`, but a reason longer than 123 bytes is cut off at
+	/// the last character which still fits.
+	///
+	/// ```rust
+	/// # use websocket::Message;
+	/// let message = Message::close_because_truncating(1000, "é".repeat(100));
+	/// assert_eq!(message.payload.len(), 122);
+	/// ```
+	pub fn close_because_truncating<S>(code: u16, reason: S) -> Self
+	where
+		S: Into<Cow<'a, str>>,
+	{
+		let reason = match reason.into() {
+			Cow::Borrowed(reason) => Cow::Borrowed(&reason[..reason_boundary(reason)]),
+			Cow::Owned(mut reason) => {
+				let end = reason_boundary(&reason);
+				reason.truncate(end);
+				Cow::Owned(reason)
+			}
+		};
+		Message::close_because(code, reason)
+	}
+
+	/// Create a new WebSocket message that signals the end of a WebSocket
+	/// connection with a `CloseCode` and a text reason, like `close_because`.
+	///
+	/// ```rust
+	/// # use websocket::{CloseCode, Message};
+	/// let message = Message::close_with(CloseCode::GoingAway, "shutting down");
+	/// assert_eq!(message.cd_status_code, Some(1001));
+	/// ```
+	pub fn close_with<S>(code: CloseCode, reason: S) -> Self
+	where
+		S: Into<Cow<'a, str>>,
+	{
+		Message::close_because(code.into(), reason)
+	}
+
 	/// Create a ping WebSocket message, a pong is usually sent back
 	/// after sending this with the same data
+	///
+	/// The data can be at most 125 bytes long, sending more fails.
 	pub fn ping<P>(data: P) -> Self
 	where
 		P: IntoCowBytes<'a>,
@@ -112,6 +208,10 @@ impl<'a> Message<'a> {
 
 	/// Create a pong WebSocket message, usually a response to a
 	/// ping message
+	///
+	/// The data can be at most 125 bytes long, sending more fails. It may
+	/// also be empty, and a pong can be sent without any ping as a one-way
+	/// heartbeat, the other end doesn't answer it.
 	pub fn pong<P>(data: P) -> Self
 	where
 		P: IntoCowBytes<'a>,
@@ -123,6 +223,8 @@ impl<'a> Message<'a> {
 	#[cfg_attr(feature = "cargo-clippy", allow(wrong_self_convention))]
 	/// Convert a ping message to a pong, keeping the data.
 	/// This will fail if the original message is not a ping.
+	///
+	/// The message is changed in place, its payload isn't copied.
 	pub fn into_pong(&mut self) -> Result<(), ()> {
 		if self.opcode == Type::Ping {
 			self.opcode = Type::Pong;
@@ -131,6 +233,85 @@ impl<'a> Message<'a> {
 			Err(())
 		}
 	}
+
+	/// The opcode of the dataframe this message is sent in.
+	pub fn opcode(&self) -> Opcode {
+		self.opcode.into()
+	}
+
+	/// The length of the payload as it is sent, for a close message with a
+	/// status code that's the 2 bytes of the code and the reason.
+	///
+	///```rust
+	///# use websocket::Message;
+	///assert_eq!(Message::text("hello").len(), 5);
+	///assert_eq!(Message::close_because(1000, "bye").len(), 5);
+	///assert_eq!(Message::close().len(), 0);
+	///```
+	pub fn len(&self) -> usize {
+		ws::dataframe::DataFrame::size(self)
+	}
+
+	/// Whether the payload is empty, see `len`.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The dataframes this message is sent in, borrowing the payload.
+	///
+	/// With `Some(chunk_size)` a data message is fragmented into dataframes
+	/// with at most that many bytes each, the frames are only created while
+	/// iterating. Control messages are never fragmented.
+	///
+	///```rust
+	///# use websocket::Message;
+	///let message = Message::text("Hello, World!");
+	///let frames: Vec<_> = message.dataframes(Some(5)).collect();
+	///assert_eq!(frames.len(), 3);
+	///assert_eq!(&frames[2].data[..], b"ld!");
+	///```
+	pub fn dataframes(&self, chunk_size: Option<usize>) -> MessageDataFrames<'_> {
+		MessageDataFrames::new(self, chunk_size)
+	}
+
+	/// Converts this into an `OwnedMessage`, copying the payload if it is
+	/// borrowed. Owned payloads are moved, like with `Cow::into_owned`.
+	///
+	///```rust
+	///# use websocket::{Message, OwnedMessage};
+	///let message = Message::binary(vec![1, 2, 3]);
+	///assert_eq!(message.into_owned(), OwnedMessage::Binary(vec![1, 2, 3]));
+	///```
+	pub fn into_owned(self) -> OwnedMessage {
+		self.into()
+	}
+
+	/// Checks if this message is a close message.
+	pub fn is_close(&self) -> bool {
+		self.opcode == Type::Close
+	}
+
+	/// Checks if this message is a control message.
+	/// Control messages are either `Close`, `Ping`, or `Pong`.
+	pub fn is_control(&self) -> bool {
+		self.opcode().is_control()
+	}
+
+	/// Checks if this message is a data message.
+	/// Data messages are either `Text` or `Binary`.
+	pub fn is_data(&self) -> bool {
+		!self.is_control()
+	}
+
+	/// Checks if this message is a ping message.
+	pub fn is_ping(&self) -> bool {
+		self.opcode == Type::Ping
+	}
+
+	/// Checks if this message is a pong message.
+	pub fn is_pong(&self) -> bool {
+		self.opcode == Type::Pong
+	}
 }
 
 impl<'a> ws::dataframe::DataFrame for Message<'a> {
@@ -172,6 +353,13 @@ impl<'a> ws::dataframe::DataFrame for Message<'a> {
 			self.payload.into_owned()
 		}
 	}
+
+	fn payload_bytes(&self) -> Option<&[u8]> {
+		match self.cd_status_code {
+			Some(_) => None,
+			None => Some(&*self.payload),
+		}
+	}
 }
 
 impl<'a> ws::Message for Message<'a> {
@@ -180,43 +368,102 @@ impl<'a> ws::Message for Message<'a> {
 		self.write_to(writer, masked)
 	}
 
+	fn serialize_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_to_with_mask(writer, masking_key)
+	}
+
+	fn serialize_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_to_buffer(buffer, masking_key)
+	}
+
+	fn serialize_fragmented(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_fragmented_to_buffer(buffer, fragment_size, next_mask)
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize {
 		self.frame_size(masked)
 	}
 
+	fn is_control(&self) -> bool {
+		Message::is_control(self)
+	}
+
+	fn opcode_and_payload(&self) -> Option<(u8, Cow<'_, [u8]>)> {
+		Some((DataFrameTrait::opcode(self), DataFrameTrait::payload(self)))
+	}
+
+	fn opcode_and_size(&self) -> Option<(u8, usize)> {
+		Some((DataFrameTrait::opcode(self), DataFrameTrait::size(self)))
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
 	where
 		D: DataFrameTrait,
 	{
-		let opcode = frames
+		let raw_opcode = frames
 			.first()
 			.ok_or(WebSocketError::ProtocolError("No dataframes provided"))
 			.map(ws::dataframe::DataFrame::opcode)?;
-		let opcode = Opcode::new(opcode);
+		let opcode = Opcode::new(raw_opcode);
 
 		let payload_size = frames.iter().map(ws::dataframe::DataFrame::size).sum();
+		let single_frame = frames.len() == 1;
+		let last = frames.len() - 1;
 
-		let mut data = Vec::with_capacity(payload_size);
+		match opcode {
+			Some(Opcode::Continuation) => {
+				return Err(ProtocolError::UnexpectedContinuation.into());
+			}
+			Some(opcode) if opcode.is_control() && !single_frame => {
+				return Err(ProtocolError::FragmentedControlFrame(raw_opcode).into());
+			}
+			_ => (),
+		}
+
+		let mut data = Vec::with_capacity(if single_frame { 0 } else { payload_size });
 
 		for (i, dataframe) in frames.into_iter().enumerate() {
 			if i > 0 && dataframe.opcode() != Opcode::Continuation as u8 {
-				return Err(WebSocketError::ProtocolError(
-					"Unexpected non-continuation data frame",
-				));
+				return Err(ProtocolError::ExpectedContinuation(dataframe.opcode()).into());
+			}
+			if dataframe.is_last() != (i == last) {
+				return Err(ProtocolError::UnfinishedMessage(raw_opcode).into());
 			}
 			if *dataframe.reserved() != [false; 3] {
-				return Err(WebSocketError::ProtocolError(
-					"Unsupported reserved bits received",
-				));
+				let bits = dataframe
+					.reserved()
+					.iter()
+					.zip(&[0x40, 0x20, 0x10])
+					.filter(|&(&set, _)| set)
+					.fold(0, |bits, (_, &bit)| bits | bit);
+				return Err(ProtocolError::ReservedBits(bits).into());
+			}
+			if single_frame {
+				// no need to copy the payload of unfragmented messages
+				data = dataframe.take_payload();
+			} else {
+				data.append(&mut dataframe.take_payload());
 			}
-			data.append(&mut dataframe.take_payload());
 		}
 
 		if opcode == Some(Opcode::Text) {
 			if let Err(e) = from_utf8(data.as_slice()) {
-				return Err(e.into());
+				return Err(ProtocolError::InvalidUtf8(e).into());
 			}
 		}
 
@@ -228,9 +475,17 @@ impl<'a> ws::Message for Message<'a> {
 			},
 			Some(Opcode::Binary) => Message::binary(data),
 			Some(Opcode::Close) => {
+				// a status code is two bytes, there is nothing in between
+				if data.len() == 1 {
+					return Err(ProtocolError::InvalidClosePayload.into());
+				}
 				if !data.is_empty() {
 					let status_code = (&data[..]).read_u16::<BigEndian>()?;
-					let reason = bytes_to_string(&data[2..])?;
+					// RFC 6455 section 7.4: the connection has to be failed
+					if !CloseCode::from(status_code).is_sendable() {
+						return Err(ProtocolError::InvalidCloseCode(status_code).into());
+					}
+					let reason = bytes_to_string(&data[2..]).map_err(ProtocolError::InvalidUtf8)?;
 					Message::close_because(status_code, reason)
 				} else {
 					Message::close()
@@ -238,7 +493,7 @@ impl<'a> ws::Message for Message<'a> {
 			}
 			Some(Opcode::Ping) => Message::ping(data),
 			Some(Opcode::Pong) => Message::pong(data),
-			_ => return Err(WebSocketError::ProtocolError("Unsupported opcode received")),
+			_ => return Err(ProtocolError::ReservedOpcode(raw_opcode).into()),
 		};
 		Ok(msg)
 	}
@@ -252,25 +507,114 @@ impl<'a> ws::Message for Message<'a> {
 /// please use the `Message` struct (which contains a `Cow`).
 ///
 /// Note that `OwnedMessage` and `Message` can be converted into each other.
+///
+/// With the `serde` feature messages can be serialized, the payloads of
+/// binary messages, pings and pongs as bytes.
 #[derive(Eq, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum OwnedMessage {
 	/// A message containing UTF-8 text data
 	Text(String),
 	/// A message containing binary data
-	Binary(Vec<u8>),
+	Binary(#[cfg_attr(feature = "serde", serde(with = "byte_buf"))] Vec<u8>),
 	/// A message which indicates closure of the WebSocket connection.
 	/// This message may or may not contain data.
+	///
+	/// The reason can be at most 123 bytes long, sending a longer one fails.
 	Close(Option<CloseData>),
 	/// A ping message - should be responded to with a pong message.
 	/// Usually the pong message will be sent with the same data as the
 	/// received ping message.
-	Ping(Vec<u8>),
+	///
+	/// The data can be at most 125 bytes long, sending more fails.
+	Ping(#[cfg_attr(feature = "serde", serde(with = "byte_buf"))] Vec<u8>),
 	/// A pong message, sent in response to a Ping message, usually
 	/// containing the same data as the received ping message.
-	Pong(Vec<u8>),
+	///
+	/// The data can be at most 125 bytes long, sending more fails. It may
+	/// also be empty, and a pong can be sent without any ping as a one-way
+	/// heartbeat, the other end doesn't answer it.
+	Pong(#[cfg_attr(feature = "serde", serde(with = "byte_buf"))] Vec<u8>),
+}
+
+// Serializes payloads as bytes, not as sequences of numbers.
+#[cfg(feature = "serde")]
+mod byte_buf {
+	use serde::de::{Deserializer, Error, SeqAccess, Visitor};
+	use serde::ser::Serializer;
+	use std::cmp;
+	use std::fmt;
+
+	pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.serialize_bytes(bytes)
+	}
+
+	pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		deserializer.deserialize_byte_buf(ByteBufVisitor)
+	}
+
+	struct ByteBufVisitor;
+
+	impl<'de> Visitor<'de> for ByteBufVisitor {
+		type Value = Vec<u8>;
+
+		fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+			formatter.write_str("bytes")
+		}
+
+		fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+			Ok(bytes.to_vec())
+		}
+
+		fn visit_byte_buf<E: Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+			Ok(bytes)
+		}
+
+		fn visit_seq<A>(self, mut seq: A) -> Result<Vec<u8>, A::Error>
+		where
+			A: SeqAccess<'de>,
+		{
+			// don't trust the hint too much, the length comes from the input
+			let mut bytes = Vec::with_capacity(cmp::min(seq.size_hint().unwrap_or(0), 4096));
+			while let Some(byte) = seq.next_element()? {
+				bytes.push(byte);
+			}
+			Ok(bytes)
+		}
+	}
 }
 
 impl OwnedMessage {
+	/// A `Text` message from bytes which should be UTF-8, invalid sequences
+	/// are replaced with `U+FFFD`. Valid data isn't copied.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///let message = OwnedMessage::text_lossy(b"caf\xe9".to_vec());
+	///assert_eq!(message, OwnedMessage::Text("caf\u{FFFD}".to_string()));
+	///```
+	pub fn text_lossy(bytes: Vec<u8>) -> OwnedMessage {
+		OwnedMessage::Text(string_from_utf8_lossy(bytes))
+	}
+
+	/// A `Text` message from bytes which have to be UTF-8, without copying
+	/// them.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///assert!(OwnedMessage::text_checked("café".into()).is_ok());
+	///assert!(OwnedMessage::text_checked(b"caf\xe9".to_vec()).is_err());
+	///```
+	pub fn text_checked(bytes: Vec<u8>) -> Result<OwnedMessage, FromUtf8Error> {
+		String::from_utf8(bytes).map(OwnedMessage::Text)
+	}
+
 	/// Checks if this message is a close message.
 	///
 	///```rust
@@ -342,6 +686,268 @@ impl OwnedMessage {
 			_ => false,
 		}
 	}
+
+	/// The opcode of the dataframe this message is sent in.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///# use websocket::dataframe::Opcode;
+	///assert_eq!(OwnedMessage::Close(None).opcode(), Opcode::Close);
+	///```
+	pub fn opcode(&self) -> Opcode {
+		match *self {
+			OwnedMessage::Text(_) => Opcode::Text,
+			OwnedMessage::Binary(_) => Opcode::Binary,
+			OwnedMessage::Close(_) => Opcode::Close,
+			OwnedMessage::Ping(_) => Opcode::Ping,
+			OwnedMessage::Pong(_) => Opcode::Pong,
+		}
+	}
+
+	/// The length of the payload as it is sent, for a close message with
+	/// `CloseData` that's the 2 bytes of the code and the reason.
+	///
+	///```rust
+	///# use websocket::{CloseData, OwnedMessage};
+	///assert_eq!(OwnedMessage::Binary(vec![0; 3]).len(), 3);
+	///let bye = CloseData::new(1000, "bye".to_string());
+	///assert_eq!(OwnedMessage::Close(Some(bye)).len(), 5);
+	///assert!(OwnedMessage::Close(None).is_empty());
+	///```
+	pub fn len(&self) -> usize {
+		ws::dataframe::DataFrame::size(self)
+	}
+
+	/// Whether the payload is empty, see `len`.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The dataframes this message is sent in, borrowing the payload, see
+	/// `Message::dataframes`.
+	pub fn dataframes(&self, chunk_size: Option<usize>) -> MessageDataFrames<'_> {
+		MessageDataFrames::new(self, chunk_size)
+	}
+
+	/// Takes the text of a `Text` message, any other message (including
+	/// `Binary` ones and control messages) is returned as the error.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///let text = OwnedMessage::Text("hi".to_string());
+	///assert_eq!(text.into_text(), Ok("hi".to_string()));
+	///let ping = OwnedMessage::Ping(vec![]);
+	///assert_eq!(ping.into_text(), Err(OwnedMessage::Ping(vec![])));
+	///```
+	pub fn into_text(self) -> Result<String, OwnedMessage> {
+		match self {
+			OwnedMessage::Text(text) => Ok(text),
+			message => Err(message),
+		}
+	}
+
+	/// Takes the data of a `Binary` message, or the UTF-8 bytes of a `Text`
+	/// message. Control messages are returned as the error, even though
+	/// pings and pongs carry data too.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///let text = OwnedMessage::Text("hi".to_string());
+	///assert_eq!(text.into_binary(), Ok(b"hi".to_vec()));
+	///let close = OwnedMessage::Close(None);
+	///assert_eq!(close.into_binary(), Err(OwnedMessage::Close(None)));
+	///```
+	pub fn into_binary(self) -> Result<Vec<u8>, OwnedMessage> {
+		match self {
+			OwnedMessage::Text(text) => Ok(text.into_bytes()),
+			OwnedMessage::Binary(data) => Ok(data),
+			message => Err(message),
+		}
+	}
+
+	/// Turns a `Ping` into the `Pong` answering it, the payload is moved
+	/// over. Any other message is returned unchanged as the error.
+	///
+	///```rust
+	///# use websocket::OwnedMessage;
+	///let ping = OwnedMessage::Ping(b"heartbeat".to_vec());
+	///assert_eq!(ping.into_pong(), Ok(OwnedMessage::Pong(b"heartbeat".to_vec())));
+	///let pong = OwnedMessage::Pong(vec![]);
+	///assert_eq!(pong.into_pong(), Err(OwnedMessage::Pong(vec![])));
+	///```
+	pub fn into_pong(self) -> Result<OwnedMessage, OwnedMessage> {
+		match self {
+			OwnedMessage::Ping(data) => Ok(OwnedMessage::Pong(data)),
+			message => Err(message),
+		}
+	}
+
+	/// The text of a `Text` message, `None` for any other message.
+	pub fn as_text(&self) -> Option<&str> {
+		match *self {
+			OwnedMessage::Text(ref text) => Some(text),
+			_ => None,
+		}
+	}
+
+	/// The data of a `Binary` message or the UTF-8 bytes of a `Text` message
+	/// like `into_binary`, `None` for control messages.
+	pub fn as_binary(&self) -> Option<&[u8]> {
+		match *self {
+			OwnedMessage::Text(ref text) => Some(text.as_bytes()),
+			OwnedMessage::Binary(ref data) => Some(data),
+			_ => None,
+		}
+	}
+}
+
+/// Like `OwnedMessage`, but the payloads of binary messages, pings and
+/// pongs are held in a container of type `P`, e.g. `Bytes`.
+///
+/// `MessageCodecBuilder::build_with_payload` creates a codec which decodes
+/// these. Any codec can send them too, a `GenericMessage<Bytes>` is the
+/// cheapest way to broadcast binary messages: cloning it doesn't copy the
+/// payload.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum GenericMessage<P> {
+	/// A message containing UTF-8 text data
+	Text(String),
+	/// A message containing binary data
+	Binary(P),
+	/// A message which indicates closure of the WebSocket connection.
+	/// This message may or may not contain data.
+	Close(Option<CloseData>),
+	/// A ping message - should be responded to with a pong message.
+	Ping(P),
+	/// A pong message, sent in response to a Ping message, usually
+	/// containing the same data as the received ping message.
+	Pong(P),
+}
+
+impl<P: AsRef<[u8]>> GenericMessage<P> {
+	/// A `Message` which borrows the payload of this one.
+	pub fn as_message(&self) -> Message<'_> {
+		match *self {
+			GenericMessage::Text(ref text) => Message::text(&text[..]),
+			GenericMessage::Binary(ref data) => Message::binary(data.as_ref()),
+			GenericMessage::Close(Some(ref data)) => {
+				Message::close_because(data.status_code, &data.reason[..])
+			}
+			GenericMessage::Close(None) => Message::close(),
+			GenericMessage::Ping(ref data) => Message::ping(data.as_ref()),
+			GenericMessage::Pong(ref data) => Message::pong(data.as_ref()),
+		}
+	}
+}
+
+impl<P> ws::Message for GenericMessage<P>
+where
+	P: AsRef<[u8]> + From<Vec<u8>>,
+{
+	fn serialize(&self, writer: &mut dyn Write, masked: bool) -> WebSocketResult<()> {
+		self.write_to(writer, masked)
+	}
+
+	fn serialize_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_to_with_mask(writer, masking_key)
+	}
+
+	fn serialize_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_to_buffer(buffer, masking_key)
+	}
+
+	fn serialize_fragmented(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_fragmented_to_buffer(buffer, fragment_size, next_mask)
+	}
+
+	fn message_size(&self, masked: bool) -> usize {
+		self.frame_size(masked)
+	}
+
+	fn is_control(&self) -> bool {
+		DataFrameTrait::opcode(self) >= 8
+	}
+
+	fn opcode_and_payload(&self) -> Option<(u8, Cow<'_, [u8]>)> {
+		Some((DataFrameTrait::opcode(self), DataFrameTrait::payload(self)))
+	}
+
+	fn opcode_and_size(&self) -> Option<(u8, usize)> {
+		Some((DataFrameTrait::opcode(self), DataFrameTrait::size(self)))
+	}
+
+	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
+	where
+		D: DataFrameTrait,
+	{
+		Ok(match OwnedMessage::from_dataframes(frames)? {
+			OwnedMessage::Text(text) => GenericMessage::Text(text),
+			OwnedMessage::Binary(data) => GenericMessage::Binary(P::from(data)),
+			OwnedMessage::Close(data) => GenericMessage::Close(data),
+			OwnedMessage::Ping(data) => GenericMessage::Ping(P::from(data)),
+			OwnedMessage::Pong(data) => GenericMessage::Pong(P::from(data)),
+		})
+	}
+}
+
+/// The payload is written straight from `P`, so `encode_vectored` of the
+/// codecs doesn't copy it either.
+impl<P: AsRef<[u8]>> ws::dataframe::DataFrame for GenericMessage<P> {
+	#[inline(always)]
+	fn is_last(&self) -> bool {
+		true
+	}
+
+	fn opcode(&self) -> u8 {
+		(match *self {
+			GenericMessage::Text(_) => Type::Text,
+			GenericMessage::Binary(_) => Type::Binary,
+			GenericMessage::Close(_) => Type::Close,
+			GenericMessage::Ping(_) => Type::Ping,
+			GenericMessage::Pong(_) => Type::Pong,
+		}) as u8
+	}
+
+	#[inline(always)]
+	fn reserved(&self) -> &[bool; 3] {
+		FALSE_RESERVED_BITS
+	}
+
+	fn size(&self) -> usize {
+		self.as_message().size()
+	}
+
+	fn write_payload(&self, socket: &mut dyn Write) -> WebSocketResult<()> {
+		self.as_message().write_payload(socket)
+	}
+
+	fn payload_bytes(&self) -> Option<&[u8]> {
+		match *self {
+			GenericMessage::Text(ref text) => Some(text.as_bytes()),
+			GenericMessage::Binary(ref data)
+			| GenericMessage::Ping(ref data)
+			| GenericMessage::Pong(ref data) => Some(data.as_ref()),
+			GenericMessage::Close(None) => Some(&[]),
+			GenericMessage::Close(Some(_)) => None,
+		}
+	}
+
+	fn take_payload(self) -> Vec<u8> {
+		self.as_message().take_payload()
+	}
 }
 
 impl ws::Message for OwnedMessage {
@@ -350,11 +956,48 @@ impl ws::Message for OwnedMessage {
 		self.write_to(writer, masked)
 	}
 
+	fn serialize_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_to_with_mask(writer, masking_key)
+	}
+
+	fn serialize_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_to_buffer(buffer, masking_key)
+	}
+
+	fn serialize_fragmented(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_fragmented_to_buffer(buffer, fragment_size, next_mask)
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize {
 		self.frame_size(masked)
 	}
 
+	fn is_control(&self) -> bool {
+		OwnedMessage::is_control(self)
+	}
+
+	fn opcode_and_payload(&self) -> Option<(u8, Cow<'_, [u8]>)> {
+		Some((DataFrameTrait::opcode(self), DataFrameTrait::payload(self)))
+	}
+
+	fn opcode_and_size(&self) -> Option<(u8, usize)> {
+		Some((DataFrameTrait::opcode(self), DataFrameTrait::size(self)))
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
 	where
@@ -416,6 +1059,17 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 		Ok(())
 	}
 
+	fn payload_bytes(&self) -> Option<&[u8]> {
+		match *self {
+			OwnedMessage::Text(ref txt) => Some(txt.as_bytes()),
+			OwnedMessage::Binary(ref bin) => Some(bin),
+			OwnedMessage::Ping(ref data) => Some(data),
+			OwnedMessage::Pong(ref data) => Some(data),
+			OwnedMessage::Close(None) => Some(&[]),
+			OwnedMessage::Close(Some(_)) => None,
+		}
+	}
+
 	fn take_payload(self) -> Vec<u8> {
 		match self {
 			OwnedMessage::Text(txt) => txt.into_bytes(),
@@ -442,23 +1096,83 @@ impl From<String> for OwnedMessage {
 	}
 }
 
+impl<'a> From<&'a str> for OwnedMessage {
+	fn from(text: &'a str) -> Self {
+		OwnedMessage::Text(text.to_string())
+	}
+}
+
 impl From<Vec<u8>> for OwnedMessage {
 	fn from(buf: Vec<u8>) -> Self {
 		OwnedMessage::Binary(buf)
 	}
 }
 
+impl<'a> From<&'a [u8]> for OwnedMessage {
+	fn from(buf: &'a [u8]) -> Self {
+		OwnedMessage::Binary(buf.to_vec())
+	}
+}
+
+impl From<CloseData> for OwnedMessage {
+	fn from(data: CloseData) -> Self {
+		OwnedMessage::Close(Some(data))
+	}
+}
+
+impl From<Option<CloseData>> for OwnedMessage {
+	fn from(data: Option<CloseData>) -> Self {
+		OwnedMessage::Close(data)
+	}
+}
+
+impl<'a> From<String> for Message<'a> {
+	fn from(text: String) -> Self {
+		Message::text(text)
+	}
+}
+
+impl<'a> From<&'a str> for Message<'a> {
+	fn from(text: &'a str) -> Self {
+		Message::text(text)
+	}
+}
+
+impl<'a> From<Vec<u8>> for Message<'a> {
+	fn from(buf: Vec<u8>) -> Self {
+		Message::binary(buf)
+	}
+}
+
+impl<'a> From<&'a [u8]> for Message<'a> {
+	fn from(buf: &'a [u8]) -> Self {
+		Message::binary(buf)
+	}
+}
+
+impl<'a> From<CloseData> for Message<'a> {
+	fn from(data: CloseData) -> Self {
+		Message::close_because(data.status_code, data.reason)
+	}
+}
+
+impl<'a> From<Option<CloseData>> for Message<'a> {
+	fn from(data: Option<CloseData>) -> Self {
+		match data {
+			Some(data) => data.into(),
+			None => Message::close(),
+		}
+	}
+}
+
 impl<'m> From<Message<'m>> for OwnedMessage {
 	fn from(message: Message<'m>) -> Self {
 		match message.opcode {
-			Type::Text => {
-				let convert = String::from_utf8_lossy(&message.payload).into_owned();
-				OwnedMessage::Text(convert)
-			}
+			Type::Text => OwnedMessage::Text(cow_bytes_into_string(message.payload)),
 			Type::Close => match message.cd_status_code {
 				Some(code) => OwnedMessage::Close(Some(CloseData {
 					status_code: code,
-					reason: String::from_utf8_lossy(&message.payload).into_owned(),
+					reason: cow_bytes_into_string(message.payload),
 				})),
 				None => OwnedMessage::Close(None),
 			},
@@ -469,6 +1183,16 @@ impl<'m> From<Message<'m>> for OwnedMessage {
 	}
 }
 
+// Reuses the allocation of owned payloads which are valid UTF-8 (which is
+// always the case for payloads validated by `from_dataframes`).
+fn cow_bytes_into_string(payload: Cow<[u8]>) -> String {
+	match payload {
+		Cow::Owned(bytes) => String::from_utf8(bytes)
+			.unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()),
+		Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+	}
+}
+
 impl<'m> From<OwnedMessage> for Message<'m> {
 	fn from(message: OwnedMessage) -> Self {
 		match message {
@@ -484,8 +1208,111 @@ impl<'m> From<OwnedMessage> for Message<'m> {
 	}
 }
 
+/// The status code of a close message, as registered with IANA.
+///
+/// Not every code may be sent in a close message, see `is_sendable`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum CloseCode {
+	/// 1000, the purpose of the connection was fulfilled
+	Normal,
+	/// 1001, the endpoint goes away, e.g. a server shutting down
+	GoingAway,
+	/// 1002, the peer violated the protocol
+	ProtocolError,
+	/// 1003, a message of a type the endpoint can't accept
+	UnsupportedData,
+	/// 1005, only used locally for a close message without a status code
+	NoStatusReceived,
+	/// 1006, only used locally for a connection closed without a close message
+	AbnormalClosure,
+	/// 1007, a message with data inconsistent with its type, e.g. invalid UTF-8
+	InvalidPayload,
+	/// 1008, a message violated the policy of the endpoint
+	PolicyViolation,
+	/// 1009, a message was too big to process
+	MessageTooBig,
+	/// 1010, the server didn't negotiate an extension the client requires
+	MandatoryExtension,
+	/// 1011, the server hit an unexpected condition
+	InternalError,
+	/// 1012, the server is restarting
+	ServiceRestart,
+	/// 1013, the server is overloaded, the client should try again later
+	TryAgainLater,
+	/// 1014, a gateway got an invalid response from an upstream server
+	BadGateway,
+	/// 1015, only used locally for a failed TLS handshake
+	TlsHandshake,
+	/// Any other code, e.g. one for applications (4000-4999)
+	Other(u16),
+}
+
+impl CloseCode {
+	/// Whether this code may be sent in a close message.
+	///
+	/// These are the codes defined by RFC 6455 and its registry except the
+	/// ones which are only used locally (1005, 1006 and 1015), and the ranges
+	/// for libraries (3000-3999) and applications (4000-4999). Receiving any
+	/// other code has to fail the connection.
+	pub fn is_sendable(self) -> bool {
+		let code = u16::from(self);
+		(1000..=1003).contains(&code)
+			|| (1007..=1014).contains(&code)
+			|| (3000..=4999).contains(&code)
+	}
+}
+
+impl From<u16> for CloseCode {
+	fn from(code: u16) -> CloseCode {
+		use self::CloseCode::*;
+		match code {
+			1000 => Normal,
+			1001 => GoingAway,
+			1002 => ProtocolError,
+			1003 => UnsupportedData,
+			1005 => NoStatusReceived,
+			1006 => AbnormalClosure,
+			1007 => InvalidPayload,
+			1008 => PolicyViolation,
+			1009 => MessageTooBig,
+			1010 => MandatoryExtension,
+			1011 => InternalError,
+			1012 => ServiceRestart,
+			1013 => TryAgainLater,
+			1014 => BadGateway,
+			1015 => TlsHandshake,
+			code => Other(code),
+		}
+	}
+}
+
+impl From<CloseCode> for u16 {
+	fn from(code: CloseCode) -> u16 {
+		use self::CloseCode::*;
+		match code {
+			Normal => 1000,
+			GoingAway => 1001,
+			ProtocolError => 1002,
+			UnsupportedData => 1003,
+			NoStatusReceived => 1005,
+			AbnormalClosure => 1006,
+			InvalidPayload => 1007,
+			PolicyViolation => 1008,
+			MessageTooBig => 1009,
+			MandatoryExtension => 1010,
+			InternalError => 1011,
+			ServiceRestart => 1012,
+			TryAgainLater => 1013,
+			BadGateway => 1014,
+			TlsHandshake => 1015,
+			Other(code) => code,
+		}
+	}
+}
+
 /// Represents data contained in a Close message
 #[derive(Eq, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CloseData {
 	/// The status-code of the CloseData
 	pub status_code: u16,
@@ -501,6 +1328,34 @@ impl CloseData {
 			reason,
 		}
 	}
+
+	/// Create a new CloseData object which can be sent, i.e. `code` is
+	/// sendable (see `CloseCode::is_sendable`) and `reason` is at most 123
+	/// bytes long. Anything else is a `WebSocketError::DataFrameError`.
+	pub fn try_new(code: CloseCode, reason: String) -> WebSocketResult<CloseData> {
+		if !code.is_sendable() {
+			return Err(WebSocketError::DataFrameError(
+				"Close status code can't be sent",
+			));
+		}
+		if reason.len() > MAX_CLOSE_REASON {
+			return Err(WebSocketError::DataFrameError("Close reason too long"));
+		}
+		Ok(CloseData::new(code.into(), reason))
+	}
+
+	/// Create a new CloseData object, a `reason` longer than 123 bytes is cut
+	/// off at the last character which still fits so it can be sent.
+	pub fn new_truncating(status_code: u16, mut reason: String) -> CloseData {
+		let end = reason_boundary(&reason);
+		reason.truncate(end);
+		CloseData::new(status_code, reason)
+	}
+
+	/// The status code as a `CloseCode`.
+	pub fn code(&self) -> CloseCode {
+		self.status_code.into()
+	}
 	/// Convert this into a vector of bytes
 	pub fn into_bytes(self) -> io::Result<Vec<u8>> {
 		let mut buf = Vec::new();
@@ -536,3 +1391,548 @@ impl<'a> IntoCowBytes<'a> for Cow<'a, [u8]> {
 		self
 	}
 }
+
+// The length of the longest prefix of `reason` which fits into a close
+// message.
+fn reason_boundary(reason: &str) -> usize {
+	let mut end = cmp::min(reason.len(), MAX_CLOSE_REASON);
+	while !reason.is_char_boundary(end) {
+		end -= 1;
+	}
+	end
+}
+
+// Like `String::from_utf8_lossy`, but keeps the buffer if it's valid.
+fn string_from_utf8_lossy(bytes: Vec<u8>) -> String {
+	match String::from_utf8(bytes) {
+		Ok(text) => text,
+		Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+	}
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+	use dataframe::{BorrowedDataFrame, DataFrame};
+	use result::WebSocketErrorKind;
+	use ws::dataframe::DataFrame as DataFrameable;
+	use ws::Message as MessageTrait;
+
+	#[test]
+	fn test_close_codes() {
+		for code in 0..=u16::max_value() {
+			assert_eq!(u16::from(CloseCode::from(code)), code);
+		}
+		assert_eq!(CloseCode::from(1009), CloseCode::MessageTooBig);
+		assert_eq!(CloseCode::from(4000), CloseCode::Other(4000));
+
+		let sendable = [1000, 1001, 1003, 1007, 1011, 1014, 3000, 4999];
+		for &code in sendable.iter() {
+			assert!(CloseCode::from(code).is_sendable(), "{}", code);
+		}
+		let invalid = [0, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000, 65535];
+		for &code in invalid.iter() {
+			assert!(!CloseCode::from(code).is_sendable(), "{}", code);
+		}
+	}
+
+	#[test]
+	fn test_close_data() {
+		let data = CloseData::try_new(CloseCode::GoingAway, "bye".to_string()).unwrap();
+		assert_eq!(data, CloseData::new(1001, "bye".to_string()));
+		assert_eq!(data.code(), CloseCode::GoingAway);
+
+		assert!(CloseData::try_new(CloseCode::Normal, "x".repeat(123)).is_ok());
+		let error = CloseData::try_new(CloseCode::Normal, "x".repeat(124)).unwrap_err();
+		assert!(!error.is_fatal(), "{:?}", error);
+		let error = CloseData::try_new(CloseCode::NoStatusReceived, String::new()).unwrap_err();
+		assert!(!error.is_fatal(), "{:?}", error);
+		assert!(CloseData::try_new(CloseCode::Other(999), String::new()).is_err());
+
+		assert_eq!(
+			Message::close_with(CloseCode::Normal, "done"),
+			Message::close_because(1000, "done")
+		);
+	}
+
+	#[test]
+	fn test_owned_message_from() {
+		let bytes: &[u8] = &[1, 2, 3];
+		let close = CloseData::new(1000, "bye".to_string());
+		let conversions = vec![
+			(
+				OwnedMessage::from("hi".to_string()),
+				OwnedMessage::Text("hi".to_string()),
+			),
+			(
+				OwnedMessage::from("hi"),
+				OwnedMessage::Text("hi".to_string()),
+			),
+			(
+				OwnedMessage::from(vec![1, 2, 3]),
+				OwnedMessage::Binary(vec![1, 2, 3]),
+			),
+			(
+				OwnedMessage::from(bytes),
+				OwnedMessage::Binary(vec![1, 2, 3]),
+			),
+			// an empty text is still a text
+			(OwnedMessage::from(""), OwnedMessage::Text(String::new())),
+			(
+				OwnedMessage::from(close.clone()),
+				OwnedMessage::Close(Some(close.clone())),
+			),
+			(OwnedMessage::from(None), OwnedMessage::Close(None)),
+		];
+		for (converted, expected) in conversions {
+			assert_eq!(converted, expected);
+		}
+	}
+
+	#[test]
+	fn test_message_from() {
+		let bytes: &[u8] = &[1, 2, 3];
+		let close = CloseData::new(1000, "bye".to_string());
+		let conversions = vec![
+			(Message::from("hi".to_string()), Message::text("hi")),
+			(Message::from("hi"), Message::text("hi")),
+			(Message::from(vec![1, 2, 3]), Message::binary(bytes)),
+			(Message::from(bytes), Message::binary(bytes)),
+			(Message::from(close), Message::close_because(1000, "bye")),
+			(Message::from(None), Message::close()),
+		];
+		for (converted, expected) in conversions {
+			assert_eq!(converted, expected);
+		}
+
+		// borrowed data stays borrowed
+		match Message::from("hi").payload {
+			Cow::Borrowed(payload) => assert_eq!(payload, b"hi"),
+			Cow::Owned(_) => panic!("payload was copied"),
+		}
+	}
+
+	#[test]
+	fn test_owned_message_accessors() {
+		let text = OwnedMessage::Text("hi".to_string());
+		assert_eq!(text.as_text(), Some("hi"));
+		assert_eq!(text.as_binary(), Some(&b"hi"[..]));
+		assert_eq!(text.clone().into_text(), Ok("hi".to_string()));
+		assert_eq!(text.into_binary(), Ok(b"hi".to_vec()));
+
+		let binary = OwnedMessage::Binary(vec![0xFF]);
+		assert_eq!(binary.as_text(), None);
+		assert_eq!(binary.as_binary(), Some(&[0xFF][..]));
+		assert_eq!(binary.clone().into_text(), Err(binary.clone()));
+		assert_eq!(binary.into_binary(), Ok(vec![0xFF]));
+
+		// control messages are neither, their data isn't lost
+		let controls = vec![
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string()))),
+			OwnedMessage::Ping(b"ping".to_vec()),
+			OwnedMessage::Pong(b"pong".to_vec()),
+		];
+		for message in controls {
+			assert_eq!(message.as_text(), None);
+			assert_eq!(message.as_binary(), None);
+			assert_eq!(message.clone().into_text(), Err(message.clone()));
+			assert_eq!(message.clone().into_binary(), Err(message));
+		}
+	}
+
+	#[test]
+	fn test_message_kinds() {
+		let messages = vec![
+			(OwnedMessage::Text("hi".to_string()), Opcode::Text),
+			(OwnedMessage::Binary(vec![1]), Opcode::Binary),
+			(OwnedMessage::Close(None), Opcode::Close),
+			(
+				OwnedMessage::Close(Some(CloseData::new(1000, String::new()))),
+				Opcode::Close,
+			),
+			(OwnedMessage::Ping(vec![]), Opcode::Ping),
+			(OwnedMessage::Pong(vec![]), Opcode::Pong),
+		];
+		for (owned, opcode) in messages {
+			let message = Message::from(owned.clone());
+			assert_eq!(owned.opcode(), opcode);
+			assert_eq!(message.opcode(), opcode);
+			assert_eq!(DataFrameable::opcode(&owned), opcode as u8);
+
+			let control = opcode.is_control();
+			for &is_control in &[owned.is_control(), message.is_control()] {
+				assert_eq!(is_control, control);
+			}
+			for &is_data in &[
+				owned.is_data(),
+				message.is_data(),
+				MessageTrait::is_data(&owned),
+				MessageTrait::is_data(&::std::sync::Arc::new(message.clone())),
+			] {
+				assert_eq!(is_data, !control);
+			}
+			assert_eq!(owned.is_close(), opcode == Opcode::Close);
+			assert_eq!(message.is_close(), opcode == Opcode::Close);
+			assert_eq!(message.is_ping(), opcode == Opcode::Ping);
+			assert_eq!(message.is_pong(), opcode == Opcode::Pong);
+		}
+	}
+
+	#[test]
+	fn test_receive_invalid_close_code() {
+		let close = |code: u16| DataFrame::close(code, "").unwrap();
+		assert_eq!(
+			OwnedMessage::from_dataframes(vec![close(4000)]).unwrap(),
+			OwnedMessage::Close(Some(CloseData::new(4000, String::new())))
+		);
+		for &code in [1005, 1006, 1015, 999].iter() {
+			match OwnedMessage::from_dataframes(vec![close(code)]) {
+				Err(WebSocketError::Protocol(ProtocolError::InvalidCloseCode(c))) => {
+					assert_eq!(c, code)
+				}
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+	}
+
+	#[test]
+	fn test_close_reason_limit() {
+		// 61 two byte characters, the last one straddles the limit
+		let reason = "é".repeat(62);
+		let error = Message::try_close_because(1000, &reason[..]).unwrap_err();
+		assert_eq!(error.kind(), WebSocketErrorKind::Usage);
+		assert!(!error.is_fatal(), "{:?}", error);
+		assert!(Message::try_close_because(1000, &reason[..122]).is_ok());
+		assert!(Message::close_because(1000, &reason[..])
+			.serialize(&mut Vec::new(), false)
+			.is_err());
+
+		let message = Message::close_because_truncating(1000, &reason[..]);
+		assert_eq!(message.payload, Cow::Borrowed(reason[..122].as_bytes()));
+		let mut buf = Vec::new();
+		message.serialize(&mut buf, false).unwrap();
+		assert_eq!(buf.len(), 2 + 124);
+		let message = Message::close_because_truncating(1000, reason.clone());
+		assert_eq!(message.payload.len(), 122);
+		assert_eq!(
+			Message::close_because_truncating(1000, "bye"),
+			Message::close_because(1000, "bye")
+		);
+
+		let data = CloseData::new_truncating(1000, "a€".repeat(41));
+		assert_eq!(data.reason, format!("{}a", "a€".repeat(30)));
+		assert_eq!(data.reason.len(), 121);
+		assert!(CloseData::try_new(CloseCode::Normal, "a€".repeat(41)).is_err());
+		assert!(OwnedMessage::Close(Some(data))
+			.serialize(&mut Vec::new(), false)
+			.is_ok());
+	}
+
+	#[test]
+	fn test_owned_conversions_move_payloads() {
+		let payload = vec![0u8; 16];
+		let ptr = payload.as_ptr();
+		match Message::binary(payload).into_owned() {
+			OwnedMessage::Binary(payload) => assert_eq!(payload.as_ptr(), ptr),
+			message => panic!("not a binary message: {:?}", message),
+		}
+
+		let reason = "going away".to_string();
+		let ptr = reason.as_ptr();
+		let message: Message<'static> =
+			OwnedMessage::Close(Some(CloseData::new(1001, reason))).into();
+		assert_eq!(message.cd_status_code, Some(1001));
+		assert_eq!(message.payload.as_ptr(), ptr);
+		match message.into_owned() {
+			OwnedMessage::Close(Some(data)) => assert_eq!(data.reason.as_ptr(), ptr),
+			message => panic!("not a close message: {:?}", message),
+		}
+
+		// borrowed payloads are copied
+		let text = "borrowed";
+		assert_eq!(
+			Message::text(text).into_owned(),
+			OwnedMessage::Text(text.to_string())
+		);
+	}
+
+	#[test]
+	fn test_len_and_message_size() {
+		let mut messages = Vec::new();
+		for &len in &[0, 2, 125, 126, 65535, 65536] {
+			messages.push(OwnedMessage::Text("a".repeat(len)));
+			messages.push(OwnedMessage::Binary(vec![0; len]));
+			if len <= 125 {
+				messages.push(OwnedMessage::Ping(vec![0; len]));
+				messages.push(OwnedMessage::Pong(vec![0; len]));
+			}
+			if (2..=125).contains(&len) {
+				let reason = "a".repeat(len - 2);
+				messages.push(OwnedMessage::Close(Some(CloseData::new(1000, reason))));
+			}
+		}
+		messages.push(OwnedMessage::Close(None));
+
+		for message in messages {
+			let len = message.len();
+			let extended_len = match len {
+				0..=125 => 0,
+				126..=65535 => 2,
+				_ => 8,
+			};
+			let borrowed = Message::from(message.clone());
+			assert_eq!(borrowed.len(), len);
+			assert_eq!(message.is_empty(), len == 0);
+			assert_eq!(borrowed.is_empty(), len == 0);
+			for &masked in &[false, true] {
+				let header = 2 + extended_len + if masked { 4 } else { 0 };
+				assert_eq!(message.message_size(masked), len + header);
+				assert_eq!(borrowed.message_size(masked), len + header);
+
+				let mut bytes = Vec::new();
+				message.serialize(&mut bytes, masked).unwrap();
+				assert_eq!(bytes.len(), len + header, "{:?}", message.opcode());
+			}
+		}
+	}
+
+	#[test]
+	fn test_into_pong() {
+		let payload = b"heartbeat".to_vec();
+		let ptr = payload.as_ptr();
+		match OwnedMessage::Ping(payload).into_pong() {
+			Ok(OwnedMessage::Pong(payload)) => assert_eq!(payload.as_ptr(), ptr),
+			other => panic!("not a pong: {:?}", other),
+		}
+		for message in vec![
+			OwnedMessage::Text("ping".to_string()),
+			OwnedMessage::Binary(b"ping".to_vec()),
+			OwnedMessage::Close(None),
+			OwnedMessage::Pong(b"ping".to_vec()),
+		] {
+			assert_eq!(message.clone().into_pong(), Err(message));
+		}
+
+		let mut ping = Message::ping(&b"heartbeat"[..]);
+		ping.into_pong().unwrap();
+		assert_eq!(ping, Message::pong(&b"heartbeat"[..]));
+		assert!(ping.into_pong().is_err());
+		assert!(Message::text("ping").into_pong().is_err());
+
+		// empty and unsolicited pongs are sent and received like any other
+		let mut bytes = Vec::new();
+		Message::pong(Vec::new())
+			.serialize(&mut bytes, false)
+			.unwrap();
+		assert_eq!(bytes, vec![0x8A, 0x00]);
+		let frame = DataFrame::read_dataframe(&mut &bytes[..], false).unwrap();
+		assert_eq!(
+			OwnedMessage::from_dataframes(vec![frame]).unwrap(),
+			OwnedMessage::Pong(Vec::new())
+		);
+	}
+
+	#[test]
+	fn test_close_payload_round_trip() {
+		fn round_trip(message: &OwnedMessage) -> WebSocketResult<OwnedMessage> {
+			let mut buf = Vec::new();
+			message.serialize(&mut buf, false)?;
+			let frame = DataFrame::read_dataframe(&mut &buf[..], false)?;
+			OwnedMessage::from_dataframes(vec![frame])
+		}
+
+		let messages = vec![
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData::new(1000, String::new()))),
+			OwnedMessage::Close(Some(CloseData::new(1001, "bye ✓".to_string()))),
+		];
+		for (message, &size) in messages.iter().zip([0, 2, 2 + 7].iter()) {
+			assert_eq!(message.size(), size);
+			assert_eq!(&round_trip(message).unwrap(), message);
+
+			// also through the borrowed message and dataframe
+			let borrowed = Message::from(message.clone());
+			assert_eq!(DataFrameable::size(&borrowed), size);
+			let frame = BorrowedDataFrame::from(borrowed.clone());
+			assert_eq!(frame.data.len(), size);
+			assert_eq!(Message::from_dataframes(vec![frame]).unwrap(), borrowed);
+		}
+
+		// half a status code
+		let frame = DataFrame::new(true, Opcode::Close, vec![0x03]);
+		match Message::from_dataframes(vec![frame]) {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidClosePayload)) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn test_from_dataframes_sequencing() {
+		let frame = |finished, opcode| DataFrame::new(finished, opcode, b"ab".to_vec());
+		let message = Message::from_dataframes(vec![
+			frame(false, Opcode::Text),
+			frame(false, Opcode::Continuation),
+			frame(true, Opcode::Continuation),
+		])
+		.unwrap();
+		assert_eq!(message, Message::text("ababab"));
+
+		let invalid = vec![
+			// starts with a continuation
+			vec![frame(true, Opcode::Continuation)],
+			vec![
+				frame(false, Opcode::Continuation),
+				frame(true, Opcode::Continuation),
+			],
+			// a new data frame in the middle
+			vec![frame(false, Opcode::Text), frame(true, Opcode::Binary)],
+			vec![frame(false, Opcode::Binary), frame(true, Opcode::Text)],
+			// a control frame in the middle
+			vec![frame(false, Opcode::Binary), frame(true, Opcode::Ping)],
+			// a fragmented control message
+			vec![
+				frame(false, Opcode::Ping),
+				frame(true, Opcode::Continuation),
+			],
+			// FIN is missing at the end, or set before it
+			vec![frame(false, Opcode::Text)],
+			vec![
+				frame(false, Opcode::Text),
+				frame(false, Opcode::Continuation),
+			],
+			vec![frame(true, Opcode::Text), frame(true, Opcode::Continuation)],
+		];
+		for frames in invalid {
+			match Message::from_dataframes(frames.clone()) {
+				Err(WebSocketError::Protocol(_)) => (),
+				other => panic!("{:?} gave {:?}", frames, other),
+			}
+		}
+	}
+
+	#[test]
+	fn test_text_lossy() {
+		// valid text keeps its buffer
+		let bytes = "café".to_string().into_bytes();
+		let ptr = bytes.as_ptr();
+		match OwnedMessage::text_lossy(bytes) {
+			OwnedMessage::Text(text) => assert_eq!(text.as_ptr(), ptr),
+			message => panic!("not a text message: {:?}", message),
+		}
+		let data = b"caf\xc3\xa9";
+		match Message::text_lossy(&data[..]).payload {
+			Cow::Borrowed(payload) => assert_eq!(payload.as_ptr(), data.as_ptr()),
+			Cow::Owned(_) => panic!("valid text was copied"),
+		}
+
+		assert_eq!(
+			OwnedMessage::text_lossy(b"a\xff\xfeb".to_vec()),
+			OwnedMessage::Text("a\u{FFFD}\u{FFFD}b".to_string())
+		);
+		assert_eq!(
+			Message::text_lossy(vec![0xe2, 0x82]),
+			Message::text("\u{FFFD}")
+		);
+		let error = OwnedMessage::text_checked(vec![b'a', 0xff]).unwrap_err();
+		assert_eq!(error.into_bytes(), vec![b'a', 0xff]);
+	}
+
+	#[test]
+	fn test_lazy_dataframes() {
+		let message = Message::binary(vec![0u8; 10]);
+		let frames: Vec<_> = message.dataframes(Some(4)).collect();
+		assert_eq!(
+			frames
+				.iter()
+				.map(|frame| (frame.finished, frame.opcode, frame.data.len()))
+				.collect::<Vec<_>>(),
+			vec![
+				(false, Opcode::Binary, 4),
+				(false, Opcode::Continuation, 4),
+				(true, Opcode::Continuation, 2),
+			]
+		);
+		// the frames borrow the payload of the message
+		for frame in &frames {
+			assert!(match frame.data {
+				Cow::Borrowed(_) => true,
+				Cow::Owned(_) => false,
+			});
+		}
+		assert_eq!(message.dataframes(None).count(), 1);
+
+		let owned = OwnedMessage::Text("Hello".to_string());
+		let frames: Vec<_> = owned.dataframes(Some(2)).collect();
+		assert_eq!(frames.len(), 3);
+		assert_eq!(frames[0].opcode, Opcode::Text);
+
+		// control messages aren't fragmented, the close payload is built once
+		let close = OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string())));
+		let frames: Vec<_> = close.dataframes(Some(1)).collect();
+		assert_eq!(frames.len(), 1);
+		assert!(frames[0].finished);
+		assert_eq!(&frames[0].data[..], b"\x03\xe8bye");
+		assert_eq!(Message::ping(&b"ping"[..]).dataframes(Some(1)).count(), 1);
+	}
+}
+
+#[cfg(all(feature = "serde", test))]
+mod serde_tests {
+	use super::*;
+	use bincode;
+	use serde_json;
+
+	fn messages() -> Vec<OwnedMessage> {
+		vec![
+			OwnedMessage::Text("hello".to_string()),
+			OwnedMessage::Binary(vec![0, 1, 255]),
+			OwnedMessage::Binary(vec![]),
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData::new(1000, String::new()))),
+			OwnedMessage::Close(Some(CloseData::new(1001, "bye".to_string()))),
+			OwnedMessage::Ping(b"ping".to_vec()),
+			OwnedMessage::Pong(b"pong".to_vec()),
+		]
+	}
+
+	#[test]
+	fn json_round_trip() {
+		let expected = [
+			r#"{"Text":"hello"}"#,
+			r#"{"Binary":[0,1,255]}"#,
+			r#"{"Binary":[]}"#,
+			r#"{"Close":null}"#,
+			r#"{"Close":{"status_code":1000,"reason":""}}"#,
+			r#"{"Close":{"status_code":1001,"reason":"bye"}}"#,
+			r#"{"Ping":[112,105,110,103]}"#,
+			r#"{"Pong":[112,111,110,103]}"#,
+		];
+		for (message, expected) in messages().into_iter().zip(expected.iter()) {
+			let json = serde_json::to_string(&message).unwrap();
+			assert_eq!(json, *expected);
+			assert_eq!(
+				serde_json::from_str::<OwnedMessage>(&json).unwrap(),
+				message
+			);
+		}
+	}
+
+	#[test]
+	fn bincode_round_trip() {
+		for message in messages() {
+			let encoded = bincode::serialize(&message).unwrap();
+			assert_eq!(
+				bincode::deserialize::<OwnedMessage>(&encoded).unwrap(),
+				message
+			);
+		}
+
+		// bytes are written as they are: the variant (4 bytes), the length
+		// (8 bytes) and the data
+		let encoded = bincode::serialize(&OwnedMessage::Binary(vec![7; 100])).unwrap();
+		assert_eq!(encoded.len(), 4 + 8 + 100);
+		assert_eq!(&encoded[..4], &[1, 0, 0, 0]);
+		assert_eq!(&encoded[12..], &[7; 100][..]);
+	}
+}
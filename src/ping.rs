@@ -0,0 +1,196 @@
+//! Measuring the round-trip time of a connection with pings.
+//!
+//! A `PingManager` hands out pings with a sequence number as payload and
+//! matches the pongs coming back, which echo the payload, to the pings. It
+//! doesn't send or receive anything itself, so it works with the sync client
+//! as well as with the codecs.
+//!
+//! ```rust
+//! # use std::time::Duration;
+//! # use websocket::ping::PingManager;
+//! # use websocket::OwnedMessage;
+//! let mut pings = PingManager::new(Duration::from_secs(30));
+//! let ping = pings.make_ping();
+//! // send the ping, the other end answers with a pong
+//! let pong = ping.into_pong().unwrap();
+//! if let OwnedMessage::Pong(ref payload) = pong {
+//!     let rtt = pings.handle_pong(payload).unwrap();
+//!     assert!(rtt < Duration::from_secs(30));
+//! }
+//! ```
+use byteorder::{BigEndian, ByteOrder};
+use message::OwnedMessage;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Keeps track of the pings which weren't answered yet.
+///
+/// RFC 6455 allows answering only the most recent of several pings, so a
+/// pong counts as the answer to all pings sent before the one it echoes.
+/// Pings which aren't answered within the timeout are forgotten, pongs which
+/// don't match any outstanding ping are ignored.
+#[derive(Debug, Clone)]
+pub struct PingManager {
+	timeout: Duration,
+	next: u64,
+	outstanding: VecDeque<(u64, Instant)>,
+}
+
+impl PingManager {
+	/// A manager which forgets pings that aren't answered within `timeout`.
+	pub fn new(timeout: Duration) -> Self {
+		PingManager {
+			timeout,
+			next: 0,
+			outstanding: VecDeque::new(),
+		}
+	}
+
+	/// How long a ping waits for its pong.
+	pub fn timeout(&self) -> Duration {
+		self.timeout
+	}
+
+	/// Change how long a ping waits for its pong, this applies to the
+	/// outstanding pings too.
+	pub fn set_timeout(&mut self, timeout: Duration) {
+		self.timeout = timeout;
+	}
+
+	/// The number of pings which are still waiting for a pong, some of them
+	/// may have timed out already.
+	pub fn outstanding(&self) -> usize {
+		self.outstanding.len()
+	}
+
+	/// Creates the next ping, sent now.
+	pub fn make_ping(&mut self) -> OwnedMessage {
+		self.make_ping_at(Instant::now())
+	}
+
+	/// Creates the next ping, which is sent at `now`.
+	pub fn make_ping_at(&mut self, now: Instant) -> OwnedMessage {
+		self.expire(now);
+		let sequence = self.next;
+		self.next = self.next.wrapping_add(1);
+		self.outstanding.push_back((sequence, now));
+
+		let mut payload = vec![0; 8];
+		BigEndian::write_u64(&mut payload, sequence);
+		OwnedMessage::Ping(payload)
+	}
+
+	/// Handles the payload of a pong received now, see `handle_pong_at`.
+	pub fn handle_pong(&mut self, payload: &[u8]) -> Option<Duration> {
+		self.handle_pong_at(payload, Instant::now())
+	}
+
+	/// Handles the payload of a pong received at `now`.
+	///
+	/// Returns the round-trip time if the pong answers an outstanding ping,
+	/// that ping and the ones sent before it aren't outstanding anymore.
+	pub fn handle_pong_at(&mut self, payload: &[u8], now: Instant) -> Option<Duration> {
+		self.expire(now);
+		if payload.len() != 8 {
+			return None;
+		}
+		let sequence = BigEndian::read_u64(payload);
+		let position = self
+			.outstanding
+			.iter()
+			.position(|&(outstanding, _)| outstanding == sequence)?;
+		let (_, sent) = self.outstanding[position];
+		self.outstanding.drain(..=position);
+		Some(now.duration_since(sent))
+	}
+
+	// Forget the pings which have timed out at `now`.
+	fn expire(&mut self, now: Instant) {
+		while let Some(&(_, sent)) = self.outstanding.front() {
+			if now.duration_since(sent) <= self.timeout {
+				break;
+			}
+			self.outstanding.pop_front();
+		}
+	}
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+
+	fn payload(message: OwnedMessage) -> Vec<u8> {
+		match message {
+			OwnedMessage::Ping(payload) => payload,
+			message => panic!("not a ping: {:?}", message),
+		}
+	}
+
+	#[test]
+	fn test_round_trip_times() {
+		let start = Instant::now();
+		let ms = |ms| start + Duration::from_millis(ms);
+		let mut pings = PingManager::new(Duration::from_secs(1));
+
+		let first = payload(pings.make_ping_at(ms(0)));
+		let second = payload(pings.make_ping_at(ms(10)));
+		assert_ne!(first, second);
+		assert_eq!(pings.outstanding(), 2);
+
+		assert_eq!(
+			pings.handle_pong_at(&first, ms(50)),
+			Some(Duration::from_millis(50))
+		);
+		assert_eq!(pings.outstanding(), 1);
+		// answered already
+		assert_eq!(pings.handle_pong_at(&first, ms(60)), None);
+		assert_eq!(
+			pings.handle_pong_at(&second, ms(70)),
+			Some(Duration::from_millis(60))
+		);
+		assert_eq!(pings.outstanding(), 0);
+	}
+
+	#[test]
+	fn test_only_latest_ping_answered() {
+		let start = Instant::now();
+		let ms = |ms| start + Duration::from_millis(ms);
+		let mut pings = PingManager::new(Duration::from_secs(1));
+
+		let first = payload(pings.make_ping_at(ms(0)));
+		pings.make_ping_at(ms(10));
+		let last = payload(pings.make_ping_at(ms(20)));
+		assert_eq!(
+			pings.handle_pong_at(&last, ms(25)),
+			Some(Duration::from_millis(5))
+		);
+		// the earlier pings count as answered
+		assert_eq!(pings.outstanding(), 0);
+		assert_eq!(pings.handle_pong_at(&first, ms(30)), None);
+	}
+
+	#[test]
+	fn test_unsolicited_and_stale_pongs() {
+		let start = Instant::now();
+		let ms = |ms| start + Duration::from_millis(ms);
+		let mut pings = PingManager::new(Duration::from_millis(100));
+
+		assert_eq!(pings.handle_pong_at(b"", ms(0)), None);
+		assert_eq!(pings.handle_pong_at(b"heartbeat", ms(0)), None);
+		assert_eq!(pings.handle_pong_at(&[0, 0, 0, 0, 0, 0, 0, 9], ms(0)), None);
+
+		let stale = payload(pings.make_ping_at(ms(0)));
+		let fresh = payload(pings.make_ping_at(ms(50)));
+		assert_eq!(pings.handle_pong_at(&stale, ms(120)), None);
+		assert_eq!(pings.outstanding(), 1);
+		assert_eq!(
+			pings.handle_pong_at(&fresh, ms(120)),
+			Some(Duration::from_millis(70))
+		);
+
+		pings.make_ping_at(ms(200));
+		pings.set_timeout(Duration::from_millis(10));
+		pings.make_ping_at(ms(250));
+		assert_eq!(pings.outstanding(), 1);
+	}
+}
@@ -1,18 +1,21 @@
 //! The default implementation of a WebSocket Receiver.
 
-use std::io::Read;
+use std::cmp;
 use std::io::Result as IoResult;
+use std::io::{self, Read};
+use std::net::SocketAddr;
 
 use hyper::buffer::BufReader;
 
-use dataframe::{DataFrame, Opcode};
+use dataframe::{parse_header, DataFrame, Opcode, ParseResult};
 use message::OwnedMessage;
-use result::{WebSocketError, WebSocketResult};
+use result::{ProtocolError, WebSocketError, WebSocketResult};
 pub use stream::sync::Shutdown;
 use stream::sync::{AsTcpStream, Stream};
 use ws;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{DataFrameIterator, MessageIterator};
+use ws::util::utf8::Utf8Validator;
 
 /// This reader bundles an existing stream with a parsing algorithm.
 /// It is used by the client in its `.split()` function as the reading component.
@@ -67,12 +70,36 @@ where
 	pub fn shutdown_all(&self) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
 	}
+
+	/// See [`TcpStream::peer_addr`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.peer_addr).
+	pub fn peer_addr(&self) -> IoResult<SocketAddr> {
+		self.stream.get_ref().as_tcp().peer_addr()
+	}
+
+	/// See [`TcpStream::local_addr`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.local_addr).
+	pub fn local_addr(&self) -> IoResult<SocketAddr> {
+		self.stream.get_ref().as_tcp().local_addr()
+	}
 }
 
 /// A Receiver that wraps a Reader and provides a default implementation using
 /// DataFrames and Messages.
+///
+/// The bytes of a dataframe and the dataframes of a message are kept until
+/// they are complete, so reading from a nonblocking stream or one with a read
+/// timeout can be retried after an error with `WebSocketErrorKind::WouldBlock`,
+/// nothing which was already read gets lost. The `WouldBlock` and `TimedOut`
+/// errors of the reader are a `WebSocketError::Timeout`. The next call has to
+/// use the same reader.
+///
+/// The fragments of a text message are checked for valid UTF-8 as they
+/// arrive, an invalid text fails before the rest of it is read.
 pub struct Receiver {
 	buffer: Vec<DataFrame>,
+	partial: Vec<u8>,
+	validator: Option<Utf8Validator>,
 	mask: bool,
 }
 
@@ -81,9 +108,71 @@ impl Receiver {
 	pub fn new(mask: bool) -> Receiver {
 		Receiver {
 			buffer: Vec::new(),
+			partial: Vec::new(),
+			validator: None,
 			mask,
 		}
 	}
+
+	/// Whether a part of a dataframe or message was read, which the next
+	/// call completes.
+	pub fn has_partial(&self) -> bool {
+		!self.partial.is_empty() || !self.buffer.is_empty()
+	}
+
+	// Read up to `len` more bytes of the current dataframe, the bytes read
+	// so far stay in place if the reader fails.
+	fn read_partial<R>(&mut self, reader: &mut R, len: usize) -> WebSocketResult<()>
+	where
+		R: Read,
+	{
+		let filled = self.partial.len();
+		self.partial.resize(filled + len, 0);
+		loop {
+			match reader.read(&mut self.partial[filled..]) {
+				Ok(read) => {
+					self.partial.truncate(filled + read);
+					if read == 0 {
+						return Err(WebSocketError::NoDataAvailable);
+					}
+					return Ok(());
+				}
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+				Err(ref e)
+					if e.kind() == io::ErrorKind::WouldBlock
+						|| e.kind() == io::ErrorKind::TimedOut =>
+				{
+					self.partial.truncate(filled);
+					return Err(WebSocketError::Timeout);
+				}
+				Err(e) => {
+					self.partial.truncate(filled);
+					return Err(e.into());
+				}
+			}
+		}
+	}
+
+	// Check the payload of the next fragment of a text message, the
+	// message is dropped if it can't be valid UTF-8 anymore.
+	fn validate_text(&mut self, frame: &DataFrame) -> WebSocketResult<()> {
+		let valid = match self.validator {
+			Some(ref mut validator) => validator.feed(&frame.data).and_then(|_| {
+				if frame.finished {
+					validator.finish()
+				} else {
+					Ok(())
+				}
+			}),
+			None => return Ok(()),
+		};
+		if let Err(e) = valid {
+			self.buffer.clear();
+			self.validator = None;
+			return Err(ProtocolError::InvalidUtf8(e).into());
+		}
+		Ok(())
+	}
 }
 
 impl ws::Receiver for Receiver {
@@ -96,7 +185,36 @@ impl ws::Receiver for Receiver {
 	where
 		R: Read,
 	{
-		DataFrame::read_dataframe(reader, self.mask)
+		loop {
+			// the connection is unusable after an invalid header, it isn't
+			// kept for a retry
+			let parsed = match parse_header(&self.partial) {
+				Ok(parsed) => parsed,
+				Err(e) => {
+					self.partial.clear();
+					return Err(e);
+				}
+			};
+			let needed = match parsed {
+				ParseResult::Incomplete { needed } => needed,
+				ParseResult::Complete { header, header_len } => {
+					if header.len > (usize::MAX - header_len) as u64 {
+						self.partial.clear();
+						return Err(WebSocketError::MessageTooLarge);
+					}
+					let missing = header_len + header.len as usize - self.partial.len();
+					if missing == 0 {
+						let body = self.partial.split_off(header_len);
+						self.partial.clear();
+						return DataFrame::read_dataframe_body(header, body, self.mask);
+					}
+					// don't trust the header with the allocation, the buffer
+					// grows as the payload actually arrives
+					cmp::min(missing, 64 * 1024)
+				}
+			};
+			self.read_partial(reader, needed)?;
+		}
 	}
 
 	/// Returns the data frames that constitute one message.
@@ -108,11 +226,14 @@ impl ws::Receiver for Receiver {
 			let first = self.recv_dataframe(reader)?;
 
 			if first.opcode == Opcode::Continuation {
-				return Err(WebSocketError::ProtocolError(
-					"Unexpected continuation data frame opcode",
-				));
+				return Err(ProtocolError::UnexpectedContinuation.into());
 			}
 
+			self.validator = match first.opcode {
+				Opcode::Text => Some(Utf8Validator::new()),
+				_ => None,
+			};
+			self.validate_text(&first)?;
 			let finished = first.finished;
 			self.buffer.push(first);
 			finished
@@ -126,20 +247,188 @@ impl ws::Receiver for Receiver {
 
 			match next.opcode as u8 {
 				// Continuation opcode
-				0 => self.buffer.push(next),
+				0 => {
+					self.validate_text(&next)?;
+					self.buffer.push(next);
+				}
 				// Control frame
 				8...15 => {
 					return Ok(vec![next]);
 				}
 				// Others
-				_ => {
-					return Err(WebSocketError::ProtocolError(
-						"Unexpected data frame opcode",
-					));
-				}
+				opcode => return Err(ProtocolError::ExpectedContinuation(opcode).into()),
 			}
 		}
 
+		self.validator = None;
 		Ok(::std::mem::replace(&mut self.buffer, Vec::new()))
 	}
 }
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+	use message::{CloseCode, CloseData, Message};
+	use result::WebSocketErrorKind;
+	use ws::Sender as SenderTrait;
+
+	// Hands out one byte per read and fails with `WouldBlock` in between.
+	struct Trickle<'a> {
+		data: &'a [u8],
+		blocked: bool,
+	}
+
+	impl<'a> Read for Trickle<'a> {
+		fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+			self.blocked = !self.blocked;
+			if self.blocked {
+				return Err(io::Error::new(io::ErrorKind::WouldBlock, "try again"));
+			}
+			if self.data.is_empty() || buf.is_empty() {
+				return Ok(0);
+			}
+			buf[0] = self.data[0];
+			self.data = &self.data[1..];
+			Ok(1)
+		}
+	}
+
+	#[test]
+	fn test_resume_after_would_block() {
+		let mut bytes = Vec::new();
+		let mut sender = ::sender::Sender::new(true);
+		let fragment = DataFrame::new(false, Opcode::Text, b"Hel".to_vec());
+		sender.send_dataframe(&mut bytes, &fragment).unwrap();
+		sender
+			.send_message(&mut bytes, &Message::ping(&b"ping"[..]))
+			.unwrap();
+		let last = DataFrame::new(true, Opcode::Continuation, b"lo".to_vec());
+		sender.send_dataframe(&mut bytes, &last).unwrap();
+		let long = vec![7; 300];
+		sender
+			.send_message(&mut bytes, &Message::binary(&long[..]))
+			.unwrap();
+		sender
+			.send_message(&mut bytes, &Message::close_because(1000, "bye"))
+			.unwrap();
+
+		let mut reader = Trickle {
+			data: &bytes,
+			blocked: false,
+		};
+		let mut receiver = Receiver::new(true);
+		let mut messages = Vec::new();
+		let mut blocked = 0;
+		while messages.len() < 4 {
+			match receiver.recv_message(&mut reader) {
+				Ok(message) => messages.push(message),
+				Err(e) => {
+					assert!(
+						match e {
+							WebSocketError::Timeout => true,
+							_ => false,
+						},
+						"{:?}",
+						e
+					);
+					assert_eq!(e.kind(), WebSocketErrorKind::WouldBlock);
+					assert!(!e.is_fatal());
+					blocked += 1;
+				}
+			}
+		}
+		assert_eq!(blocked, bytes.len());
+		assert!(!receiver.has_partial());
+		assert_eq!(
+			messages,
+			vec![
+				OwnedMessage::Ping(b"ping".to_vec()),
+				OwnedMessage::Text("Hello".to_string()),
+				OwnedMessage::Binary(long),
+				OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string()))),
+			]
+		);
+	}
+
+	#[test]
+	fn test_partial_dataframe() {
+		let mut bytes = Vec::new();
+		::sender::Sender::new(false)
+			.send_message(&mut bytes, &Message::text("Hello"))
+			.unwrap();
+		let mut receiver = Receiver::new(false);
+
+		let mut reader = Trickle {
+			data: &bytes[..4],
+			blocked: true,
+		};
+		while receiver.partial.len() < 4 {
+			let _ = receiver.recv_dataframe(&mut reader);
+		}
+		assert!(receiver.has_partial());
+		// the connection ends in the middle of the dataframe
+		match receiver.recv_dataframe(&mut &[][..]) {
+			Err(WebSocketError::NoDataAvailable) => (),
+			other => panic!("Expected NoDataAvailable, got {:?}", other),
+		}
+		let frame = receiver.recv_dataframe(&mut &bytes[4..]).unwrap();
+		assert_eq!(frame, DataFrame::new(true, Opcode::Text, b"Hello".to_vec()));
+	}
+
+	#[test]
+	fn test_huge_length() {
+		// a binary frame claiming a u64::MAX byte payload
+		let bytes = [0x82, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+		let mut reader = Trickle {
+			data: &bytes,
+			blocked: false,
+		};
+		let mut receiver = Receiver::new(false);
+		let error = loop {
+			match receiver.recv_dataframe(&mut reader) {
+				Err(WebSocketError::Timeout) => (),
+				Err(e) => break e,
+				Ok(frame) => panic!("unexpected frame {:?}", frame),
+			}
+		};
+		assert!(error.is_fatal(), "{:?}", error);
+		assert!(!receiver.has_partial());
+	}
+
+	#[test]
+	fn test_split_code_point() {
+		let mut bytes = Vec::new();
+		let mut sender = ::sender::Sender::new(false);
+		let smiley = "😀".as_bytes();
+		let first = DataFrame::new(false, Opcode::Text, smiley[..2].to_vec());
+		sender.send_dataframe(&mut bytes, &first).unwrap();
+		let last = DataFrame::new(true, Opcode::Continuation, smiley[2..].to_vec());
+		sender.send_dataframe(&mut bytes, &last).unwrap();
+
+		let mut receiver = Receiver::new(false);
+		let message = receiver.recv_message(&mut &bytes[..]).unwrap();
+		assert_eq!(message, OwnedMessage::Text("😀".to_string()));
+	}
+
+	#[test]
+	fn test_invalid_utf8_fails_fast() {
+		let mut bytes = Vec::new();
+		let mut sender = ::sender::Sender::new(false);
+		let first = DataFrame::new(false, Opcode::Text, b"a".to_vec());
+		sender.send_dataframe(&mut bytes, &first).unwrap();
+		// a lone continuation byte
+		let middle = DataFrame::new(false, Opcode::Continuation, vec![0x80]);
+		sender.send_dataframe(&mut bytes, &middle).unwrap();
+
+		// the last fragment never arrives, the error doesn't wait for it
+		let mut receiver = Receiver::new(false);
+		let error = receiver.recv_message(&mut &bytes[..]).unwrap_err();
+		match error {
+			WebSocketError::Protocol(ProtocolError::InvalidUtf8(_)) => (),
+			ref e => panic!("Expected InvalidUtf8, got {:?}", e),
+		}
+		assert_eq!(error.close_code(), Some(CloseCode::InvalidPayload));
+		assert!(error.is_fatal());
+		assert!(!receiver.has_partial());
+	}
+}
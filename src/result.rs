@@ -1,13 +1,16 @@
 //! The result type used within Rust-WebSocket
 
+use header::InvalidKeyError;
 pub use hyper::status::StatusCode;
 use hyper::Error as HttpError;
+use message::CloseCode;
 use server::upgrade::HyperIntoWsError;
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
 use std::io;
 use std::str::Utf8Error;
+use std::string::FromUtf8Error;
 use url::ParseError;
 
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
@@ -35,6 +38,9 @@ pub mod async {
 pub enum WebSocketError {
 	/// A WebSocket protocol error
 	ProtocolError(&'static str),
+	/// The other end violated the WebSocket protocol, the connection should
+	/// be closed with `ProtocolError::close_code`
+	Protocol(ProtocolError),
 	/// Invalid WebSocket request error
 	RequestError(&'static str),
 	/// Invalid WebSocket response error
@@ -43,8 +49,33 @@ pub enum WebSocketError {
 	DataFrameError(&'static str),
 	/// Received unexpected status code
 	StatusCodeError(StatusCode),
+	/// An HTTP proxy didn't open a tunnel to the target, e.g. with 407 when
+	/// it needs credentials or 502 when it can't reach the target
+	ProxyStatusCodeError(StatusCode),
+	/// A SOCKS5 proxy didn't open a tunnel to the target
+	Socks5(Socks5Error),
 	/// No data available
 	NoDataAvailable,
+	/// An incoming message or dataframe exceeded the configured maximum size,
+	/// or the memory the platform can address
+	MessageTooLarge,
+	/// The server rejected the handshake because it doesn't support the
+	/// requested WebSocket version, it supports the given ones instead
+	UnsupportedVersion(Vec<u8>),
+	/// A codec's send buffer is full, the message was dropped without being
+	/// sent, see `MessageCodec::set_send_buffer_limit`
+	SendBufferFull,
+	/// Receiving timed out, see `stream::sync::set_timeouts`. On a
+	/// nonblocking stream this means that nothing can be read right now. What
+	/// was read so far is kept, receiving can be retried.
+	///
+	/// Connecting fails with it when the handshake takes longer than the
+	/// timeout of the `ConnectOptions`.
+	Timeout,
+	/// The connection was closed before a Close message was received
+	ConnectionDropped,
+	/// The connection was closed in the middle of a dataframe
+	IncompleteFrame,
 	/// An input/output error
 	IoError(io::Error),
 	/// An HTTP parsing error
@@ -66,22 +97,125 @@ pub enum WebSocketError {
 	Utf8Error(Utf8Error),
 }
 
-impl fmt::Display for WebSocketError {
-	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-		fmt.write_str("WebSocketError: ")?;
-		fmt.write_str(self.description())?;
-		Ok(())
-	}
+/// The broad category of a `WebSocketError`, see `WebSocketError::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WebSocketErrorKind {
+	/// The other end violated the protocol or sent something this end
+	/// doesn't accept, e.g. a too large message. The connection has to be
+	/// closed, see `WebSocketError::close_code`.
+	Protocol,
+	/// Reading from or writing to the connection failed, or it was closed.
+	/// The connection is gone.
+	Io,
+	/// The operation can't complete right now, e.g. on a nonblocking socket
+	/// or with a full send buffer. The connection is fine, try again later.
+	WouldBlock,
+	/// The opening handshake failed, e.g. because of an HTTP or TLS error or
+	/// an invalid or rejected response. There is no connection, but
+	/// connecting again may succeed.
+	Handshake,
+	/// Invalid input from this end, e.g. a URL which isn't a WebSocket URL or
+	/// a dataframe which can't be sent. Nothing was sent, an existing
+	/// connection is fine.
+	Usage,
 }
 
-impl Error for WebSocketError {
-	fn description(&self) -> &str {
+impl WebSocketError {
+	/// The category of this error:
+	///
+	/// - `ProtocolError`, `Protocol`, `MessageTooLarge` and `Utf8Error` are
+	///   `Protocol` errors
+	/// - `NoDataAvailable`, `ConnectionDropped`, `IncompleteFrame` and
+	///   `IoError` are `Io` errors, except for an `IoError` of kind
+	///   `WouldBlock` or `Interrupted`
+	/// - `SendBufferFull`, `Timeout` and those `IoError`s are `WouldBlock`
+	///   errors
+	/// - `RequestError`, `ResponseError`, `StatusCodeError`,
+	///   `ProxyStatusCodeError`, `Socks5`, `UnsupportedVersion`, `HttpError`
+	///   and the TLS errors are `Handshake` errors
+	/// - `DataFrameError`, `UrlError` and `WebSocketUrlError` are `Usage`
+	///   errors
+	pub fn kind(&self) -> WebSocketErrorKind {
+		use self::WebSocketErrorKind::*;
+		match *self {
+			WebSocketError::ProtocolError(_)
+			| WebSocketError::Protocol(_)
+			| WebSocketError::MessageTooLarge
+			| WebSocketError::Utf8Error(_) => Protocol,
+			WebSocketError::IoError(ref error) => match error.kind() {
+				io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => WouldBlock,
+				_ => Io,
+			},
+			WebSocketError::NoDataAvailable
+			| WebSocketError::ConnectionDropped
+			| WebSocketError::IncompleteFrame => Io,
+			WebSocketError::SendBufferFull | WebSocketError::Timeout => WouldBlock,
+			WebSocketError::RequestError(_)
+			| WebSocketError::ResponseError(_)
+			| WebSocketError::StatusCodeError(_)
+			| WebSocketError::ProxyStatusCodeError(_)
+			| WebSocketError::Socks5(_)
+			| WebSocketError::UnsupportedVersion(_)
+			| WebSocketError::HttpError(_) => Handshake,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			WebSocketError::TlsError(_)
+			| WebSocketError::TlsHandshakeFailure
+			| WebSocketError::TlsHandshakeInterruption => Handshake,
+			WebSocketError::DataFrameError(_)
+			| WebSocketError::UrlError(_)
+			| WebSocketError::WebSocketUrlError(_) => Usage,
+		}
+	}
+
+	/// Whether the connection is unusable after this error, i.e. for
+	/// `Protocol`, `Io` and `Handshake` errors (see `kind`). Only the latter
+	/// are worth reconnecting right away for.
+	pub fn is_fatal(&self) -> bool {
+		match self.kind() {
+			WebSocketErrorKind::Protocol
+			| WebSocketErrorKind::Io
+			| WebSocketErrorKind::Handshake => true,
+			WebSocketErrorKind::WouldBlock | WebSocketErrorKind::Usage => false,
+		}
+	}
+
+	/// The status code to close the connection with after this error, if
+	/// the other end is to blame for it.
+	///
+	/// ```rust
+	/// # use websocket::result::{ProtocolError, WebSocketError};
+	/// # use websocket::CloseCode;
+	/// let error = WebSocketError::Protocol(ProtocolError::ReservedOpcode(0x3));
+	/// assert_eq!(error.close_code(), Some(CloseCode::ProtocolError));
+	/// assert_eq!(WebSocketError::MessageTooLarge.close_code(), Some(CloseCode::MessageTooBig));
+	/// assert_eq!(WebSocketError::SendBufferFull.close_code(), None);
+	/// ```
+	pub fn close_code(&self) -> Option<CloseCode> {
+		match *self {
+			WebSocketError::Protocol(ref error) => Some(error.close_code()),
+			WebSocketError::MessageTooLarge => Some(CloseCode::MessageTooBig),
+			WebSocketError::Utf8Error(_) => Some(CloseCode::InvalidPayload),
+			_ => None,
+		}
+	}
+
+	// What went wrong, without the data of the error.
+	fn message(&self) -> &'static str {
 		match *self {
 			WebSocketError::ProtocolError(_) => "WebSocket protocol error",
+			WebSocketError::Protocol(_) => "WebSocket protocol violation",
 			WebSocketError::RequestError(_) => "WebSocket request error",
 			WebSocketError::ResponseError(_) => "WebSocket response error",
 			WebSocketError::DataFrameError(_) => "WebSocket data frame error",
 			WebSocketError::NoDataAvailable => "No data available",
+			WebSocketError::MessageTooLarge => "Message too large",
+			WebSocketError::UnsupportedVersion(_) => {
+				"WebSocket version not supported by the server"
+			}
+			WebSocketError::SendBufferFull => "Send buffer full",
+			WebSocketError::Timeout => "Timed out",
+			WebSocketError::ConnectionDropped => "Connection closed without a close message",
+			WebSocketError::IncompleteFrame => "Connection closed in the middle of a dataframe",
 			WebSocketError::IoError(_) => "I/O failure",
 			WebSocketError::HttpError(_) => "HTTP failure",
 			WebSocketError::UrlError(_) => "URL failure",
@@ -94,11 +228,46 @@ impl Error for WebSocketError {
 			WebSocketError::Utf8Error(_) => "UTF-8 failure",
 			WebSocketError::WebSocketUrlError(_) => "WebSocket URL failure",
 			WebSocketError::StatusCodeError(_) => "Received unexpected status code",
+			WebSocketError::ProxyStatusCodeError(_) => "HTTP proxy refused to connect",
+			WebSocketError::Socks5(_) => "SOCKS5 proxy failure",
+		}
+	}
+}
+
+impl fmt::Display for WebSocketError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.write_str("WebSocketError: ")?;
+		fmt.write_str(self.message())?;
+		match *self {
+			WebSocketError::ProtocolError(message)
+			| WebSocketError::RequestError(message)
+			| WebSocketError::ResponseError(message)
+			| WebSocketError::DataFrameError(message) => write!(fmt, ": {}", message),
+			WebSocketError::Protocol(ref error) => write!(fmt, ": {}", error),
+			WebSocketError::Socks5(ref error) => write!(fmt, ": {}", error),
+			WebSocketError::StatusCodeError(status)
+			| WebSocketError::ProxyStatusCodeError(status) => write!(fmt, " {}", status),
+			WebSocketError::UnsupportedVersion(ref versions) => {
+				let versions: Vec<_> = versions.iter().map(u8::to_string).collect();
+				write!(fmt, " (server supports versions {})", versions.join(", "))
+			}
+			WebSocketError::IoError(ref error) => write!(fmt, ": {}", error),
+			WebSocketError::HttpError(ref error) => write!(fmt, ": {}", error),
+			WebSocketError::UrlError(ref error) => write!(fmt, ": {}", error),
+			WebSocketError::WebSocketUrlError(ref error) => write!(fmt, ": {}", error.message()),
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			WebSocketError::TlsError(ref error) => write!(fmt, ": {}", error),
+			WebSocketError::Utf8Error(ref error) => write!(fmt, ": {}", error),
+			_ => Ok(()),
 		}
 	}
+}
 
-	fn cause(&self) -> Option<&Error> {
+impl Error for WebSocketError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match *self {
+			WebSocketError::Protocol(ref error) => Some(error),
+			WebSocketError::Socks5(ref error) => Some(error),
 			WebSocketError::IoError(ref error) => Some(error),
 			WebSocketError::HttpError(ref error) => Some(error),
 			WebSocketError::UrlError(ref error) => Some(error),
@@ -111,6 +280,222 @@ impl Error for WebSocketError {
 	}
 }
 
+/// A violation of the WebSocket protocol which the codecs detect in the data
+/// the other end sent, or which is found in the headers of its handshake, see
+/// `WebSocketError::Protocol`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+	/// A dataframe with one of the reserved opcodes (0x3-0x7 and 0xB-0xF),
+	/// which are not used by any negotiated extension
+	ReservedOpcode(u8),
+	/// A dataframe with reserved bits set which no negotiated extension
+	/// uses, given like in the first byte of the header (RSV1 is 0x40)
+	ReservedBits(u8),
+	/// A control frame with the given opcode and FIN cleared, control frames
+	/// must not be fragmented
+	FragmentedControlFrame(u8),
+	/// A control frame with a payload of the given length, control frames can
+	/// have at most 125 bytes
+	ControlFrameTooLarge(u64),
+	/// A dataframe with a payload length of the given value which isn't
+	/// encoded in as few bytes as possible
+	NonMinimalLength(u64),
+	/// A dataframe with a payload length of the given value in the eight
+	/// byte form which has the most significant bit set
+	InvalidLength(u64),
+	/// A continuation frame although no fragmented message was started
+	UnexpectedContinuation,
+	/// A data message with the given opcode whose last frame has FIN
+	/// cleared, or whose frames before the last one don't
+	UnfinishedMessage(u8),
+	/// A data frame with the given opcode in the middle of a fragmented
+	/// message, only continuation frames may follow the first one
+	ExpectedContinuation(u8),
+	/// A dataframe with the given opcode marked as compressed, only the
+	/// first frame of a data message can be
+	UnexpectedCompression(u8),
+	/// A dataframe which is masked although it comes from a server, or
+	/// unmasked although it comes from a client
+	UnexpectedMask {
+		/// The opcode of the dataframe
+		opcode: u8,
+		/// Whether the dataframe was masked
+		masked: bool,
+	},
+	/// A text message or close reason which isn't valid UTF-8
+	InvalidUtf8(Utf8Error),
+	/// A close message with a status code which must not be sent
+	InvalidCloseCode(u16),
+	/// A close message with a payload of a single byte, which can't hold a
+	/// status code
+	InvalidClosePayload,
+	/// A message split into more than the given number of fragments, see
+	/// `MessageCodec::set_max_fragments`
+	TooManyFragments(usize),
+	/// A Sec-WebSocket-Version header which isn't a list of numbers from 0
+	/// to 255
+	InvalidVersion,
+	/// A Sec-WebSocket-Key header which isn't the Base64 encoding of 16 bytes
+	InvalidKey(InvalidKeyError),
+}
+
+impl ProtocolError {
+	/// The status code to close the connection with after this violation:
+	/// `InvalidPayload` (1007) for invalid UTF-8, `MessageTooBig` (1009) for
+	/// too many fragments and `ProtocolError` (1002) for anything else.
+	pub fn close_code(&self) -> CloseCode {
+		match *self {
+			ProtocolError::InvalidUtf8(_) => CloseCode::InvalidPayload,
+			ProtocolError::TooManyFragments(_) => CloseCode::MessageTooBig,
+			_ => CloseCode::ProtocolError,
+		}
+	}
+}
+
+impl fmt::Display for ProtocolError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ProtocolError::ReservedOpcode(opcode) => {
+				write!(fmt, "reserved opcode {:#X} received", opcode)
+			}
+			ProtocolError::ReservedBits(bits) => {
+				write!(fmt, "unsupported reserved bits {:#X} received", bits)
+			}
+			ProtocolError::FragmentedControlFrame(opcode) => {
+				write!(
+					fmt,
+					"fragmented control frame received (opcode {:#X})",
+					opcode
+				)
+			}
+			ProtocolError::ControlFrameTooLarge(len) => {
+				write!(fmt, "control frame with {} bytes received", len)
+			}
+			ProtocolError::NonMinimalLength(len) => {
+				write!(fmt, "non-minimally encoded length {} received", len)
+			}
+			ProtocolError::InvalidLength(len) => {
+				write!(
+					fmt,
+					"length {:#X} with the most significant bit set received",
+					len
+				)
+			}
+			ProtocolError::UnexpectedContinuation => {
+				fmt.write_str("unexpected continuation frame received")
+			}
+			ProtocolError::UnfinishedMessage(opcode) => write!(
+				fmt,
+				"message with opcode {:#X} not finished by its last frame received",
+				opcode
+			),
+			ProtocolError::ExpectedContinuation(opcode) => write!(
+				fmt,
+				"data frame with opcode {:#X} in a fragmented message received",
+				opcode
+			),
+			ProtocolError::UnexpectedCompression(opcode) => write!(
+				fmt,
+				"unexpected compressed dataframe received (opcode {:#X})",
+				opcode
+			),
+			ProtocolError::UnexpectedMask { opcode, masked } => {
+				let masked = if masked { "masked" } else { "unmasked" };
+				write!(fmt, "{} dataframe received (opcode {:#X})", masked, opcode)
+			}
+			ProtocolError::InvalidUtf8(ref error) => {
+				write!(fmt, "invalid UTF-8 received: {}", error)
+			}
+			ProtocolError::InvalidCloseCode(code) => {
+				write!(fmt, "invalid close status code {} received", code)
+			}
+			ProtocolError::InvalidClosePayload => fmt.write_str("invalid close payload received"),
+			ProtocolError::TooManyFragments(max) => {
+				write!(fmt, "message with more than {} fragments received", max)
+			}
+			ProtocolError::InvalidVersion => {
+				fmt.write_str("invalid Sec-WebSocket-Version received")
+			}
+			ProtocolError::InvalidKey(ref error) => {
+				write!(fmt, "invalid Sec-WebSocket-Key received: {}", error)
+			}
+		}
+	}
+}
+
+impl Error for ProtocolError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match *self {
+			ProtocolError::InvalidUtf8(ref error) => Some(error),
+			ProtocolError::InvalidKey(ref error) => Some(error),
+			_ => None,
+		}
+	}
+}
+
+impl From<ProtocolError> for WebSocketError {
+	fn from(err: ProtocolError) -> WebSocketError {
+		WebSocketError::Protocol(err)
+	}
+}
+
+/// Why a SOCKS5 proxy didn't open a tunnel, see
+/// `stream::sync::connect_via_socks5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Socks5Error {
+	/// The proxy accepts none of the offered authentication methods, e.g.
+	/// because it needs a user name and password
+	NoAcceptableAuth,
+	/// The proxy rejected the user name and password
+	AuthFailed,
+	/// The proxy couldn't connect to the target, with the reply code of
+	/// RFC 1928, e.g. 5 if the target refused the connection
+	ConnectFailed(u8),
+	/// The proxy's answer isn't valid SOCKS5
+	InvalidResponse,
+}
+
+impl Socks5Error {
+	/// Whether the target refused the connection, i.e. `ConnectFailed(5)`.
+	pub fn is_connection_refused(&self) -> bool {
+		*self == Socks5Error::ConnectFailed(5)
+	}
+}
+
+impl fmt::Display for Socks5Error {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Socks5Error::NoAcceptableAuth => {
+				fmt.write_str("no acceptable authentication method offered")
+			}
+			Socks5Error::AuthFailed => fmt.write_str("authentication failed"),
+			Socks5Error::ConnectFailed(reply) => {
+				let reason = match reply {
+					1 => "general failure",
+					2 => "connection not allowed by ruleset",
+					3 => "network unreachable",
+					4 => "host unreachable",
+					5 => "connection refused",
+					6 => "TTL expired",
+					7 => "command not supported",
+					8 => "address type not supported",
+					_ => "unknown reply",
+				};
+				write!(fmt, "connecting failed: {} ({})", reason, reply)
+			}
+			Socks5Error::InvalidResponse => fmt.write_str("invalid response"),
+		}
+	}
+}
+
+impl Error for Socks5Error {}
+
+impl From<Socks5Error> for WebSocketError {
+	fn from(err: Socks5Error) -> WebSocketError {
+		WebSocketError::Socks5(err)
+	}
+}
+
 impl From<io::Error> for WebSocketError {
 	fn from(err: io::Error) -> WebSocketError {
 		if err.kind() == io::ErrorKind::UnexpectedEof {
@@ -155,6 +540,12 @@ impl From<Utf8Error> for WebSocketError {
 	}
 }
 
+impl From<FromUtf8Error> for WebSocketError {
+	fn from(err: FromUtf8Error) -> WebSocketError {
+		WebSocketError::Utf8Error(err.utf8_error())
+	}
+}
+
 #[cfg(feature = "async")]
 impl From<::codec::http::HttpCodecError> for WebSocketError {
 	fn from(src: ::codec::http::HttpCodecError) -> Self {
@@ -182,6 +573,7 @@ impl From<HyperIntoWsError> for WebSocketError {
 			UnsupportedHttpVersion => ProtocolError("Unsupported request HTTP version"),
 			UnsupportedWebsocketVersion => ProtocolError("Unsupported WebSocket version"),
 			NoSecWsKeyHeader => ProtocolError("Missing Sec-WebSocket-Key header"),
+			InvalidSecWsKey(error) => Protocol(self::ProtocolError::InvalidKey(error)),
 			NoWsUpgradeHeader => ProtocolError("Invalid Upgrade WebSocket header"),
 			NoUpgradeHeader => ProtocolError("Missing Upgrade WebSocket header"),
 			NoWsConnectionHeader => ProtocolError("Invalid Connection WebSocket header"),
@@ -201,20 +593,152 @@ pub enum WSUrlErrorKind {
 	NoHostName,
 }
 
+impl WSUrlErrorKind {
+	fn message(&self) -> &'static str {
+		match *self {
+			WSUrlErrorKind::CannotSetFragment => "WebSocket URL cannot set fragment",
+			WSUrlErrorKind::InvalidScheme => "WebSocket URL invalid scheme",
+			WSUrlErrorKind::NoHostName => "WebSocket URL no host name provided",
+		}
+	}
+}
+
 impl fmt::Display for WSUrlErrorKind {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		fmt.write_str("WebSocket Url Error: ")?;
-		fmt.write_str(self.description())?;
-		Ok(())
+		fmt.write_str(self.message())
 	}
 }
 
-impl Error for WSUrlErrorKind {
-	fn description(&self) -> &str {
-		match *self {
-			WSUrlErrorKind::CannotSetFragment => "WebSocket URL cannot set fragment",
-			WSUrlErrorKind::InvalidScheme => "WebSocket URL invalid scheme",
-			WSUrlErrorKind::NoHostName => "WebSocket URL no host name provided",
+impl Error for WSUrlErrorKind {}
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_error_kinds() {
+		use super::WebSocketErrorKind::*;
+		let io = |kind| WebSocketError::IoError(io::Error::new(kind, "test"));
+		let utf8 = ::std::str::from_utf8(&[0xFF]).unwrap_err();
+		let errors = vec![
+			(WebSocketError::ProtocolError("test"), Protocol),
+			(ProtocolError::ReservedOpcode(0x3).into(), Protocol),
+			(WebSocketError::MessageTooLarge, Protocol),
+			(ProtocolError::UnfinishedMessage(0x1).into(), Protocol),
+			(WebSocketError::Utf8Error(utf8), Protocol),
+			(io(io::ErrorKind::ConnectionReset), Io),
+			(io(io::ErrorKind::WouldBlock), WouldBlock),
+			(io(io::ErrorKind::Interrupted), WouldBlock),
+			(WebSocketError::NoDataAvailable, Io),
+			(WebSocketError::ConnectionDropped, Io),
+			(WebSocketError::IncompleteFrame, Io),
+			(WebSocketError::SendBufferFull, WouldBlock),
+			(WebSocketError::Timeout, WouldBlock),
+			(WebSocketError::RequestError("test"), Handshake),
+			(WebSocketError::ResponseError("test"), Handshake),
+			(
+				WebSocketError::StatusCodeError(StatusCode::Forbidden),
+				Handshake,
+			),
+			(
+				WebSocketError::ProxyStatusCodeError(StatusCode::BadGateway),
+				Handshake,
+			),
+			(Socks5Error::AuthFailed.into(), Handshake),
+			(WebSocketError::UnsupportedVersion(vec![13]), Handshake),
+			(WebSocketError::HttpError(HttpError::Method), Handshake),
+			(WebSocketError::DataFrameError("test"), Usage),
+			(WebSocketError::UrlError(ParseError::EmptyHost), Usage),
+			(WSUrlErrorKind::InvalidScheme.into(), Usage),
+		];
+		for (error, kind) in errors {
+			assert_eq!(error.kind(), kind, "{:?}", error);
+			assert_eq!(
+				error.is_fatal(),
+				kind != WouldBlock && kind != Usage,
+				"{:?}",
+				error
+			);
+		}
+	}
+
+	#[test]
+	fn test_display_includes_data() {
+		let errors: Vec<(WebSocketError, &str)> = vec![
+			(
+				WebSocketError::ProtocolError("Unknown subprotocol"),
+				"WebSocketError: WebSocket protocol error: Unknown subprotocol",
+			),
+			(
+				WebSocketError::StatusCodeError(StatusCode::Forbidden),
+				"WebSocketError: Received unexpected status code 403 Forbidden",
+			),
+			(
+				WebSocketError::ProxyStatusCodeError(StatusCode::ProxyAuthenticationRequired),
+				"WebSocketError: HTTP proxy refused to connect 407 Proxy Authentication Required",
+			),
+			(
+				ProtocolError::ReservedOpcode(0x3).into(),
+				"WebSocketError: WebSocket protocol violation: reserved opcode 0x3 received",
+			),
+			(
+				WebSocketError::UnsupportedVersion(vec![8, 13]),
+				"WebSocketError: WebSocket version not supported by the server \
+				 (server supports versions 8, 13)",
+			),
+			(
+				ProtocolError::InvalidCloseCode(1005).into(),
+				"WebSocketError: WebSocket protocol violation: invalid close status code 1005 received",
+			),
+			(
+				Socks5Error::ConnectFailed(5).into(),
+				"WebSocketError: SOCKS5 proxy failure: connecting failed: connection refused (5)",
+			),
+			(
+				io::Error::new(io::ErrorKind::Other, "broken pipe").into(),
+				"WebSocketError: I/O failure: broken pipe",
+			),
+			(
+				WSUrlErrorKind::NoHostName.into(),
+				"WebSocketError: WebSocket URL failure: WebSocket URL no host name provided",
+			),
+		];
+		for (error, display) in errors {
+			assert_eq!(error.to_string(), display);
+		}
+		assert_eq!(
+			WSUrlErrorKind::InvalidScheme.to_string(),
+			"WebSocket Url Error: WebSocket URL invalid scheme"
+		);
+	}
+
+	#[test]
+	fn test_source_chain() {
+		let error: WebSocketError = io::Error::new(io::ErrorKind::Other, "broken pipe").into();
+		assert_eq!(error.source().unwrap().to_string(), "broken pipe");
+
+		let utf8 = ::std::str::from_utf8(&[0xFF]).unwrap_err();
+		let error: WebSocketError = ProtocolError::InvalidUtf8(utf8).into();
+		let protocol = error.source().unwrap();
+		assert_eq!(protocol.source().unwrap().to_string(), utf8.to_string());
+
+		let error: WebSocketError = String::from_utf8(vec![0xFF]).unwrap_err().into();
+		assert_eq!(error.source().unwrap().to_string(), utf8.to_string());
+
+		assert!(WebSocketError::NoDataAvailable.source().is_none());
+		assert!(WSUrlErrorKind::NoHostName.source().is_none());
+	}
+
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	#[test]
+	fn test_tls_error_kinds() {
+		for error in &[
+			WebSocketError::TlsHandshakeFailure,
+			WebSocketError::TlsHandshakeInterruption,
+		] {
+			assert_eq!(error.kind(), WebSocketErrorKind::Handshake);
+			assert!(error.is_fatal());
 		}
 	}
 }
@@ -3,6 +3,7 @@
 use result::WebSocketResult;
 use std::io::Result as IoResult;
 use std::io::Write;
+use std::net::SocketAddr;
 use stream::sync::AsTcpStream;
 pub use stream::sync::Shutdown;
 use ws;
@@ -57,6 +58,18 @@ where
 	pub fn shutdown_all(&self) -> IoResult<()> {
 		self.stream.as_tcp().shutdown(Shutdown::Both)
 	}
+
+	/// See [`TcpStream::peer_addr`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.peer_addr).
+	pub fn peer_addr(&self) -> IoResult<SocketAddr> {
+		self.stream.as_tcp().peer_addr()
+	}
+
+	/// See [`TcpStream::local_addr`]
+	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.local_addr).
+	pub fn local_addr(&self) -> IoResult<SocketAddr> {
+		self.stream.as_tcp().local_addr()
+	}
 }
 
 /// A Sender that wraps a Writer and provides a default implementation using
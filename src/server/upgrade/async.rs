@@ -117,7 +117,7 @@ where
 	/// into it's original stream. The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject(self) -> SinkSend<Framed<S, HttpServerCodec>> {
-		self.internal_reject(None)
+		self.internal_reject(StatusCode::BadRequest, None)
 	}
 
 	/// Asynchronously send a rejection message with custom headers and
@@ -125,11 +125,19 @@ where
 	///  The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject_with(self, headers: &Headers) -> SinkSend<Framed<S, HttpServerCodec>> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(StatusCode::BadRequest, Some(headers))
+	}
+
+	/// Asynchronously send a `403 Forbidden` response, e.g. to a client whose
+	/// origin isn't allowed (see `origin_allowed`), and deconstruct `self`
+	/// into it's original stream.
+	pub fn reject_forbidden(self) -> SinkSend<Framed<S, HttpServerCodec>> {
+		self.internal_reject(StatusCode::Forbidden, None)
 	}
 
 	fn internal_reject(
 		mut self,
+		status: StatusCode,
 		headers: Option<&Headers>,
 	) -> SinkSend<Framed<S, HttpServerCodec>> {
 		if let Some(custom) = headers {
@@ -140,7 +148,7 @@ where
 		let duplex = Framed::from_parts(parts);
 		duplex.send(Incoming {
 			version: self.request.version,
-			subject: StatusCode::BadRequest,
+			subject: status,
 			headers: self.headers,
 		})
 	}
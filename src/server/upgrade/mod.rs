@@ -2,7 +2,8 @@
 //! WebSocket client.
 use header::extensions::Extension;
 use header::{
-	Origin, WebSocketAccept, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion,
+	InvalidKeyError, Origin, OriginMatcher, WebSocketAccept, WebSocketExtensions, WebSocketKey,
+	WebSocketProtocol, WebSocketVersion,
 };
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
@@ -16,6 +17,10 @@ use hyper::status::StatusCode;
 use hyper::uri::RequestUri;
 use unicase::UniCase;
 
+#[cfg(any(feature = "sync", feature = "async"))]
+use header::has_token;
+#[cfg(any(feature = "sync", feature = "async"))]
+use hyper::header::Header;
 #[cfg(any(feature = "sync", feature = "async"))]
 use hyper::version::HttpVersion;
 
@@ -133,6 +138,19 @@ where
 		self.request.headers.get::<Origin>().map(|o| &o.0 as &str)
 	}
 
+	/// Whether the origin of the client is allowed by `policy`, clients whose
+	/// origin isn't should be rejected with `reject_forbidden`.
+	///
+	/// Without a policy every origin is allowed. Requests without an Origin
+	/// header are allowed as well, only browsers send one and other clients
+	/// can claim any origin anyway.
+	pub fn origin_allowed(&self, policy: Option<&OriginMatcher>) -> bool {
+		match (policy, self.request.headers.get::<Origin>()) {
+			(Some(allowed), Some(origin)) => origin.matches(allowed),
+			_ => true,
+		}
+	}
+
 	#[cfg(feature = "sync")]
 	fn send(&mut self, status: StatusCode) -> io::Result<()> {
 		let data = format!(
@@ -175,6 +193,9 @@ pub enum HyperIntoWsError {
 	UnsupportedWebsocketVersion,
 	/// A websocket upgrade request must contain a key
 	NoSecWsKeyHeader,
+	/// The key of a websocket upgrade request is invalid, the request should
+	/// be answered with 400 Bad Request
+	InvalidSecWsKey(InvalidKeyError),
 	/// A websocket upgrade request must ask to upgrade to a `websocket`
 	NoWsUpgradeHeader,
 	/// A websocket upgrade request must contain an `Upgrade` header
@@ -191,31 +212,40 @@ pub enum HyperIntoWsError {
 
 impl Display for HyperIntoWsError {
 	fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
-		fmt.write_str(self.description())
+		match *self {
+			HyperIntoWsError::InvalidSecWsKey(ref e) => write!(fmt, "{}: {}", self.message(), e),
+			HyperIntoWsError::Io(ref e) => e.fmt(fmt),
+			HyperIntoWsError::Parsing(ref e) => e.fmt(fmt),
+			_ => fmt.write_str(self.message()),
+		}
 	}
 }
 
-impl Error for HyperIntoWsError {
-	fn description(&self) -> &str {
+impl HyperIntoWsError {
+	fn message(&self) -> &'static str {
 		use self::HyperIntoWsError::*;
 		match *self {
 			MethodNotGet => "Request method must be GET",
 			UnsupportedHttpVersion => "Unsupported request HTTP version",
 			UnsupportedWebsocketVersion => "Unsupported WebSocket version",
 			NoSecWsKeyHeader => "Missing Sec-WebSocket-Key header",
+			InvalidSecWsKey(_) => "Invalid Sec-WebSocket-Key header",
 			NoWsUpgradeHeader => "Invalid Upgrade WebSocket header",
 			NoUpgradeHeader => "Missing Upgrade WebSocket header",
 			NoWsConnectionHeader => "Invalid Connection WebSocket header",
 			NoConnectionHeader => "Missing Connection WebSocket header",
-			Io(ref e) => e.description(),
-			Parsing(ref e) => e.description(),
+			Io(_) => "I/O failure",
+			Parsing(_) => "Failed to parse the request",
 		}
 	}
+}
 
-	fn cause(&self) -> Option<&Error> {
+impl Error for HyperIntoWsError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match *self {
 			HyperIntoWsError::Io(ref e) => Some(e),
 			HyperIntoWsError::Parsing(ref e) => Some(e),
+			HyperIntoWsError::InvalidSecWsKey(ref e) => Some(e),
 			_ => None,
 		}
 	}
@@ -264,38 +294,28 @@ pub fn validate(
 		}
 	}
 
-	if headers.get::<WebSocketKey>().is_none() {
-		return Err(HyperIntoWsError::NoSecWsKeyHeader);
+	match headers.get_raw(WebSocketKey::header_name()) {
+		Some(lines) if lines.len() == 1 => {
+			let key = ::std::str::from_utf8(&lines[0])
+				.map_err(|_| HyperIntoWsError::InvalidSecWsKey(InvalidKeyError::InvalidBase64))?;
+			WebSocketKey::validate(key).map_err(HyperIntoWsError::InvalidSecWsKey)?;
+		}
+		_ => return Err(HyperIntoWsError::NoSecWsKeyHeader),
 	}
 
-	match headers.get() {
-		Some(&Upgrade(ref upgrade)) => {
-			if upgrade.iter().all(|u| u.name != ProtocolName::WebSocket) {
-				return Err(HyperIntoWsError::NoWsUpgradeHeader);
-			}
-		}
-		None => return Err(HyperIntoWsError::NoUpgradeHeader),
-	};
-
-	fn check_connection_header(headers: &[ConnectionOption]) -> bool {
-		for header in headers {
-			if let ConnectionOption::ConnectionHeader(ref h) = *header {
-				if UniCase(h as &str) == UniCase("upgrade") {
-					return true;
-				}
-			}
-		}
-		false
+	if headers.get_raw("Upgrade").is_none() {
+		return Err(HyperIntoWsError::NoUpgradeHeader);
+	}
+	if !has_token(headers, "Upgrade", "websocket") {
+		return Err(HyperIntoWsError::NoWsUpgradeHeader);
 	}
 
-	match headers.get() {
-		Some(&Connection(ref connection)) => {
-			if !check_connection_header(connection) {
-				return Err(HyperIntoWsError::NoWsConnectionHeader);
-			}
-		}
-		None => return Err(HyperIntoWsError::NoConnectionHeader),
-	};
+	if headers.get_raw("Connection").is_none() {
+		return Err(HyperIntoWsError::NoConnectionHeader);
+	}
+	if !has_token(headers, "Connection", "upgrade") {
+		return Err(HyperIntoWsError::NoWsConnectionHeader);
+	}
 
 	Ok(())
 }
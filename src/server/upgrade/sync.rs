@@ -78,20 +78,30 @@ where
 
 	/// Reject the client's request to make a websocket connection.
 	pub fn reject(self) -> Result<S, (S, io::Error)> {
-		self.internal_reject(None)
+		self.internal_reject(StatusCode::BadRequest, None)
 	}
 
 	/// Reject the client's request to make a websocket connection
 	/// and send extra headers.
 	pub fn reject_with(self, headers: &Headers) -> Result<S, (S, io::Error)> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(StatusCode::BadRequest, Some(headers))
 	}
 
-	fn internal_reject(mut self, headers: Option<&Headers>) -> Result<S, (S, io::Error)> {
+	/// Reject the client's request with `403 Forbidden`, e.g. because its
+	/// origin isn't allowed (see `origin_allowed`).
+	pub fn reject_forbidden(self) -> Result<S, (S, io::Error)> {
+		self.internal_reject(StatusCode::Forbidden, None)
+	}
+
+	fn internal_reject(
+		mut self,
+		status: StatusCode,
+		headers: Option<&Headers>,
+	) -> Result<S, (S, io::Error)> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.iter());
 		}
-		match self.send(StatusCode::BadRequest) {
+		match self.send(status) {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
@@ -1,7 +1,22 @@
 //! Provides the default stream type for WebSocket connections.
 
-use std::fmt::Arguments;
+#[cfg(any(feature = "sync", feature = "async"))]
+use hyper::buffer::BufReader;
+#[cfg(any(feature = "sync", feature = "async"))]
+use hyper::http::h1::parse_response;
+#[cfg(any(feature = "sync", feature = "async"))]
+use hyper::status::StatusCode;
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+use native_tls::TlsStream;
+#[cfg(any(feature = "sync", feature = "async"))]
+use result::{Socks5Error, WebSocketError, WebSocketResult};
+use std::fmt::{self, Arguments};
 use std::io::{self, Read, Write};
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::time::Duration;
 
 /// Represents a stream that can be read from, and written to.
 /// This is an abstraction around readable and writable things to be able
@@ -9,6 +24,250 @@ use std::io::{self, Read, Write};
 pub trait Stream: Read + Write {}
 impl<S> Stream for S where S: Read + Write {}
 
+/// Options of the TCP socket under a stream, also if it is wrapped in TLS.
+///
+/// They are set with `sync::set_options` and `async::set_options`, or by
+/// `ClientBuilder::stream_options` right after connecting.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use websocket::stream::StreamOptions;
+/// let options = StreamOptions {
+///     nodelay: true,
+///     keepalive: Some(Duration::from_secs(60)),
+///     ..StreamOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamOptions {
+	/// Send small writes right away instead of collecting them first, i.e.
+	/// disable Nagle's algorithm. This lowers the latency of small messages.
+	pub nodelay: bool,
+	/// Send keepalive probes after the connection was idle this long, e.g.
+	/// to keep it open through a NAT. `None` turns them off.
+	pub keepalive: Option<Duration>,
+	/// The size of the receive buffer of the socket in bytes, `None` leaves
+	/// it to the OS.
+	pub recv_buffer: Option<usize>,
+	/// The size of the send buffer of the socket in bytes, `None` leaves it
+	/// to the OS.
+	pub send_buffer: Option<usize>,
+}
+
+/// A stream connected over the network which knows the addresses of both of
+/// its ends, like a TCP connection or a TLS connection over one.
+///
+/// The async clients, which are framed streams, forward it, so the address
+/// of the peer can still be found after the handshake. The halves of a split
+/// async client can't, ask before splitting it.
+pub trait PeerAddr {
+	/// The address of the other end of the connection.
+	fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+	/// The address of this end of the connection.
+	fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+impl<S> PeerAddr for TlsStream<S>
+where
+	S: PeerAddr,
+{
+	fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.get_ref().peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.get_ref().local_addr()
+	}
+}
+
+impl<T> PeerAddr for Box<T>
+where
+	T: PeerAddr + ?Sized,
+{
+	fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.deref().peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.deref().local_addr()
+	}
+}
+
+/// Credentials for a proxy. `connect_via_http_proxy` sends them as
+/// `Proxy-Authorization: Basic`, `connect_via_socks5` uses the user name and
+/// password authentication of RFC 1929.
+///
+/// The `Debug` output leaves out the password.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyAuth {
+	/// The user name.
+	pub username: String,
+	/// The password.
+	pub password: String,
+}
+
+impl fmt::Debug for ProxyAuth {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ProxyAuth")
+			.field("username", &self.username)
+			.field("password", &format_args!("***"))
+			.finish()
+	}
+}
+
+impl ProxyAuth {
+	/// Basic credentials of `username` with `password`.
+	pub fn basic<U, P>(username: U, password: P) -> Self
+	where
+		U: Into<String>,
+		P: Into<String>,
+	{
+		ProxyAuth {
+			username: username.into(),
+			password: password.into(),
+		}
+	}
+}
+
+// a status line and a few headers, anything longer isn't a proxy's answer
+#[cfg(any(feature = "sync", feature = "async"))]
+const MAX_PROXY_RESPONSE: usize = 8192;
+
+#[cfg(any(feature = "sync", feature = "async"))]
+const PROXY_CLOSED: &str = "Proxy closed the connection";
+
+#[cfg(any(feature = "sync", feature = "async"))]
+fn connect_request(host: &str, port: u16, auth: Option<&ProxyAuth>) -> Vec<u8> {
+	// IPv6 addresses are put in brackets in the authority
+	let authority = if host.contains(':') && !host.starts_with('[') {
+		format!("[{}]:{}", host, port)
+	} else {
+		format!("{}:{}", host, port)
+	};
+	let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+	if let Some(auth) = auth {
+		let credentials = base64::encode(&format!("{}:{}", auth.username, auth.password));
+		request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+	}
+	request.push_str("\r\n");
+	request.into_bytes()
+}
+
+// Adds the next byte of the proxy's response and returns whether the whole
+// response was read. The response is read byte by byte because everything
+// after it belongs to the tunnel.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn push_response_byte(response: &mut Vec<u8>, byte: u8) -> WebSocketResult<bool> {
+	response.push(byte);
+	if !response.ends_with(b"\r\n\r\n") {
+		if response.len() >= MAX_PROXY_RESPONSE {
+			return Err(WebSocketError::ResponseError("Proxy response is too long"));
+		}
+		return Ok(false);
+	}
+
+	let response = parse_response(&mut BufReader::new(&response[..]))?;
+	let status = StatusCode::from_u16(response.subject.0);
+	if !status.is_success() {
+		return Err(WebSocketError::ProxyStatusCodeError(status));
+	}
+	Ok(true)
+}
+
+// the version byte of SOCKS5 and of its user name/password authentication
+#[cfg(any(feature = "sync", feature = "async"))]
+const SOCKS5: u8 = 5;
+#[cfg(any(feature = "sync", feature = "async"))]
+const SOCKS5_USER_PASS: u8 = 1;
+
+#[cfg(any(feature = "sync", feature = "async"))]
+const SOCKS5_TOO_LONG: &str = "SOCKS5 user names, passwords and host names have at most 255 bytes";
+
+// The methods the client supports, without authentication and with a user
+// name and password if it has them.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn socks5_greeting(auth: Option<&ProxyAuth>) -> WebSocketResult<Vec<u8>> {
+	match auth {
+		Some(auth) if auth.username.len() > 255 || auth.password.len() > 255 => {
+			Err(WebSocketError::RequestError(SOCKS5_TOO_LONG))
+		}
+		Some(_) => Ok(vec![SOCKS5, 2, 0x00, 0x02]),
+		None => Ok(vec![SOCKS5, 1, 0x00]),
+	}
+}
+
+// Checks the method the proxy picked, returns the credentials to send if it
+// wants them.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn socks5_method(answer: [u8; 2], auth: Option<&ProxyAuth>) -> WebSocketResult<Option<Vec<u8>>> {
+	match (answer, auth) {
+		([SOCKS5, 0x00], _) => Ok(None),
+		([SOCKS5, 0x02], Some(auth)) => {
+			let mut credentials = vec![SOCKS5_USER_PASS, auth.username.len() as u8];
+			credentials.extend_from_slice(auth.username.as_bytes());
+			credentials.push(auth.password.len() as u8);
+			credentials.extend_from_slice(auth.password.as_bytes());
+			Ok(Some(credentials))
+		}
+		([SOCKS5, 0xFF], _) => Err(Socks5Error::NoAcceptableAuth.into()),
+		_ => Err(Socks5Error::InvalidResponse.into()),
+	}
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+fn socks5_auth_status(answer: [u8; 2]) -> WebSocketResult<()> {
+	match answer {
+		[SOCKS5_USER_PASS, 0] => Ok(()),
+		[SOCKS5_USER_PASS, _] => Err(Socks5Error::AuthFailed.into()),
+		_ => Err(Socks5Error::InvalidResponse.into()),
+	}
+}
+
+// The CONNECT command, host names are sent as they are so the proxy resolves
+// them.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn socks5_connect_request(host: &str, port: u16) -> WebSocketResult<Vec<u8>> {
+	let mut request = vec![SOCKS5, 0x01, 0x00];
+	let unbracketed = host.trim_start_matches('[').trim_end_matches(']');
+	match unbracketed.parse() {
+		Ok(IpAddr::V4(ip)) => {
+			request.push(0x01);
+			request.extend_from_slice(&ip.octets());
+		}
+		Ok(IpAddr::V6(ip)) => {
+			request.push(0x04);
+			request.extend_from_slice(&ip.octets());
+		}
+		Err(_) if host.len() > 255 => return Err(WebSocketError::RequestError(SOCKS5_TOO_LONG)),
+		Err(_) => {
+			request.push(0x03);
+			request.push(host.len() as u8);
+			request.extend_from_slice(host.as_bytes());
+		}
+	}
+	request.push((port >> 8) as u8);
+	request.push(port as u8);
+	Ok(request)
+}
+
+// Checks the first five bytes of the proxy's reply to the CONNECT command and
+// returns how many bytes of it are left. Its end is the address the proxy
+// connected from, an IPv4 or IPv6 address or a host name with length byte,
+// and the port.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn socks5_reply(reply: [u8; 5]) -> WebSocketResult<usize> {
+	match reply {
+		[SOCKS5, 0x00, 0x00, 0x01, _] => Ok(3 + 2),
+		[SOCKS5, 0x00, 0x00, 0x04, _] => Ok(15 + 2),
+		[SOCKS5, 0x00, 0x00, 0x03, len] => Ok(len as usize + 2),
+		[SOCKS5, 0x00, _, _, _] => Err(Socks5Error::InvalidResponse.into()),
+		[SOCKS5, code, _, _, _] => Err(Socks5Error::ConnectFailed(code).into()),
+		_ => Err(Socks5Error::InvalidResponse.into()),
+	}
+}
+
 /// If you would like to combine an input stream and an output stream into a single
 /// stream to talk websockets over then this is the struct for you!
 ///
@@ -67,16 +326,34 @@ where
 /// A collection of traits and implementations for async streams.
 #[cfg(feature = "async")]
 pub mod async {
+	pub use super::PeerAddr;
+	pub use super::ProxyAuth;
 	pub use super::ReadWritePair;
-	use futures::Poll;
+	pub use super::StreamOptions;
+	use futures::future::{self, Either, Loop};
+	use futures::{Future, Poll};
+	use result::WebSocketError;
 	use std::io::{self, Read, Write};
+	use std::net::SocketAddr;
+	use tokio_codec::Framed;
+	use tokio_io::io::{read_exact, write_all};
 	pub use tokio_io::io::{ReadHalf, WriteHalf};
 	pub use tokio_io::{AsyncRead, AsyncWrite};
 	pub use tokio_tcp::TcpStream;
+	#[cfg(feature = "async-ssl")]
+	use tokio_tls::TlsStream;
+
+	#[cfg(unix)]
+	pub use self::unix::UnixStream;
 
 	/// A stream that can be read from and written to asynchronously.
 	/// This let's us abstract over many async streams like tcp, ssl,
 	/// udp, ssh, etc.
+	///
+	/// Anything which is `AsyncRead + AsyncWrite` is a `Stream`, the codecs,
+	/// `ClientBuilder::async_connect_on` and the upgrades of the server don't
+	/// need anything else. Only connecting to a URL and `Server` are about
+	/// TCP.
 	pub trait Stream: AsyncRead + AsyncWrite {}
 	impl<S> Stream for S where S: AsyncRead + AsyncWrite {}
 
@@ -96,18 +373,352 @@ pub mod async {
 			self.1.shutdown()
 		}
 	}
+
+	/// The ability to borrow the `TcpStream` under an async stream, so one
+	/// can set options on it.
+	pub trait AsTcpStream {
+		/// Get a borrow of the TcpStream
+		fn as_tcp(&self) -> &TcpStream;
+	}
+
+	impl AsTcpStream for TcpStream {
+		fn as_tcp(&self) -> &TcpStream {
+			self
+		}
+	}
+
+	#[cfg(feature = "async-ssl")]
+	impl<S> AsTcpStream for TlsStream<S>
+	where
+		S: AsTcpStream,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref().get_ref().as_tcp()
+		}
+	}
+
+	impl<S, C> AsTcpStream for Framed<S, C>
+	where
+		S: AsTcpStream,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref().as_tcp()
+		}
+	}
+
+	impl<T> AsTcpStream for Box<T>
+	where
+		T: AsTcpStream + ?Sized,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			(**self).as_tcp()
+		}
+	}
+
+	/// Sets `options` on the TCP socket under `stream`.
+	pub fn set_options<S>(stream: &S, options: &StreamOptions) -> io::Result<()>
+	where
+		S: AsTcpStream + ?Sized,
+	{
+		let tcp = stream.as_tcp();
+		tcp.set_nodelay(options.nodelay)?;
+		tcp.set_keepalive(options.keepalive)?;
+		if let Some(size) = options.recv_buffer {
+			tcp.set_recv_buffer_size(size)?;
+		}
+		if let Some(size) = options.send_buffer {
+			tcp.set_send_buffer_size(size)?;
+		}
+		Ok(())
+	}
+
+	/// Connects to `target_host:target_port` through the HTTP proxy at
+	/// `proxy_addr`, with a `CONNECT` request.
+	///
+	/// This is the async version of `sync::connect_via_http_proxy`, the tunnel
+	/// can be wrapped in TLS and given to `ClientBuilder::async_connect_on`.
+	pub fn connect_via_http_proxy(
+		proxy_addr: &SocketAddr,
+		target_host: &str,
+		target_port: u16,
+		auth: Option<ProxyAuth>,
+	) -> Box<dyn Future<Item = TcpStream, Error = WebSocketError> + Send> {
+		let request = super::connect_request(target_host, target_port, auth.as_ref());
+		let tunnel = TcpStream::connect(proxy_addr)
+			.and_then(move |stream| write_all(stream, request))
+			.map_err(WebSocketError::from)
+			.and_then(|(stream, _)| {
+				future::loop_fn((stream, Vec::new()), |(stream, mut response)| {
+					read_exact(stream, [0])
+						.map_err(|e| match e.kind() {
+							io::ErrorKind::UnexpectedEof => {
+								WebSocketError::ResponseError(super::PROXY_CLOSED)
+							}
+							_ => e.into(),
+						})
+						.and_then(move |(stream, byte)| {
+							if super::push_response_byte(&mut response, byte[0])? {
+								Ok(Loop::Break(stream))
+							} else {
+								Ok(Loop::Continue((stream, response)))
+							}
+						})
+				})
+			});
+		Box::new(tunnel)
+	}
+
+	/// Connects to `target_host:target_port` through the SOCKS5 proxy at
+	/// `proxy_addr`, this is the async version of `sync::connect_via_socks5`.
+	pub fn connect_via_socks5(
+		proxy_addr: &SocketAddr,
+		target_host: &str,
+		target_port: u16,
+		auth: Option<ProxyAuth>,
+	) -> Box<dyn Future<Item = TcpStream, Error = WebSocketError> + Send> {
+		let greeting = match super::socks5_greeting(auth.as_ref()) {
+			Ok(greeting) => greeting,
+			Err(e) => return Box::new(future::err(e)),
+		};
+		let request = match super::socks5_connect_request(target_host, target_port) {
+			Ok(request) => request,
+			Err(e) => return Box::new(future::err(e)),
+		};
+
+		let tunnel = TcpStream::connect(proxy_addr)
+			.and_then(move |stream| write_all(stream, greeting))
+			.and_then(|(stream, _)| read_exact(stream, [0; 2]))
+			.map_err(WebSocketError::from)
+			.and_then(
+				move |(stream, answer)| match super::socks5_method(answer, auth.as_ref()) {
+					Ok(Some(credentials)) => Either::A(
+						write_all(stream, credentials)
+							.and_then(|(stream, _)| read_exact(stream, [0; 2]))
+							.map_err(WebSocketError::from)
+							.and_then(|(stream, answer)| {
+								super::socks5_auth_status(answer).map(|_| stream)
+							}),
+					),
+					result => Either::B(future::result(result.map(|_| stream))),
+				},
+			)
+			.and_then(move |stream| {
+				write_all(stream, request)
+					.and_then(|(stream, _)| read_exact(stream, [0; 5]))
+					.map_err(WebSocketError::from)
+			})
+			.and_then(|(stream, reply)| {
+				future::result(super::socks5_reply(reply)).and_then(|len| {
+					read_exact(stream, vec![0; len])
+						.map(|(stream, _)| stream)
+						.map_err(WebSocketError::from)
+				})
+			});
+		Box::new(tunnel)
+	}
+
+	impl PeerAddr for TcpStream {
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			TcpStream::peer_addr(self)
+		}
+
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			TcpStream::local_addr(self)
+		}
+	}
+
+	#[cfg(feature = "async-ssl")]
+	impl<S> PeerAddr for TlsStream<S>
+	where
+		S: PeerAddr,
+	{
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			self.get_ref().peer_addr()
+		}
+
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			self.get_ref().local_addr()
+		}
+	}
+
+	impl<S, C> PeerAddr for Framed<S, C>
+	where
+		S: PeerAddr,
+	{
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			self.get_ref().peer_addr()
+		}
+
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			self.get_ref().local_addr()
+		}
+	}
+
+	#[cfg(unix)]
+	mod unix {
+		use futures::{Async, Poll};
+		use mio::unix::EventedFd;
+		use mio::{self, Evented, PollOpt, Ready, Token};
+		use std::io::{self, Read, Write};
+		use std::net::Shutdown;
+		use std::os::unix::io::AsRawFd;
+		use std::os::unix::net::{self, SocketAddr};
+		use std::path::Path;
+		use tokio_io::{AsyncRead, AsyncWrite};
+		use tokio_reactor::{Handle, PollEvented};
+
+		/// A Unix domain socket which can be read from and written to
+		/// asynchronously, e.g. to offer a WebSocket interface of a local
+		/// daemon without a TCP port.
+		///
+		/// Messages are framed over it like over a `TcpStream`, e.g. with
+		/// `MessageCodec`, and the upgrades of the server work with it too.
+		#[derive(Debug)]
+		pub struct UnixStream {
+			io: PollEvented<Socket>,
+		}
+
+		impl UnixStream {
+			/// Connects to the socket at `path`.
+			///
+			/// Connecting to a Unix domain socket doesn't wait for the other
+			/// end to accept the connection, so this doesn't block.
+			pub fn connect<P>(path: P, handle: &Handle) -> io::Result<UnixStream>
+			where
+				P: AsRef<Path>,
+			{
+				UnixStream::from_std(net::UnixStream::connect(path)?, handle)
+			}
+
+			/// Registers a connected socket with the reactor of `handle`, it
+			/// is switched to nonblocking mode.
+			pub fn from_std(stream: net::UnixStream, handle: &Handle) -> io::Result<UnixStream> {
+				stream.set_nonblocking(true)?;
+				Ok(UnixStream {
+					io: PollEvented::new_with_handle(Socket(stream), handle)?,
+				})
+			}
+
+			/// Creates a pair of sockets connected to each other.
+			pub fn pair(handle: &Handle) -> io::Result<(UnixStream, UnixStream)> {
+				let (a, b) = net::UnixStream::pair()?;
+				Ok((
+					UnixStream::from_std(a, handle)?,
+					UnixStream::from_std(b, handle)?,
+				))
+			}
+
+			/// The address of this end of the connection.
+			pub fn local_addr(&self) -> io::Result<SocketAddr> {
+				self.get_ref().local_addr()
+			}
+
+			/// The address of the other end of the connection, which often
+			/// has no path, e.g. for the client of a server socket.
+			pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+				self.get_ref().peer_addr()
+			}
+
+			/// The socket itself, e.g. to set options.
+			pub fn get_ref(&self) -> &net::UnixStream {
+				&self.io.get_ref().0
+			}
+		}
+
+		impl Read for UnixStream {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+				self.io.read(buf)
+			}
+		}
+
+		impl Write for UnixStream {
+			fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+				self.io.write(buf)
+			}
+
+			fn flush(&mut self) -> io::Result<()> {
+				self.io.flush()
+			}
+		}
+
+		impl AsyncRead for UnixStream {}
+
+		impl AsyncWrite for UnixStream {
+			fn shutdown(&mut self) -> Poll<(), io::Error> {
+				self.get_ref().shutdown(Shutdown::Write)?;
+				Ok(Async::Ready(()))
+			}
+		}
+
+		// mio only knows about the file descriptor of the socket
+		#[derive(Debug)]
+		struct Socket(net::UnixStream);
+
+		impl Evented for Socket {
+			fn register(
+				&self,
+				poll: &mio::Poll,
+				token: Token,
+				interest: Ready,
+				opts: PollOpt,
+			) -> io::Result<()> {
+				EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+			}
+
+			fn reregister(
+				&self,
+				poll: &mio::Poll,
+				token: Token,
+				interest: Ready,
+				opts: PollOpt,
+			) -> io::Result<()> {
+				EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+			}
+
+			fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+				EventedFd(&self.0.as_raw_fd()).deregister(poll)
+			}
+		}
+
+		impl Read for Socket {
+			fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+				self.0.read(buf)
+			}
+		}
+
+		impl Write for Socket {
+			fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+				self.0.write(buf)
+			}
+
+			fn flush(&mut self) -> io::Result<()> {
+				self.0.flush()
+			}
+		}
+	}
 }
 
 /// A collection of traits and implementations for synchronous streams.
 #[cfg(feature = "sync")]
 pub mod sync {
+	pub use super::PeerAddr;
+	pub use super::ProxyAuth;
 	pub use super::ReadWritePair;
+	pub use super::StreamOptions;
 	#[cfg(feature = "sync-ssl")]
 	pub use native_tls::TlsStream;
+	use net2::TcpStreamExt;
+	use result::{WebSocketError, WebSocketResult};
 	use std::io::{self, Read, Write};
 	pub use std::net::Shutdown;
+	use std::net::SocketAddr;
 	pub use std::net::TcpStream;
+	use std::net::ToSocketAddrs;
 	use std::ops::Deref;
+	#[cfg(unix)]
+	pub use std::os::unix::net::UnixStream;
+	use std::sync::{Arc, Mutex, MutexGuard};
+	use std::time::Duration;
 
 	pub use super::Stream;
 
@@ -122,7 +733,8 @@ pub mod sync {
 	/// `TcpStream` is an example. This trait marks this ability so one can split
 	/// up the client into two parts.
 	///
-	/// Notice however that this is not possible to do with SSL.
+	/// TLS streams can't be cloned, their halves share the stream, see
+	/// `split_shared`.
 	pub trait Splittable {
 		/// The reading component of this type
 		type Reader: Read;
@@ -155,6 +767,131 @@ pub mod sync {
 		}
 	}
 
+	#[cfg(unix)]
+	impl Splittable for UnixStream {
+		type Reader = UnixStream;
+		type Writer = UnixStream;
+
+		fn split(self) -> io::Result<(UnixStream, UnixStream)> {
+			self.try_clone().map(|s| (s, self))
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	impl Splittable for TlsStream<TcpStream> {
+		type Reader = SharedHalf<TlsStream<TcpStream>>;
+		type Writer = SharedHalf<TlsStream<TcpStream>>;
+
+		fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+			split_shared(self)
+		}
+	}
+
+	/// A half of a stream which can't be cloned, like a TLS stream, see
+	/// `split_shared`.
+	pub struct SharedHalf<S> {
+		stream: Arc<Mutex<S>>,
+		tcp: TcpStream,
+		// the stream may have read more than it returned, e.g. a TLS stream
+		// which decrypted a whole record
+		buffered: bool,
+	}
+
+	/// Splits a blocking stream which can't be cloned into two halves, which
+	/// share it behind a lock. One of them can be blocked on reading while
+	/// the other one writes.
+	///
+	/// The reading half waits for data on the TCP socket without holding the
+	/// lock and only takes it to read what arrived, so it never keeps the
+	/// writing half waiting for long. The read timeout of the socket applies
+	/// as usual. The halves can't be used with a nonblocking stream.
+	pub fn split_shared<S>(stream: S) -> io::Result<(SharedHalf<S>, SharedHalf<S>)>
+	where
+		S: AsTcpStream + Read + Write,
+	{
+		let (reader_tcp, writer_tcp) = {
+			let tcp = stream.as_tcp();
+			(tcp.try_clone()?, tcp.try_clone()?)
+		};
+		let stream = Arc::new(Mutex::new(stream));
+		let reader = SharedHalf {
+			stream: stream.clone(),
+			tcp: reader_tcp,
+			buffered: true,
+		};
+		let writer = SharedHalf {
+			stream,
+			tcp: writer_tcp,
+			buffered: true,
+		};
+		Ok((reader, writer))
+	}
+
+	impl<S> SharedHalf<S> {
+		fn lock<'a>(&'a self) -> io::Result<MutexGuard<'a, S>> {
+			self.stream
+				.lock()
+				.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other half panicked"))
+		}
+	}
+
+	impl<S> Read for SharedHalf<S>
+	where
+		S: Read,
+	{
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			loop {
+				// block until the socket is readable, or the connection was closed
+				let eof = if self.buffered {
+					false
+				} else {
+					self.tcp.peek(&mut [0])? == 0
+				};
+
+				// the socket is only nonblocking while the lock is held, so the
+				// writing half never notices
+				let result = {
+					let mut stream = self.lock()?;
+					self.tcp.set_nonblocking(true)?;
+					let result = stream.read(buf);
+					self.tcp.set_nonblocking(false)?;
+					result
+				};
+				match result {
+					// e.g. only a part of a TLS record arrived yet
+					Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && !eof => {
+						self.buffered = false;
+					}
+					Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(0),
+					Ok(read) => {
+						self.buffered = read > 0;
+						return Ok(read);
+					}
+					result => return result,
+				}
+			}
+		}
+	}
+
+	impl<S> Write for SharedHalf<S>
+	where
+		S: Write,
+	{
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.lock()?.write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			self.lock()?.flush()
+		}
+	}
+
+	impl<S> AsTcpStream for SharedHalf<S> {
+		fn as_tcp(&self) -> &TcpStream {
+			&self.tcp
+		}
+	}
+
 	/// The ability access a borrow to an underlying TcpStream,
 	/// so one can set options on the stream such as `nonblocking`.
 	pub trait AsTcpStream {
@@ -168,6 +905,16 @@ pub mod sync {
 		}
 	}
 
+	impl PeerAddr for TcpStream {
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			TcpStream::peer_addr(self)
+		}
+
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			TcpStream::local_addr(self)
+		}
+	}
+
 	#[cfg(feature = "sync-ssl")]
 	impl AsTcpStream for TlsStream<TcpStream> {
 		fn as_tcp(&self) -> &TcpStream {
@@ -183,4 +930,1051 @@ pub mod sync {
 			self.deref().as_tcp()
 		}
 	}
+
+	/// Sets the read and write timeouts of the TCP socket under `stream`,
+	/// `None` blocks forever.
+	///
+	/// Receiving fails with a `WebSocketError::Timeout` when the read timeout
+	/// elapses, a dataframe which was read partly is completed by the next
+	/// call. A write timeout is an `IoError`, as the receiving end can't make
+	/// sense of a partly written dataframe the connection has to be closed.
+	pub fn set_timeouts<S>(
+		stream: &S,
+		read: Option<Duration>,
+		write: Option<Duration>,
+	) -> io::Result<()>
+	where
+		S: AsTcpStream + ?Sized,
+	{
+		let tcp = stream.as_tcp();
+		tcp.set_read_timeout(read)?;
+		tcp.set_write_timeout(write)
+	}
+
+	/// Sets `options` on the TCP socket under `stream`.
+	pub fn set_options<S>(stream: &S, options: &StreamOptions) -> io::Result<()>
+	where
+		S: AsTcpStream + ?Sized,
+	{
+		let tcp = stream.as_tcp();
+		tcp.set_nodelay(options.nodelay)?;
+		TcpStreamExt::set_keepalive(tcp, options.keepalive)?;
+		if let Some(size) = options.recv_buffer {
+			tcp.set_recv_buffer_size(size)?;
+		}
+		if let Some(size) = options.send_buffer {
+			tcp.set_send_buffer_size(size)?;
+		}
+		Ok(())
+	}
+	/// Connects to `target_host:target_port` through the HTTP proxy at
+	/// `proxy_addr`, with a `CONNECT` request.
+	///
+	/// The returned stream is the tunnel to the target, a TLS connector can
+	/// wrap it for `wss://` URLs and `ClientBuilder::connect_on` does the
+	/// handshake. If the proxy answers with anything but a 2xx status, e.g.
+	/// 407 when it needs `auth`, that is a
+	/// `WebSocketError::ProxyStatusCodeError`.
+	pub fn connect_via_http_proxy<A>(
+		proxy_addr: A,
+		target_host: &str,
+		target_port: u16,
+		auth: Option<ProxyAuth>,
+	) -> WebSocketResult<TcpStream>
+	where
+		A: ToSocketAddrs,
+	{
+		let stream = TcpStream::connect(proxy_addr)?;
+		http_proxy_tunnel(stream, target_host, target_port, auth)
+	}
+
+	/// Like `connect_via_http_proxy`, but on a `stream` which is connected to
+	/// the proxy already, e.g. one with timeouts.
+	pub fn http_proxy_tunnel<S>(
+		mut stream: S,
+		target_host: &str,
+		target_port: u16,
+		auth: Option<ProxyAuth>,
+	) -> WebSocketResult<S>
+	where
+		S: Read + Write,
+	{
+		stream.write_all(&super::connect_request(
+			target_host,
+			target_port,
+			auth.as_ref(),
+		))?;
+
+		let mut response = Vec::new();
+		let mut byte = [0];
+		loop {
+			match stream.read(&mut byte) {
+				Ok(0) => return Err(WebSocketError::ResponseError(super::PROXY_CLOSED)),
+				Ok(_) => {
+					if super::push_response_byte(&mut response, byte[0])? {
+						return Ok(stream);
+					}
+				}
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+	/// Connects to `target_host:target_port` through the SOCKS5 proxy at
+	/// `proxy_addr`, authenticating with `auth` if the proxy asks for it.
+	///
+	/// Host names are resolved by the proxy. Like with
+	/// `connect_via_http_proxy` the returned stream is the tunnel to the
+	/// target. If the proxy refuses, that is a `WebSocketError::Socks5`,
+	/// telling a failed authentication apart from a failed connection to the
+	/// target.
+	pub fn connect_via_socks5<A>(
+		proxy_addr: A,
+		target_host: &str,
+		target_port: u16,
+		auth: Option<ProxyAuth>,
+	) -> WebSocketResult<TcpStream>
+	where
+		A: ToSocketAddrs,
+	{
+		let greeting = super::socks5_greeting(auth.as_ref())?;
+		let request = super::socks5_connect_request(target_host, target_port)?;
+		let mut stream = TcpStream::connect(proxy_addr)?;
+
+		stream.write_all(&greeting)?;
+		let mut answer = [0; 2];
+		stream.read_exact(&mut answer)?;
+		if let Some(credentials) = super::socks5_method(answer, auth.as_ref())? {
+			stream.write_all(&credentials)?;
+			stream.read_exact(&mut answer)?;
+			super::socks5_auth_status(answer)?;
+		}
+
+		stream.write_all(&request)?;
+		let mut reply = [0; 5];
+		stream.read_exact(&mut reply)?;
+		let mut bound_addr = vec![0; super::socks5_reply(reply)?];
+		stream.read_exact(&mut bound_addr)?;
+		Ok(stream)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use message::OwnedMessage;
+
+	#[cfg(unix)]
+	fn messages() -> Vec<OwnedMessage> {
+		vec![
+			OwnedMessage::Text("over a Unix socket".to_string()),
+			OwnedMessage::Binary(vec![0, 1, 2, 0xFF]),
+		]
+	}
+
+	#[cfg(all(feature = "sync", unix))]
+	#[test]
+	fn sync_unix_stream_round_trip() {
+		use client::builder::ClientBuilder;
+		use server::upgrade::sync::IntoWs;
+		use std::thread;
+		use stream::sync::UnixStream;
+
+		let (client, server) = UnixStream::pair().unwrap();
+		let server = thread::spawn(move || {
+			let upgrade = match server.into_ws() {
+				Ok(upgrade) => upgrade,
+				Err(e) => panic!("invalid handshake: {}", e.3),
+			};
+			let mut client = upgrade.accept().map_err(|e| e.1).unwrap();
+			for _ in 0..2 {
+				let message = client.recv_message().unwrap();
+				client.send_message(&message).unwrap();
+			}
+		});
+
+		let client = ClientBuilder::new("ws://localhost/")
+			.unwrap()
+			.connect_on(client)
+			.unwrap();
+		let (mut reader, mut writer) = client.split().unwrap();
+		for message in messages() {
+			writer.send_message(&message).unwrap();
+			assert_eq!(reader.recv_message().unwrap(), message);
+		}
+		server.join().unwrap();
+	}
+
+	#[cfg(all(feature = "async", unix))]
+	#[test]
+	fn async_unix_stream_round_trip() {
+		use codec::ws::{Context, MessageCodec};
+		use futures::{future, stream, Future, Sink, Stream};
+		use result::WebSocketError;
+		use std::io;
+		use stream::async::UnixStream;
+		use tokio;
+		use tokio_codec::Decoder;
+		use tokio_reactor::Handle;
+
+		let f = future::lazy(|| {
+			let (client, server) = UnixStream::pair(&Handle::default())?;
+			let client = MessageCodec::default(Context::Client).framed(client);
+			let server = MessageCodec::default(Context::Server).framed(server);
+			let sent = client.send_all(stream::iter_ok::<_, WebSocketError>(messages()));
+			let received = server.take(2).collect();
+			Ok::<_, io::Error>(sent.join(received).map(|(_, received)| received))
+		})
+		.flatten();
+
+		let received = tokio::runtime::Builder::new()
+			.build()
+			.unwrap()
+			.block_on(f)
+			.unwrap();
+		assert_eq!(received, messages());
+	}
+
+	#[cfg(all(feature = "async", unix))]
+	#[test]
+	fn async_handshake_over_any_stream() {
+		use client::builder::ClientBuilder;
+		use futures::{future, stream, Future, Sink, Stream};
+		use result::WebSocketError;
+		use server::upgrade::async::IntoWs;
+		use std::io;
+		use stream::async::UnixStream;
+		use tokio;
+		use tokio_reactor::Handle;
+
+		let f = future::lazy(|| {
+			let (client, server) = UnixStream::pair(&Handle::default())?;
+			let echo = server
+				.into_ws()
+				.map_err(|e| e.3.into())
+				.and_then(|upgrade| upgrade.accept())
+				.and_then(|(client, _)| {
+					let (sink, stream) = client.split();
+					stream.take(2).forward(sink)
+				});
+			let client = ClientBuilder::new("ws://localhost/")
+				.unwrap()
+				.async_connect_on(client)
+				.and_then(|(client, _)| {
+					client.send_all(stream::iter_ok::<_, WebSocketError>(messages()))
+				})
+				.and_then(|(client, _)| client.take(2).collect());
+			Ok::<_, io::Error>(echo.join(client).map(|(_, received)| received))
+		})
+		.flatten();
+
+		let received = tokio::runtime::Builder::new()
+			.build()
+			.unwrap()
+			.block_on(f)
+			.unwrap();
+		assert_eq!(received, messages());
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_addrs_after_split() {
+		use client::builder::ClientBuilder;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let accept = thread::spawn(move || {
+			let upgrade = match server.accept() {
+				Ok(upgrade) => upgrade,
+				Err(e) => panic!("invalid connection: {}", e.error),
+			};
+			let client = upgrade.accept().map_err(|e| e.1).unwrap();
+			(client.local_addr().unwrap(), client.peer_addr().unwrap())
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		let (server_local, server_peer) = accept.join().unwrap();
+		assert_eq!(client.peer_addr().unwrap(), server_local);
+		assert_eq!(client.local_addr().unwrap(), server_peer);
+
+		let (reader, writer) = client.split().unwrap();
+		assert_eq!(reader.peer_addr().unwrap(), server_local);
+		assert_eq!(writer.peer_addr().unwrap(), server_local);
+		assert_eq!(reader.local_addr().unwrap(), server_peer);
+		assert_eq!(writer.local_addr().unwrap(), server_peer);
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_addrs_through_framed() {
+		use client::builder::ClientBuilder;
+		use futures::{Future, Stream};
+		use result::WebSocketError;
+		use server::async::Server;
+		use stream::async::PeerAddr;
+		use tokio;
+		use tokio_reactor::Handle;
+
+		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let accept = server
+			.incoming()
+			.take(1)
+			.into_future()
+			.map_err(|e| WebSocketError::from(e.0.error))
+			.and_then(|(upgrade, _)| {
+				let (upgrade, peer) = upgrade.unwrap();
+				upgrade.accept().map(move |(client, _)| {
+					assert_eq!(client.peer_addr().unwrap(), peer);
+					(client.local_addr().unwrap(), peer)
+				})
+			});
+		let connect = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.async_connect_insecure();
+
+		let ((server_local, server_peer), (client, _)) =
+			runtime.block_on(accept.join(connect)).unwrap();
+		assert_eq!(server_local, addr);
+		assert_eq!(client.peer_addr().unwrap(), addr);
+		assert_eq!(client.local_addr().unwrap(), server_peer);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_stream_options() {
+		use client::builder::ClientBuilder;
+		use net2::TcpStreamExt;
+		use server::sync::Server;
+		use std::thread;
+		use std::time::Duration;
+		use stream::sync::{set_options, StreamOptions};
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let accept = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			upgrade.accept().map_err(|e| e.1).unwrap()
+		});
+
+		let options = StreamOptions {
+			nodelay: true,
+			keepalive: Some(Duration::from_secs(60)),
+			..StreamOptions::default()
+		};
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.stream_options(options)
+			.connect_insecure()
+			.unwrap();
+		let tcp = client.stream_ref();
+		assert!(tcp.nodelay().unwrap());
+		assert_eq!(
+			TcpStreamExt::keepalive(tcp).unwrap(),
+			Some(Duration::from_secs(60))
+		);
+
+		let accepted = accept.join().unwrap();
+		assert!(!accepted.stream_ref().nodelay().unwrap());
+		set_options(accepted.stream_ref(), &options).unwrap();
+		assert!(accepted.stream_ref().nodelay().unwrap());
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_stream_options() {
+		use client::builder::ClientBuilder;
+		use futures::{Future, Stream};
+		use server::async::Server;
+		use std::time::Duration;
+		use stream::async::StreamOptions;
+		use tokio;
+		use tokio_reactor::Handle;
+
+		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let accept = server
+			.incoming()
+			.take(1)
+			.into_future()
+			.map_err(|_| ())
+			.and_then(|(upgrade, _)| upgrade.unwrap().0.accept().map_err(|_| ()));
+		runtime.spawn(accept.map(|_| ()));
+
+		let options = StreamOptions {
+			nodelay: true,
+			keepalive: Some(Duration::from_secs(60)),
+			..StreamOptions::default()
+		};
+		let (client, _) = runtime
+			.block_on(
+				ClientBuilder::new(&format!("ws://{}", addr))
+					.unwrap()
+					.stream_options(options)
+					.async_connect_insecure(),
+			)
+			.unwrap();
+		assert!(client.get_ref().nodelay().unwrap());
+		assert_eq!(
+			client.get_ref().keepalive().unwrap(),
+			Some(Duration::from_secs(60))
+		);
+	}
+
+	#[cfg(any(feature = "sync", feature = "async"))]
+	const ESTABLISHED: &str =
+		"HTTP/1.1 200 Connection established\r\nProxy-Agent: test\r\nVia: 1.1 test\r\n\r\n";
+
+	// A CONNECT proxy for a single connection. It passes the request on and
+	// answers with `response`, if that is a 200 it tunnels to the target.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	fn connect_proxy(
+		response: &'static str,
+	) -> (::std::net::SocketAddr, ::std::sync::mpsc::Receiver<String>) {
+		use std::io::{self, Read, Write};
+		use std::net::{Shutdown, TcpListener, TcpStream};
+		use std::sync::mpsc;
+		use std::thread;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (requests, received) = mpsc::channel();
+		thread::spawn(move || {
+			let (mut client, _) = listener.accept().unwrap();
+			let mut request = Vec::new();
+			let mut byte = [0];
+			while !request.ends_with(b"\r\n\r\n") {
+				client.read_exact(&mut byte).unwrap();
+				request.push(byte[0]);
+			}
+			let request = String::from_utf8(request).unwrap();
+			let target = request.split(' ').nth(1).unwrap().to_string();
+			requests.send(request).unwrap();
+			client.write_all(response.as_bytes()).unwrap();
+			if !response.starts_with("HTTP/1.1 200") {
+				return;
+			}
+
+			let mut upstream = TcpStream::connect(&target[..]).unwrap();
+			let mut to_upstream = (client.try_clone().unwrap(), upstream.try_clone().unwrap());
+			thread::spawn(move || {
+				let _ = io::copy(&mut to_upstream.0, &mut to_upstream.1);
+				let _ = to_upstream.1.shutdown(Shutdown::Write);
+			});
+			let _ = io::copy(&mut upstream, &mut client);
+			let _ = client.shutdown(Shutdown::Write);
+		});
+		(addr, received)
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_connect_via_http_proxy() {
+		use client::builder::ClientBuilder;
+		use server::sync::Server;
+		use std::thread;
+		use stream::sync::{connect_via_http_proxy, ProxyAuth};
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let target = server.local_addr().unwrap();
+		let echo = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			let mut client = upgrade.accept().map_err(|e| e.1).unwrap();
+			let message: OwnedMessage = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+		});
+
+		let (proxy, requests) = connect_proxy(ESTABLISHED);
+		let auth = ProxyAuth::basic("user", "pass");
+		let tunnel = connect_via_http_proxy(proxy, "127.0.0.1", target.port(), Some(auth)).unwrap();
+		assert_eq!(
+			requests.recv().unwrap(),
+			format!(
+				"CONNECT 127.0.0.1:{0} HTTP/1.1\r\nHost: 127.0.0.1:{0}\r\n\
+				 Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+				target.port()
+			)
+		);
+
+		let mut client = ClientBuilder::new(&format!("ws://{}", target))
+			.unwrap()
+			.connect_on(tunnel)
+			.unwrap();
+		let message = OwnedMessage::Text("through the proxy".to_string());
+		client.send_message(&message).unwrap();
+		assert_eq!(client.recv_message().unwrap(), message);
+		echo.join().unwrap();
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_http_proxy_refuses() {
+		use hyper::status::StatusCode;
+		use result::WebSocketError;
+		use stream::sync::connect_via_http_proxy;
+
+		let (proxy, requests) = connect_proxy(
+			"HTTP/1.1 407 Proxy Authentication Required\r\n\
+			 Proxy-Authenticate: Basic realm=\"test\"\r\nContent-Length: 0\r\n\r\n",
+		);
+		match connect_via_http_proxy(proxy, "::1", 443, None) {
+			Err(WebSocketError::ProxyStatusCodeError(StatusCode::ProxyAuthenticationRequired)) => {}
+			Err(e) => panic!("unexpected error: {}", e),
+			Ok(_) => panic!("the proxy refused"),
+		}
+		assert_eq!(
+			requests.recv().unwrap(),
+			"CONNECT [::1]:443 HTTP/1.1\r\nHost: [::1]:443\r\n\r\n"
+		);
+
+		let (proxy, _) = connect_proxy("HTTP/1.1 200 OK\r\n");
+		match connect_via_http_proxy(proxy, "example.com", 443, None) {
+			Err(WebSocketError::ResponseError(_)) => (),
+			Err(e) => panic!("unexpected error: {}", e),
+			Ok(_) => panic!("the response is incomplete"),
+		}
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_connect_via_http_proxy() {
+		use client::builder::ClientBuilder;
+		use futures::{Future, Sink, Stream};
+		use server::async::Server;
+		use stream::async::connect_via_http_proxy;
+		use tokio;
+		use tokio_reactor::Handle;
+
+		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let target = server.local_addr().unwrap();
+		let echo = server
+			.incoming()
+			.take(1)
+			.into_future()
+			.map_err(|_| ())
+			.and_then(|(upgrade, _)| upgrade.unwrap().0.accept().map_err(|_| ()))
+			.and_then(|(client, _)| {
+				let (sink, stream) = client.split();
+				stream.take(1).forward(sink).map_err(|_| ())
+			});
+		runtime.spawn(echo.map(|_| ()));
+
+		let (proxy, requests) = connect_proxy(ESTABLISHED);
+		let connect = connect_via_http_proxy(&proxy, "127.0.0.1", target.port(), None).and_then(
+			move |tunnel| {
+				ClientBuilder::new(&format!("ws://{}", target))
+					.unwrap()
+					.async_connect_on(tunnel)
+			},
+		);
+		let (client, _) = runtime.block_on(connect).unwrap();
+		assert_eq!(
+			requests.recv().unwrap(),
+			format!(
+				"CONNECT 127.0.0.1:{0} HTTP/1.1\r\nHost: 127.0.0.1:{0}\r\n\r\n",
+				target.port()
+			)
+		);
+
+		let message = OwnedMessage::Text("through the proxy".to_string());
+		let client = runtime.block_on(client.send(message.clone())).unwrap();
+		let (echoed, _) = runtime
+			.block_on(client.into_future())
+			.map_err(|e| e.0)
+			.unwrap();
+		assert_eq!(echoed, Some(message));
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_client_through_http_proxy() {
+		use client::builder::{ClientBuilder, ConnectOptions, HttpProxy};
+		use server::sync::Server;
+		use std::thread;
+		use stream::ProxyAuth;
+
+		for auth in &[None, Some(ProxyAuth::basic("user", "pass"))] {
+			let mut server = Server::bind("127.0.0.1:0").unwrap();
+			let target = server.local_addr().unwrap();
+			let echo = thread::spawn(move || {
+				let upgrade = server.accept().ok().unwrap();
+				let mut client = upgrade.accept().map_err(|e| e.1).unwrap();
+				let message: OwnedMessage = client.recv_message().unwrap();
+				client.send_message(&message).unwrap();
+			});
+
+			let (proxy, requests) = connect_proxy(ESTABLISHED);
+			let mut client = ClientBuilder::new(&format!("ws://127.0.0.1:{}", target.port()))
+				.unwrap()
+				.connect_options(ConnectOptions {
+					proxy: Some(HttpProxy {
+						host: "127.0.0.1".to_string(),
+						port: proxy.port(),
+						auth: auth.clone(),
+					}),
+					..ConnectOptions::default()
+				})
+				.connect_insecure()
+				.unwrap();
+			let request = requests.recv().unwrap();
+			assert!(
+				request.starts_with(&format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n", target.port())),
+				"{}",
+				request
+			);
+			assert_eq!(
+				request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"),
+				auth.is_some()
+			);
+
+			let message = OwnedMessage::Text("through the proxy".to_string());
+			client.send_message(&message).unwrap();
+			assert_eq!(client.recv_message().unwrap(), message);
+			echo.join().unwrap();
+		}
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_client_http_proxy_refuses() {
+		use client::builder::{ClientBuilder, ConnectOptions, HttpProxy};
+		use hyper::status::StatusCode;
+		use result::WebSocketError;
+
+		let refusals = [
+			(
+				"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+				StatusCode::ProxyAuthenticationRequired,
+			),
+			(
+				"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n",
+				StatusCode::BadGateway,
+			),
+		];
+		for &(response, status) in refusals.iter() {
+			let (proxy, requests) = connect_proxy(response);
+			let result = ClientBuilder::new("ws://unreachable.example/")
+				.unwrap()
+				.connect_options(ConnectOptions {
+					proxy: Some(HttpProxy {
+						host: "127.0.0.1".to_string(),
+						port: proxy.port(),
+						auth: None,
+					}),
+					..ConnectOptions::default()
+				})
+				.connect_insecure();
+			match result {
+				Err(WebSocketError::ProxyStatusCodeError(ref refused)) if *refused == status => (),
+				Err(e) => panic!("unexpected error: {}", e),
+				Ok(_) => panic!("the proxy refused"),
+			}
+			// the proxy and not the client resolves the target
+			assert!(requests
+				.recv()
+				.unwrap()
+				.starts_with("CONNECT unreachable.example:80 HTTP/1.1\r\n"));
+		}
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_client_through_http_proxy() {
+		use client::builder::{ClientBuilder, ConnectOptions, HttpProxy};
+		use futures::{Future, Sink, Stream};
+		use server::async::Server;
+		use stream::ProxyAuth;
+		use tokio;
+		use tokio_reactor::Handle;
+
+		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+		let server = Server::bind("127.0.0.1:0", &Handle::default()).unwrap();
+		let target = server.local_addr().unwrap();
+		let echo = server
+			.incoming()
+			.take(1)
+			.into_future()
+			.map_err(|_| ())
+			.and_then(|(upgrade, _)| upgrade.unwrap().0.accept().map_err(|_| ()))
+			.and_then(|(client, _)| {
+				let (sink, stream) = client.split();
+				stream.take(1).forward(sink).map_err(|_| ())
+			});
+		runtime.spawn(echo.map(|_| ()));
+
+		let (proxy, requests) = connect_proxy(ESTABLISHED);
+		let connect = ClientBuilder::new(&format!("ws://127.0.0.1:{}", target.port()))
+			.unwrap()
+			.connect_options(ConnectOptions {
+				proxy: Some(HttpProxy {
+					host: "127.0.0.1".to_string(),
+					port: proxy.port(),
+					auth: Some(ProxyAuth::basic("user", "pass")),
+				}),
+				..ConnectOptions::default()
+			})
+			.async_connect_insecure();
+		let (client, _) = runtime.block_on(connect).unwrap();
+		assert_eq!(
+			requests.recv().unwrap(),
+			format!(
+				"CONNECT 127.0.0.1:{0} HTTP/1.1\r\nHost: 127.0.0.1:{0}\r\n\
+				 Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+				target.port()
+			)
+		);
+
+		let message = OwnedMessage::Text("through the proxy".to_string());
+		let client = runtime.block_on(client.send(message.clone())).unwrap();
+		let (echoed, _) = runtime
+			.block_on(client.into_future())
+			.map_err(|e| e.0)
+			.unwrap();
+		assert_eq!(echoed, Some(message));
+	}
+
+	// A SOCKS5 proxy for a single connection. It wants `credentials` if there
+	// are any, passes the CONNECT request on and answers with `reply`. If that
+	// is a success, "tunnel" follows as if the target had sent it.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	fn socks5_proxy(
+		credentials: Option<(&'static str, &'static str)>,
+		reply: &'static [u8],
+	) -> (::std::net::SocketAddr, ::std::sync::mpsc::Receiver<Vec<u8>>) {
+		use std::io::{Read, Write};
+		use std::net::{TcpListener, TcpStream};
+		use std::sync::mpsc;
+		use std::thread;
+
+		fn read(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+			let mut buf = vec![0; len];
+			stream.read_exact(&mut buf).unwrap();
+			buf
+		}
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (requests, received) = mpsc::channel();
+		thread::spawn(move || {
+			let (mut client, _) = listener.accept().unwrap();
+			let greeting = read(&mut client, 2);
+			let methods = read(&mut client, greeting[1] as usize);
+			match credentials {
+				Some(_) if !methods.contains(&0x02) => {
+					return client.write_all(&[5, 0xFF]).unwrap();
+				}
+				Some((username, password)) => {
+					client.write_all(&[5, 0x02]).unwrap();
+					let len = read(&mut client, 2)[1] as usize;
+					let user = read(&mut client, len);
+					let len = read(&mut client, 1)[0] as usize;
+					let pass = read(&mut client, len);
+					if user != username.as_bytes() || pass != password.as_bytes() {
+						return client.write_all(&[1, 1]).unwrap();
+					}
+					client.write_all(&[1, 0]).unwrap();
+				}
+				None => client.write_all(&[5, 0x00]).unwrap(),
+			}
+
+			let mut request = read(&mut client, 4);
+			let len = match request[3] {
+				0x01 => 4,
+				0x04 => 16,
+				_ => {
+					request.extend(read(&mut client, 1));
+					request[4] as usize
+				}
+			};
+			request.extend(read(&mut client, len + 2));
+			let _ = requests.send(request);
+			client.write_all(reply).unwrap();
+			if reply[1] == 0 {
+				client.write_all(b"tunnel").unwrap();
+			}
+		});
+		(addr, received)
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_socks5_without_auth() {
+		use std::io::Read;
+		use stream::sync::connect_via_socks5;
+
+		let (proxy, requests) = socks5_proxy(None, &[5, 0, 0, 1, 10, 0, 0, 1, 0xC3, 0x50]);
+		let mut tunnel = connect_via_socks5(proxy, "example.com", 443, None).unwrap();
+		let mut expected = vec![5, 1, 0, 3, 11];
+		expected.extend_from_slice(b"example.com");
+		expected.extend_from_slice(&[0x01, 0xBB]);
+		assert_eq!(requests.recv().unwrap(), expected);
+
+		let mut data = [0; 6];
+		tunnel.read_exact(&mut data).unwrap();
+		assert_eq!(&data, b"tunnel");
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_socks5_with_auth() {
+		use std::io::Read;
+		use stream::sync::{connect_via_socks5, ProxyAuth};
+
+		let (proxy, requests) = socks5_proxy(
+			Some(("user", "pass")),
+			b"\x05\x00\x00\x03\x09proxy.lan\x00\x50",
+		);
+		let auth = ProxyAuth::basic("user", "pass");
+		let mut tunnel = connect_via_socks5(proxy, "::1", 8080, Some(auth)).unwrap();
+		let mut expected = vec![5, 1, 0, 4];
+		expected.extend_from_slice(&[0; 15]);
+		expected.extend_from_slice(&[1, 0x1F, 0x90]);
+		assert_eq!(requests.recv().unwrap(), expected);
+
+		let mut data = [0; 6];
+		tunnel.read_exact(&mut data).unwrap();
+		assert_eq!(&data, b"tunnel");
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_socks5_refuses() {
+		use result::{Socks5Error, WebSocketError};
+		use stream::sync::{connect_via_socks5, ProxyAuth};
+
+		let socks5_error = |result: ::result::WebSocketResult<_>| match result {
+			Err(WebSocketError::Socks5(error)) => error,
+			Err(e) => panic!("unexpected error: {}", e),
+			Ok(_) => panic!("the proxy refused"),
+		};
+		let success = &[5, 0, 0, 1, 10, 0, 0, 1, 0xC3, 0x50];
+
+		let (proxy, _) = socks5_proxy(Some(("user", "pass")), success);
+		let auth = ProxyAuth::basic("user", "wrong");
+		let error = socks5_error(connect_via_socks5(proxy, "example.com", 443, Some(auth)));
+		assert_eq!(error, Socks5Error::AuthFailed);
+
+		let (proxy, _) = socks5_proxy(Some(("user", "pass")), success);
+		let error = socks5_error(connect_via_socks5(proxy, "example.com", 443, None));
+		assert_eq!(error, Socks5Error::NoAcceptableAuth);
+
+		let (proxy, _) = socks5_proxy(None, &[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+		let error = socks5_error(connect_via_socks5(proxy, "127.0.0.1", 443, None));
+		assert!(error.is_connection_refused());
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_socks5() {
+		use futures::Future;
+		use stream::async::{connect_via_socks5, ProxyAuth};
+		use tokio;
+		use tokio_io::io::read_exact;
+
+		let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+		let (proxy, requests) = socks5_proxy(
+			Some(("user", "pass")),
+			&[
+				5, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xC3, 0x50,
+			],
+		);
+		let auth = ProxyAuth::basic("user", "pass");
+		let (_, data) = runtime
+			.block_on(
+				connect_via_socks5(&proxy, "127.0.0.1", 80, Some(auth))
+					.and_then(|tunnel| read_exact(tunnel, [0; 6]).map_err(Into::into)),
+			)
+			.unwrap();
+		assert_eq!(&data, b"tunnel");
+		assert_eq!(
+			requests.recv().unwrap(),
+			vec![5, 1, 0, 1, 127, 0, 0, 1, 0, 80]
+		);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_read_timeout() {
+		use client::builder::ClientBuilder;
+		use message::Message;
+		use result::WebSocketError;
+		use sender::Sender;
+		use server::sync::Server;
+		use std::sync::mpsc;
+		use std::thread;
+		use std::time::Duration;
+		use stream::sync::set_timeouts;
+		use ws::Sender as SenderTrait;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let (resume, resumed) = mpsc::channel();
+		let stall = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			let mut client = upgrade.accept().map_err(|e| e.1).unwrap();
+			let mut frame = Vec::new();
+			Sender::new(false)
+				.send_message(&mut frame, &Message::text("after a pause"))
+				.unwrap();
+			client.writer_mut().write_all(&frame[..5]).unwrap();
+			resumed.recv().unwrap();
+			client.writer_mut().write_all(&frame[5..]).unwrap();
+		});
+
+		let mut client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		set_timeouts(client.stream_ref(), Some(Duration::from_millis(50)), None).unwrap();
+		for _ in 0..2 {
+			match client.recv_message() {
+				Err(WebSocketError::Timeout) => (),
+				other => panic!("expected a timeout: {:?}", other),
+			}
+		}
+
+		resume.send(()).unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("after a pause".to_string())
+		);
+		stall.join().unwrap();
+	}
+
+	// Echoes every message back until it echoed a close message.
+	#[cfg(feature = "sync")]
+	fn echo_until_close<S>(mut client: ::client::sync::Client<S>)
+	where
+		S: ::stream::sync::Stream,
+	{
+		loop {
+			let message: OwnedMessage = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+			if message.is_close() {
+				break;
+			}
+		}
+	}
+
+	// Sends a few messages through the halves of `stream`, the reading half
+	// is blocked on receiving before anything is sent.
+	#[cfg(feature = "sync")]
+	fn check_split_shared<S>(stream: S)
+	where
+		S: ::stream::sync::AsTcpStream + ::std::io::Read + ::std::io::Write + Send + 'static,
+	{
+		use hyper::buffer::BufReader;
+		use receiver::{Reader, Receiver};
+		use sender::{Sender, Writer};
+		use std::thread;
+		use std::time::{Duration, Instant};
+		use stream::sync::split_shared;
+
+		let (read, write) = split_shared(stream).unwrap();
+		let mut reader = Reader {
+			stream: BufReader::new(read),
+			receiver: Receiver::new(false),
+		};
+		let mut writer = Writer {
+			stream: write,
+			sender: Sender::new(true),
+		};
+
+		let receiving = thread::spawn(move || {
+			let mut messages = Vec::new();
+			loop {
+				let message = reader.recv_message().unwrap();
+				let close = message.is_close();
+				messages.push(message);
+				if close {
+					return messages;
+				}
+			}
+		});
+		thread::sleep(Duration::from_millis(50));
+
+		// the idle reading half doesn't keep the writing half waiting
+		let start = Instant::now();
+		let mut sent = Vec::new();
+		for i in 0..100 {
+			let message = OwnedMessage::Text(format!("message {}", i));
+			writer.send_message(&message).unwrap();
+			sent.push(message);
+		}
+		assert!(start.elapsed() < Duration::from_millis(500));
+		let close = OwnedMessage::Close(None);
+		writer.send_message(&close).unwrap();
+		sent.push(close);
+
+		assert_eq!(receiving.join().unwrap(), sent);
+	}
+
+	#[cfg(feature = "sync")]
+	#[test]
+	fn sync_split_shared() {
+		use client::builder::ClientBuilder;
+		use server::sync::Server;
+		use std::thread;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let echo = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			echo_until_close(upgrade.accept().map_err(|e| e.1).unwrap());
+		});
+
+		let client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		let (tcp, buffered) = client.into_stream();
+		if let Some((_, pos, cap)) = buffered {
+			assert_eq!(pos, cap);
+		}
+		check_split_shared(tcp);
+		echo.join().unwrap();
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	#[test]
+	fn sync_split_shared_tls() {
+		use client::builder::{ClientBuilder, TlsOptions};
+		use native_tls::{Certificate, Identity, TlsAcceptor};
+		use server::sync::Server;
+		use std::thread;
+
+		let identity =
+			Identity::from_pkcs12(include_bytes!("../tests/certs/server.p12"), "websocket")
+				.unwrap();
+		let acceptor = TlsAcceptor::new(identity).unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", acceptor).unwrap();
+		let port = server.local_addr().unwrap().port();
+		let echo = thread::spawn(move || {
+			let upgrade = server.accept().ok().unwrap();
+			echo_until_close(upgrade.accept().map_err(|e| e.1).unwrap());
+		});
+
+		let ca = Certificate::from_pem(include_bytes!("../tests/certs/ca.pem")).unwrap();
+		let options = TlsOptions {
+			extra_roots: vec![ca],
+			..TlsOptions::default()
+		};
+		let client = ClientBuilder::new(&format!("wss://localhost:{}", port))
+			.unwrap()
+			.connect_secure(Some(options.connector().unwrap()))
+			.unwrap();
+		let (tls, buffered) = client.into_stream();
+		if let Some((_, pos, cap)) = buffered {
+			assert_eq!(pos, cap);
+		}
+		check_split_shared(tls);
+		echo.join().unwrap();
+	}
 }
@@ -3,6 +3,7 @@
 //! optimize the memory footprint of a dataframe for their
 //! own needs, and be able to use custom dataframes quickly
 use result::WebSocketResult;
+use std::borrow::Cow;
 use std::io::Write;
 use ws::util::header as dfh;
 use ws::util::mask;
@@ -22,25 +23,19 @@ pub trait DataFrame {
 	/// How long (in bytes) is this dataframe's payload
 	fn size(&self) -> usize;
 
-	/// Get's the size of the entire dataframe in bytes,
-	/// i.e. header and payload.
+	/// Get's the size of the entire dataframe in bytes, i.e. header and
+	/// payload, exactly as many as `write_to` writes.
 	fn frame_size(&self, masked: bool) -> usize {
-		// one byte for the opcode & reserved & fin
-		1
-        // depending on the size of the payload, add the right payload len bytes
-        + match self.size() {
-            s if s <= 125 => 1,
-            s if s <= 65535 => 3,
-            _ => 9,
-        }
-        // add the mask size if there is one
-        + if masked {
-            4
-        } else {
-            0
-        }
-        // finally add the payload len
-        + self.size()
+		let size = self.size();
+		// the payload length is in the second byte of the header (after fin,
+		// reserved & opcode) up to 125 bytes, longer ones need 2 or 8 more
+		let extended_len = match size {
+			0..=125 => 0,
+			126..=65535 => 2,
+			_ => 8,
+		};
+		let mask = if masked { 4 } else { 0 };
+		2 + extended_len + mask + size
 	}
 
 	/// Write the payload to a writer
@@ -49,45 +44,146 @@ pub trait DataFrame {
 	/// Takes the payload out into a vec
 	fn take_payload(self) -> Vec<u8>;
 
-	/// Writes a DataFrame to a Writer.
-	fn write_to(&self, writer: &mut Write, mask: bool) -> WebSocketResult<()> {
-		let mut flags = dfh::DataFrameFlags::empty();
-		if self.is_last() {
-			flags.insert(dfh::DataFrameFlags::FIN);
-		}
-		{
-			let reserved = self.reserved();
-			if reserved[0] {
-				flags.insert(dfh::DataFrameFlags::RSV1);
-			}
-			if reserved[1] {
-				flags.insert(dfh::DataFrameFlags::RSV2);
-			}
-			if reserved[2] {
-				flags.insert(dfh::DataFrameFlags::RSV3);
+	/// The payload as one contiguous slice, if it is available without
+	/// serializing it first. This allows writing the payload without
+	/// copying it, see `write_header`.
+	fn payload_bytes(&self) -> Option<&[u8]> {
+		None
+	}
+
+	/// The payload, borrowed if `payload_bytes` provides it and written into
+	/// a new buffer otherwise.
+	fn payload(&self) -> Cow<'_, [u8]> {
+		match self.payload_bytes() {
+			Some(payload) => Cow::Borrowed(payload),
+			None => {
+				let mut payload = Vec::with_capacity(self.size());
+				self.write_payload(&mut payload)
+					.expect("failed to write the payload into a buffer");
+				Cow::Owned(payload)
 			}
 		}
+	}
 
-		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
-
+	/// Writes only the header of this DataFrame, masked with the given key.
+	///
+	/// If the header is not masked, the header followed by `payload_bytes`
+	/// (if there are any) form the whole dataframe.
+	fn write_header(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
 		let header = dfh::DataFrameHeader {
-			flags,
+			flags: header_flags(self.is_last(), self.reserved()),
 			opcode: self.opcode() as u8,
 			mask: masking_key,
 			len: self.size() as u64,
 		};
 
-		let mut data = Vec::<u8>::new();
-		dfh::write_header(&mut data, header)?;
+		dfh::write_header(writer, header)
+	}
+
+	/// Writes a DataFrame to a Writer.
+	fn write_to(&self, writer: &mut Write, mask: bool) -> WebSocketResult<()> {
+		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
+		self.write_to_with_mask(writer, masking_key)
+	}
+
+	/// Writes a DataFrame to a Writer, masking it with exactly the given
+	/// key (or not at all if it is `None`).
+	fn write_to_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		let mut data = Vec::with_capacity(self.frame_size(masking_key.is_some()));
+		self.write_to_buffer(&mut data, masking_key)?;
+		writer.write_all(data.as_slice())?;
+		Ok(())
+	}
 
+	/// Writes a DataFrame straight into an in-memory buffer, masking it with
+	/// exactly the given key (or not at all if it is `None`).
+	///
+	/// Unlike `write_to` this issues many small writes instead of collecting
+	/// the whole dataframe in a temporary buffer first, so it should only be
+	/// used with writers that are buffers themselves.
+	fn write_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.write_header(buffer, masking_key)?;
 		match masking_key {
-			Some(mask) => {
-				let mut masker = Masker::new(mask, &mut data);
-				self.write_payload(&mut masker)?
+			Some(mask) => self.write_payload(&mut Masker::new(mask, buffer)),
+			None => self.write_payload(buffer),
+		}
+	}
+
+	/// Writes a DataFrame straight into an in-memory buffer like
+	/// `write_to_buffer`, but splits payloads longer than `fragment_size`
+	/// into a series of continuation frames. Each of them is masked with the
+	/// next key returned by `next_mask`.
+	///
+	/// Control frames are never fragmented, the reserved bits are only set
+	/// on the first fragment and the FIN bit only on the last one.
+	fn write_fragmented_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		if self.opcode() >= 8 || fragment_size == 0 || self.size() <= fragment_size {
+			return self.write_to_buffer(buffer, next_mask());
+		}
+
+		let collected;
+		let payload = match self.payload_bytes() {
+			Some(payload) => payload,
+			None => {
+				let mut data = Vec::with_capacity(self.size());
+				self.write_payload(&mut data)?;
+				collected = data;
+				&collected[..]
 			}
-			None => self.write_payload(&mut data)?,
 		};
-		writer.write_all(data.as_slice())?;
+
+		let mut fragments = payload.chunks(fragment_size).peekable();
+		let mut first = true;
+		while let Some(fragment) = fragments.next() {
+			let is_last = self.is_last() && fragments.peek().is_none();
+			let masking_key = next_mask();
+			let header = dfh::DataFrameHeader {
+				flags: header_flags(is_last, if first { self.reserved() } else { &[false; 3] }),
+				opcode: if first { self.opcode() } else { 0 },
+				mask: masking_key,
+				len: fragment.len() as u64,
+			};
+			dfh::write_header(buffer, header)?;
+			match masking_key {
+				Some(mask) => Masker::new(mask, buffer).write_all(fragment)?,
+				None => buffer.write_all(fragment)?,
+			}
+			first = false;
+		}
 		Ok(())
 	}
 }
+
+fn header_flags(is_last: bool, reserved: &[bool; 3]) -> dfh::DataFrameFlags {
+	let mut flags = dfh::DataFrameFlags::empty();
+	if is_last {
+		flags.insert(dfh::DataFrameFlags::FIN);
+	}
+	if reserved[0] {
+		flags.insert(dfh::DataFrameFlags::RSV1);
+	}
+	if reserved[1] {
+		flags.insert(dfh::DataFrameFlags::RSV2);
+	}
+	if reserved[2] {
+		flags.insert(dfh::DataFrameFlags::RSV3);
+	}
+	flags
+}
@@ -3,7 +3,9 @@
 //! See the `ws` module documentation for more information.
 
 use result::WebSocketResult;
+use std::borrow::Cow;
 use std::io::Write;
+use std::sync::Arc;
 use ws::dataframe::DataFrame as DataFrameable;
 
 /// A trait for WebSocket messages
@@ -11,9 +13,144 @@ pub trait Message: Sized {
 	/// Writes this message to the writer
 	fn serialize(&self, &mut Write, masked: bool) -> WebSocketResult<()>;
 
+	/// Writes this message to the writer, masking it with exactly the given
+	/// key (or not at all if it is `None`).
+	///
+	/// The default implementation falls back to `serialize`, which masks
+	/// with a randomly generated key instead.
+	fn serialize_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.serialize(writer, masking_key.is_some())
+	}
+
+	/// Writes this message straight into an in-memory buffer, masking it with
+	/// exactly the given key (or not at all if it is `None`).
+	///
+	/// The default implementation falls back to `serialize_with_mask`.
+	fn serialize_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		self.serialize_with_mask(buffer, masking_key)
+	}
+
+	/// Writes this message straight into an in-memory buffer, splitting it
+	/// into dataframes with payloads of at most `fragment_size` bytes each
+	/// masked with the next key returned by `next_mask`.
+	///
+	/// Control messages must never be fragmented. The default implementation
+	/// doesn't fragment at all and falls back to `serialize_to_buffer`.
+	fn serialize_fragmented(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		let _ = fragment_size;
+		self.serialize_to_buffer(buffer, next_mask())
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize;
 
+	/// Whether this is a single dataframe which codecs have to send exactly
+	/// as it is, i.e. without fragmenting or compressing it.
+	fn is_raw_dataframe(&self) -> bool {
+		false
+	}
+
+	/// Whether this is a control message (close, ping or pong), which codecs
+	/// send even if their send buffer is full.
+	fn is_control(&self) -> bool {
+		false
+	}
+
+	/// Whether this is a data message (text or binary), i.e. not a control
+	/// message.
+	fn is_data(&self) -> bool {
+		!self.is_control()
+	}
+
+	/// The opcode and payload of the single dataframe this message is sent
+	/// as, for codecs which send a different payload, e.g. a compressed one.
+	///
+	/// The default implementation returns `None`, such messages are always
+	/// sent as they are.
+	fn opcode_and_payload(&self) -> Option<(u8, Cow<'_, [u8]>)> {
+		None
+	}
+
+	/// The opcode and payload size of the message as it is sent, i.e. of its
+	/// first dataframe and of all its fragments together, which codecs use
+	/// for their statistics.
+	///
+	/// The default implementation uses `opcode_and_payload`.
+	fn opcode_and_size(&self) -> Option<(u8, usize)> {
+		self.opcode_and_payload()
+			.map(|(opcode, payload)| (opcode, payload.len()))
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self>;
 }
+
+/// Shared messages can be sent without giving up (or cloning) the payload,
+/// e.g. a `MessageCodec<Arc<OwnedMessage>>` can broadcast the same message to
+/// many peers by cloning only the `Arc`.
+impl<M> Message for Arc<M>
+where
+	M: Message,
+{
+	fn serialize(&self, writer: &mut dyn Write, masked: bool) -> WebSocketResult<()> {
+		(**self).serialize(writer, masked)
+	}
+
+	fn serialize_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		(**self).serialize_with_mask(writer, masking_key)
+	}
+
+	fn serialize_to_buffer(
+		&self,
+		buffer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		(**self).serialize_to_buffer(buffer, masking_key)
+	}
+
+	fn serialize_fragmented(
+		&self,
+		buffer: &mut dyn Write,
+		fragment_size: usize,
+		next_mask: &mut dyn FnMut() -> Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		(**self).serialize_fragmented(buffer, fragment_size, next_mask)
+	}
+
+	fn message_size(&self, masked: bool) -> usize {
+		(**self).message_size(masked)
+	}
+
+	fn is_raw_dataframe(&self) -> bool {
+		(**self).is_raw_dataframe()
+	}
+
+	fn is_control(&self) -> bool {
+		(**self).is_control()
+	}
+
+	fn opcode_and_size(&self) -> Option<(u8, usize)> {
+		(**self).opcode_and_size()
+	}
+
+	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self> {
+		M::from_dataframes(frames).map(Arc::new)
+	}
+}
@@ -1,7 +1,9 @@
 //! Utility functions for reading and writing data frame headers.
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use result::{WebSocketError, WebSocketResult};
+use dataframe::Opcode;
+use result::{ProtocolError, WebSocketError, WebSocketResult};
+use std::convert::TryFrom;
 use std::io::{Read, Write};
 
 bitflags! {
@@ -69,68 +71,127 @@ pub fn write_header(writer: &mut Write, header: DataFrameHeader) -> WebSocketRes
 	Ok(())
 }
 
-/// Reads a data frame header.
-pub fn read_header<R>(reader: &mut R) -> WebSocketResult<DataFrameHeader>
-where
-	R: Read,
-{
-	let byte0 = reader.read_u8()?;
-	let byte1 = reader.read_u8()?;
+/// The result of `parse_header` on the start of a dataframe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseResult {
+	/// The header was parsed, it takes up the first `header_len` bytes.
+	Complete {
+		/// The parsed header.
+		header: DataFrameHeader,
+		/// The length of the header in bytes, the payload starts after it.
+		header_len: usize,
+	},
+	/// The header is incomplete, at least `needed` more bytes are required.
+	///
+	/// The exact length of a header is only known after its first two bytes,
+	/// after those `needed` is the number of missing header bytes.
+	Incomplete {
+		/// How many bytes have to be added to the prefix.
+		needed: usize,
+	},
+}
+
+/// Parses the data frame header at the start of `buf`, which may also
+/// contain (a part of) the payload after the header.
+///
+/// Unlike `read_header` this doesn't consume anything: the header is only
+/// parsed once it is complete, before that this reports how many bytes are
+/// missing.
+pub fn parse_header(buf: &[u8]) -> WebSocketResult<ParseResult> {
+	if buf.len() < 2 {
+		return Ok(ParseResult::Incomplete {
+			needed: 2 - buf.len(),
+		});
+	}
+	let byte0 = buf[0];
+	let byte1 = buf[1];
 
 	let flags = DataFrameFlags::from_bits_truncate(byte0);
-	let opcode = byte0 & 0x0F;
+	let opcode = Opcode::try_from(byte0 & 0x0F)?;
 
+	let len_bytes = match byte1 & 0x7F {
+		126 => 2,
+		127 => 8,
+		_ => 0,
+	};
+	let mask_bytes = if byte1 & 0x80 == 0x80 { 4 } else { 0 };
+	let header_len = 2 + len_bytes + mask_bytes;
+	if buf.len() < header_len {
+		return Ok(ParseResult::Incomplete {
+			needed: header_len - buf.len(),
+		});
+	}
+
+	let mut rest = &buf[2..header_len];
 	let len = match byte1 & 0x7F {
-		0...125 => u64::from(byte1 & 0x7F),
+		0..=125 => u64::from(byte1 & 0x7F),
 		126 => {
-			let len = u64::from(reader.read_u16::<BigEndian>()?);
+			let len = u64::from(rest.read_u16::<BigEndian>()?);
 			if len <= 125 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
+				return Err(ProtocolError::NonMinimalLength(len).into());
 			}
 			len
 		}
 		127 => {
-			let len = reader.read_u64::<BigEndian>()?;
+			let len = rest.read_u64::<BigEndian>()?;
 			if len <= 65535 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
+				return Err(ProtocolError::NonMinimalLength(len).into());
+			}
+			if len >> 63 != 0 {
+				return Err(ProtocolError::InvalidLength(len).into());
 			}
 			len
 		}
 		_ => unreachable!(),
 	};
 
-	if opcode >= 8 {
+	if opcode.is_control() {
 		if len >= 126 {
-			return Err(WebSocketError::DataFrameError(
-				"Control frame length too long",
-			));
+			return Err(ProtocolError::ControlFrameTooLarge(len).into());
 		}
 		if !flags.contains(DataFrameFlags::FIN) {
-			return Err(WebSocketError::ProtocolError(
-				"Illegal fragmented control frame",
-			));
+			return Err(ProtocolError::FragmentedControlFrame(opcode.into()).into());
 		}
 	}
 
-	let mask = if byte1 & 0x80 == 0x80 {
-		Some([
-			reader.read_u8()?,
-			reader.read_u8()?,
-			reader.read_u8()?,
-			reader.read_u8()?,
-		])
+	let mask = if mask_bytes > 0 {
+		let mut mask = [0; 4];
+		mask.copy_from_slice(rest);
+		Some(mask)
 	} else {
 		None
 	};
 
-	Ok(DataFrameHeader {
-		flags,
-		opcode,
-		mask,
-		len,
+	Ok(ParseResult::Complete {
+		header: DataFrameHeader {
+			flags,
+			opcode: opcode.into(),
+			mask,
+			len,
+		},
+		header_len,
 	})
 }
 
+/// Reads a data frame header.
+pub fn read_header<R>(reader: &mut R) -> WebSocketResult<DataFrameHeader>
+where
+	R: Read,
+{
+	// the longest header: two bytes, an eight byte length and a mask
+	let mut buf = [0; 14];
+	let mut filled = 0;
+	loop {
+		match parse_header(&buf[..filled])? {
+			ParseResult::Complete { header, .. } => return Ok(header),
+			ParseResult::Incomplete { needed } => {
+				reader.read_exact(&mut buf[filled..filled + needed])?;
+				filled += needed;
+			}
+		}
+	}
+}
+
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
@@ -192,6 +253,140 @@ mod tests {
 		assert_eq!(&obtained[..], &expected[..]);
 	}
 
+	#[test]
+	fn test_read_header_rejects_non_minimal_lengths() {
+		// 100 in the two byte form
+		let header = [0x82, 0x7E, 0x00, 0x64];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::Protocol(ProtocolError::NonMinimalLength(100))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		// 125 in the two byte form
+		let header = [0x82, 0x7E, 0x00, 0x7D];
+		assert!(read_header(&mut &header[..]).is_err());
+		// 126 is fine in the two byte form
+		let header = [0x82, 0x7E, 0x00, 0x7E];
+		assert_eq!(read_header(&mut &header[..]).unwrap().len, 126);
+
+		// 65535 in the eight byte form
+		let header = [0x82, 0x7F, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::Protocol(ProtocolError::NonMinimalLength(65535))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		// 100 in the eight byte form
+		let header = [0x82, 0x7F, 0, 0, 0, 0, 0, 0, 0, 0x64];
+		assert!(read_header(&mut &header[..]).is_err());
+		// 65536 is fine in the eight byte form
+		let header = [0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0];
+		assert_eq!(read_header(&mut &header[..]).unwrap().len, 65536);
+	}
+
+	#[test]
+	fn test_read_header_rejects_most_significant_bit() {
+		let header = [0x82, 0x7F, 0x80, 0, 0, 0, 0, 0, 0, 0];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::Protocol(ProtocolError::InvalidLength(0x8000_0000_0000_0000))) => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+		let header = [0x82, 0x7F, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+		assert_eq!(
+			read_header(&mut &header[..]).unwrap().len,
+			0x7FFF_FFFF_FFFF_FFFF
+		);
+	}
+
+	#[test]
+	fn test_parse_header_prefixes() {
+		// a masked binary frame with a 300 byte payload
+		let header = [0x82, 0xFE, 0x01, 0x2C, 1, 2, 3, 4];
+		let needed = [2, 1, 6, 5, 4, 3, 2, 1];
+		for (len, &needed) in needed.iter().enumerate() {
+			assert_eq!(
+				parse_header(&header[..len]).unwrap(),
+				ParseResult::Incomplete { needed }
+			);
+		}
+		let expected = ParseResult::Complete {
+			header: DataFrameHeader {
+				flags: DataFrameFlags::FIN,
+				opcode: 2,
+				mask: Some([1, 2, 3, 4]),
+				len: 300,
+			},
+			header_len: 8,
+		};
+		assert_eq!(parse_header(&header).unwrap(), expected);
+		// the payload after the header is left alone
+		let mut frame = header.to_vec();
+		frame.extend_from_slice(&[0; 300]);
+		assert_eq!(parse_header(&frame).unwrap(), expected);
+
+		// partial eight byte lengths, unmasked
+		let header = [0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0];
+		for len in 2..header.len() {
+			assert_eq!(
+				parse_header(&header[..len]).unwrap(),
+				ParseResult::Incomplete {
+					needed: header.len() - len
+				}
+			);
+		}
+		match parse_header(&header).unwrap() {
+			ParseResult::Complete { header, header_len } => {
+				assert_eq!(header.len, 65536);
+				assert_eq!(header_len, 10);
+			}
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// invalid headers fail as soon as that is known
+		match parse_header(&[0x83]) {
+			Ok(ParseResult::Incomplete { needed: 1 }) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		match parse_header(&[0x83, 0x7E]) {
+			Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(3))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn test_read_header_rejects_reserved_opcodes() {
+		for opcode in (3..8).chain(11..16) {
+			let header = [0x80 | opcode, 0x00];
+			match read_header(&mut &header[..]) {
+				Err(WebSocketError::Protocol(ProtocolError::ReservedOpcode(o))) => {
+					assert_eq!(o, opcode)
+				}
+				other => panic!("unexpected result {:?}", other),
+			}
+		}
+		for &opcode in [0, 1, 2, 8, 9, 10].iter() {
+			let header = [0x80 | opcode, 0x00];
+			assert_eq!(read_header(&mut &header[..]).unwrap().opcode, opcode);
+		}
+	}
+
+	#[test]
+	fn test_read_header_rejects_invalid_control_frames() {
+		// ping with 126 bytes of payload
+		let header = [0x89, 0x7E, 0x00, 0x7E];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::Protocol(ProtocolError::ControlFrameTooLarge(126))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		// close without FIN
+		let header = [0x08, 0x00];
+		match read_header(&mut &header[..]) {
+			Err(WebSocketError::Protocol(ProtocolError::FragmentedControlFrame(0x8))) => (),
+			other => panic!("unexpected result {:?}", other),
+		}
+		// a 125 byte pong is fine
+		let header = [0x8A, 0x7D];
+		assert_eq!(read_header(&mut &header[..]).unwrap().len, 125);
+	}
+
 	#[bench]
 	fn bench_read_header(b: &mut test::Bencher) {
 		let header = vec![0x42u8, 0xFE, 0x02, 0x00, 0x02, 0x04, 0x08, 0x10];
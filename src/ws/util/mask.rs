@@ -1,5 +1,6 @@
 //! Utility functions for masking data frame payload data
 use rand;
+use std::cmp;
 use std::io::Result as IoResult;
 use std::io::Write;
 
@@ -25,12 +26,16 @@ impl<'w> Masker<'w> {
 
 impl<'w> Write for Masker<'w> {
 	fn write(&mut self, data: &[u8]) -> IoResult<usize> {
-		let mut buf = Vec::with_capacity(data.len());
-		for &byte in data.iter() {
-			buf.push(byte ^ self.key[self.pos]);
-			self.pos = (self.pos + 1) % self.key.len();
-		}
-		self.end.write(&buf)
+		// mask in chunks on the stack instead of allocating for every write
+		let mut buf = [0u8; 1024];
+		let len = cmp::min(data.len(), buf.len());
+		buf[..len].copy_from_slice(&data[..len]);
+		let mut key = self.key;
+		key.rotate_left(self.pos);
+		apply_mask(&mut buf[..len], key);
+		let written = self.end.write(&buf[..len])?;
+		self.pos = (self.pos + written) % self.key.len();
+		Ok(written)
 	}
 
 	fn flush(&mut self) -> IoResult<()> {
@@ -43,16 +48,63 @@ pub fn gen_mask() -> [u8; 4] {
 	rand::random()
 }
 
-/// Masks data to send to a server and writes
+/// Masks data to send to a server (or unmasks received data) into a new
+/// buffer, copying and masking it in one pass
 pub fn mask_data(mask: [u8; 4], data: &[u8]) -> Vec<u8> {
+	let word = u64::from_ne_bytes([
+		mask[0], mask[1], mask[2], mask[3], mask[0], mask[1], mask[2], mask[3],
+	]);
 	let mut out = Vec::with_capacity(data.len());
-	let zip_iter = data.iter().zip(mask.iter().cycle());
-	for (&buf_item, &key_item) in zip_iter {
-		out.push(buf_item ^ key_item);
+	let mut chunks = data.chunks_exact(8);
+	for chunk in &mut chunks {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(chunk);
+		out.extend_from_slice(&(u64::from_ne_bytes(bytes) ^ word).to_ne_bytes());
 	}
+	// a multiple of 8 bytes was masked, so the tail starts with the key again
+	let tail = chunks.remainder().iter().enumerate();
+	out.extend(tail.map(|(i, byte)| byte ^ mask[i % 4]));
 	out
 }
 
+/// Masks (or unmasks) data in place, without allocating a new buffer
+pub fn mask_data_in_place(mask: [u8; 4], data: &mut [u8]) {
+	apply_mask(data, mask);
+}
+
+/// XORs `buf` with `key` repeated over its whole length, the first byte of
+/// `buf` with the first byte of the key. Masking and unmasking are the same
+/// operation.
+///
+/// The bulk of the buffer is processed 8 bytes at a time, which the compiler
+/// can turn into SIMD instructions.
+pub fn apply_mask(buf: &mut [u8], key: [u8; 4]) {
+	// the bytes before the first 8 byte boundary, so the words are aligned
+	let head = cmp::min(buf.as_ptr().align_offset(8), buf.len());
+	let (head, rest) = buf.split_at_mut(head);
+	for (i, byte) in head.iter_mut().enumerate() {
+		*byte ^= key[i % 4];
+	}
+
+	// the key continues where the head ended
+	let mut key = key;
+	key.rotate_left(head.len() % 4);
+	let word = u64::from_ne_bytes([
+		key[0], key[1], key[2], key[3], key[0], key[1], key[2], key[3],
+	]);
+
+	let mut chunks = rest.chunks_exact_mut(8);
+	for chunk in &mut chunks {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(chunk);
+		chunk.copy_from_slice(&(u64::from_ne_bytes(bytes) ^ word).to_ne_bytes());
+	}
+	// a multiple of 8 bytes was masked, so the tail starts with the key again
+	for (i, byte) in chunks.into_remainder().iter_mut().enumerate() {
+		*byte ^= key[i % 4];
+	}
+}
+
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
@@ -68,6 +120,90 @@ mod tests {
 
 		assert_eq!(original, reversed);
 		assert_eq!(obtained, expected);
+
+		let mut in_place = original.clone();
+		mask_data_in_place(key, &mut in_place);
+		assert_eq!(in_place, expected);
+	}
+
+	#[test]
+	fn test_masker_matches_mask_data() {
+		let key = [1u8, 2u8, 3u8, 4u8];
+		let original: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+		let mut output = Vec::new();
+		{
+			let mut masker = Masker::new(key, &mut output);
+			masker.write_all(&original[..5]).unwrap();
+			masker.write_all(&original[5..]).unwrap();
+		}
+		assert_eq!(output, mask_data(key, &original));
+	}
+
+	// the byte by byte implementation `apply_mask` replaced
+	fn apply_mask_bytewise(buf: &mut [u8], key: [u8; 4]) {
+		for (byte, &key) in buf.iter_mut().zip(key.iter().cycle()) {
+			*byte ^= key;
+		}
+	}
+
+	#[test]
+	fn test_apply_mask() {
+		let key = [0x12u8, 0x34, 0x56, 0x78];
+		let data: Vec<u8> = (0..40).map(|i| (i * 7) as u8).collect();
+		for len in 0..=32 {
+			// different offsets from the alignment of the buffer
+			for offset in 0..4 {
+				let mut expected = data.clone();
+				apply_mask_bytewise(&mut expected[offset..offset + len], key);
+
+				let mut masked = data.clone();
+				apply_mask(&mut masked[offset..offset + len], key);
+				assert_eq!(masked, expected, "length {}, offset {}", len, offset);
+
+				apply_mask(&mut masked[offset..offset + len], key);
+				assert_eq!(masked, data, "length {}, offset {}", len, offset);
+
+				let copied = mask_data(key, &data[offset..offset + len]);
+				assert_eq!(copied, &expected[offset..offset + len]);
+			}
+		}
+	}
+
+	#[test]
+	fn test_masker_split_writes() {
+		let key = [1u8, 2u8, 3u8, 4u8];
+		let original: Vec<u8> = (0..100).map(|i| i as u8).collect();
+		for split in 0..8 {
+			let mut output = Vec::new();
+			{
+				let mut masker = Masker::new(key, &mut output);
+				masker.write_all(&original[..split]).unwrap();
+				masker.write_all(&original[split..]).unwrap();
+			}
+			assert_eq!(output, mask_data(key, &original));
+		}
+	}
+
+	#[bench]
+	fn bench_apply_mask(b: &mut test::Bencher) {
+		let mut buffer = vec![0u8; 64 * 1024];
+		let key = gen_mask();
+		b.bytes = buffer.len() as u64;
+		b.iter(|| {
+			apply_mask(&mut buffer, key);
+			test::black_box(&mut buffer);
+		});
+	}
+
+	#[bench]
+	fn bench_apply_mask_bytewise(b: &mut test::Bencher) {
+		let mut buffer = vec![0u8; 64 * 1024];
+		let key = gen_mask();
+		b.bytes = buffer.len() as u64;
+		b.iter(|| {
+			apply_mask_bytewise(&mut buffer, key);
+			test::black_box(&mut buffer);
+		});
 	}
 
 	#[bench]
@@ -2,6 +2,7 @@
 
 pub mod header;
 pub mod mask;
+pub mod utf8;
 
 use std::str::from_utf8;
 use std::str::Utf8Error;
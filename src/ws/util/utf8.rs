@@ -0,0 +1,92 @@
+//! Incremental UTF-8 validation for text messages split into many dataframes
+use std::str::{from_utf8, Utf8Error};
+
+/// Validates UTF-8 which arrives in chunks, e.g. the payloads of the
+/// fragments of a text message.
+///
+/// Code points can be split across chunks, the incomplete bytes at the end of
+/// a chunk are kept until the next one arrives.
+#[derive(Debug, Clone, Default)]
+pub struct Utf8Validator {
+	partial: [u8; 4],
+	partial_len: usize,
+}
+
+impl Utf8Validator {
+	/// Create a validator expecting the start of a text.
+	pub fn new() -> Self {
+		Utf8Validator::default()
+	}
+
+	/// Validate the next chunk, this fails as soon as the text can't
+	/// become valid UTF-8 anymore.
+	pub fn feed(&mut self, mut data: &[u8]) -> Result<(), Utf8Error> {
+		// complete a code point left over from the previous chunk first
+		while self.partial_len > 0 && !data.is_empty() {
+			self.partial[self.partial_len] = data[0];
+			self.partial_len += 1;
+			data = &data[1..];
+			match from_utf8(&self.partial[..self.partial_len]) {
+				Ok(_) => self.partial_len = 0,
+				Err(ref e) if e.error_len().is_none() => (),
+				Err(e) => return Err(e),
+			}
+		}
+
+		if let Err(e) = from_utf8(data) {
+			if e.error_len().is_some() {
+				return Err(e);
+			}
+			let tail = &data[e.valid_up_to()..];
+			self.partial[..tail.len()].copy_from_slice(tail);
+			self.partial_len = tail.len();
+		}
+		Ok(())
+	}
+
+	/// Check that the text didn't end in the middle of a code point, and get
+	/// ready for the next text.
+	pub fn finish(&mut self) -> Result<(), Utf8Error> {
+		let partial_len = self.partial_len;
+		self.partial_len = 0;
+		from_utf8(&self.partial[..partial_len]).map(|_| ())
+	}
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_split_code_points() {
+		let text = "a€😀b".as_bytes();
+		for i in 0..text.len() {
+			for j in i..text.len() {
+				let mut validator = Utf8Validator::new();
+				validator.feed(&text[..i]).unwrap();
+				validator.feed(&text[i..j]).unwrap();
+				validator.feed(&text[j..]).unwrap();
+				validator.finish().unwrap();
+			}
+		}
+	}
+
+	#[test]
+	fn test_invalid_sequences() {
+		let mut validator = Utf8Validator::new();
+		assert!(validator.feed(&[b'a', 0x80]).is_err());
+
+		// a 4 byte code point which is cut short by an ASCII byte
+		let mut validator = Utf8Validator::new();
+		validator.feed(&[0xF0, 0x9F]).unwrap();
+		assert!(validator.feed(&[0x98, b'a']).is_err());
+
+		// text ends in the middle of a code point
+		let mut validator = Utf8Validator::new();
+		validator.feed(&[0xE2, 0x82]).unwrap();
+		assert!(validator.finish().is_err());
+		// the validator can be used again
+		validator.feed(b"ok").unwrap();
+		validator.finish().unwrap();
+	}
+}
@@ -0,0 +1,24 @@
+#!/usr/bin/env bash
+# Generates the certificates of the TLS tests: a CA, and a certificate for
+# localhost signed by it, bundled with its key as server.p12 (password
+# "websocket"). They are valid for 100 years.
+set -euo pipefail
+cd "$(dirname "$0")"
+
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 \
+	-subj "/CN=websocket test CA" -keyout ca.key -out ca.pem
+
+openssl req -newkey rsa:2048 -nodes -subj "/CN=localhost" \
+	-keyout server.key -out server.csr
+cat > server.ext <<EXT
+basicConstraints = CA:FALSE
+keyUsage = digitalSignature, keyEncipherment
+extendedKeyUsage = serverAuth
+subjectAltName = DNS:localhost, IP:127.0.0.1
+EXT
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+	-days 36500 -extfile server.ext -out server.pem
+openssl pkcs12 -export -inkey server.key -in server.pem -certfile ca.pem \
+	-passout pass:websocket -out server.p12
+
+rm ca.key ca.srl server.csr server.ext server.key server.pem